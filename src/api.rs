@@ -0,0 +1,567 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::sync;
+
+use crate::builder::{BuiltFile, FileMetadata, PageAction, WriteError};
+use crate::editor::GotoRequest;
+use crate::git;
+use crate::markdown::{Diagnostic, Heading};
+use crate::msg::{MsgBuilder, MsgSrv};
+
+/// Result of requests without other response data
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiResponse {
+    pub success: bool,
+    pub msg: String,
+}
+
+/// Rendered contents of a file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileResponse {
+    pub path: String,
+    pub title: Option<String>,
+    pub word_count: usize,
+    /// Changes, whenever the rendered contents change
+    pub version: String,
+    /// Modification time of the file (RFC 3339)
+    pub modified: Option<String>,
+    pub last_commit: Option<git::Commit>,
+    /// Problems found while building the file
+    pub diagnostics: Vec<Diagnostic>,
+    pub html: String,
+}
+
+/// A file rendered at a revision
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevisionResponse {
+    pub path: String,
+    pub rev: String,
+    pub title: Option<String>,
+    pub html: String,
+}
+
+/// Rendered changes of a file between two revisions
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffResponse {
+    pub path: String,
+    pub from: String,
+    /// ``None`` for the file in the working tree
+    pub to: Option<String>,
+    pub html: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevisionQuery {
+    pub rev: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    pub from: String,
+    /// The file in the working tree is compared, if no revision is given
+    pub to: Option<String>,
+}
+
+/// Result of changing pages
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageResponse {
+    /// Path of the created, renamed or deleted page
+    pub path: String,
+    /// Pages, which links to the renamed page were changed
+    pub changed_files: Vec<String>,
+}
+
+/// Markdown source of a file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceResponse {
+    pub path: String,
+    pub markdown: String,
+    /// Hash of the source, which has to be sent when saving the file
+    pub version: String,
+}
+
+/// New markdown source of a file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveRequest {
+    pub markdown: String,
+}
+
+/// Result of saving a file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveResponse {
+    pub path: String,
+    /// Hash of the saved source
+    pub version: String,
+}
+
+fn error(status: StatusCode, msg: String) -> Response {
+    (
+        status,
+        Json(ApiResponse {
+            success: false,
+            msg,
+        }),
+    )
+        .into_response()
+}
+
+/// Paths of pages start with a slash, but requests may omit it
+fn absolute_path(path: String) -> String {
+    if path.starts_with('/') {
+        path
+    } else {
+        format!("/{}", path)
+    }
+}
+
+fn not_found(path: &str) -> Response {
+    error(StatusCode::NOT_FOUND, format!("File {} not found", path))
+}
+
+pub fn forbidden(msg: &str) -> Response {
+    error(StatusCode::FORBIDDEN, msg.to_string())
+}
+
+pub fn precondition_required() -> Response {
+    error(
+        StatusCode::PRECONDITION_REQUIRED,
+        "The version of the changed source is required in the If-Match header".to_string(),
+    )
+}
+
+fn internal_error() -> Response {
+    error(StatusCode::GONE, "Internal server error".to_string())
+}
+
+fn write_error(path: &str, err: WriteError) -> Response {
+    match err {
+        WriteError::NotFound => not_found(path),
+        WriteError::Conflict(version) => (
+            StatusCode::PRECONDITION_FAILED,
+            [(header::ETAG, format!("\"{}\"", version))],
+            Json(ApiResponse {
+                success: false,
+                msg: format!("File {} was changed in the meantime", path),
+            }),
+        )
+            .into_response(),
+        WriteError::AlreadyExists => error(
+            StatusCode::CONFLICT,
+            format!("File {} already exists", path),
+        ),
+        WriteError::InvalidPath => error(
+            StatusCode::BAD_REQUEST,
+            format!("{} isn't a valid path of a markdown file", path),
+        ),
+        WriteError::OtherMount => error(
+            StatusCode::BAD_REQUEST,
+            "Files can't be moved to other mounted directories".to_string(),
+        ),
+        WriteError::ReadOnly => error(StatusCode::FORBIDDEN, format!("{} can't be edited", path)),
+        WriteError::Io(err) => error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed changing {}: {}", path, err),
+        ),
+    }
+}
+
+/// Requests a built file from the builders
+async fn built_file(
+    path: &str,
+    tx_file: &sync::mpsc::Sender<MsgBuilder>,
+) -> Result<BuiltFile, Response> {
+    let (tx_onefile, rx_onefile) = sync::oneshot::channel();
+    tx_file
+        .send(MsgBuilder::File(path.to_string(), tx_onefile))
+        .await
+        .map_err(|_| internal_error())?;
+
+    match rx_onefile.await {
+        Ok((Some(file), _)) => Ok(file),
+        Ok((None, _)) => Err(not_found(path)),
+        Err(_) => Err(internal_error()),
+    }
+}
+
+pub async fn ping() -> Response {
+    Json(ApiResponse {
+        success: true,
+        msg: "Pong".to_string(),
+    })
+    .into_response()
+}
+
+/// Lists all files with their metadata
+pub async fn files(tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    let (tx_metadata, rx_metadata) = sync::oneshot::channel();
+    if tx_file
+        .send(MsgBuilder::Metadata(tx_metadata))
+        .await
+        .is_err()
+    {
+        return internal_error();
+    }
+
+    match rx_metadata.await {
+        Ok(mut metadata) => {
+            metadata.sort_by(|a, b| a.path.cmp(&b.path));
+            Json(metadata).into_response()
+        }
+        Err(_) => internal_error(),
+    }
+}
+
+/// Returns the metadata of the files with problems, sorted by path
+pub async fn problem_files(tx_file: &sync::mpsc::Sender<MsgBuilder>) -> Option<Vec<FileMetadata>> {
+    let (tx_metadata, rx_metadata) = sync::oneshot::channel();
+    tx_file.send(MsgBuilder::Metadata(tx_metadata)).await.ok()?;
+
+    let mut files: Vec<FileMetadata> = rx_metadata
+        .await
+        .ok()?
+        .into_iter()
+        .filter(|file| !file.diagnostics.is_empty())
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Some(files)
+}
+
+/// Lists the files with problems found while building them
+pub async fn problems(tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    match problem_files(&tx_file).await {
+        Some(files) => Json(files).into_response(),
+        None => internal_error(),
+    }
+}
+
+/// Returns the rendered contents of a file
+pub async fn file(path: String, tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    match built_file(&path, &tx_file).await {
+        Ok(file) => Json(FileResponse {
+            version: file.version(),
+            path,
+            title: file.title,
+            word_count: file.word_count,
+            modified: file.modified,
+            last_commit: file.last_commit,
+            diagnostics: file.diagnostics,
+            html: file.contents,
+        })
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Returns the markdown source of a file
+pub async fn source(path: String, tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    let (tx_source, rx_source) = sync::oneshot::channel();
+    if tx_file
+        .send(MsgBuilder::Source(path.clone(), tx_source))
+        .await
+        .is_err()
+    {
+        return internal_error();
+    }
+
+    match rx_source.await {
+        Ok(Some(markdown)) => {
+            let version = crate::builder::content_hash(&markdown);
+            (
+                [(header::ETAG, format!("\"{}\"", version))],
+                Json(SourceResponse {
+                    path,
+                    markdown,
+                    version,
+                }),
+            )
+                .into_response()
+        }
+        Ok(None) => not_found(&path),
+        Err(_) => internal_error(),
+    }
+}
+
+/// Writes the markdown source of a file. The file is only replaced, if its source still has the
+/// hash given by ``if_match`` (``None`` for any source), otherwise changes made in the meantime
+/// would be lost.
+pub async fn save(
+    path: String,
+    request: SaveRequest,
+    if_match: Option<String>,
+    tx_file: sync::mpsc::Sender<MsgBuilder>,
+) -> Response {
+    let (tx_saved, rx_saved) = sync::oneshot::channel();
+    if tx_file
+        .send(MsgBuilder::Save(
+            path.clone(),
+            request.markdown,
+            if_match,
+            tx_saved,
+        ))
+        .await
+        .is_err()
+    {
+        return internal_error();
+    }
+
+    match rx_saved.await {
+        Ok(Ok(version)) => (
+            [(header::ETAG, format!("\"{}\"", version))],
+            Json(SaveResponse { path, version }),
+        )
+            .into_response(),
+        Ok(Err(err)) => write_error(&path, err),
+        Err(_) => internal_error(),
+    }
+}
+
+/// Creates, renames or deletes a page
+pub async fn page(action: PageAction, tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    let action = match action {
+        PageAction::Create { path } => PageAction::Create {
+            path: absolute_path(path),
+        },
+        PageAction::Rename {
+            path,
+            new_path,
+            rewrite_links,
+        } => PageAction::Rename {
+            path: absolute_path(path),
+            new_path: absolute_path(new_path),
+            rewrite_links,
+        },
+        PageAction::Delete { path } => PageAction::Delete {
+            path: absolute_path(path),
+        },
+    };
+    let path = match &action {
+        PageAction::Rename { new_path, .. } => new_path.clone(),
+        action => action.path().to_string(),
+    };
+
+    let (tx_changed, rx_changed) = sync::oneshot::channel();
+    if tx_file
+        .send(MsgBuilder::Page(action, tx_changed))
+        .await
+        .is_err()
+    {
+        return internal_error();
+    }
+
+    match rx_changed.await {
+        Ok(Ok(changed_files)) => Json(PageResponse {
+            path,
+            changed_files,
+        })
+        .into_response(),
+        Ok(Err(err)) => write_error(&path, err),
+        Err(_) => internal_error(),
+    }
+}
+
+/// Requests the location of a file in the file system from the builders
+async fn fs_path(
+    path: &str,
+    tx_file: &sync::mpsc::Sender<MsgBuilder>,
+) -> Result<PathBuf, Response> {
+    let (tx_fs_path, rx_fs_path) = sync::oneshot::channel();
+    tx_file
+        .send(MsgBuilder::FsPath(path.to_string(), tx_fs_path))
+        .await
+        .map_err(|_| internal_error())?;
+
+    match rx_fs_path.await {
+        Ok(Some(fs_path)) => Ok(fs_path),
+        Ok(None) => Err(not_found(path)),
+        Err(_) => Err(internal_error()),
+    }
+}
+
+/// Builds markdown like the file would be built
+async fn render(
+    path: &str,
+    markdown: String,
+    tx_file: &sync::mpsc::Sender<MsgBuilder>,
+) -> Result<BuiltFile, Response> {
+    let (tx_built, rx_built) = sync::oneshot::channel();
+    tx_file
+        .send(MsgBuilder::Render(path.to_string(), markdown, tx_built))
+        .await
+        .map_err(|_| internal_error())?;
+
+    match rx_built.await {
+        Ok(Some(built)) => Ok(built),
+        Ok(None) => Err(not_found(path)),
+        Err(_) => Err(internal_error()),
+    }
+}
+
+/// Reads the file at a revision
+async fn git_show(path: &str, fs_path: &Path, rev: &str) -> Result<String, Response> {
+    git::show(fs_path, rev).await.map_err(|err| {
+        error(
+            StatusCode::NOT_FOUND,
+            format!("Failed reading {} at {}: {}", path, rev, err),
+        )
+    })
+}
+
+/// Lists the commits, which changed a file
+pub async fn history(path: String, tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    let fs_path = match fs_path(&path, &tx_file).await {
+        Ok(fs_path) => fs_path,
+        Err(response) => return response,
+    };
+
+    match git::history(&fs_path).await {
+        Ok(commits) => Json(commits).into_response(),
+        Err(err) => error(
+            StatusCode::NOT_FOUND,
+            format!("No history of {}: {}", path, err),
+        ),
+    }
+}
+
+/// Returns the commit, which last changed each line of a file
+pub async fn blame(path: String, tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    let fs_path = match fs_path(&path, &tx_file).await {
+        Ok(fs_path) => fs_path,
+        Err(response) => return response,
+    };
+
+    match git::blame(&fs_path).await {
+        Ok(lines) => Json(lines).into_response(),
+        Err(err) => error(
+            StatusCode::NOT_FOUND,
+            format!("No history of {}: {}", path, err),
+        ),
+    }
+}
+
+/// Returns a file rendered at a revision
+pub async fn revision(
+    path: String,
+    query: RevisionQuery,
+    tx_file: sync::mpsc::Sender<MsgBuilder>,
+) -> Response {
+    let result = async {
+        let fs_path = fs_path(&path, &tx_file).await?;
+        let markdown = git_show(&path, &fs_path, &query.rev).await?;
+        render(&path, markdown, &tx_file).await
+    };
+
+    match result.await {
+        Ok(built) => Json(RevisionResponse {
+            path,
+            rev: query.rev,
+            title: built.title,
+            html: built.contents,
+        })
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Returns the rendered changes of a file between two revisions or a revision and the working
+/// tree
+pub async fn diff(
+    path: String,
+    query: DiffQuery,
+    tx_file: sync::mpsc::Sender<MsgBuilder>,
+) -> Response {
+    let result = async {
+        let fs_path = fs_path(&path, &tx_file).await?;
+        let old = git_show(&path, &fs_path, &query.from).await?;
+        let new = match &query.to {
+            Some(to) => git_show(&path, &fs_path, to).await?,
+            None => tokio::fs::read_to_string(&fs_path)
+                .await
+                .map_err(|_| not_found(&path))?,
+        };
+
+        let old = render(&path, old, &tx_file).await?;
+        let new = render(&path, new, &tx_file).await?;
+        Ok::<_, Response>(crate::patch::diff_html(&old.contents, &new.contents))
+    };
+
+    match result.await {
+        Ok(html) => Json(DiffResponse {
+            path,
+            from: query.from,
+            to: query.to,
+            html,
+        })
+        .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Returns the headings of a file
+pub async fn outline(path: String, tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    match built_file(&path, &tx_file).await {
+        Ok(file) => Json::<Vec<Heading>>(crate::markdown::outline(&file.contents)).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Returns the progress of building the files
+pub async fn status(tx_file: sync::mpsc::Sender<MsgBuilder>) -> Response {
+    let (tx_status, rx_status) = sync::oneshot::channel();
+    if tx_file.send(MsgBuilder::Status(tx_status)).await.is_err() {
+        return internal_error();
+    }
+
+    match rx_status.await {
+        Ok(status) => Json(status).into_response(),
+        Err(_) => internal_error(),
+    }
+}
+
+/// Shows the position of an editor's cursor in all browsers showing the file
+pub async fn goto(
+    request: GotoRequest,
+    tx_file: sync::mpsc::Sender<MsgBuilder>,
+    tx_srv: sync::mpsc::Sender<MsgSrv>,
+) -> Response {
+    let path = absolute_path(request.path);
+    log::debug!("Goto {}:{}", path, request.line);
+
+    let file = match built_file(&path, &tx_file).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+
+    let block = crate::patch::block_of_line(&file, request.line);
+    tx_srv
+        .send(MsgSrv::Goto(path, request.line, block))
+        .await
+        .ok();
+
+    Json(ApiResponse {
+        success: true,
+        msg: "Ok".to_string(),
+    })
+    .into_response()
+}
@@ -1,4 +1,3 @@
-use crate::ParserType;
 /*
  *  md-dir-builder serve markdown files in a given directory
  *  Copyright (C) 2022 Fionn Langhans
@@ -15,15 +14,19 @@ use crate::ParserType;
  *
  *  You should have received a copy of the GNU General Public License
  */
-use crate::markdown::MarkdownParser;
+use crate::config::BuilderConfig;
+use crate::git::Commit;
+use crate::markdown::{Diagnostic, Highlighting, MarkdownParser};
 use crate::msg::MsgBuilder;
 use crate::msg::MsgInternalBuilder;
+use crate::patch::ContentPatch;
+use crate::ParserType;
 use ahash::RandomState;
-use futures::Future;
+use futures::{Future, StreamExt};
 use regex::Regex;
 use serde::Deserialize;
 use serde::Serialize;
-use std::{cmp::Ordering, collections::HashMap, path::Path, sync::Arc};
+use std::{cmp::Ordering, collections::HashMap, io::Write, path::Path, sync::Arc};
 use tokio::sync::{self, Mutex};
 
 #[cfg(feature = "watchman")]
@@ -33,21 +36,197 @@ use watchman_client::prelude::*;
 pub struct BuiltFile {
     pub contents: String,
     pub word_count: usize,
+    /// Source line of every top-level block of the contents (empty, if unknown)
+    #[serde(default)]
+    pub block_lines: Vec<usize>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Modification time of the file (RFC 3339)
+    #[serde(default)]
+    pub modified: Option<String>,
+    /// Last commit changing the file, if it's part of a git repository
+    #[serde(default)]
+    pub last_commit: Option<Commit>,
+    /// Problems found while building the file
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    /// The file is only shown and can't be edited (e.g. a notebook)
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Information about a built file without its contents
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct FileMetadata {
+    pub path: String,
+    pub title: Option<String>,
+    pub word_count: usize,
+    /// Modification time of the file (RFC 3339)
+    pub modified: Option<String>,
+    pub last_commit: Option<Commit>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Progress of building the files
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct BuildStatus {
+    /// Number of built files
+    pub files: usize,
+    /// Number of files currently being built
+    pub processing: usize,
+}
+
+impl BuiltFile {
+    /// Identifies the built contents, so clients can tell, if they have missed changes
+    pub fn version(&self) -> String {
+        content_hash(&self.contents)
+    }
+}
+
+/// Hash of a text, used to detect changes
+pub fn content_hash(text: &str) -> String {
+    let hasher = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+    format!("{:x}", hasher.checksum(text.as_bytes()))
+}
+
+/// Reasons, why changing a file failed
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum WriteError {
+    NotFound,
+    /// The file was changed since it was read, contains the hash of the current source
+    Conflict(String),
+    AlreadyExists,
+    /// The path can't be used for a markdown file
+    InvalidPath,
+    /// Files can't be moved between mounted directories
+    OtherMount,
+    /// The file is shown, but can't be changed (e.g. a notebook)
+    ReadOnly,
+    Io(String),
+}
+
+impl From<std::io::Error> for WriteError {
+    fn from(err: std::io::Error) -> Self {
+        WriteError::Io(err.to_string())
+    }
+}
+
+/// Changes of the pages requested by users
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum PageAction {
+    /// Creates a page from the template
+    Create { path: String },
+    /// Moves a page and optionally changes the relative links to it in all other pages
+    Rename {
+        path: String,
+        new_path: String,
+        #[serde(default)]
+        rewrite_links: bool,
+    },
+    /// Moves a page to the trash directory
+    Delete { path: String },
 }
 
-impl From<BuiltFile> for json::JsonValue {
-    fn from(bf: BuiltFile) -> Self {
-        json::object! {
-            "contents": bf.contents,
-            "word_count": bf.word_count
+impl PageAction {
+    /// Path of the page the action changes
+    pub fn path(&self) -> &str {
+        match self {
+            PageAction::Create { path }
+            | PageAction::Rename { path, .. }
+            | PageAction::Delete { path } => path,
         }
     }
 }
 
+/// Directory in the served directory, deleted pages are moved to
+pub const TRASH_DIR: &str = ".trash";
+
+/// Contents of new pages without a configured template, ``{title}`` is replaced by the file name
+static DEFAULT_TEMPLATE: &str = "# {title}\n";
+
+/// Replaces the file atomically with the contents, so the watcher and readers never see a
+/// partially written file. The permissions of an existing file are kept and symlinks are
+/// followed, so the file they point to is replaced.
+pub fn write_atomically(file: &Path, contents: &str) -> std::io::Result<()> {
+    let file = match std::fs::canonicalize(file) {
+        Ok(target) => target,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => file.to_path_buf(),
+        Err(err) => return Err(err),
+    };
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (temp_file, mut temp) = create_temp_file(&file, &name)?;
+
+    temp.write_all(contents.as_bytes())
+        .and_then(|_| match std::fs::metadata(&file) {
+            Ok(metadata) => temp.set_permissions(metadata.permissions()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        })
+        .and_then(|_| std::fs::rename(&temp_file, &file))
+        .inspect_err(|_| {
+            std::fs::remove_file(&temp_file).ok();
+        })
+}
+
+/// Writes a new file atomically like ``write_atomically``, but fails with ``AlreadyExists``, if
+/// the file exists, instead of replacing it.
+pub fn write_new(file: &Path, contents: &str) -> std::io::Result<()> {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (temp_file, mut temp) = create_temp_file(file, &name)?;
+
+    let written = temp
+        .write_all(contents.as_bytes())
+        .and_then(|_| std::fs::hard_link(&temp_file, file));
+    std::fs::remove_file(&temp_file).ok();
+
+    written
+}
+
+/// Creates a new temporary file next to the file. The name is random and an existing file (or
+/// symlink) is never written to.
+fn create_temp_file(
+    file: &Path,
+    name: &str,
+) -> std::io::Result<(std::path::PathBuf, std::fs::File)> {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default()
+        ^ std::process::id();
+
+    let mut last_error = None;
+    for attempt in 0..100u32 {
+        // Hidden and without a markdown extension, so it's never built
+        let temp_file = file.with_file_name(format!(
+            ".{}.{:08x}.md-dir-builder~",
+            name,
+            seed.wrapping_add(attempt.wrapping_mul(0x9e37_79b9))
+        ));
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_file)
+        {
+            Ok(temp) => return Ok((temp_file, temp)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => last_error = Some(err),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| std::io::Error::other("No temporary file name left")))
+}
+
 use super::MsgSrv;
 use std::fs;
 
-static IGNORE_DIRS: &[&str] = &[".git"];
+pub(crate) static IGNORE_DIRS: &[&str] = &[".git", ".ipynb_checkpoints", TRASH_DIR];
 
 /// Searches for directories. Directories which are equal to any directory in `IGNORE_DIRS` are
 /// neither returned, nor searched through (they will be ignored by this function).
@@ -97,7 +276,7 @@ fn broad_dir_search(path_str: &String) -> Vec<String> {
 }
 
 /// Searches for all markdown files in a directory and returns their relative path (to `path_str`)
-fn broad_file_search(path_str: String) -> Vec<String> {
+fn broad_file_search(path_str: String, config: &BuilderConfig) -> Vec<String> {
     let path_str_clone = std::rc::Rc::new(path_str.clone());
     let path = Path::new(&path_str);
     if let Ok(files) = path.read_dir() {
@@ -106,16 +285,16 @@ fn broad_file_search(path_str: String) -> Vec<String> {
                 Ok(entry) => {
                     let file_name = std::rc::Rc::new(entry.file_name().into_string().unwrap());
                     if entry.path().is_dir() {
-                        if IGNORE_DIRS.contains(&file_name.as_str()) {
+                        if config.is_ignored(file_name.as_str()) {
                             vec![]
                         } else {
                             let newdir = format!("{}/{}", &path_str_clone, file_name);
-                            broad_file_search(newdir)
+                            broad_file_search(newdir, config)
                                 .iter()
                                 .map(|path| format!("{}/{}", file_name, path))
                                 .collect()
                         }
-                    } else if file_name.ends_with(".md") {
+                    } else if config.is_markdown(file_name.as_str()) {
                         vec![format!("{}", file_name)]
                     } else {
                         vec![]
@@ -140,24 +319,144 @@ fn broad_file_search(path_str: String) -> Vec<String> {
 
 pub type ProcessingMap = HashMap<String, Arc<Mutex<()>>, RandomState>;
 
+/// Returns the parser for the file: the converter or the parser configured for its extension,
+/// the notebook parser (parsing markdown cells with the configured parser) or else the
+/// configured parser
+fn create_parser(config: &BuilderConfig, path: &str) -> Box<dyn MarkdownParser + Send> {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .unwrap_or_default();
+    if let Some(command) = config.converters.get(extension) {
+        return Box::new(crate::markdown::ExternalParser::new(
+            command.clone(),
+            extension,
+        ));
+    }
+
+    match config.parsers.get(extension) {
+        Some(parser) => parser_of_type(config, *parser, Some(extension)),
+        None if extension == crate::markdown::NOTEBOOK_EXTENSION => Box::new(
+            crate::markdown::NotebookParser::new(parser_of_type(config, config.parser, None)),
+        ),
+        None => parser_of_type(config, config.parser, None),
+    }
+}
+
+/// Returns a parser of the type. ``other_format`` is the extension of files of another format
+/// than markdown the parser is configured for.
+fn parser_of_type(
+    config: &BuilderConfig,
+    parser: ParserType,
+    other_format: Option<&str>,
+) -> Box<dyn MarkdownParser + Send> {
+    match parser {
+        ParserType::CommonMark => Box::new(crate::markdown::CommonMarkParser::new(
+            config.commonmark_extensions.clone(),
+        )),
+        ParserType::Pandoc => {
+            let mut options = config.pandoc.clone();
+            // Pandoc reads files of known other formats in the format of their extension, other
+            // files in the configured format
+            if let Some(format) = other_format.and_then(crate::markdown::pandoc_format) {
+                options.from = format.to_string();
+            }
+            Box::new(crate::markdown::PandocParser::new(options))
+        }
+        ParserType::RestructuredText => Box::new(crate::markdown::RestructuredTextParser),
+        ParserType::AsciiDoc => Box::new(crate::markdown::AsciiDocParser),
+    }
+}
+
+/// Builds the source of the file at ``path`` with the parser for it
+pub fn build_file(config: &BuilderConfig, path: &str, markdown: &str) -> BuiltFile {
+    let mut parser = create_parser(config, path);
+    let rendered = parser.parse_to_html(markdown);
+    let mut diagnostics = rendered.diagnostics;
+    let diagrams =
+        crate::markdown::render_diagrams(&rendered.html, markdown, &config.diagram_commands);
+    diagnostics.extend(diagrams.diagnostics);
+    let mut html = diagrams.html;
+    if config.highlighting == Highlighting::Server {
+        html = crate::markdown::highlight_code_blocks(&html);
+    }
+    let block_lines = parser.block_lines(markdown);
+    let title = crate::markdown::title(&html);
+
+    let mut file = BuiltFile {
+        contents: html,
+        word_count: count_words(&parser.text(markdown)),
+        block_lines,
+        title,
+        modified: None,
+        last_commit: None,
+        diagnostics: Vec::new(),
+        read_only: crate::markdown::is_notebook(path),
+    };
+    for diagnostic in diagnostics.iter_mut() {
+        diagnostic.block = diagnostic
+            .line
+            .and_then(|line| crate::patch::block_of_line(&file, line));
+    }
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+    file.diagnostics = diagnostics;
+
+    file
+}
+
+/// Returns whether the file at ``path`` is parsed with pandoc (like in [`create_parser`])
+fn uses_pandoc(config: &BuilderConfig, path: &str) -> bool {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .unwrap_or_default();
+
+    !config.converters.contains_key(extension)
+        && config.parsers.get(extension).unwrap_or(&config.parser) == &ParserType::Pandoc
+}
+
+/// Builds the file like [`build_file`] in a blocking thread. Files parsed with pandoc wait for a
+/// permit first, so at most ``pandoc.concurrency`` are converted at once.
+async fn build_file_blocking(config: &BuilderConfig, path: &str, markdown: String) -> BuiltFile {
+    let _permit = if uses_pandoc(config, path) {
+        crate::markdown::pandoc_permits(config.pandoc.concurrency)
+            .acquire_owned()
+            .await
+            .ok()
+    } else {
+        None
+    };
+
+    let config = config.clone();
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || build_file(&config, &path, &markdown))
+        .await
+        .expect("Building a file panicked")
+}
+
 /// Process a markdown file. Converts it to HTML and saves the result in ``map``.
-/// During processing, the a lock is generated in ``processing``.
+/// During processing, the a lock is generated in ``processing``. The last commit of the file is
+/// taken from ``last_commits`` (by the path relative to ``dir``), if the last commits of all
+/// files were collected, otherwise it's looked up.
 ///
 /// ## Result
 ///
 /// Returns ``true`` on success, otherwise ``false``.
+#[allow(clippy::too_many_arguments)]
 async fn process_file<
     ReadFile: Fn(String) -> anyhow::Result<String> + Clone + std::marker::Sync,
 >(
-    parser_type: ParserType,
+    config: &BuilderConfig,
+    mount: &str,
     dir: &Path,
     file_str: &String,
     map: Arc<Mutex<HashMap<String, BuiltFile, RandomState>>>,
     files: Arc<Mutex<Vec<String>>>,
     processing: Arc<Mutex<ProcessingMap>>,
     fs_read_file: ReadFile,
+    last_commits: Option<&HashMap<String, Commit>>,
 ) -> bool {
-    let webpath = format!("/{}", file_str);
+    let webpath = format!("{}/{}", mount, file_str);
     log::debug!("Processing file {}", webpath);
 
     processing
@@ -170,21 +469,21 @@ async fn process_file<
     let path = dir.join(file_str);
     match fs_read_file(path.to_string_lossy().to_string()) {
         Ok(result) => {
-            let result = result.as_str();
-            let html = match parser_type {
-                ParserType::CommonMark => {
-                    crate::markdown::CommonMarkParser::default().parse_to_html(result)
-                }
-                ParserType::Pandoc => {
-                    crate::markdown::PandocParser::default().parse_to_html(result)
-                }
+            let modified = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .map(|modified| chrono::DateTime::<chrono::Local>::from(modified).to_rfc3339());
+            let last_commit = match last_commits {
+                Some(last_commits) => last_commits.get(file_str).cloned(),
+                None => crate::git::last_commit(&path).await.ok().flatten(),
             };
 
             map.lock().await.insert(
                 webpath.clone(),
                 BuiltFile {
-                    contents: html,
-                    word_count: count_words(result),
+                    modified,
+                    last_commit,
+                    ..build_file_blocking(config, file_str, result).await
                 },
             );
             let mut files = files.lock().await;
@@ -209,6 +508,235 @@ async fn process_file<
     success
 }
 
+/// Sends the changed list of files to the servers
+async fn announce_files(
+    mount: &str,
+    webpath: &str,
+    files: &Arc<Mutex<Vec<String>>>,
+    tx_srv: &sync::mpsc::Sender<MsgSrv>,
+) {
+    sort_files(files.clone()).await;
+    let all_files = files.lock().await.clone();
+    tx_srv
+        .send(MsgSrv::NewFile(
+            mount.to_string(),
+            webpath.to_string(),
+            all_files,
+        ))
+        .await
+        .unwrap();
+}
+
+/// Forgets a built file. Returns ``false``, if the file wasn't built.
+async fn remove_file(
+    webpath: &str,
+    map: &Arc<Mutex<HashMap<String, BuiltFile, RandomState>>>,
+    files: &Arc<Mutex<Vec<String>>>,
+) -> bool {
+    map.lock().await.remove(webpath);
+    let mut files = files.lock().await;
+    let count = files.len();
+    files.retain(|file| file != webpath);
+
+    files.len() != count
+}
+
+/// Returns the path of a page relative to the served directory. Fails, if the page can't be a
+/// markdown file of the mounted directory (e.g. it's in an ignored directory) or can't be changed
+/// (notebooks).
+fn page_file<'a>(
+    mount: &str,
+    webpath: &'a str,
+    config: &BuilderConfig,
+) -> Result<&'a str, WriteError> {
+    let file = webpath
+        .strip_prefix(mount)
+        .and_then(|file| file.strip_prefix('/'))
+        .ok_or(WriteError::InvalidPath)?;
+
+    if file
+        .split('/')
+        .any(|part| part.is_empty() || part == "." || part == ".." || IGNORE_DIRS.contains(&part))
+        || !config.is_markdown(file)
+    {
+        return Err(WriteError::InvalidPath);
+    }
+    if crate::markdown::is_notebook(file) {
+        return Err(WriteError::ReadOnly);
+    }
+
+    Ok(file)
+}
+
+/// Creates, renames or deletes a page and updates the built files and the sidebars accordingly.
+/// Returns the pages, which links were changed.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn change_page<
+    ReadFile: Fn(String) -> anyhow::Result<String> + Clone + std::marker::Sync,
+>(
+    action: PageAction,
+    config: &BuilderConfig,
+    mount: &str,
+    dir: &Path,
+    map: Arc<Mutex<HashMap<String, BuiltFile, RandomState>>>,
+    files: Arc<Mutex<Vec<String>>>,
+    processing: Arc<Mutex<ProcessingMap>>,
+    fs_read_file: ReadFile,
+    tx_srv: &sync::mpsc::Sender<MsgSrv>,
+) -> Result<Vec<String>, WriteError> {
+    log::info!("Changing pages: {:?}", action);
+
+    let mut changed = Vec::new();
+    match action {
+        PageAction::Create { path } => {
+            let file = page_file(mount, &path, config)?;
+            let fs_path = dir.join(file);
+            if fs_path.exists() {
+                return Err(WriteError::AlreadyExists);
+            }
+
+            let template = match &config.page_template {
+                Some(template) => fs_read_file(dir.join(template).to_string_lossy().to_string())
+                    .map_err(|err| WriteError::Io(err.to_string()))?,
+                None => DEFAULT_TEMPLATE.to_string(),
+            };
+            let title = fs_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            if let Some(parent) = fs_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // Fails, if the page was created in the meantime
+            write_new(&fs_path, &template.replace("{title}", &title)).map_err(|err| {
+                if err.kind() == std::io::ErrorKind::AlreadyExists {
+                    WriteError::AlreadyExists
+                } else {
+                    WriteError::Io(err.to_string())
+                }
+            })?;
+
+            process_file(
+                config,
+                mount,
+                dir,
+                &file.to_string(),
+                map,
+                files.clone(),
+                processing,
+                fs_read_file,
+                None,
+            )
+            .await;
+            announce_files(mount, &path, &files, tx_srv).await;
+        }
+        PageAction::Rename {
+            path,
+            new_path,
+            rewrite_links,
+        } => {
+            if !files.lock().await.contains(&path) {
+                return Err(WriteError::NotFound);
+            }
+
+            let file = page_file(mount, &path, config)?;
+            let new_file = page_file(mount, &new_path, config)?;
+            let new_fs_path = dir.join(new_file);
+            if new_fs_path.exists() {
+                return Err(WriteError::AlreadyExists);
+            }
+
+            if let Some(parent) = new_fs_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(dir.join(file), &new_fs_path)?;
+
+            // Relative links of the page itself have to point to the same files from its new
+            // directory
+            if config.is_markdown_source(new_file) {
+                if let Ok(markdown) = fs_read_file(new_fs_path.to_string_lossy().to_string()) {
+                    if let Some(markdown) = crate::links::rebase_links(&markdown, &path, &new_path)
+                    {
+                        write_atomically(&new_fs_path, &markdown)?;
+                    }
+                }
+            }
+
+            remove_file(&path, &map, &files).await;
+            process_file(
+                config,
+                mount,
+                dir,
+                &new_file.to_string(),
+                map,
+                files.clone(),
+                processing,
+                fs_read_file.clone(),
+                None,
+            )
+            .await;
+
+            if rewrite_links {
+                // The changed pages are rebuilt after the watcher noticed the change. Links are
+                // only found in files parsed as markdown.
+                let pages = files.lock().await.clone();
+                for page in pages.into_iter().filter(|page| *page != new_path) {
+                    let page_file = &page[mount.len() + 1..];
+                    if !config.is_markdown_source(page_file) {
+                        continue;
+                    }
+
+                    let fs_path = dir.join(page_file);
+                    let markdown = match fs_read_file(fs_path.to_string_lossy().to_string()) {
+                        Ok(markdown) => markdown,
+                        Err(_) => continue,
+                    };
+
+                    if let Some(markdown) =
+                        crate::links::rewrite_links(&markdown, &page, &path, &new_path)
+                    {
+                        write_atomically(&fs_path, &markdown)?;
+                        changed.push(page);
+                    }
+                }
+            }
+
+            announce_files(mount, &new_path, &files, tx_srv).await;
+        }
+        PageAction::Delete { path } => {
+            if !files.lock().await.contains(&path) {
+                return Err(WriteError::NotFound);
+            }
+
+            let file = page_file(mount, &path, config)?;
+            let mut trash_path = dir.join(TRASH_DIR).join(file);
+            if trash_path.exists() {
+                // Keep earlier deleted versions
+                let timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|duration| duration.as_secs())
+                    .unwrap_or_default();
+                let name = trash_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                trash_path = trash_path.with_file_name(format!("{}.{}", name, timestamp));
+            }
+
+            if let Some(parent) = trash_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(dir.join(file), &trash_path)?;
+
+            remove_file(&path, &map, &files).await;
+            announce_files(mount, &path, &files, tx_srv).await;
+        }
+    }
+
+    Ok(changed)
+}
+
 async fn sort_files(files: Arc<Mutex<Vec<String>>>) {
     files.lock().await.sort_by(|a, b| -> Ordering {
         let cnt_dir_a = a.matches('/').count();
@@ -224,10 +752,12 @@ async fn sort_files(files: Arc<Mutex<Vec<String>>>) {
 
 /// Creats the Markdown to HTML builder
 ///
-/// This watches the given directory ``path_str`` and rebuilds new or modified files.
+/// This watches the given directory ``path_str`` and rebuilds new or modified files. The files
+/// are served below the URL prefix ``mount``.
 pub async fn builder(
-    parser_type: ParserType,
+    config: BuilderConfig,
     tx_srv: sync::mpsc::Sender<MsgSrv>,
+    mount: String,
     path_str: String,
     tx_file: sync::mpsc::Sender<MsgBuilder>,
     rx_file: sync::mpsc::Receiver<MsgBuilder>,
@@ -245,8 +775,9 @@ pub async fn builder(
     let fs_change = watch_inotify;
 
     builder_with_fs_change(
-        parser_type,
+        config,
         tx_srv,
+        mount,
         path_str,
         tx_file,
         rx_file,
@@ -268,9 +799,11 @@ fn std_read_file(s: String) -> anyhow::Result<String> {
 /// Creats the Markdown to HTML builder with filechange watcher
 ///
 /// This watches the given directory ``path_str`` and rebuilds new or modified files.
+#[allow(clippy::too_many_arguments)]
 pub async fn builder_with_fs_change<R, T, ReadFile, BroadFileSearch>(
-    parser_type: ParserType,
+    config: BuilderConfig,
     tx_srv: sync::mpsc::Sender<MsgSrv>,
+    mount: String,
     path_str: String,
     tx_file: sync::mpsc::Sender<MsgBuilder>,
     rx_file: sync::mpsc::Receiver<MsgBuilder>,
@@ -278,7 +811,7 @@ pub async fn builder_with_fs_change<R, T, ReadFile, BroadFileSearch>(
     fs_read_file: ReadFile,
     broad_file_search: BroadFileSearch,
 ) where
-    BroadFileSearch: Fn(String) -> Vec<String>,
+    BroadFileSearch: Fn(String, &BuilderConfig) -> Vec<String> + Clone + Sync + Send + 'static,
     ReadFile: Fn(String) -> anyhow::Result<String> + Clone + Sync + Send + 'static,
     R: Future<Output = anyhow::Result<()>> + Sync + Send,
     T: Fn(sync::mpsc::Sender<MsgInternalBuilder>, String) -> R + Sync + Send + 'static,
@@ -309,40 +842,58 @@ pub async fn builder_with_fs_change<R, T, ReadFile, BroadFileSearch>(
 
     let files_to_build = {
         let path_str = path_str.clone();
-        broad_file_search(path_str)
+        broad_file_search(path_str, &config)
     };
 
     log::debug!("Starting file builder");
 
+    let (tx_builder, rx_builder) = sync::mpsc::channel(crate::CHANNEL_COUNT);
+
     // Listen to queries from the server
     let server_queries_handle = {
         let map = map.clone();
         let processing = processing.clone();
         let files = files.clone();
+        let tx_builder = tx_builder.clone();
+        let mount = mount.clone();
+        let path_str = path_str.clone();
+        let fs_read_file = fs_read_file.clone();
 
-        server_queries(rx_file, processing, map, files)
+        server_queries(
+            rx_file,
+            tx_builder,
+            mount,
+            path_str,
+            processing,
+            map,
+            files,
+            fs_read_file,
+        )
     };
 
     // Listen to file (created,modified,deleted) events and react accordingly
-    let (tx_builder, rx_builder) = sync::mpsc::channel(crate::CHANNEL_COUNT);
     let file_builder_handle = {
+        let mount = mount.clone();
         let path_str = path_str.clone();
         let map = map.clone();
         let files = files.clone();
         let processing = processing.clone();
         let fs_read_file = fs_read_file.clone();
         let tx_file = tx_file.clone();
+        let config = config.clone();
 
         file_builder(
-            parser_type,
+            config,
             rx_builder,
             tx_file,
             tx_srv,
+            mount,
             path_str,
             processing,
             map,
             files,
             fs_read_file,
+            broad_file_search,
         )
     };
 
@@ -355,7 +906,8 @@ pub async fn builder_with_fs_change<R, T, ReadFile, BroadFileSearch>(
         let fs_read_file = fs_read_file.clone();
 
         initial_build(
-            parser_type,
+            &config,
+            &mount,
             files_to_build,
             path_str,
             processing,
@@ -371,7 +923,6 @@ pub async fn builder_with_fs_change<R, T, ReadFile, BroadFileSearch>(
     // Listen to file changes in the specified directory
     let fs_change_handle = {
         let tx_builder = tx_builder.clone();
-        let path_str = path_str;
 
         async move {
             if let Err(err) = fs_change(tx_builder.clone(), path_str).await {
@@ -389,19 +940,28 @@ pub async fn builder_with_fs_change<R, T, ReadFile, BroadFileSearch>(
     let _ = tokio::join!(
         server_queries_handle,
         file_builder_handle,
-        initial_build_handle,
-        fs_change_handle
+        initial_build_handle
     );
 
+    // Watching files never finishes by itself
+    fs_change_handle.abort();
+
     log::debug!("Exited builder files");
 }
 
-async fn server_queries(
+#[allow(clippy::too_many_arguments)]
+async fn server_queries<ReadFile>(
     mut rx_file: sync::mpsc::Receiver<MsgBuilder>,
+    tx_builder: sync::mpsc::Sender<MsgInternalBuilder>,
+    mount: String,
+    path_str: String,
     processing: Arc<Mutex<ProcessingMap>>,
     map: Arc<Mutex<HashMap<String, BuiltFile, RandomState>>>,
     files: Arc<Mutex<Vec<String>>>,
-) {
+    fs_read_file: ReadFile,
+) where
+    ReadFile: Fn(String) -> anyhow::Result<String>,
+{
     log::debug!("Started file communication");
 
     while let Some(msg) = rx_file.recv().await {
@@ -431,7 +991,113 @@ async fn server_queries(
                     .send(files)
                     .unwrap_or_else(|err| log::error!("{:?}", err));
             }
+            MsgBuilder::Source(path, result) => {
+                // Only files of the directory, which are served, can be read
+                let source = if files.lock().await.contains(&path) {
+                    let file = &path[mount.len() + 1..];
+                    let file = Path::new(&path_str).join(file);
+                    fs_read_file(file.to_string_lossy().to_string())
+                        .map_err(|err| log::error!("Failed reading {}: {}", path, err))
+                        .ok()
+                } else {
+                    None
+                };
+
+                result
+                    .send(source)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::FsPath(path, result) => {
+                let fs_path = if files.lock().await.contains(&path) {
+                    Some(Path::new(&path_str).join(&path[mount.len() + 1..]))
+                } else {
+                    None
+                };
+
+                result
+                    .send(fs_path)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Render(path, markdown, result) => {
+                let (tx_built, rx_built) = sync::oneshot::channel();
+                tx_builder
+                    .send(MsgInternalBuilder::Render(path, markdown, tx_built))
+                    .await
+                    .ok();
+
+                result
+                    .send(rx_built.await.ok())
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Save(path, contents, expected_hash, result) => {
+                let saved = if crate::markdown::is_notebook(&path) {
+                    Err(WriteError::ReadOnly)
+                } else if files.lock().await.contains(&path) {
+                    let file = Path::new(&path_str).join(&path[mount.len() + 1..]);
+                    match fs_read_file(file.to_string_lossy().to_string()) {
+                        Ok(current)
+                            if expected_hash.is_some_and(|hash| hash != content_hash(&current)) =>
+                        {
+                            Err(WriteError::Conflict(content_hash(&current)))
+                        }
+                        Ok(_) => {
+                            log::info!("Saving {}", path);
+                            write_atomically(&file, &contents)
+                                .map(|_| content_hash(&contents))
+                                .map_err(|err| WriteError::Io(err.to_string()))
+                        }
+                        Err(err) => Err(WriteError::Io(err.to_string())),
+                    }
+                } else {
+                    Err(WriteError::NotFound)
+                };
+
+                result
+                    .send(saved)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Metadata(result) => {
+                let metadata = map
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|(path, file)| FileMetadata {
+                        path: path.clone(),
+                        title: file.title.clone(),
+                        word_count: file.word_count,
+                        modified: file.modified.clone(),
+                        last_commit: file.last_commit.clone(),
+                        diagnostics: file.diagnostics.clone(),
+                    })
+                    .collect();
+                result
+                    .send(metadata)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Status(result) => {
+                let status = BuildStatus {
+                    files: files.lock().await.len(),
+                    processing: processing.lock().await.len(),
+                };
+                result
+                    .send(status)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Page(action, result) => {
+                // Pages are changed by the file builder, so it doesn't build files concurrently
+                tx_builder
+                    .send(MsgInternalBuilder::Page(action, result))
+                    .await
+                    .ok();
+            }
+            MsgBuilder::Reconfigure(config) => {
+                tx_builder
+                    .send(MsgInternalBuilder::Reconfigure(Box::new(config)))
+                    .await
+                    .ok();
+            }
             MsgBuilder::Exit() => {
+                tx_builder.send(MsgInternalBuilder::Exit()).await.ok();
                 break;
             }
         }
@@ -455,7 +1121,7 @@ fn count_words(words: &str) -> usize {
     .collect();
 
     words
-        .split(|c| c == '\n' || c == ' ')
+        .split(['\n', ' '])
         .map(move |w| {
             regexs_replace
                 .iter()
@@ -465,18 +1131,22 @@ fn count_words(words: &str) -> usize {
         .count()
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn file_builder<
     ReadFile: Fn(String) -> anyhow::Result<String> + Clone + std::marker::Sync,
+    BroadFileSearch: Fn(String, &BuilderConfig) -> Vec<String>,
 >(
-    parser_type: ParserType,
+    mut config: BuilderConfig,
     mut rx_builder: sync::mpsc::Receiver<MsgInternalBuilder>,
     tx_file: sync::mpsc::Sender<MsgBuilder>,
     tx_srv: sync::mpsc::Sender<MsgSrv>,
+    mount: String,
     path_str: String,
     processing: Arc<Mutex<ProcessingMap>>,
     map: Arc<Mutex<HashMap<String, BuiltFile, RandomState>>>,
     files: Arc<Mutex<Vec<String>>>,
     fs_read_file: ReadFile,
+    broad_file_search: BroadFileSearch,
 ) {
     log::debug!("Started file builder listener");
     let path = Path::new(&path_str);
@@ -485,17 +1155,21 @@ async fn file_builder<
         log::debug!("File builder listener event: {:?}", msg);
 
         match msg {
+            MsgInternalBuilder::FileCreated(file) if !config.is_markdown(&file) => {}
+            MsgInternalBuilder::FileModified(file) if !config.is_markdown(&file) => {}
             MsgInternalBuilder::FileCreated(file) => {
-                let webpath = format!("/{}", file);
+                let webpath = format!("{}/{}", mount, file);
                 if !files.lock().await.contains(&webpath)
                     && process_file(
-                        parser_type,
+                        &config,
+                        &mount,
                         path,
                         &file,
                         map.clone(),
                         files.clone(),
                         processing.clone(),
                         fs_read_file.clone(),
+                        None,
                     )
                     .await
                 {
@@ -504,33 +1178,119 @@ async fn file_builder<
                         webpath,
                         true
                     );
-                    sort_files(files.clone()).await;
+                    announce_files(&mount, &webpath, &files, &tx_srv).await;
+                    // Editors might replace files by deleting and creating them, so clients
+                    // showing the file have to be updated
+                    let content = map.lock().await.get(&webpath).unwrap().clone();
                     tx_srv
-                        .send(MsgSrv::NewFile(webpath, files.lock().await.clone()))
+                        .send(MsgSrv::File(
+                            mount.clone(),
+                            webpath,
+                            Box::new(content),
+                            None,
+                        ))
                         .await
                         .unwrap();
                 }
             }
             MsgInternalBuilder::FileModified(file) => {
+                let webpath = format!("{}/{}", mount, file);
+                let previous = map.lock().await.get(&webpath).cloned();
+                // Files moved into the directory aren't created
+                let is_new = !files.lock().await.contains(&webpath);
                 if process_file(
-                    parser_type,
+                    &config,
+                    &mount,
                     path,
                     &file,
                     map.clone(),
                     files.clone(),
                     processing.clone(),
                     fs_read_file.clone(),
+                    None,
                 )
                 .await
                 {
-                    let webpath = format!("/{}", file);
-                    log::debug!("Sending processed file {} to server", webpath);
+                    log::debug!(
+                        "Sending processed file {} to server (is_new: {})",
+                        webpath,
+                        is_new
+                    );
+                    if is_new {
+                        announce_files(&mount, &webpath, &files, &tx_srv).await;
+                    }
+
                     let content = map.lock().await.get(&webpath).unwrap().clone();
-                    tx_srv.send(MsgSrv::File(webpath, content)).await.unwrap();
+                    let patch = previous
+                        .as_ref()
+                        .and_then(|previous| ContentPatch::diff(previous, &content));
+                    tx_srv
+                        .send(MsgSrv::File(
+                            mount.clone(),
+                            webpath,
+                            Box::new(content),
+                            patch,
+                        ))
+                        .await
+                        .unwrap();
+                }
+            }
+            MsgInternalBuilder::FileDeleted(file) => {
+                let webpath = format!("{}/{}", mount, file);
+                if remove_file(&webpath, &map, &files).await {
+                    announce_files(&mount, &webpath, &files, &tx_srv).await;
                 }
             }
-            MsgInternalBuilder::FileDeleted(_file) => {}
-            MsgInternalBuilder::Ignore() => {}
+            MsgInternalBuilder::Render(path, markdown, result) => {
+                result
+                    .send(build_file_blocking(&config, &path, markdown).await)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgInternalBuilder::Page(action, result) => {
+                let changed = change_page(
+                    action,
+                    &config,
+                    &mount,
+                    path,
+                    map.clone(),
+                    files.clone(),
+                    processing.clone(),
+                    fs_read_file.clone(),
+                    &tx_srv,
+                )
+                .await;
+
+                result
+                    .send(changed)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgInternalBuilder::Reconfigure(new_config) => {
+                log::debug!("Rebuilding all files of {}/ with {:?}", mount, new_config);
+                config = *new_config;
+
+                // Ignores and extensions might have changed
+                let files_to_build = broad_file_search(path_str.clone(), &config);
+                let webpaths: Vec<String> = files_to_build
+                    .iter()
+                    .map(|file| format!("{}/{}", mount, file))
+                    .collect();
+                files.lock().await.retain(|file| webpaths.contains(file));
+                map.lock().await.retain(|file, _| webpaths.contains(file));
+
+                initial_build(
+                    &config,
+                    &mount,
+                    files_to_build,
+                    path_str.clone(),
+                    processing.clone(),
+                    map.clone(),
+                    files.clone(),
+                    fs_read_file.clone(),
+                )
+                .await;
+
+                tx_srv.send(MsgSrv::Reload()).await.unwrap();
+            }
             MsgInternalBuilder::Exit() => {
                 let _ = tokio::join!(
                     tx_srv.send(MsgSrv::Exit()),
@@ -544,10 +1304,12 @@ async fn file_builder<
     log::debug!("Exited file builder listener");
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn initial_build<
     ReadFile: Fn(String) -> anyhow::Result<String> + Clone + std::marker::Sync,
 >(
-    parser_type: ParserType,
+    config: &BuilderConfig,
+    mount: &str,
     files_to_build: Vec<String>,
     path_str: String,
     processing: Arc<Mutex<ProcessingMap>>,
@@ -556,28 +1318,48 @@ async fn initial_build<
     fs_read_file: ReadFile,
 ) {
     log::debug!("About to process files: {:?}", files_to_build);
-    for file in files_to_build {
-        let map = map.clone();
-        let processing = processing.clone();
-        let files = files.clone();
-        let path = Path::new(&path_str);
-        process_file(
-            parser_type,
-            path,
-            &file,
-            map,
-            files,
-            processing,
-            fs_read_file.clone(),
-        )
+    // A single git log instead of one for every file
+    let last_commits = crate::git::last_commits(Path::new(&path_str))
+        .await
+        .unwrap_or_else(|err| {
+            log::debug!("No git history of {}: {}", path_str, err);
+            HashMap::new()
+        });
+    // Built concurrently, so pandoc and the converters can run in parallel
+    let concurrency = config
+        .pandoc
+        .concurrency
+        .max(crate::markdown::default_concurrency());
+    futures::stream::iter(files_to_build)
+        .for_each_concurrent(concurrency, |file| {
+            let map = map.clone();
+            let processing = processing.clone();
+            let files = files.clone();
+            let fs_read_file = fs_read_file.clone();
+            let last_commits = &last_commits;
+            let path_str = &path_str;
+            async move {
+                process_file(
+                    config,
+                    mount,
+                    Path::new(path_str),
+                    &file,
+                    map,
+                    files,
+                    processing,
+                    fs_read_file,
+                    Some(last_commits),
+                )
+                .await;
+            }
+        })
         .await;
-    }
 
     sort_files(files).await;
 }
 
 #[cfg(not(feature = "notify"))]
-#[cfg(not(feature = "watcheman"))]
+#[cfg(not(feature = "watchman"))]
 async fn watch_inotify(
     tx_builder: sync::mpsc::Sender<MsgInternalBuilder>,
     path_str: String,
@@ -588,89 +1370,84 @@ async fn watch_inotify(
 
     use inotify::{EventMask, Inotify, WatchMask};
 
-    let mut inotify = Inotify::init()?;
+    let inotify = Inotify::init()?;
+
+    // Editors (and saving from the browser) replace files by renaming temporary files
+    let watch_mask = WatchMask::MODIFY
+        | WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_TO
+        | WatchMask::MOVED_FROM;
 
     let mut wd_to_dir = HashMap::new();
 
-    wd_to_dir.insert(
-        inotify.add_watch(
-            path,
-            WatchMask::MODIFY | WatchMask::CREATE | WatchMask::DELETE,
-        )?,
-        String::new(),
-    );
+    wd_to_dir.insert(inotify.watches().add(path, watch_mask)?, String::new());
 
     let dirs = broad_dir_search(&path_str);
     for dir in dirs.iter() {
         let dir_path = path.join(dir);
         wd_to_dir.insert(
-            inotify.add_watch(
-                dir_path,
-                WatchMask::MODIFY | WatchMask::CREATE | WatchMask::DELETE,
-            )?,
+            inotify.watches().add(dir_path, watch_mask)?,
             dir.to_string(),
         );
     }
 
     log::debug!("Watching current directory for activity...");
 
-    let mut buffer = [0u8; 4096];
-    loop {
-        let mut events = inotify.read_events_blocking(&mut buffer)?;
-
-        let is_markdown = regex::Regex::new(r"\.md$").unwrap();
+    // Reading events asynchronously, so no worker thread of the runtime is blocked
+    let mut events = inotify.into_event_stream([0u8; 4096])?;
+    while let Some(event) = events.next().await {
+        let event = event?;
+        if let (Some(dir), Some(name)) = (wd_to_dir.get(&event.wd), event.name) {
+            let file = if dir.is_empty() {
+                name.to_string_lossy().to_string()
+            } else {
+                format!("{}/{}", dir, name.to_string_lossy())
+            };
 
-        while let Some(event) = events.next() {
-            if let (Some(dir), Some(name)) = (wd_to_dir.get(&event.wd), event.name) {
-                let file = if dir.is_empty() {
-                    name.to_string_lossy().to_string()
+            if event.mask.contains(EventMask::CREATE) {
+                if event.mask.contains(EventMask::ISDIR) {
+                    log::debug!("Directory created: {}/{:?}", dir, name);
+                    wd_to_dir.insert(
+                        events.watches().add(path.join(&file), watch_mask)?,
+                        file.to_string(),
+                    );
                 } else {
-                    format!("{}/{}", dir, name.to_string_lossy())
-                };
-
-                if event.mask.contains(EventMask::CREATE) {
-                    if event.mask.contains(EventMask::ISDIR) {
-                        log::debug!("Directory created: {}/{:?}", dir, event.name);
-                        wd_to_dir.insert(
-                            inotify.add_watch(
-                                &file,
-                                WatchMask::MODIFY | WatchMask::CREATE | WatchMask::DELETE,
-                            )?,
-                            file.to_string(),
-                        );
-                    } else if is_markdown.is_match(file.as_str()) {
-                        tx_builder
-                            .send(MsgInternalBuilder::FileCreated(file.clone()))
-                            .await
-                            .unwrap();
-                        log::debug!("File created: {}", file);
-                    }
-                } else if event.mask.contains(EventMask::DELETE) {
-                    if event.mask.contains(EventMask::ISDIR) {
-                        log::debug!("Directory deleted: {}/{:?}", dir, event.name);
-                    } else if is_markdown.is_match(file.as_str()) {
-                        tx_builder
-                            .send(MsgInternalBuilder::FileDeleted(file.clone()))
-                            .await
-                            .unwrap();
-                        log::debug!("File deleted: {}", file);
-                    }
-                } else if event.mask.contains(EventMask::MODIFY) {
-                    if event.mask.contains(EventMask::ISDIR) {
-                        log::debug!("Directory modified: {}/{:?}", dir, event.name);
-                    } else if is_markdown.is_match(file.as_str()) {
-                        tx_builder
-                            .send(MsgInternalBuilder::FileModified(file.clone()))
-                            .await
-                            .unwrap();
-                        log::debug!("File modified: {}", file);
-                    }
+                    tx_builder
+                        .send(MsgInternalBuilder::FileCreated(file.clone()))
+                        .await
+                        .unwrap();
+                    log::debug!("File created: {}", file);
+                }
+            } else if event.mask.contains(EventMask::DELETE)
+                || event.mask.contains(EventMask::MOVED_FROM)
+            {
+                if event.mask.contains(EventMask::ISDIR) {
+                    log::debug!("Directory deleted: {}/{:?}", dir, name);
+                } else {
+                    tx_builder
+                        .send(MsgInternalBuilder::FileDeleted(file.clone()))
+                        .await
+                        .unwrap();
+                    log::debug!("File deleted: {}", file);
+                }
+            } else if event.mask.contains(EventMask::MODIFY)
+                || event.mask.contains(EventMask::MOVED_TO)
+            {
+                if event.mask.contains(EventMask::ISDIR) {
+                    log::debug!("Directory modified: {}/{:?}", dir, name);
+                } else {
+                    tx_builder
+                        .send(MsgInternalBuilder::FileModified(file.clone()))
+                        .await
+                        .unwrap();
+                    log::debug!("File modified: {}", file);
                 }
             }
-
-            crate::why_is_this_necessary(&tx_builder, MsgInternalBuilder::Ignore()).await;
         }
     }
+
+    Ok(())
 }
 
 #[cfg(feature = "notify")]
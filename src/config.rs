@@ -0,0 +1,558 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+use tokio::sync;
+
+use crate::{
+    markdown::{
+        CommonMarkExtension, CommonMarkPreset, ExternalCommand, Highlighting, PandocOptions,
+        DEFAULT_CONVERTER_TIMEOUT, DEFAULT_DIAGRAM_COMMANDS,
+    },
+    msg::{MsgBuilder, MsgSrv},
+    ui::{Theme, UiConfig},
+    ParserType,
+};
+
+/// Name of the configuration file searched for in the served directory
+pub const CONFIG_FILE_NAME: &str = "md-dir-builder.toml";
+
+static DEFAULT_EXTENSIONS: &[&str] = &["md"];
+
+/// Parsers of the file extensions of other formats
+static DEFAULT_PARSERS: &[(&str, ParserType)] = &[
+    ("rst", ParserType::RestructuredText),
+    ("adoc", ParserType::AsciiDoc),
+    ("asciidoc", ParserType::AsciiDoc),
+];
+
+/// Configuration from a configuration file, the environment or the command line. Every option is
+/// optional, so configurations from different sources can be merged.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub port: Option<u16>,
+    pub bind: Option<Vec<IpAddr>>,
+    pub base_path: Option<String>,
+    pub parser: Option<ParserType>,
+    /// Names (or patterns with `*` and `?`) of files and directories to ignore
+    pub ignore: Option<Vec<String>>,
+    /// File extensions of markdown files (without the dot)
+    pub extensions: Option<Vec<String>>,
+    pub theme: Option<Theme>,
+    /// Scroll to and highlight changed contents after live updates
+    pub scroll_to_changes: Option<bool>,
+    /// Allow editing the markdown files in the browser
+    pub edit: Option<bool>,
+    /// File (relative to the served directory) new pages are created from
+    pub page_template: Option<String>,
+    /// Highlight code blocks in the browser or while building
+    pub highlighting: Option<Highlighting>,
+    #[serde(default)]
+    pub commonmark: CommonMarkConfig,
+    #[serde(default)]
+    pub pandoc: PandocConfig,
+    #[serde(default)]
+    pub diagrams: DiagramConfig,
+    /// Parsers by file extension (in addition to or replacing the default ones)
+    pub parsers: Option<BTreeMap<String, ParserType>>,
+    /// Commands converting files to HTML by file extension (instead of the parser)
+    pub converters: Option<BTreeMap<String, ConverterConfig>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CommonMarkConfig {
+    /// Extensions to start from [default: default]
+    pub preset: Option<CommonMarkPreset>,
+    /// Extensions enabled in addition to the ones of the preset
+    pub extensions: Option<Vec<CommonMarkExtension>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PandocConfig {
+    /// Input format with extensions [default: markdown]
+    pub from: Option<String>,
+    /// JSON filters (``--filter``)
+    pub filters: Option<Vec<String>>,
+    /// Lua filters (``--lua-filter``)
+    pub lua_filters: Option<Vec<String>>,
+    /// Additional arguments passed to pandoc
+    pub args: Option<Vec<String>>,
+    /// Maximum number of files converted by pandoc at once [default: number of CPUs]
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DiagramConfig {
+    /// Render code blocks of diagram languages [default: true]
+    pub enabled: Option<bool>,
+    /// Commands by language, which read a diagram from stdin and write SVG to stdout (in
+    /// addition to or replacing the default ones, an empty command disables a language)
+    pub commands: Option<BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConverterConfig {
+    /// Program and arguments. ``{input}`` is replaced with a file containing the source and
+    /// ``{output}`` with the file to write the HTML to, else stdin and stdout are used.
+    pub command: Vec<String>,
+    /// Seconds until converting a file is aborted [default: 30]
+    pub timeout: Option<u64>,
+}
+
+/// Settings of a builder, which can be changed while running
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuilderConfig {
+    pub parser: ParserType,
+    pub ignore: Vec<String>,
+    pub extensions: Vec<String>,
+    pub pandoc: PandocOptions,
+    pub commonmark_extensions: Vec<CommonMarkExtension>,
+    /// Commands rendering diagrams by language
+    pub diagram_commands: BTreeMap<String, Vec<String>>,
+    /// Parsers of other formats by extension
+    pub parsers: BTreeMap<String, ParserType>,
+    /// Commands converting files by extension
+    pub converters: BTreeMap<String, ExternalCommand>,
+    pub highlighting: Highlighting,
+    /// File (relative to the served directory) new pages are created from
+    pub page_template: Option<String>,
+}
+
+impl Default for BuilderConfig {
+    fn default() -> Self {
+        Config::default().builder_config()
+    }
+}
+
+impl BuilderConfig {
+    /// Returns ``true``, if the file or directory name should be ignored
+    pub fn is_ignored(&self, name: &str) -> bool {
+        crate::builder::IGNORE_DIRS.contains(&name)
+            || self
+                .ignore
+                .iter()
+                .any(|pattern| matches_pattern(pattern, name))
+    }
+
+    /// Returns ``true``, if the file at the path (relative to the served directory) is a markdown
+    /// file (or a notebook or a file of another format with a parser or a converter), that isn't
+    /// ignored
+    pub fn is_markdown(&self, path: &str) -> bool {
+        let has_extension = path.rsplit_once('.').is_some_and(|(_, ext)| {
+            self.extensions.iter().any(|extension| extension == ext)
+                || self.parsers.contains_key(ext)
+                || self.converters.contains_key(ext)
+                || ext == crate::markdown::NOTEBOOK_EXTENSION
+        });
+
+        has_extension && !path.split('/').any(|name| self.is_ignored(name))
+    }
+
+    /// Returns ``true``, if the file at the path is parsed as markdown (not as another format, by
+    /// a converter or as a notebook), so the links in it can be rewritten
+    pub fn is_markdown_source(&self, path: &str) -> bool {
+        let extension = path
+            .rsplit_once('.')
+            .map(|(_, ext)| ext)
+            .unwrap_or_default();
+        if self.converters.contains_key(extension)
+            || extension == crate::markdown::NOTEBOOK_EXTENSION
+        {
+            return false;
+        }
+
+        let parser = match self.parsers.get(extension) {
+            Some(parser) => *parser,
+            None if self.extensions.iter().any(|ext| ext == extension) => self.parser,
+            None => return false,
+        };
+        match parser {
+            ParserType::CommonMark => true,
+            ParserType::Pandoc => {
+                crate::markdown::pandoc_format(extension).is_none()
+                    && crate::markdown::is_markdown_format(&self.pandoc.from)
+            }
+            ParserType::RestructuredText | ParserType::AsciiDoc => false,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration file at the given path
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let config = toml::from_str(&contents)?;
+
+        Ok(config)
+    }
+
+    /// Drops the options, which run commands (converters, diagram commands and the filters and
+    /// arguments of pandoc), allow editing files or set the addresses to listen at. Anyone able
+    /// to add files to the served directory could set them in a configuration file found there,
+    /// so they're only taken from files given explicitly, the environment and the command line.
+    pub fn without_untrusted(mut self, path: &Path) -> Config {
+        let mut ignored = Vec::new();
+        if self.converters.take().is_some() {
+            ignored.push("converters");
+        }
+        if self.diagrams.commands.take().is_some() {
+            ignored.push("diagrams.commands");
+        }
+        if self.pandoc.filters.take().is_some() {
+            ignored.push("pandoc.filters");
+        }
+        if self.pandoc.lua_filters.take().is_some() {
+            ignored.push("pandoc.lua-filters");
+        }
+        if self.pandoc.args.take().is_some() {
+            ignored.push("pandoc.args");
+        }
+        if self.edit.take().is_some() {
+            ignored.push("edit");
+        }
+        if self.bind.take().is_some() {
+            ignored.push("bind");
+        }
+
+        if !ignored.is_empty() {
+            log::warn!(
+                "Ignoring {} in {}, pass the file with --config to use them",
+                ignored.join(", "),
+                path.to_string_lossy()
+            );
+        }
+
+        self
+    }
+
+    /// Combines two configurations. Options set in ``self`` take precedence over the ones in
+    /// ``other``.
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            port: self.port.or(other.port),
+            bind: self.bind.or(other.bind),
+            base_path: self.base_path.or(other.base_path),
+            parser: self.parser.or(other.parser),
+            ignore: self.ignore.or(other.ignore),
+            extensions: self.extensions.or(other.extensions),
+            theme: self.theme.or(other.theme),
+            scroll_to_changes: self.scroll_to_changes.or(other.scroll_to_changes),
+            edit: self.edit.or(other.edit),
+            page_template: self.page_template.or(other.page_template),
+            highlighting: self.highlighting.or(other.highlighting),
+            commonmark: CommonMarkConfig {
+                preset: self.commonmark.preset.or(other.commonmark.preset),
+                extensions: self.commonmark.extensions.or(other.commonmark.extensions),
+            },
+            pandoc: PandocConfig {
+                from: self.pandoc.from.or(other.pandoc.from),
+                filters: self.pandoc.filters.or(other.pandoc.filters),
+                lua_filters: self.pandoc.lua_filters.or(other.pandoc.lua_filters),
+                args: self.pandoc.args.or(other.pandoc.args),
+                concurrency: self.pandoc.concurrency.or(other.pandoc.concurrency),
+            },
+            diagrams: DiagramConfig {
+                enabled: self.diagrams.enabled.or(other.diagrams.enabled),
+                commands: self.diagrams.commands.or(other.diagrams.commands),
+            },
+            parsers: self.parsers.or(other.parsers),
+            converters: self.converters.or(other.converters),
+        }
+    }
+
+    /// Returns ``true``, if files are parsed with the parser (as markdown or by extension)
+    pub fn uses_parser(&self, parser: ParserType) -> bool {
+        let config = self.builder_config();
+        config.parser == parser
+            || config.parsers.iter().any(|(extension, used)| {
+                *used == parser && !config.converters.contains_key(extension)
+            })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(8080)
+    }
+
+    pub fn bind(&self) -> Vec<IpAddr> {
+        self.bind.clone().unwrap_or_else(|| {
+            vec![
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)),
+            ]
+        })
+    }
+
+    pub fn base_path(&self) -> String {
+        crate::router::normalize_base_path(self.base_path.as_deref().unwrap_or_default())
+    }
+
+    pub fn builder_config(&self) -> BuilderConfig {
+        BuilderConfig {
+            parser: self.parser.unwrap_or_default(),
+            ignore: self.ignore.clone().unwrap_or_default(),
+            extensions: self.extensions.clone().unwrap_or_else(|| {
+                DEFAULT_EXTENSIONS
+                    .iter()
+                    .map(|extension| extension.to_string())
+                    .collect()
+            }),
+            pandoc: PandocOptions {
+                from: self
+                    .pandoc
+                    .from
+                    .clone()
+                    .unwrap_or_else(|| "markdown".to_string()),
+                filters: self.pandoc.filters.clone().unwrap_or_default(),
+                lua_filters: self.pandoc.lua_filters.clone().unwrap_or_default(),
+                args: self.pandoc.args.clone().unwrap_or_default(),
+                concurrency: self
+                    .pandoc
+                    .concurrency
+                    .unwrap_or_else(crate::markdown::default_concurrency),
+            },
+            commonmark_extensions: self.commonmark_extensions(),
+            diagram_commands: self.diagram_commands(),
+            parsers: self.parsers(),
+            converters: self.converters(),
+            highlighting: self.highlighting.unwrap_or_default(),
+            page_template: self.page_template.clone(),
+        }
+    }
+
+    fn commonmark_extensions(&self) -> Vec<CommonMarkExtension> {
+        let mut extensions = self.commonmark.preset.unwrap_or_default().extensions();
+        for extension in self.commonmark.extensions.iter().flatten() {
+            if !extensions.contains(extension) {
+                extensions.push(*extension);
+            }
+        }
+
+        extensions
+    }
+
+    fn diagram_commands(&self) -> BTreeMap<String, Vec<String>> {
+        if !self.diagrams.enabled.unwrap_or(true) {
+            return BTreeMap::new();
+        }
+
+        let mut commands: BTreeMap<String, Vec<String>> = DEFAULT_DIAGRAM_COMMANDS
+            .iter()
+            .map(|(language, command)| {
+                (
+                    language.to_string(),
+                    command.iter().map(|arg| arg.to_string()).collect(),
+                )
+            })
+            .collect();
+        commands.extend(self.diagrams.commands.clone().unwrap_or_default());
+        commands.retain(|_, command| !command.is_empty());
+
+        commands
+    }
+
+    fn parsers(&self) -> BTreeMap<String, ParserType> {
+        let mut parsers: BTreeMap<String, ParserType> = DEFAULT_PARSERS
+            .iter()
+            .map(|(extension, parser)| (extension.to_string(), *parser))
+            .collect();
+        for (extension, parser) in self.parsers.iter().flatten() {
+            parsers.insert(extension.trim_start_matches('.').to_string(), *parser);
+        }
+
+        parsers
+    }
+
+    fn converters(&self) -> BTreeMap<String, ExternalCommand> {
+        self.converters
+            .iter()
+            .flatten()
+            .filter(|(_, converter)| !converter.command.is_empty())
+            .map(|(extension, converter)| {
+                (
+                    extension.trim_start_matches('.').to_string(),
+                    ExternalCommand {
+                        command: converter.command.clone(),
+                        timeout: converter
+                            .timeout
+                            .map(Duration::from_secs)
+                            .unwrap_or(DEFAULT_CONVERTER_TIMEOUT),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub fn ui_config(&self, mounts: Vec<String>) -> UiConfig {
+        UiConfig {
+            base_path: self.base_path(),
+            mounts,
+            theme: self.theme.unwrap_or_default(),
+            scroll_to_changes: self.scroll_to_changes.unwrap_or(true),
+            edit: self.edit.unwrap_or(false),
+        }
+    }
+}
+
+/// Determines the configuration file to use. Returns the explicitly given file or, if it exists,
+/// the configuration file in the served directory, and whether all options of it are trusted
+/// (see ``Config::without_untrusted``).
+pub fn find_config_file(explicit: Option<&str>, directory: &str) -> Option<(PathBuf, bool)> {
+    if let Some(explicit) = explicit {
+        return Some((PathBuf::from(explicit), true));
+    }
+
+    let path = Path::new(directory).join(CONFIG_FILE_NAME);
+    if path.is_file() {
+        Some((path, false))
+    } else {
+        None
+    }
+}
+
+/// Reads a configuration file, without the untrusted options, if the file was found in the served
+/// directory
+pub fn load_config_file(path: &Path, trusted: bool) -> anyhow::Result<Config> {
+    let config = Config::load(path)?;
+    if trusted {
+        Ok(config)
+    } else {
+        Ok(config.without_untrusted(path))
+    }
+}
+
+/// Matches a name against a pattern, which may contain `*` (any number of characters) and `?`
+/// (exactly one character)
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some('*'), _) => {
+                matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..]))
+            }
+            (Some('?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+            (Some(p), Some(n)) if p == n => matches(&pattern[1..], &name[1..]),
+            _ => false,
+        }
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    matches(&pattern[..], &name[..])
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Watches the configuration file and applies changes to the builders and the rendered pages.
+///
+/// ``overrides`` is the configuration from the command line and the environment, which take
+/// precedence over the configuration file.
+pub async fn watch_config(
+    path: PathBuf,
+    trusted: bool,
+    overrides: Config,
+    mut config: Config,
+    tx_file: sync::mpsc::Sender<MsgBuilder>,
+    tx_srv: sync::mpsc::Sender<MsgSrv>,
+    tx_ui_config: sync::watch::Sender<UiConfig>,
+) {
+    log::debug!("Watching configuration file {}", path.to_string_lossy());
+
+    let mut last_modified = modified(&path);
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let current_modified = modified(&path);
+        if current_modified == last_modified {
+            continue;
+        }
+
+        last_modified = current_modified;
+
+        let new_config = match load_config_file(&path, trusted) {
+            Ok(file_config) => overrides.clone().merge(file_config),
+            Err(err) => {
+                log::error!(
+                    "Failed reloading configuration file {}: {}",
+                    path.to_string_lossy(),
+                    err
+                );
+                continue;
+            }
+        };
+
+        if new_config == config {
+            continue;
+        }
+
+        log::info!("Reloading configuration file {}", path.to_string_lossy());
+
+        if new_config.uses_parser(ParserType::Pandoc) && !config.uses_parser(ParserType::Pandoc) {
+            if let Err(err) = crate::markdown::pandoc_version() {
+                log::error!("Can't parse markdown with pandoc: {}", err);
+                continue;
+            }
+        }
+
+        if new_config.port() != config.port()
+            || new_config.bind() != config.bind()
+            || new_config.base_path() != config.base_path()
+        {
+            log::warn!("Changes of port, bind addresses and base path require a restart");
+        }
+
+        tx_ui_config.send_modify(|ui_config| {
+            ui_config.theme = new_config.theme.unwrap_or_default();
+            ui_config.scroll_to_changes = new_config.scroll_to_changes.unwrap_or(true);
+            ui_config.edit = new_config.edit.unwrap_or(false);
+        });
+
+        if new_config.builder_config() != config.builder_config() {
+            // The builders announce the reload after rebuilding all files
+            if tx_file
+                .send(MsgBuilder::Reconfigure(new_config.builder_config()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        } else if (new_config.theme != config.theme
+            || new_config.scroll_to_changes != config.scroll_to_changes
+            || new_config.edit != config.edit)
+            && tx_srv.send(MsgSrv::Reload()).await.is_err()
+        {
+            break;
+        }
+
+        config = new_config;
+    }
+}
@@ -0,0 +1,52 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// Cursor position of an editor, sent to ``/.api``
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GotoRequest {
+    /// Web path of the file (e.g. ``/README.md``)
+    pub path: String,
+    /// Line of the cursor (starting at 1)
+    pub line: usize,
+}
+
+/// Tells the server running at the address to show the given position in the browsers showing
+/// the file
+pub async fn send_goto(
+    addr: SocketAddr,
+    base_path: &str,
+    request: &GotoRequest,
+) -> anyhow::Result<()> {
+    let uri = format!("http://{}{}/.api", addr, base_path);
+    log::debug!("Sending {:?} to {}", request, uri);
+
+    let request = hyper::Request::post(uri)
+        .header("Content-Type", "application/json")
+        .body(hyper::Body::from(serde_json::to_vec(request)?))?;
+    let response = hyper::Client::new().request(request).await?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        anyhow::bail!("{}: {}", status, String::from_utf8_lossy(&body))
+    }
+}
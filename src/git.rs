@@ -0,0 +1,258 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+use std::{collections::HashMap, path::Path};
+
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+use tokio::process;
+
+/// Separates the fields of a commit in the output of ``git log``
+const FIELD_SEPARATOR: char = '\x1f';
+/// Starts the commits in the output of ``git log``
+const COMMIT_SEPARATOR: char = '\x1e';
+
+/// Commit, which changed a file
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Commit {
+    pub id: String,
+    pub author: String,
+    /// Author date (RFC 3339)
+    pub date: String,
+    /// First line of the commit message
+    pub message: String,
+    /// Path of the file in the commit (relative to the repository), which differs from the
+    /// current one, if the file was renamed since
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// Commit, which last changed a line of a file
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct BlameLine {
+    /// Line number (starting at 1)
+    pub line: usize,
+    pub commit: String,
+    pub author: String,
+    /// Author date (RFC 3339)
+    pub date: String,
+    pub text: String,
+}
+
+/// Runs git in the directory of the file and returns its output
+async fn git(file: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let dir = file
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("{} has no parent directory", file.display()))?;
+
+    git_in(dir, args).await
+}
+
+/// Runs git in the directory and returns its output
+async fn git_in(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    log::debug!("Running git {:?} in {}", args, dir.display());
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Name of the file relative to its directory, as git is run in there
+fn file_name(file: &Path) -> anyhow::Result<String> {
+    file.file_name()
+        .map(|name| format!("./{}", name.to_string_lossy()))
+        .ok_or_else(|| anyhow::anyhow!("{} isn't a file", file.display()))
+}
+
+/// Revisions are passed to git, so they must not be mistaken for options
+fn check_revision(revision: &str) -> anyhow::Result<()> {
+    let valid = !revision.is_empty()
+        && !revision.starts_with('-')
+        && revision
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | '~' | '^'));
+
+    if valid {
+        Ok(())
+    } else {
+        anyhow::bail!("Invalid revision {}", revision)
+    }
+}
+
+/// Arguments of ``git log`` listing the commits with the changed files in the format of
+/// ``parse_log``
+fn log_args(format: &str) -> Vec<&str> {
+    vec!["-c", "core.quotePath=false", "log", "--name-only", format]
+}
+
+fn log_format() -> String {
+    format!(
+        "--format={1}%H{0}%an{0}%aI{0}%s",
+        FIELD_SEPARATOR, COMMIT_SEPARATOR
+    )
+}
+
+/// Lists the commits, which changed the file (newest first)
+async fn log(file: &Path, args: &[&str]) -> anyhow::Result<Vec<Commit>> {
+    let format = log_format();
+    let file_name = file_name(file)?;
+    let mut log_args = log_args(&format);
+    log_args.extend_from_slice(args);
+    log_args.extend_from_slice(&["--", &file_name]);
+    let output = git(file, &log_args).await?;
+
+    Ok(parse_log(&output)
+        .map(|(commit, mut paths)| Commit {
+            path: paths.next().map(str::to_string),
+            ..commit
+        })
+        .collect())
+}
+
+/// Parses the output of ``git log --name-only``: the commits (without path) and the paths of
+/// the files they changed
+pub fn parse_log(output: &str) -> impl Iterator<Item = (Commit, impl Iterator<Item = &str>)> {
+    output.split(COMMIT_SEPARATOR).filter_map(|commit| {
+        let mut lines = commit.lines();
+        let mut fields = lines.next()?.split(FIELD_SEPARATOR);
+        let commit = Commit {
+            id: fields.next().filter(|id| !id.is_empty())?.to_string(),
+            author: fields.next()?.to_string(),
+            date: fields.next()?.to_string(),
+            message: fields.next()?.to_string(),
+            path: None,
+        };
+
+        Some((commit, lines.filter(|line| !line.is_empty())))
+    })
+}
+
+/// Returns the commit, which changed each file in the directory last, by the path of the file
+/// relative to the directory. Much faster than ``last_commit`` for every file.
+pub async fn last_commits(dir: &Path) -> anyhow::Result<HashMap<String, Commit>> {
+    let format = log_format();
+    let mut log_args = log_args(&format);
+    log_args.extend_from_slice(&["--relative", "--", "."]);
+    let output = git_in(dir, &log_args).await?;
+
+    let mut commits = HashMap::new();
+    // Newest commits first
+    for (commit, paths) in parse_log(&output) {
+        for path in paths {
+            commits
+                .entry(path.to_string())
+                .or_insert_with(|| commit.clone());
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Lists the commits, which changed the file (newest first), also before it was renamed
+pub async fn history(file: &Path) -> anyhow::Result<Vec<Commit>> {
+    log(file, &["--follow"]).await
+}
+
+/// Returns the commit, which changed the file last (``None``, if it wasn't committed yet)
+pub async fn last_commit(file: &Path) -> anyhow::Result<Option<Commit>> {
+    Ok(log(file, &["-1"]).await?.into_iter().next())
+}
+
+/// Returns the contents of the file at the revision. Revisions of the history before the file
+/// was renamed are shown at the path the file had then.
+pub async fn show(file: &Path, revision: &str) -> anyhow::Result<String> {
+    check_revision(revision)?;
+
+    let id = git(
+        file,
+        &["rev-parse", "--verify", &format!("{}^{{commit}}", revision)],
+    )
+    .await?;
+    let id = id.trim();
+    let path = history(file)
+        .await?
+        .into_iter()
+        .find(|commit| commit.id == id)
+        .and_then(|commit| commit.path);
+    let object = match path {
+        // Relative to the root of the repository
+        Some(path) => format!("{}:{}", id, path),
+        None => format!("{}:{}", id, file_name(file)?),
+    };
+
+    git(file, &["show", &object]).await
+}
+
+/// Returns the commit, which last changed each line of the file
+pub async fn blame(file: &Path) -> anyhow::Result<Vec<BlameLine>> {
+    let output = git(file, &["blame", "--porcelain", "--", &file_name(file)?]).await?;
+
+    Ok(parse_blame(&output))
+}
+
+/// Parses the output of ``git blame --porcelain``. Information about a commit is only given for
+/// the first line it changed.
+pub fn parse_blame(output: &str) -> Vec<BlameLine> {
+    let mut lines = Vec::new();
+    // Author and date of every commit
+    let mut commits: HashMap<String, (String, String)> = HashMap::new();
+    let mut commit = String::new();
+    let mut line = 0;
+
+    for output_line in output.lines() {
+        if let Some(text) = output_line.strip_prefix('\t') {
+            let (author, date) = commits.get(&commit).cloned().unwrap_or_default();
+            lines.push(BlameLine {
+                line,
+                commit: commit.clone(),
+                author,
+                date,
+                text: text.to_string(),
+            });
+        } else if let Some(author) = output_line.strip_prefix("author ") {
+            commits.entry(commit.clone()).or_default().0 = author.to_string();
+        } else if let Some(time) = output_line.strip_prefix("author-time ") {
+            let date = time
+                .parse()
+                .ok()
+                .and_then(|time| chrono::Utc.timestamp_opt(time, 0).single())
+                .map(|date| date.to_rfc3339())
+                .unwrap_or_default();
+            commits.entry(commit.clone()).or_default().1 = date;
+        } else {
+            // Header of a line: commit, original line and final line
+            let mut fields = output_line.split(' ');
+            if let (Some(id), Some(_), Some(final_line)) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                if (id.len() == 40 || id.len() == 64) && id.chars().all(|c| c.is_ascii_hexdigit()) {
+                    commit = id.to_string();
+                    line = final_line.parse().unwrap_or_default();
+                }
+            }
+        }
+    }
+
+    lines
+}
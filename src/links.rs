@@ -0,0 +1,130 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+/// Resolves a link relative to the file containing it. Returns ``None`` for absolute links,
+/// links to other sites and links within the file. Paths start with a slash.
+pub fn resolve_link(file: &str, link: &str) -> Option<String> {
+    if link.is_empty() || link.starts_with(['/', '#', '?']) || link.contains(':') {
+        return None;
+    }
+
+    let mut parts: Vec<&str> = file.split('/').filter(|part| !part.is_empty()).collect();
+    parts.pop(); // The file itself
+
+    for part in link.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+
+    Some(format!("/{}", parts.join("/")))
+}
+
+/// Returns the link from one file to another relative to the directory of the first one
+pub fn relative_link(from: &str, to: &str) -> String {
+    let mut from_dirs: Vec<&str> = from.split('/').filter(|part| !part.is_empty()).collect();
+    from_dirs.pop(); // The file itself
+    let to_parts: Vec<&str> = to.split('/').filter(|part| !part.is_empty()).collect();
+
+    let common = from_dirs
+        .iter()
+        .zip(to_parts.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // The last part of the target is always a file
+    let common = common.min(to_parts.len().saturating_sub(1));
+
+    let mut link: Vec<&str> = vec![".."; from_dirs.len() - common];
+    link.extend_from_slice(&to_parts[common..]);
+
+    link.join("/")
+}
+
+/// Inline links and images
+static INLINE_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\]\(\s*<?)([^)\s>]+)").unwrap());
+/// Link reference definitions
+static LINK_DEFINITION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?m)^( {0,3}\[[^\]]+\]:[ \t]*<?)([^\s>]+)").unwrap());
+
+/// Changes the relative links in the markdown of ``file``, which point to ``old``, to point to
+/// ``new``. Inline links, images and link reference definitions are changed. Returns ``None``,
+/// if no link was changed.
+pub fn rewrite_links(markdown: &str, file: &str, old: &str, new: &str) -> Option<String> {
+    replace_links(markdown, |target| {
+        (resolve_link(file, target).as_deref() == Some(old)).then(|| relative_link(file, new))
+    })
+}
+
+/// Changes the relative links in the markdown of a file moved from ``old`` to ``new``, so they
+/// point to the same files from its new directory. Returns ``None``, if no link was changed.
+pub fn rebase_links(markdown: &str, old: &str, new: &str) -> Option<String> {
+    replace_links(markdown, |target| {
+        let resolved = resolve_link(old, target)?;
+        // Links to the file itself point to its new path
+        let resolved = if resolved == old {
+            new.to_string()
+        } else {
+            resolved
+        };
+
+        (resolve_link(new, target).as_ref() != Some(&resolved))
+            .then(|| relative_link(new, &resolved))
+    })
+}
+
+/// Replaces the links outside of code, for which ``replace`` returns a new link. Anchors and
+/// queries of the links are kept. Returns ``None``, if no link was changed.
+fn replace_links(
+    markdown: &str,
+    mut replace: impl FnMut(&str) -> Option<String>,
+) -> Option<String> {
+    let mut changed = false;
+    let mut markdown = markdown.to_string();
+    for pattern in [&INLINE_LINK, &LINK_DEFINITION] {
+        let code = crate::markdown::code_ranges(&markdown);
+        markdown = pattern
+            .replace_all(&markdown, |caps: &Captures| {
+                let start = caps.get(0).unwrap().start();
+                let link = &caps[2];
+                let (target, suffix) = match link.find(['#', '?']) {
+                    Some(i) => link.split_at(i),
+                    None => (link, ""),
+                };
+
+                match replace(target) {
+                    Some(new) if !code.iter().any(|range| range.contains(&start)) => {
+                        changed = true;
+                        format!("{}{}{}", &caps[1], new, suffix)
+                    }
+                    _ => caps[0].to_string(),
+                }
+            })
+            .into_owned();
+    }
+
+    if changed {
+        Some(markdown)
+    } else {
+        None
+    }
+}
@@ -14,47 +14,92 @@
  *
  *  You should have received a copy of the GNU General Public License
  */
+mod api;
 mod builder;
+mod config;
+mod editor;
+mod git;
+mod links;
 mod markdown;
+mod mount;
 mod msg;
+mod patch;
 mod router;
 mod ui;
 
 #[cfg(test)]
 mod tests;
 
+use builder::{PageAction, WriteError};
+use config::Config;
 use log::LevelFilter;
+use mount::Mount;
 use msg::{MsgBuilder, MsgSrv};
+use serde::Deserialize;
 use simplelog::{CombinedLogger, TermLogger, TerminalMode};
 
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
 
 use clap::Parser;
 use tokio::{sync, task};
 
 pub(crate) const CHANNEL_COUNT: usize = 32;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+/// Time to wait for all tasks to finish after a shutdown was requested
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
 pub enum ParserType {
+    #[default]
     CommonMark,
     Pandoc,
+    RestructuredText,
+    #[serde(rename = "asciidoc")]
+    #[clap(name = "asciidoc")]
+    AsciiDoc,
 }
 
 /// Program to create webserver for markdown files
+///
+/// Options given on the command line take precedence over environment variables, which take
+/// precedence over the configuration file.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Port to host service at
-    #[clap(short, long, value_parser, default_value_t = 8080)]
-    port: u16,
+    /// Port to host service at [default: 8080]
+    #[clap(short, long, value_parser, env = "MD_DIR_BUILDER_PORT")]
+    port: Option<u16>,
+
+    /// Addresses to listen at [default: 127.0.0.1,::1]
+    #[clap(long, value_parser, value_delimiter = ',', env = "MD_DIR_BUILDER_BIND")]
+    bind: Vec<IpAddr>,
+
+    /// Directory where to serve the markdown files from (defaults to the current directory, if
+    /// no mounts are given)
+    #[clap(short, long, value_parser, env = "MD_DIR_BUILDER_DIRECTORY")]
+    directory: Option<String>,
+
+    /// Configuration file [default: md-dir-builder.toml in the served directory]
+    #[clap(short, long, value_parser, env = "MD_DIR_BUILDER_CONFIG")]
+    config: Option<String>,
 
-    /// Directory where to serve the markdown files from
-    #[clap(short, long, value_parser, default_value = ".")]
-    directory: String,
+    /// Additionally serve a directory under an URL prefix (e.g. /api=../api-docs)
+    #[clap(long = "mount", value_name = "PREFIX=DIRECTORY", value_parser = mount::parse_mount)]
+    mounts: Vec<Mount>,
 
-    /// Parser used to transform markdown files to HTML
-    #[clap(long, value_enum, default_value_t = ParserType::CommonMark)]
-    parser: ParserType,
+    /// Parser used to transform markdown files to HTML [default: common-mark]
+    #[clap(long, value_enum, env = "MD_DIR_BUILDER_PARSER")]
+    parser: Option<ParserType>,
+
+    /// Color scheme of the pages [default: auto]
+    #[clap(long, value_enum, env = "MD_DIR_BUILDER_THEME")]
+    theme: Option<ui::Theme>,
 
     /// Be verbose
     #[clap(short, long, value_parser, default_value_t = false)]
@@ -63,6 +108,73 @@ struct Args {
     /// Open in browser
     #[clap(long = "no-open", value_parser, default_value_t = false)]
     no_open: bool,
+
+    /// Scroll to and highlight changed contents after live updates [default]
+    #[clap(long, overrides_with = "no_scroll_to_changes")]
+    scroll_to_changes: bool,
+
+    /// Don't scroll to and highlight changed contents after live updates
+    #[clap(long, overrides_with = "scroll_to_changes")]
+    no_scroll_to_changes: bool,
+
+    /// Allow editing the markdown files in the browser
+    #[clap(long, overrides_with = "no_edit")]
+    edit: bool,
+
+    /// Don't allow editing the markdown files in the browser [default]
+    #[clap(long, overrides_with = "edit")]
+    no_edit: bool,
+
+    /// Path prefix to serve everything at (e.g. /docs behind a reverse proxy)
+    #[clap(long, value_parser, env = "MD_DIR_BUILDER_BASE_PATH")]
+    base_path: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Shows a line of a file in the browsers showing it, which are connected to the running
+    /// server (for editor integration)
+    Goto {
+        /// Markdown file in one of the served directories
+        #[clap(value_parser)]
+        file: String,
+
+        /// Line to show (starting at 1)
+        #[clap(value_parser)]
+        line: usize,
+    },
+}
+
+impl From<&Args> for Config {
+    fn from(args: &Args) -> Self {
+        Config {
+            port: args.port,
+            bind: if args.bind.is_empty() {
+                None
+            } else {
+                Some(args.bind.clone())
+            },
+            base_path: args.base_path.clone(),
+            parser: args.parser,
+            theme: args.theme,
+            scroll_to_changes: flag(args.scroll_to_changes, args.no_scroll_to_changes),
+            edit: flag(args.edit, args.no_edit),
+            ..Config::default()
+        }
+    }
+}
+
+/// Returns the value of an option given with a pair of flags (e.g. ``--edit`` and ``--no-edit``),
+/// so it can also override ``true`` in the configuration file
+fn flag(enabled: bool, disabled: bool) -> Option<bool> {
+    match (enabled, disabled) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
 }
 
 #[tokio::main]
@@ -85,118 +197,534 @@ async fn main() {
     ])
     .expect("Failed initializing logger");
 
-    let (tx_srv, rx_srv) = sync::mpsc::channel(CHANNEL_COUNT);
-    let (tx_file, rx_file) = sync::mpsc::channel(CHANNEL_COUNT);
+    let mut mounts = args.mounts.clone();
+    if args.directory.is_some() || mounts.is_empty() {
+        let directory = args.directory.clone().unwrap_or_else(|| ".".to_string());
+        mounts.insert(0, Mount::new("", &directory));
+    }
 
-    let ((app4, tx4, handle4), (app6, tx6, handle6)) = tokio::join!(
-        router::create_router(tx_file.clone()),
-        router::create_router(tx_file.clone())
-    );
+    // Options from the command line and the environment take precedence over the file
+    let overrides = Config::from(&args);
+    let config_file = config::find_config_file(args.config.as_deref(), &mounts[0].directory);
+    let config = if let Some((config_file, trusted)) = config_file.as_ref() {
+        match config::load_config_file(config_file, *trusted) {
+            Ok(file_config) => {
+                log::info!(
+                    "Loaded configuration from {}",
+                    config_file.to_string_lossy()
+                );
+                overrides.clone().merge(file_config)
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed loading configuration from {}: {}",
+                    config_file.to_string_lossy(),
+                    err
+                );
+                std::process::exit(1);
+            }
+        }
+    } else {
+        overrides.clone()
+    };
 
-    let addr4 = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), args.port).into();
-    let addr6 = SocketAddrV6::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), args.port, 0, 0).into();
+    if let Some(Command::Goto { file, line }) = args.command.as_ref() {
+        goto(&config, &mounts, file, *line).await;
+        return;
+    }
 
-    let server4 = axum::Server::try_bind(&addr4);
-    let server6 = axum::Server::try_bind(&addr6);
+    if config.uses_parser(ParserType::Pandoc) {
+        match markdown::pandoc_version() {
+            Ok(version) => log::info!("Parsing markdown with {}", version),
+            Err(err) => {
+                log::error!("Can't parse markdown with pandoc: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let server4 = server4
-        .unwrap_or_else(|_| panic!("Port {} is already in use", args.port))
-        .serve(app4.into_make_service());
-    let server6 = server6
-        .unwrap_or_else(|_| panic!("Port {} is already in use", args.port))
-        .serve(app6.into_make_service());
+    for mount in mounts.iter() {
+        log::info!("Serving {} at {}/", mount.directory, mount.prefix);
+    }
 
-    let servers_handle = task::spawn(async move {
-        let (server4, server6) = tokio::join!(server4, server6);
+    let (tx_srv, rx_srv) = sync::mpsc::channel(CHANNEL_COUNT);
+    let (tx_file, rx_file) = sync::mpsc::channel(CHANNEL_COUNT);
 
-        server4.unwrap();
-        server6.unwrap();
+    let ui_config = config.ui_config(
+        mounts
+            .iter()
+            .map(|mount| mount.prefix.clone())
+            .filter(|prefix| !prefix.is_empty())
+            .collect(),
+    );
+    let base_path = ui_config.base_path.clone();
+    let (tx_ui_config, rx_ui_config) = sync::watch::channel(ui_config);
+
+    let port = config.port();
+    let addrs: Vec<SocketAddr> = config
+        .bind()
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+
+    // Servers stop accepting connections, when this is set to true
+    let (tx_shutdown, rx_shutdown) = sync::watch::channel(false);
+
+    let mut txs = Vec::with_capacity(addrs.len());
+    let mut router_handles = Vec::with_capacity(addrs.len());
+    let mut servers = Vec::with_capacity(addrs.len());
+    for addr in addrs.iter() {
+        let (app, tx, handle) =
+            router::create_router(tx_file.clone(), tx_srv.clone(), rx_ui_config.clone()).await;
+        txs.push(tx);
+        router_handles.push(handle);
+
+        let mut rx_shutdown = rx_shutdown.clone();
+        let server = axum::Server::try_bind(addr)
+            .unwrap_or_else(|_| panic!("Address {} is already in use", addr))
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async move {
+                rx_shutdown.changed().await.ok();
+            });
+        servers.push(server);
+    }
+
+    let servers_handle = task::spawn(async move {
+        for server in futures::future::join_all(servers).await {
+            server.unwrap();
+        }
     });
 
     log::info!(
-        "Started servers on http://[::1]:{} and http://127.0.0.1:{}",
-        args.port,
-        args.port
+        "Started servers on {}",
+        addrs
+            .iter()
+            .map(|addr| format!("http://{}{}/", addr, base_path))
+            .collect::<Vec<String>>()
+            .join(" and ")
     );
 
-    let builder_handle = task::spawn(async move {
-        builder::builder(
-            args.parser,
-            tx_srv,
-            args.directory,
-            tx_file.clone(),
-            rx_file,
-        )
-        .await;
+    // Every mount has its own builder, queries are dispatched by the builders multiplexer
+    let mut builders = Vec::with_capacity(mounts.len());
+    let mut builder_handles = Vec::with_capacity(mounts.len());
+    for mount in mounts {
+        let (tx_builder_file, rx_builder_file) = sync::mpsc::channel(CHANNEL_COUNT);
+        builders.push((mount.prefix.clone(), tx_builder_file.clone()));
+
+        let tx_srv = tx_srv.clone();
+        let builder_config = config.builder_config();
+        builder_handles.push(task::spawn(async move {
+            builder::builder(
+                builder_config,
+                tx_srv,
+                mount.prefix,
+                mount.directory,
+                tx_builder_file,
+                rx_builder_file,
+            )
+            .await;
+        }));
+    }
+
+    let builder_count = builders.len();
+    let servers_multiplexer_builders = builders.clone();
+    let builders_multiplexer_handle = task::spawn(async move {
+        builders_multiplexer(rx_file, builders).await;
     });
 
     if !args.no_open {
-        webbrowser::open(format!("http://[::1]:{}", args.port).as_str()).ok();
+        if let Some(addr) = addrs.first() {
+            webbrowser::open(format!("http://{}{}/", addr, base_path).as_str()).ok();
+        }
     }
 
     log::debug!("Server is now ready");
 
+    // Apply changes of the configuration file while running
+    let config_watcher_handle = {
+        let tx_file = tx_file.clone();
+
+        task::spawn(async move {
+            if let Some((config_file, trusted)) = config_file {
+                config::watch_config(
+                    config_file,
+                    trusted,
+                    overrides,
+                    config,
+                    tx_file,
+                    tx_srv,
+                    tx_ui_config,
+                )
+                .await;
+            }
+        })
+    };
+
     let servers_multiplexer_handle = task::spawn(async move {
-        servers_multiplexer(rx_srv, tx4, tx6).await;
+        servers_multiplexer(rx_srv, servers_multiplexer_builders, builder_count, txs).await;
     });
 
-    let _ = tokio::join!(
-        handle4,
-        handle6,
-        servers_handle,
-        builder_handle,
-        servers_multiplexer_handle
-    );
+    shutdown_signal().await;
+    log::info!("Shutting down");
+
+    // Stop accepting connections, then stop the builders. After all builders exited, the
+    // websockets are closed.
+    tx_shutdown.send(true).ok();
+    config_watcher_handle.abort();
+    tx_file.send(MsgBuilder::Exit()).await.ok();
+
+    let shutdown = async {
+        tokio::join!(
+            futures::future::join_all(router_handles),
+            servers_handle,
+            futures::future::join_all(builder_handles),
+            builders_multiplexer_handle,
+            servers_multiplexer_handle
+        )
+    };
 
-    log::debug!("Exited silently");
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown)
+        .await
+        .is_err()
+    {
+        log::warn!(
+            "Not all tasks finished within {} seconds, exiting anyway",
+            SHUTDOWN_TIMEOUT.as_secs()
+        );
+    } else {
+        log::debug!("Exited silently");
+    }
+
+    markdown::remove_temp_dir();
+    std::process::exit(0);
+}
+
+/// Sends the position in the file to the server running with the given configuration
+async fn goto(config: &Config, mounts: &[Mount], file: &str, line: usize) {
+    let path = match mount::web_path_of_file(std::path::Path::new(file), mounts) {
+        Some(path) => path,
+        None => {
+            log::error!("{} isn't in any of the served directories", file);
+            std::process::exit(1);
+        }
+    };
+
+    // Servers listening at all addresses are reachable at the loopback address
+    let ip = match config.bind()[0] {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(std::net::Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+
+    let request = editor::GotoRequest { path, line };
+    if let Err(err) = editor::send_goto(
+        SocketAddr::new(ip, config.port()),
+        &config.base_path(),
+        &request,
+    )
+    .await
+    {
+        log::error!("Failed sending position to server: {}", err);
+        std::process::exit(1);
+    }
 }
 
+/// Waits until the process receives SIGINT (Ctrl-C) or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed installing Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed installing SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Dispatches the messages of the builders to the servers. A builder only knows the files of
+/// its own mount, so the lists of files of every mount are kept here.
 async fn servers_multiplexer(
     mut rx_srv: sync::mpsc::Receiver<MsgSrv>,
-    tx4: sync::mpsc::Sender<MsgSrv>,
-    tx6: sync::mpsc::Sender<MsgSrv>,
+    builders: Vec<(String, sync::mpsc::Sender<MsgBuilder>)>,
+    mut builder_count: usize,
+    txs: Vec<sync::mpsc::Sender<MsgSrv>>,
 ) {
+    // Asked only once, because a builder may wait for this multiplexer while it's asked
+    let mut mount_files = Vec::with_capacity(builders.len());
+    for (mount, tx_builder) in builders {
+        mount_files.push((mount, files_of_builder(&tx_builder).await));
+    }
+
     while let Some(msg) = rx_srv.recv().await {
         log::debug!("General server event: {:?}", msg);
 
-        match msg {
-            MsgSrv::File(path, content) => {
-                let (msg0, msg1) = tokio::join!(
-                    tx4.send(MsgSrv::File(path.clone(), content.clone())),
-                    tx6.send(MsgSrv::File(path, content))
-                );
-
-                msg0.unwrap();
-                msg1.unwrap();
-            }
-            MsgSrv::NewFile(path, all_files) => {
-                let (msg0, msg1) = tokio::join!(
-                    tx4.send(MsgSrv::NewFile(path.clone(), all_files.clone())),
-                    tx6.send(MsgSrv::NewFile(path, all_files))
-                );
-
-                msg0.unwrap();
-                msg1.unwrap();
+        let msg = match msg {
+            MsgSrv::NewFile(mount, path, files) => {
+                if let Some((_, mount_files)) = mount_files
+                    .iter_mut()
+                    .find(|(mount_prefix, _)| *mount_prefix == mount)
+                {
+                    *mount_files = files;
+                }
+
+                let all_files = mount_files
+                    .iter()
+                    .flat_map(|(_, files)| files.iter().cloned())
+                    .collect();
+                MsgSrv::NewFile(mount, path, all_files)
             }
             MsgSrv::Exit() => {
-                let (msg0, msg1) = tokio::join!(tx4.send(MsgSrv::Exit()), tx6.send(MsgSrv::Exit()));
+                builder_count = builder_count.saturating_sub(1);
+                if builder_count > 0 {
+                    // Other mounts are still served
+                    continue;
+                }
 
                 // ignore errors
-                msg0.ok();
-                msg1.ok();
+                futures::future::join_all(txs.iter().map(|tx| tx.send(MsgSrv::Exit()))).await;
                 break;
             }
-        }
+            msg => msg,
+        };
+
+        // ignore errors, routers may have shut down already
+        futures::future::join_all(txs.iter().map(|tx| tx.send(msg.clone()))).await;
     }
 }
 
-/// This function exists, because a mpsc tokio channel wouldn't properly drain itself. This could
-/// tries to force that.
-pub(crate) async fn why_is_this_necessary<T: Sized + Clone>(
-    sender: &sync::mpsc::Sender<T>,
-    ignore: T,
+/// Dispatches queries from the servers to the builder of the mount the requested file belongs to.
+/// The list of all files contains the files of every mount.
+async fn builders_multiplexer(
+    mut rx_file: sync::mpsc::Receiver<MsgBuilder>,
+    builders: Vec<(String, sync::mpsc::Sender<MsgBuilder>)>,
 ) {
-    for _ in 0..sender.capacity() + 1 {
-        sender.send(ignore.clone()).await.ok();
+    while let Some(msg) = rx_file.recv().await {
+        log::debug!("General builder event: {:?}", msg);
+
+        match msg {
+            MsgBuilder::File(path, result) => {
+                let mut file = None;
+                if let Some(tx_builder) = builder_of(&path, &builders[..]) {
+                    let (tx_onefile, rx_onefile) = sync::oneshot::channel();
+                    if tx_builder
+                        .send(MsgBuilder::File(path, tx_onefile))
+                        .await
+                        .is_ok()
+                    {
+                        if let Ok((built_file, _)) = rx_onefile.await {
+                            file = built_file;
+                        }
+                    }
+                }
+
+                let all_files = all_files(&builders[..]).await;
+                result
+                    .send((file, all_files))
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::AllFiles(result) => {
+                let all_files = all_files(&builders[..]).await;
+                result
+                    .send(all_files)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Source(path, result) => {
+                let mut source = None;
+                if let Some(tx_builder) = builder_of(&path, &builders[..]) {
+                    let (tx_source, rx_source) = sync::oneshot::channel();
+                    if tx_builder
+                        .send(MsgBuilder::Source(path, tx_source))
+                        .await
+                        .is_ok()
+                    {
+                        source = rx_source.await.ok().flatten();
+                    }
+                }
+
+                result
+                    .send(source)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::FsPath(path, result) => {
+                let mut fs_path = None;
+                if let Some(tx_builder) = builder_of(&path, &builders[..]) {
+                    let (tx_fs_path, rx_fs_path) = sync::oneshot::channel();
+                    if tx_builder
+                        .send(MsgBuilder::FsPath(path, tx_fs_path))
+                        .await
+                        .is_ok()
+                    {
+                        fs_path = rx_fs_path.await.ok().flatten();
+                    }
+                }
+
+                result
+                    .send(fs_path)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Render(path, markdown, result) => {
+                let mut built = None;
+                if let Some(tx_builder) = builder_of(&path, &builders[..]) {
+                    let (tx_built, rx_built) = sync::oneshot::channel();
+                    if tx_builder
+                        .send(MsgBuilder::Render(path, markdown, tx_built))
+                        .await
+                        .is_ok()
+                    {
+                        built = rx_built.await.ok().flatten();
+                    }
+                }
+
+                result
+                    .send(built)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Save(path, contents, expected_hash, result) => {
+                let mut saved = Err(WriteError::NotFound);
+                if let Some(tx_builder) = builder_of(&path, &builders[..]) {
+                    let (tx_saved, rx_saved) = sync::oneshot::channel();
+                    if tx_builder
+                        .send(MsgBuilder::Save(path, contents, expected_hash, tx_saved))
+                        .await
+                        .is_ok()
+                    {
+                        saved = rx_saved
+                            .await
+                            .unwrap_or_else(|err| Err(WriteError::Io(err.to_string())));
+                    }
+                }
+
+                result
+                    .send(saved)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Page(action, result) => {
+                let mut changed = Err(WriteError::NotFound);
+                if let Some(tx_builder) = builder_of(action.path(), &builders[..]) {
+                    let same_mount = match &action {
+                        PageAction::Rename { new_path, .. } => builder_of(new_path, &builders[..])
+                            .is_some_and(|tx_other| tx_other.same_channel(tx_builder)),
+                        _ => true,
+                    };
+
+                    if !same_mount {
+                        changed = Err(WriteError::OtherMount);
+                    } else {
+                        let (tx_changed, rx_changed) = sync::oneshot::channel();
+                        if tx_builder
+                            .send(MsgBuilder::Page(action, tx_changed))
+                            .await
+                            .is_ok()
+                        {
+                            changed = rx_changed
+                                .await
+                                .unwrap_or_else(|err| Err(WriteError::Io(err.to_string())));
+                        }
+                    }
+                }
+
+                result
+                    .send(changed)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Metadata(result) => {
+                let mut metadata = Vec::new();
+                for (_, tx_builder) in builders.iter() {
+                    let (tx_metadata, rx_metadata) = sync::oneshot::channel();
+                    if tx_builder
+                        .send(MsgBuilder::Metadata(tx_metadata))
+                        .await
+                        .is_ok()
+                    {
+                        if let Ok(mut files) = rx_metadata.await {
+                            metadata.append(&mut files);
+                        }
+                    }
+                }
+
+                result
+                    .send(metadata)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Status(result) => {
+                let mut status = builder::BuildStatus::default();
+                for (_, tx_builder) in builders.iter() {
+                    let (tx_status, rx_status) = sync::oneshot::channel();
+                    if tx_builder.send(MsgBuilder::Status(tx_status)).await.is_ok() {
+                        if let Ok(builder_status) = rx_status.await {
+                            status.files += builder_status.files;
+                            status.processing += builder_status.processing;
+                        }
+                    }
+                }
+
+                result
+                    .send(status)
+                    .unwrap_or_else(|err| log::error!("{:?}", err));
+            }
+            MsgBuilder::Reconfigure(config) => {
+                for (_, tx_builder) in builders.iter() {
+                    tx_builder
+                        .send(MsgBuilder::Reconfigure(config.clone()))
+                        .await
+                        .ok();
+                }
+            }
+            MsgBuilder::Exit() => {
+                for (_, tx_builder) in builders.iter() {
+                    tx_builder.send(MsgBuilder::Exit()).await.ok();
+                }
+                break;
+            }
+        }
+    }
+}
+
+/// Returns the builder of the mount the web path belongs to
+fn builder_of<'a>(
+    path: &str,
+    builders: &'a [(String, sync::mpsc::Sender<MsgBuilder>)],
+) -> Option<&'a sync::mpsc::Sender<MsgBuilder>> {
+    let prefix = mount::find_mount(path, builders.iter().map(|(p, _)| p.as_str()))?;
+    builders
+        .iter()
+        .find(|(p, _)| p == prefix)
+        .map(|(_, tx_builder)| tx_builder)
+}
+
+/// Collects the files of all builders
+async fn all_files(builders: &[(String, sync::mpsc::Sender<MsgBuilder>)]) -> Vec<String> {
+    let mut all_files = Vec::new();
+    for (_, tx_builder) in builders {
+        all_files.append(&mut files_of_builder(tx_builder).await);
+    }
+
+    all_files
+}
+
+/// Queries the files of a mount from its builder (none, if the builder exited)
+async fn files_of_builder(tx_builder: &sync::mpsc::Sender<MsgBuilder>) -> Vec<String> {
+    let (tx_files, rx_files) = sync::oneshot::channel();
+    if tx_builder
+        .send(MsgBuilder::AllFiles(tx_files))
+        .await
+        .is_ok()
+    {
+        rx_files.await.unwrap_or_default()
+    } else {
+        Vec::new()
     }
 }
@@ -0,0 +1,1781 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+//! Parses AsciiDoc to HTML. Supports the commonly used parts of Asciidoctor: sections, lists,
+//! delimited blocks, tables, admonitions, images, links, cross references, footnotes, attributes
+//! and math.
+
+use std::collections::{HashMap, HashSet};
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use super::{
+    html::{alert, code_block, escape, footnote, footnote_reference, table},
+    math, Diagnostic, MarkdownParser, Rendered, Severity,
+};
+
+/// Labels of admonitions, which are also the type of alert they are shown as
+static ADMONITIONS: &[&str] = &["NOTE", "TIP", "IMPORTANT", "WARNING", "CAUTION"];
+
+static ATTRIBUTE_ENTRY: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^:(!?[\w][\w-]*!?):(?:\s+(.*))?$").unwrap());
+static LIST_ITEM: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(\*{1,5}|-|\.{1,5}|\d+\.|[a-z]\.|<\d+>|<\.>)\s+(.*)$").unwrap());
+static DESCRIPTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(\S.*?)(:{2,4}|;;)(?:\s+(.*))?$").unwrap());
+static BLOCK_MACRO: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(image|video|audio|include|toc)::([^\[]*)\[(.*)\]$").unwrap());
+static ANCHOR_LINE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\[(?:\[([\w:.-]+)(?:,\s*(.*))?\]|#([\w:-]+)[^\]]*)\]$").unwrap());
+static INLINE_ANCHOR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\[\[([\w:.-]+)(?:,\s*([^\]]*))?\]\]|\[#([\w:-]+)\]|anchor:([\w:.-]+)\[([^\]]*)\]")
+        .unwrap()
+});
+static ADMONITION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(NOTE|TIP|IMPORTANT|WARNING|CAUTION):\s+").unwrap());
+/// Specifier of a table cell (span, alignment and style, e.g. ``2+^a``)
+static CELL_SPEC: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:\d+\*)?(?:\d*(?:\.\d+)?\+)?(?:[<^>](?:\.[<^>])?)?([adehlmsv])?$").unwrap()
+});
+static INLINE: Lazy<InlinePatterns> = Lazy::new(InlinePatterns::new);
+
+/// Maximum number of nested blocks (e.g. delimited blocks). Blocks are parsed recursively, so
+/// deeper ones would overflow the stack.
+const MAX_NESTING: usize = 100;
+
+/// Attributes every document has
+static BUILTIN_ATTRIBUTES: &[(&str, &str)] = &[
+    ("empty", ""),
+    ("sp", " "),
+    ("nbsp", "\u{a0}"),
+    ("zwsp", "\u{200b}"),
+    ("wj", "\u{2060}"),
+    ("apos", "'"),
+    ("quot", "\""),
+    ("lsquo", "\u{2018}"),
+    ("rsquo", "\u{2019}"),
+    ("ldquo", "\u{201c}"),
+    ("rdquo", "\u{201d}"),
+    ("deg", "\u{b0}"),
+    ("plus", "+"),
+    ("brvbar", "\u{a6}"),
+    ("vbar", "|"),
+    ("amp", "&"),
+    ("lt", "<"),
+    ("gt", ">"),
+    ("startsb", "["),
+    ("endsb", "]"),
+    ("caret", "^"),
+    ("asterisk", "*"),
+    ("tilde", "~"),
+    ("backslash", "\\"),
+    ("backtick", "`"),
+    ("two-colons", "::"),
+    ("two-semicolons", ";;"),
+    ("cpp", "C++"),
+];
+
+/// Replaced characters of the escaped text
+static REPLACEMENTS: &[(&str, &str)] = &[
+    (r"\(C\)", "&#169;"),
+    (r"\(R\)", "&#174;"),
+    (r"\(TM\)", "&#8482;"),
+    (r"(^|\s)--(\s|$)", "$1&#8212;$2"),
+    (r"(\w)--(\w)", "$1&#8212;$2"),
+    (r"\.\.\.", "&#8230;"),
+    (r"(\w)'(\w)", "$1&#8217;$2"),
+    (r"-&gt;", "&#8594;"),
+    (r"=&gt;", "&#8658;"),
+    (r"&lt;-", "&#8592;"),
+    (r"&lt;=", "&#8656;"),
+];
+
+/// Marks the HTML of the inline markup replaced while substituting the rest of the text
+const STASH_START: char = '\u{f000}';
+const STASH_END: char = '\u{f001}';
+
+/// Line of the source with its number (starting at 1)
+type Line = (usize, String);
+
+#[derive(Default)]
+pub struct AsciiDocParser;
+
+impl MarkdownParser for AsciiDocParser {
+    fn parse_to_html(&mut self, source: &str) -> Rendered {
+        let (blocks, diagnostics) = parse(source);
+
+        Rendered {
+            html: blocks.into_iter().map(|block| block.html + "\n").collect(),
+            diagnostics,
+        }
+    }
+
+    fn block_lines(&mut self, source: &str) -> Vec<usize> {
+        parse(source)
+            .0
+            .into_iter()
+            .map(|block| block.line)
+            .collect()
+    }
+}
+
+/// Top-level element of the HTML
+struct Block {
+    /// Line the element starts at
+    line: usize,
+    html: String,
+}
+
+fn parse(source: &str) -> (Vec<Block>, Vec<Diagnostic>) {
+    // The placeholders of stashed HTML can't be written in the source (a placeholder stashed
+    // as itself would be replaced forever)
+    let lines: Vec<Line> = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line
+                .trim_end()
+                .replace([STASH_START, STASH_END], "\u{fffd}");
+            (i + 1, line)
+        })
+        .collect();
+
+    let mut document = Document::new(&lines);
+    let mut blocks = document.parse_blocks(&lines, true);
+
+    // Footnotes are listed at the end of the document
+    let last_line = lines.len().max(1);
+    for (i, html) in document.footnotes.iter().enumerate() {
+        let label = (i + 1).to_string();
+        blocks.push(Block {
+            line: last_line,
+            html: footnote(&label, &label, &format!("<p>{}</p>", html)),
+        });
+    }
+
+    (blocks, document.diagnostics)
+}
+
+/// Attributes of a block (``[style,positional,name=value]``) and its title (``.Title``)
+#[derive(Clone, Default)]
+struct BlockAttributes {
+    style: Option<String>,
+    positional: Vec<String>,
+    named: HashMap<String, String>,
+    id: Option<String>,
+    options: Vec<String>,
+    title: Option<String>,
+}
+
+impl BlockAttributes {
+    /// Adds the attributes of the list in the brackets of a block attribute line or a macro.
+    /// The first attribute of block attribute lines may be a shorthand (``style#id%option``).
+    fn parse(&mut self, list: &str, shorthand: bool) {
+        for (i, attribute) in split_attributes(list).into_iter().enumerate() {
+            match attribute.split_once('=') {
+                Some((name, value)) if !attribute.starts_with(['"', '\'']) => {
+                    let name = name.trim();
+                    let value = unquote(value.trim());
+                    match name {
+                        "id" => self.id = Some(value),
+                        "options" | "opts" => self
+                            .options
+                            .extend(value.split(',').map(|option| option.trim().to_string())),
+                        _ => {
+                            self.named.insert(name.to_string(), value);
+                        }
+                    }
+                }
+                _ if i == 0 && shorthand => {
+                    let mut parts = attribute.split_inclusive(['#', '.', '%']).peekable();
+                    let mut marker = None;
+                    let mut style = String::new();
+                    while let Some(part) = parts.next() {
+                        let (value, next) = match part.chars().last() {
+                            Some(c @ ('#' | '.' | '%')) if parts.peek().is_some() => {
+                                (&part[..part.len() - 1], Some(c))
+                            }
+                            _ => (part, None),
+                        };
+                        match marker {
+                            None => style.push_str(value),
+                            Some('#') => self.id = Some(value.to_string()),
+                            Some('%') => self.options.push(value.to_string()),
+                            _ => {}
+                        }
+                        marker = next;
+                    }
+
+                    let style = unquote(style.trim());
+                    if !style.is_empty() {
+                        self.style = Some(style.clone());
+                    }
+                    self.positional.push(style);
+                }
+                _ => self.positional.push(unquote(&attribute)),
+            }
+        }
+    }
+
+    fn style_is(&self, style: &str) -> bool {
+        self.style.as_deref() == Some(style)
+    }
+
+    /// Kind of alert, if the style is an admonition
+    fn admonition(&self) -> Option<&'static str> {
+        let style = self.style.as_deref()?;
+        ADMONITIONS.iter().copied().find(|label| *label == style)
+    }
+
+    /// Language of a listing, if it's source code (diagrams are listings with their type as
+    /// style)
+    fn language(&self, default: Option<&str>) -> Option<String> {
+        match self.style.as_deref() {
+            Some("source") => self
+                .positional
+                .get(1)
+                .filter(|language| !language.is_empty())
+                .cloned()
+                .or_else(|| default.map(str::to_string)),
+            None | Some("listing" | "literal") => None,
+            Some(style) => Some(style.to_string()),
+        }
+    }
+}
+
+struct Document {
+    attributes: HashMap<String, String>,
+    /// Anchors of the section titles by the line of the title
+    title_ids: HashMap<usize, String>,
+    /// Known anchors with the text of references to them
+    anchors: HashMap<String, Option<String>>,
+    /// Anchors of the sections by their title, for references by title
+    titles: HashMap<String, String>,
+    /// Rendered footnotes in order
+    footnotes: Vec<String>,
+    /// HTML replaced by placeholders while substituting inline markup. Shared by all texts, so
+    /// the placeholders in a nested text (e.g. of a footnote) are replaced in it too.
+    stash: Vec<String>,
+    /// Numbers of the named footnotes
+    footnote_ids: HashMap<String, usize>,
+    /// Number of nested blocks being parsed
+    depth: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Patterns of the inline markup
+struct InlinePatterns {
+    hard_break: Regex,
+    escaped: Regex,
+    passthrough: Regex,
+    stem: Regex,
+    monospace: Regex,
+    monospace_constrained: Regex,
+    constrained_passthrough: Regex,
+    attribute: Regex,
+    footnote: Regex,
+    anchor: Regex,
+    cross_reference: Regex,
+    image: Regex,
+    keyboard: Regex,
+    link: Regex,
+    url: Regex,
+    quotes: Vec<(Regex, &'static str, bool)>,
+    replacements: Vec<(Regex, &'static str)>,
+    stashed: Regex,
+}
+
+impl Document {
+    /// Collects the anchors of the document, which can be referenced before their definition
+    fn new(lines: &[Line]) -> Self {
+        let mut document = Document {
+            attributes: HashMap::new(),
+            title_ids: HashMap::new(),
+            anchors: HashMap::new(),
+            titles: HashMap::new(),
+            footnotes: Vec::new(),
+            stash: Vec::new(),
+            footnote_ids: HashMap::new(),
+            depth: 0,
+            diagnostics: Vec::new(),
+        };
+
+        let mut ids = HashSet::new();
+        let mut verbatim: Option<&str> = None;
+        let mut pending_id = None;
+        for (number, text) in lines {
+            if let Some(closing) = verbatim {
+                if text == closing {
+                    verbatim = None;
+                }
+                continue;
+            }
+            // Titles in listings, literals, passthroughs and comments aren't titles
+            if let Some(delimiter) = delimiter(text) {
+                if delimiter == "```" || (delimiter != "--" && "-.+/".contains(&delimiter[..1])) {
+                    verbatim = Some(delimiter);
+                    continue;
+                }
+            }
+
+            if let Some((name, value)) = document.attribute_entry(text) {
+                document.set_attribute(&name, value);
+            } else if let Some(captures) = ANCHOR_LINE.captures(text) {
+                pending_id = captures.get(1).or_else(|| captures.get(3)).map(|id| {
+                    let id = id.as_str().to_string();
+                    document.anchors.insert(
+                        id.clone(),
+                        captures.get(2).map(|text| text.as_str().to_string()),
+                    );
+                    id
+                });
+            } else if let Some((_, title)) = section_title(text) {
+                let id = match pending_id.take() {
+                    Some(id) => {
+                        document.anchors.insert(id.clone(), Some(title.clone()));
+                        id
+                    }
+                    None => {
+                        let id = generated_id(&title, &document.attributes, &ids);
+                        document.anchors.insert(id.clone(), Some(title.clone()));
+                        id
+                    }
+                };
+                ids.insert(id.clone());
+                document.titles.entry(title).or_insert_with(|| id.clone());
+                document.title_ids.insert(*number, id);
+            } else if !is_blank(text) && !text.starts_with('[') && !text.starts_with('.') {
+                pending_id = None;
+            }
+
+            for captures in INLINE_ANCHOR.captures_iter(text) {
+                let id = captures
+                    .get(1)
+                    .or_else(|| captures.get(3))
+                    .or_else(|| captures.get(4))
+                    .unwrap();
+                let reference_text = captures
+                    .get(2)
+                    .or_else(|| captures.get(5))
+                    .map(|text| text.as_str().to_string())
+                    .filter(|text| !text.is_empty());
+                document
+                    .anchors
+                    .entry(id.as_str().to_string())
+                    .or_insert(reference_text);
+            }
+        }
+        // The attributes are defined again while parsing
+        document.attributes.clear();
+
+        document
+    }
+
+    fn warn(&mut self, line: usize, message: String) {
+        self.diagnostics
+            .push(Diagnostic::new(Severity::Warning, message, Some(line)));
+    }
+
+    /// Name and value (``None`` if it's unset) of an attribute entry (``:name: value``)
+    fn attribute_entry(&self, text: &str) -> Option<(String, Option<String>)> {
+        let captures = ATTRIBUTE_ENTRY.captures(text)?;
+        let name = &captures[1];
+        if name.starts_with('!') || name.ends_with('!') {
+            return Some((name.trim_matches('!').to_string(), None));
+        }
+
+        let value = captures
+            .get(2)
+            .map(|value| value.as_str().trim().to_string())
+            .unwrap_or_default();
+        Some((name.to_string(), Some(value)))
+    }
+
+    fn set_attribute(&mut self, name: &str, value: Option<String>) {
+        match value {
+            Some(value) => {
+                let value = self.substitute_attributes(&value, None);
+                self.attributes.insert(name.to_string(), value);
+            }
+            None => {
+                self.attributes.remove(name);
+            }
+        }
+    }
+
+    /// Replaces references to attributes (``{name}``). Unknown ones are kept and reported, if
+    /// the line is given.
+    fn substitute_attributes(&mut self, text: &str, line: Option<usize>) -> String {
+        let mut unknown = Vec::new();
+        let text = INLINE
+            .attribute
+            .replace_all(text, |captures: &Captures| {
+                let name = &captures[1];
+                match self.attributes.get(name).map(String::as_str).or_else(|| {
+                    BUILTIN_ATTRIBUTES
+                        .iter()
+                        .find(|(builtin, _)| *builtin == name)
+                        .map(|(_, value)| *value)
+                }) {
+                    Some(value) => value.to_string(),
+                    None => {
+                        unknown.push(name.to_string());
+                        captures[0].to_string()
+                    }
+                }
+            })
+            .into_owned();
+
+        if let Some(line) = line {
+            for name in unknown {
+                self.warn(line, format!("Unknown attribute \"{}\"", name));
+            }
+        }
+
+        text
+    }
+
+    /// Parses the lines to blocks. Sections are only allowed at the top-level. Blocks nested too
+    /// deeply are shown as literal text.
+    fn parse_blocks(&mut self, lines: &[Line], top_level: bool) -> Vec<Block> {
+        let Some(&(number, _)) = lines.first() else {
+            return Vec::new();
+        };
+        if self.depth >= MAX_NESTING {
+            self.warn(number, "Blocks are nested too deeply".to_string());
+            let text: Vec<&str> = lines.iter().map(|(_, line)| line.as_str()).collect();
+            return vec![Block {
+                line: number,
+                html: code_block(&text.join("\n"), None),
+            }];
+        }
+
+        self.depth += 1;
+        let blocks = self.parse_blocks_in(lines, top_level);
+        self.depth -= 1;
+
+        blocks
+    }
+
+    fn parse_blocks_in(&mut self, lines: &[Line], top_level: bool) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut attributes = BlockAttributes::default();
+        let mut i = 0;
+        while i < lines.len() {
+            let (number, text) = (lines[i].0, lines[i].1.as_str());
+            if is_blank(text) || (text.starts_with("//") && !text.starts_with("////")) {
+                i += 1;
+                continue;
+            }
+
+            if let Some((name, value)) = self.attribute_entry(text) {
+                self.set_attribute(&name, value);
+                i += 1;
+                continue;
+            }
+            if text.starts_with("[[") && text.ends_with("]]") {
+                let anchor = text[2..text.len() - 2]
+                    .split(',')
+                    .next()
+                    .unwrap_or_default();
+                attributes.id = Some(anchor.trim().to_string());
+                i += 1;
+                continue;
+            }
+            if text.starts_with('[') && text.ends_with(']') {
+                attributes.parse(&text[1..text.len() - 1], true);
+                i += 1;
+                continue;
+            }
+            if text.starts_with('.')
+                && text[1..].starts_with(|c: char| c != '.' && !c.is_whitespace())
+            {
+                attributes.title = Some(text[1..].to_string());
+                i += 1;
+                continue;
+            }
+
+            let mut titled = true;
+            let html = if let Some((level, title)) =
+                top_level.then(|| section_title(text)).flatten()
+            {
+                i += 1;
+                titled = false;
+                let id = attributes
+                    .id
+                    .take()
+                    .or_else(|| self.title_ids.get(&number).cloned())
+                    .unwrap_or_default();
+                let html = format!(
+                    "<h{0} id=\"{1}\">{2}</h{0}>",
+                    level,
+                    escape(&id),
+                    self.inline(&title, number)
+                );
+
+                // The header of the document (author and revision) follows its title
+                if level == 1 && blocks.is_empty() {
+                    let mut details = Vec::new();
+                    while i < lines.len() && !is_blank(&lines[i].1) {
+                        let (line, text) = (lines[i].0, lines[i].1.as_str());
+                        if let Some((name, value)) = self.attribute_entry(text) {
+                            self.set_attribute(&name, value);
+                        } else if !text.starts_with("//") {
+                            details.push(self.inline(text, line));
+                        }
+                        i += 1;
+                    }
+                    if !details.is_empty() {
+                        blocks.push(Block { line: number, html });
+                        blocks.push(Block {
+                            line: number + 1,
+                            html: format!("<p class=\"details\">{}</p>", details.join("<br />\n")),
+                        });
+                        attributes = BlockAttributes::default();
+                        continue;
+                    }
+                }
+
+                Some(html)
+            } else if let Some(delimiter) = delimiter(text) {
+                let closing = if delimiter == "```" { "```" } else { text };
+                let end = lines[i + 1..]
+                    .iter()
+                    .position(|(_, line)| line == closing)
+                    .map(|end| i + 1 + end);
+                if end.is_none() {
+                    self.warn(
+                        number,
+                        format!("The block starting with {} isn't closed", text),
+                    );
+                }
+                let end = end.unwrap_or(lines.len());
+                let content = &lines[i + 1..end];
+                i = end + 1;
+
+                let info = text.trim_start_matches('`').trim();
+                if delimiter.starts_with(['|', ',']) {
+                    titled = false;
+                }
+                self.delimited_block(delimiter, info, content, &attributes, number)
+            } else if let Some(captures) = BLOCK_MACRO.captures(text) {
+                let (name, target, list) = (
+                    captures[1].to_string(),
+                    captures[2].to_string(),
+                    captures[3].to_string(),
+                );
+                i += 1;
+                attributes.parse(&list, false);
+                match name.as_str() {
+                    "image" => {
+                        titled = false;
+                        Some(self.block_image(&target, &attributes, number))
+                    }
+                    "video" | "audio" => Some(format!(
+                        "<p><{0} src=\"{1}\" controls></{0}></p>",
+                        name,
+                        escape(&target)
+                    )),
+                    "include" => {
+                        self.warn(number, "The include directive isn't supported".to_string());
+                        None
+                    }
+                    _ => None,
+                }
+            } else if ["'''", "---", "***", "- - -", "* * *"].contains(&text) {
+                i += 1;
+                Some("<hr />".to_string())
+            } else if text == "<<<" {
+                i += 1;
+                None
+            } else if self.list_item(number, text).is_some() {
+                let (html, end) = self.list(lines, i);
+                i = end;
+                Some(html)
+            } else if text.starts_with([' ', '\t']) && attributes.style.is_none() {
+                let end = paragraph_end(lines, i);
+                let literal: Vec<&str> = lines[i..end]
+                    .iter()
+                    .map(|(_, line)| line.as_str())
+                    .collect();
+                i = end;
+                Some(code_block(&dedent(&literal), None))
+            } else {
+                let end = paragraph_end(lines, i);
+                let paragraph = &lines[i..end];
+                i = end;
+                self.paragraph(paragraph, &attributes)
+            };
+
+            if let Some(mut html) = html {
+                if titled {
+                    if let Some(title) = &attributes.title {
+                        html = format!(
+                            "<div class=\"titled\">\n<p class=\"block-title\">{}</p>\n{}\n</div>",
+                            self.inline(title, number),
+                            html
+                        );
+                    }
+                }
+                if let Some(id) = &attributes.id {
+                    html = with_id(&html, id);
+                }
+                blocks.push(Block { line: number, html });
+            }
+            attributes = BlockAttributes::default();
+        }
+
+        blocks
+    }
+
+    fn render_blocks(&mut self, lines: &[Line]) -> String {
+        self.parse_blocks(lines, false)
+            .into_iter()
+            .map(|block| block.html)
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Renders a paragraph, which the style of its attributes may turn into another block
+    fn paragraph(&mut self, lines: &[Line], attributes: &BlockAttributes) -> Option<String> {
+        let number = lines[0].0;
+        let text: Vec<&str> = lines.iter().map(|(_, line)| line.as_str()).collect();
+        let text = text.join("\n");
+
+        if let Some(captures) = ADMONITION.captures(&text) {
+            let label = captures[1].to_string();
+            let contents = self.inline(&text[captures[0].len()..], number);
+            return Some(admonition_alert(&label, &format!("<p>{}</p>", contents)));
+        }
+
+        if let Some(label) = attributes.admonition() {
+            let contents = self.inline(&text, number);
+            return Some(admonition_alert(label, &format!("<p>{}</p>", contents)));
+        }
+
+        match attributes.style.as_deref() {
+            Some("quote") => Some(format!(
+                "<blockquote>\n<p>{}</p>{}\n</blockquote>",
+                self.inline(&text, number),
+                self.attribution(attributes, number)
+            )),
+            Some("verse") => Some(format!(
+                "<blockquote>\n<pre class=\"verse\">{}</pre>{}\n</blockquote>",
+                self.inline(&text, number),
+                self.attribution(attributes, number)
+            )),
+            Some("stem" | "latexmath") => Some(self.display_math(&text, number)),
+            Some("pass") => Some(text),
+            Some("normal" | "abstract" | "partintro") | None => {
+                Some(format!("<p>{}</p>", self.inline(&text, number)))
+            }
+            Some(_) => Some(code_block(&text, attributes.language(None).as_deref())),
+        }
+    }
+
+    /// Renders the contents of a delimited block (``None`` for comments)
+    fn delimited_block(
+        &mut self,
+        delimiter: &str,
+        info: &str,
+        content: &[Line],
+        attributes: &BlockAttributes,
+        number: usize,
+    ) -> Option<String> {
+        let text: Vec<&str> = content.iter().map(|(_, line)| line.as_str()).collect();
+        let text = text.join("\n");
+
+        if let Some(label) = attributes.admonition() {
+            if ["====", "--"].contains(&delimiter) {
+                return Some(admonition_alert(label, &self.render_blocks(content)));
+            }
+        }
+
+        match &delimiter[..1] {
+            "`" => Some(code_block(
+                &text,
+                Some(info)
+                    .filter(|info| !info.is_empty())
+                    .or(attributes.positional.get(1).map(String::as_str)),
+            )),
+            "-" if delimiter == "--" => match attributes.style.as_deref() {
+                Some("source" | "listing" | "literal") => {
+                    Some(code_block(&text, attributes.language(None).as_deref()))
+                }
+                Some("quote" | "verse") => self.quote(content, attributes, number),
+                _ => Some(format!(
+                    "<div class=\"openblock\">\n{}\n</div>",
+                    self.render_blocks(content)
+                )),
+            },
+            "-" => {
+                let default = self.attributes.get("source-language").cloned();
+                Some(code_block(
+                    &text,
+                    attributes.language(default.as_deref()).as_deref(),
+                ))
+            }
+            "." => Some(code_block(&text, attributes.language(None).as_deref())),
+            "=" => Some(format!(
+                "<div class=\"example\">\n{}\n</div>",
+                self.render_blocks(content)
+            )),
+            "*" => Some(format!(
+                "<aside class=\"sidebar\">\n{}\n</aside>",
+                self.render_blocks(content)
+            )),
+            "_" => self.quote(content, attributes, number),
+            "+" if attributes.style_is("stem") || attributes.style_is("latexmath") => {
+                Some(self.display_math(&text, number))
+            }
+            "+" => Some(text),
+            "|" | "," => Some(self.table(delimiter, content, attributes, number)),
+            _ => None,
+        }
+    }
+
+    fn quote(
+        &mut self,
+        content: &[Line],
+        attributes: &BlockAttributes,
+        number: usize,
+    ) -> Option<String> {
+        let contents = if attributes.style_is("verse") {
+            let text: Vec<&str> = content.iter().map(|(_, line)| line.as_str()).collect();
+            format!(
+                "<pre class=\"verse\">{}</pre>",
+                self.inline(&text.join("\n"), number)
+            )
+        } else {
+            self.render_blocks(content)
+        };
+
+        Some(format!(
+            "<blockquote>\n{}{}\n</blockquote>",
+            contents,
+            self.attribution(attributes, number)
+        ))
+    }
+
+    /// Author and source of a quote
+    fn attribution(&mut self, attributes: &BlockAttributes, number: usize) -> String {
+        let parts: Vec<String> = attributes
+            .positional
+            .iter()
+            .skip(1)
+            .filter(|part| !part.is_empty())
+            .map(|part| self.inline(part, number))
+            .collect();
+
+        match parts.is_empty() {
+            true => String::new(),
+            false => format!("\n<footer>&#8212; {}</footer>", parts.join(", ")),
+        }
+    }
+
+    fn display_math(&mut self, tex: &str, number: usize) -> String {
+        let (mathml, errors) = math::to_mathml(tex.trim(), true);
+        for error in errors {
+            self.warn(number, error);
+        }
+
+        format!("<p>{}</p>", mathml)
+    }
+
+    fn block_image(&mut self, target: &str, attributes: &BlockAttributes, number: usize) -> String {
+        let image = self.image(target, attributes);
+        match &attributes.title {
+            Some(title) => format!(
+                "<figure>\n{}\n<figcaption>{}</figcaption>\n</figure>",
+                image,
+                self.inline(title, number)
+            ),
+            None => format!("<p>{}</p>", image),
+        }
+    }
+
+    fn image(&mut self, target: &str, attributes: &BlockAttributes) -> String {
+        let target = self.substitute_attributes(target, None);
+        let target = match self.attributes.get("imagesdir") {
+            Some(directory) if !target.contains("://") && !target.starts_with('/') => {
+                format!("{}/{}", directory.trim_end_matches('/'), target)
+            }
+            _ => target,
+        };
+        let alt = attributes
+            .positional
+            .first()
+            .filter(|alt| !alt.is_empty())
+            .cloned()
+            .unwrap_or_else(|| {
+                let name = target.rsplit('/').next().unwrap_or_default();
+                let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+                stem.replace(['-', '_'], " ")
+            });
+
+        let mut html = format!("src=\"{}\" alt=\"{}\"", escape(&target), escape(&alt));
+        for (i, attribute) in ["width", "height"].into_iter().enumerate() {
+            let value = attributes
+                .named
+                .get(attribute)
+                .or_else(|| attributes.positional.get(i + 1));
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                html.push_str(&format!(" {}=\"{}\"", attribute, escape(value)));
+            }
+        }
+
+        match attributes.named.get("link") {
+            Some(link) => format!("<a href=\"{}\"><img {} /></a>", escape(link), html),
+            None => format!("<img {} />", html),
+        }
+    }
+
+    /// Renders the list starting at the line and returns the line after it. Items are nested by
+    /// their marker (``*``, ``**``, ``.``, ``term::`` ...), the first item with a new marker
+    /// starts a nested list.
+    fn list(&mut self, lines: &[Line], start: usize) -> (String, usize) {
+        let mut items: Vec<ListItem> = Vec::new();
+        let mut i = start;
+        while i < lines.len() {
+            let (number, text) = (lines[i].0, lines[i].1.as_str());
+            if let Some(item) = self.list_item(number, text) {
+                items.push(item);
+                i += 1;
+            } else if is_blank(text) {
+                // Blank lines only separate items
+                let next = i + lines[i..]
+                    .iter()
+                    .take_while(|(_, line)| is_blank(line))
+                    .count();
+                match lines.get(next) {
+                    Some((number, text)) if self.list_item(*number, text).is_some() => i = next,
+                    _ => break,
+                }
+            } else if text == "+" {
+                // A list continuation attaches the next block to the item
+                let block_start = i + 1;
+                let end = match lines.get(block_start).and_then(|(_, line)| delimiter(line)) {
+                    Some(delimiter) => {
+                        let opening = &lines[block_start].1;
+                        let closing = if delimiter == "```" { "```" } else { opening };
+                        lines[block_start + 1..]
+                            .iter()
+                            .position(|(_, line)| line == closing)
+                            .map_or(lines.len(), |end| block_start + end + 2)
+                    }
+                    None => {
+                        let mut end = block_start;
+                        while end < lines.len()
+                            && (end == block_start || !lines[end].1.starts_with('['))
+                            && !is_blank(&lines[end].1)
+                            && lines[end].1 != "+"
+                            && self.list_item(lines[end].0, &lines[end].1).is_none()
+                        {
+                            end += 1;
+                        }
+                        end
+                    }
+                };
+                if let Some(item) = items.last_mut() {
+                    item.attached.extend_from_slice(&lines[block_start..end]);
+                    item.attached.push((lines[end - 1].0, String::new()));
+                }
+                i = end;
+            } else if delimiter(text).is_some()
+                || section_title(text).is_some()
+                || text.starts_with('[')
+                || text.starts_with("//")
+            {
+                break;
+            } else {
+                match items.last_mut() {
+                    Some(item) => item.text.push((number, text.trim().to_string())),
+                    None => break,
+                }
+                i += 1;
+            }
+        }
+
+        let mut position = 0;
+        let html = self.render_list(&items, &mut position, &mut Vec::new());
+        (html, i)
+    }
+
+    /// Item starting at the line, if it starts one
+    fn list_item(&self, number: usize, text: &str) -> Option<ListItem> {
+        let trimmed = text.trim_start();
+        if let Some(captures) = LIST_ITEM.captures(trimmed) {
+            let mut marker = captures[1].to_string();
+            let mut start = None;
+            if marker.ends_with('.') && marker.len() > 1 && !marker.starts_with('.') {
+                let value = &marker[..marker.len() - 1];
+                start = value.parse().ok().or_else(|| {
+                    value
+                        .chars()
+                        .next()
+                        .map(|c| (c as u8 - b'a' + 1) as usize)
+                        .map(|start| start.max(1))
+                });
+                marker = if value.parse::<usize>().is_ok() {
+                    "1.".to_string()
+                } else {
+                    "a.".to_string()
+                };
+            } else if marker.starts_with('<') {
+                marker = "<>".to_string();
+            }
+
+            let mut text = captures[2].to_string();
+            let mut checked = None;
+            if !marker.starts_with(['.', '1', 'a', '<']) {
+                for (checkbox, value) in [("[x] ", true), ("[*] ", true), ("[ ] ", false)] {
+                    if let Some(rest) = text.strip_prefix(checkbox) {
+                        checked = Some(value);
+                        text = rest.to_string();
+                    }
+                }
+            }
+
+            return Some(ListItem {
+                marker,
+                start,
+                term: None,
+                checked,
+                text: vec![(number, text)],
+                attached: Vec::new(),
+            });
+        }
+
+        // Description lists don't allow indented terms, their definitions are often indented
+        if text.starts_with([' ', '\t']) || BLOCK_MACRO.is_match(text) {
+            return None;
+        }
+        let captures = DESCRIPTION.captures(text)?;
+        Some(ListItem {
+            marker: captures[2].to_string(),
+            start: None,
+            term: Some(captures[1].to_string()),
+            checked: None,
+            text: captures
+                .get(3)
+                .map(|text| vec![(number, text.as_str().to_string())])
+                .unwrap_or_default(),
+            attached: Vec::new(),
+        })
+    }
+
+    /// Renders the items with the marker of the item at the position as a list, and the items
+    /// with other markers after them nested in it
+    fn render_list(
+        &mut self,
+        items: &[ListItem],
+        position: &mut usize,
+        parents: &mut Vec<String>,
+    ) -> String {
+        let first = &items[*position];
+        let marker = first.marker.clone();
+        let (open, close) = match marker.chars().next() {
+            Some('.' | '<') => ("<ol>".to_string(), "</ol>"),
+            Some('1') => (
+                match first.start {
+                    Some(start) if start != 1 => format!("<ol start=\"{}\">", start),
+                    _ => "<ol>".to_string(),
+                },
+                "</ol>",
+            ),
+            Some('a') => (
+                match first.start {
+                    Some(start) if start != 1 => {
+                        format!("<ol type=\"a\" start=\"{}\">", start)
+                    }
+                    _ => "<ol type=\"a\">".to_string(),
+                },
+                "</ol>",
+            ),
+            Some(':' | ';') => ("<dl>".to_string(), "</dl>"),
+            _ => ("<ul>".to_string(), "</ul>"),
+        };
+
+        let mut html = vec![open];
+        while *position < items.len() {
+            let item = &items[*position];
+            if item.marker != marker {
+                break;
+            }
+            *position += 1;
+
+            let number = item.text.first().map_or(0, |(number, _)| *number);
+            let text: Vec<&str> = item.text.iter().map(|(_, line)| line.as_str()).collect();
+            let mut contents = self.inline(&text.join("\n"), number);
+            if let Some(checked) = item.checked {
+                contents = format!(
+                    "<input disabled=\"\" type=\"checkbox\"{}/>\n{}",
+                    if checked { " checked=\"\"" } else { "" },
+                    contents
+                );
+            }
+            if !item.attached.is_empty() {
+                let attached = item.attached.clone();
+                contents.push('\n');
+                contents.push_str(&self.render_blocks(&attached));
+            }
+
+            // Items with a new marker are nested in this item
+            parents.push(marker.clone());
+            while let Some(next) = items.get(*position) {
+                if parents.contains(&next.marker) {
+                    break;
+                }
+                contents.push('\n');
+                contents.push_str(&self.render_list(items, position, parents));
+            }
+            parents.pop();
+
+            match &item.term {
+                Some(term) => {
+                    html.push(format!("<dt>{}</dt>", self.inline(term, number)));
+                    html.push(format!("<dd>{}</dd>", contents));
+                }
+                None => html.push(format!("<li>{}</li>", contents)),
+            }
+        }
+        html.push(close.to_string());
+
+        html.join("\n")
+    }
+
+    /// Renders a table (``|===`` with cells starting with ``|``, or ``,===`` with comma
+    /// separated values)
+    fn table(
+        &mut self,
+        delimiter: &str,
+        content: &[Line],
+        attributes: &BlockAttributes,
+        number: usize,
+    ) -> String {
+        let csv = delimiter.starts_with(',')
+            || attributes.named.get("format").is_some_and(|f| f == "csv");
+        let mut columns = attributes.named.get("cols").map_or(0, |cols| {
+            cols.split(',')
+                .map(|column| {
+                    column
+                        .split_once('*')
+                        .and_then(|(count, _)| count.trim().parse().ok())
+                        .unwrap_or(1)
+                })
+                .sum()
+        });
+
+        // Cells with their style (``a`` for AsciiDoc contents) and lines
+        let mut cells: Vec<(Option<char>, Vec<Line>)> = Vec::new();
+        let mut first_row_cells = 0;
+        let mut first_row_line = None;
+        for (line, text) in content {
+            if csv {
+                if is_blank(text) {
+                    continue;
+                }
+                let values = super::rst::split_csv(text, ',');
+                if first_row_line.is_none() {
+                    first_row_line = Some(*line);
+                    first_row_cells = values.len();
+                }
+                cells.extend(values.into_iter().map(|value| (None, vec![(*line, value)])));
+                continue;
+            }
+
+            let segments = split_cells(text);
+            let mut style = None;
+            for (k, segment) in segments.iter().enumerate() {
+                let mut segment = segment.as_str();
+                let mut next_style = None;
+                if k + 1 < segments.len() {
+                    if let Some((before, spec_style)) = cell_spec(segment) {
+                        segment = before;
+                        next_style = spec_style;
+                    }
+                }
+
+                if k == 0 {
+                    if let Some((_, lines)) = cells.last_mut() {
+                        if segments.len() == 1 || !segment.trim().is_empty() {
+                            lines.push((*line, segment.to_string()));
+                        }
+                    }
+                } else {
+                    if first_row_line.is_none() {
+                        first_row_line = Some(*line);
+                    }
+                    if first_row_line == Some(*line) {
+                        first_row_cells += 1;
+                    }
+                    cells.push((style, vec![(*line, segment.to_string())]));
+                }
+                style = next_style;
+            }
+        }
+        if columns == 0 {
+            columns = first_row_cells.max(1);
+        }
+
+        // Without options, the first row is a header, if it's on a line followed by a blank line
+        let implicit_header = first_row_line.is_some_and(|line| {
+            first_row_cells == columns
+                && content
+                    .iter()
+                    .position(|(number, _)| *number == line)
+                    .and_then(|i| content.get(i + 1))
+                    .is_some_and(|(_, next)| is_blank(next))
+        });
+        let header = attributes.options.iter().any(|option| option == "header")
+            || (implicit_header && !attributes.options.iter().any(|option| option == "noheader"));
+
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        for chunk in cells.chunks(columns) {
+            let row = chunk
+                .iter()
+                .map(|(style, lines)| {
+                    if *style == Some('a') {
+                        let lines: Vec<Line> = lines
+                            .iter()
+                            .map(|(number, line)| (*number, line.trim().to_string()))
+                            .collect();
+                        self.render_blocks(&lines)
+                    } else {
+                        let text: Vec<&str> = lines
+                            .iter()
+                            .map(|(_, line)| line.trim())
+                            .filter(|line| !line.is_empty())
+                            .collect();
+                        let line = lines.first().map_or(number, |(number, _)| *number);
+                        self.inline(&text.join("\n"), line)
+                    }
+                })
+                .collect();
+            rows.push(row);
+        }
+
+        let header_rows = if header && !rows.is_empty() { 1 } else { 0 };
+        let caption = attributes
+            .title
+            .clone()
+            .map(|title| self.inline(&title, number));
+        table(caption, &rows[..header_rows], &rows[header_rows..])
+    }
+
+    /// Renders the inline markup of the text. Like Asciidoctor, passthroughs and literals are
+    /// replaced first, then attributes, macros, special characters, quotes and replacements.
+    fn inline(&mut self, text: &str, line: usize) -> String {
+        let stash = &mut self.stash;
+        let patterns = &*INLINE;
+
+        let text = patterns
+            .hard_break
+            .replace_all(text, |_: &Captures| stash_html(stash, "<br />".to_string()));
+        let text = patterns.escaped.replace_all(&text, |captures: &Captures| {
+            stash_html(stash, escape(&captures[1]))
+        });
+        let text = patterns
+            .passthrough
+            .replace_all(&text, |captures: &Captures| {
+                let html = match captures.get(1) {
+                    Some(raw) => raw.as_str().to_string(),
+                    None => captures[2].to_string(),
+                };
+                stash_html(stash, html)
+            });
+        let mut math_errors = Vec::new();
+        let text = patterns.stem.replace_all(&text, |captures: &Captures| {
+            let tex = captures[2].replace("\\]", "]");
+            let html = match &captures[1] {
+                "asciimath" => {
+                    math_errors.push("AsciiMath isn't supported".to_string());
+                    format!("<code>{}</code>", escape(&tex))
+                }
+                _ => {
+                    let (mathml, errors) = math::to_mathml(&tex, false);
+                    math_errors.extend(errors);
+                    mathml
+                }
+            };
+            stash_html(stash, html)
+        });
+        let text = patterns
+            .monospace
+            .replace_all(&text, |captures: &Captures| {
+                let code = captures.get(1).or_else(|| captures.get(2)).unwrap();
+                stash_html(stash, format!("<code>{}</code>", escape(code.as_str())))
+            });
+        let text = replace_constrained(&patterns.constrained_passthrough, &text, |inner| {
+            stash_html(stash, escape(inner))
+        });
+        let text = replace_constrained(&patterns.monospace_constrained, &text, |inner| {
+            stash_html(stash, format!("<code>{}</code>", escape(inner)))
+        });
+        for error in math_errors {
+            self.warn(line, error);
+        }
+
+        let text = self.substitute_attributes(&text, Some(line));
+        let text = self.macros(&text, line);
+        let mut text = escape_text(&text);
+
+        for (pattern, tag, constrained) in &INLINE.quotes {
+            let render = |role: Option<&str>, inner: &str| match (role, *tag) {
+                (Some(role), "mark") => {
+                    format!(
+                        "<span class=\"{}\">{}</span>",
+                        role.replace('.', " "),
+                        inner
+                    )
+                }
+                (Some(role), _) => format!(
+                    "<{0} class=\"{1}\">{2}</{0}>",
+                    tag,
+                    role.replace('.', " "),
+                    inner
+                ),
+                (None, _) => format!("<{0}>{1}</{0}>", tag, inner),
+            };
+            text = if *constrained {
+                // The characters around constrained quotes are part of the match, so quotes
+                // directly after each other need another pass
+                let mut replaced = text.clone();
+                for _ in 0..2 {
+                    replaced = pattern
+                        .replace_all(&replaced, |captures: &Captures| {
+                            format!(
+                                "{}{}{}",
+                                &captures[1],
+                                render(captures.get(2).map(|role| role.as_str()), &captures[3]),
+                                &captures[4]
+                            )
+                        })
+                        .into_owned();
+                }
+                replaced
+            } else {
+                pattern
+                    .replace_all(&text, |captures: &Captures| {
+                        render(captures.get(1).map(|role| role.as_str()), &captures[2])
+                    })
+                    .into_owned()
+            };
+        }
+        for (pattern, replacement) in &INLINE.replacements {
+            text = pattern.replace_all(&text, *replacement).into_owned();
+        }
+
+        // Stashed HTML may contain stashed HTML itself, which was stashed before it
+        while INLINE.stashed.is_match(&text) {
+            text = INLINE
+                .stashed
+                .replace_all(&text, |captures: &Captures| {
+                    captures[1]
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|i| self.stash.get(i).cloned())
+                        .unwrap_or_default()
+                })
+                .into_owned();
+        }
+
+        text
+    }
+
+    /// Replaces the macros (footnotes, anchors, cross references, images, keys and links) of
+    /// the text with stashed HTML
+    fn macros(&mut self, text: &str, line: usize) -> String {
+        let footnote = &INLINE.footnote;
+        let text = self.replace_macro(text, footnote, |document, groups| {
+            let id = groups[1].clone().filter(|id| !id.is_empty());
+            let text = groups[2].clone().unwrap_or_default();
+            let known = id
+                .as_ref()
+                .and_then(|id| document.footnote_ids.get(id))
+                .copied();
+            let number = match known {
+                Some(number) if text.is_empty() => number,
+                _ => {
+                    let html = document.inline(&text.replace("\\]", "]"), line);
+                    document.footnotes.push(html);
+                    let number = document.footnotes.len();
+                    if let Some(id) = id {
+                        document.footnote_ids.insert(id, number);
+                    }
+                    number
+                }
+            };
+            footnote_reference(&number.to_string(), &number.to_string())
+        });
+
+        let anchor = &INLINE.anchor;
+        let text = self.replace_macro(&text, anchor, |_, groups| {
+            let id = groups[1]
+                .clone()
+                .or_else(|| groups[2].clone())
+                .unwrap_or_default();
+            format!("<a id=\"{}\"></a>", escape(&id))
+        });
+
+        let cross_reference = &INLINE.cross_reference;
+        let text = self.replace_macro(&text, cross_reference, |document, groups| {
+            let target = groups[1]
+                .clone()
+                .or_else(|| groups[3].clone())
+                .unwrap_or_default();
+            let text = groups[2]
+                .clone()
+                .or_else(|| groups[4].clone())
+                .filter(|text| !text.trim().is_empty());
+            document.cross_reference(target.trim(), text.as_deref(), line)
+        });
+
+        let image = &INLINE.image;
+        let text = self.replace_macro(&text, image, |document, groups| {
+            let mut attributes = BlockAttributes::default();
+            attributes.parse(groups[2].as_deref().unwrap_or_default(), false);
+            document.image(groups[1].as_deref().unwrap_or_default(), &attributes)
+        });
+
+        let keyboard = &INLINE.keyboard;
+        let text = self.replace_macro(&text, keyboard, |_, groups| {
+            let keys = groups[2].as_deref().unwrap_or_default();
+            match groups[1].as_deref() {
+                Some("btn") => format!("<b class=\"button\">{}</b>", escape(keys)),
+                _ => keys
+                    .split('+')
+                    .map(|key| format!("<kbd>{}</kbd>", escape(key.trim())))
+                    .collect::<Vec<String>>()
+                    .join("+"),
+            }
+        });
+
+        let link = &INLINE.link;
+        let text = self.replace_macro(&text, link, |document, groups| {
+            let scheme = groups[1].as_deref().unwrap_or_default();
+            let target = groups[2].as_deref().unwrap_or_default();
+            let href = match scheme {
+                "mailto:" => format!("mailto:{}", target),
+                _ => target.to_string(),
+            };
+            document.link(&href, groups[3].as_deref(), line)
+        });
+
+        let url = &INLINE.url;
+        self.replace_macro(&text, url, |document, groups| {
+            let url = groups[1]
+                .clone()
+                .or_else(|| groups[2].clone())
+                .unwrap_or_default();
+            if groups[3].is_some() {
+                return document.link(&url, groups[3].as_deref(), line);
+            }
+
+            // Punctuation after bare URLs isn't part of them
+            let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', ')']);
+            format!(
+                "{}{}",
+                document.link(trimmed, None, line),
+                escape(&url[trimmed.len()..])
+            )
+        })
+    }
+
+    /// Replaces the matches of the pattern with the stashed HTML returned by ``render``, which
+    /// gets the groups after the first one. The first group of the pattern is text before the
+    /// macro, which is kept.
+    fn replace_macro(
+        &mut self,
+        text: &str,
+        pattern: &Regex,
+        render: impl Fn(&mut Self, &[Option<String>]) -> String,
+    ) -> String {
+        let mut replaced = String::new();
+        let mut last = 0;
+        for captures in pattern.captures_iter(text) {
+            let found = captures.get(0).unwrap();
+            let groups: Vec<Option<String>> = captures
+                .iter()
+                .skip(1)
+                .map(|group| group.map(|group| group.as_str().to_string()))
+                .collect();
+
+            replaced.push_str(&text[last..found.start()]);
+            replaced.push_str(groups[0].as_deref().unwrap_or_default());
+            let html = render(self, &groups);
+            replaced.push_str(&stash_html(&mut self.stash, html));
+            last = found.end();
+        }
+        replaced.push_str(&text[last..]);
+
+        replaced
+    }
+
+    fn link(&mut self, href: &str, text: Option<&str>, line: usize) -> String {
+        let mut text = text.unwrap_or_default().replace("\\]", "]");
+        let mut attributes = String::new();
+        // A caret at the end of the text opens the link in a new window
+        if text.ends_with('^') {
+            text.pop();
+            attributes.push_str(" target=\"_blank\" rel=\"noopener\"");
+        }
+        let text = match text.trim() {
+            "" => escape(href.trim_start_matches("mailto:")),
+            text => self.inline(text.trim_matches('"'), line),
+        };
+
+        format!("<a href=\"{}\"{}>{}</a>", escape(href), attributes, text)
+    }
+
+    /// Link to an anchor of this document, or to another document (``other.adoc#anchor``)
+    fn cross_reference(&mut self, target: &str, text: Option<&str>, line: usize) -> String {
+        let (href, default_text) = match target.split_once('#') {
+            Some((document, anchor)) if !document.is_empty() => {
+                let document = match document.ends_with(".adoc") {
+                    true => document.to_string(),
+                    false => format!("{}.adoc", document),
+                };
+                let href = match anchor.is_empty() {
+                    true => document.clone(),
+                    false => format!("{}#{}", document, anchor),
+                };
+                (href, document)
+            }
+            _ if target.ends_with(".adoc") => (target.to_string(), target.to_string()),
+            _ => {
+                let id = target.trim_start_matches('#');
+                if let Some(reference_text) = self.anchors.get(id) {
+                    (
+                        format!("#{}", id),
+                        reference_text
+                            .clone()
+                            .unwrap_or_else(|| format!("[{}]", id)),
+                    )
+                } else if let Some(id) = self.titles.get(id) {
+                    (format!("#{}", id), target.to_string())
+                } else {
+                    self.warn(line, format!("Unknown cross reference \"{}\"", id));
+                    (format!("#{}", id), format!("[{}]", id))
+                }
+            }
+        };
+
+        let text = match text {
+            Some(text) => self.inline(text, line),
+            None => escape(&default_text),
+        };
+        format!("<a href=\"{}\">{}</a>", escape(&href), text)
+    }
+}
+
+impl InlinePatterns {
+    fn new() -> Self {
+        // Constrained quotes need characters around them, which aren't part of words
+        let constrained = |quote: &str| {
+            Regex::new(&format!(
+                r"(?s)(^|[^\w;:}}{0}])(?:\[\.([\w.-]+)\])?{0}(\S|\S.*?\S){0}($|[^\w{0}])",
+                regex::escape(quote)
+            ))
+            .unwrap()
+        };
+        let unconstrained = |quote: &str| {
+            Regex::new(&format!(
+                r"(?s)(?:\[\.([\w.-]+)\])?{0}{0}(.+?){0}{0}",
+                regex::escape(quote)
+            ))
+            .unwrap()
+        };
+
+        InlinePatterns {
+            hard_break: Regex::new(r"(?m) \+$").unwrap(),
+            escaped: Regex::new(r"\\([*_#`^~+{\[<]|(?:https?|ftp|link|mailto|image|footnote|kbd|btn|xref|stem|latexmath|pass):)").unwrap(),
+            passthrough: Regex::new(r"(?s)\+\+\+(.+?)\+\+\+|pass:\[(.*?)\]").unwrap(),
+            stem: Regex::new(r"(stem|latexmath|asciimath):\[((?:\\\]|[^\]])*)\]").unwrap(),
+            monospace: Regex::new(r"`\+(.+?)\+`|``(.+?)``").unwrap(),
+            monospace_constrained: Regex::new(r"(^|[^\w`])`(\S|\S.*?\S)`($|[^\w`])").unwrap(),
+            constrained_passthrough: Regex::new(r"(^|[^\w+])\+(\S|\S.*?\S)\+($|[^\w+])").unwrap(),
+            attribute: Regex::new(r"\{([A-Za-z0-9_][\w-]*)\}").unwrap(),
+            footnote: Regex::new(r"()footnote:([\w-]*)\[((?:\\\]|[^\]])*)\]").unwrap(),
+            anchor: Regex::new(r"()(?:\[\[([\w:.-]+)(?:,[^\]]*)?\]\]|anchor:([\w:.-]+)\[[^\]]*\])")
+                .unwrap(),
+            cross_reference: Regex::new(r"()(?:<<([^<>,]+?)(?:,\s*([^<>]+?))?>>|xref:([^\s\[]+)\[([^\]]*)\])")
+                .unwrap(),
+            image: Regex::new(r"()image:([^\s\[:][^\s\[]*)\[([^\]]*)\]").unwrap(),
+            keyboard: Regex::new(r"()(kbd|btn):\[([^\]]+)\]").unwrap(),
+            link: Regex::new(r"()(link:|mailto:)([^\s\[]+)\[((?:\\\]|[^\]])*)\]").unwrap(),
+            url: Regex::new(
+                r"(^|[\s(\[>{;,])(?:<((?:https?|ftp|irc)://[^\s>]+)>|((?:https?|ftp|irc)://[^\s\[\]<>]+)(?:\[((?:\\\]|[^\]])*)\])?)",
+            )
+            .unwrap(),
+            quotes: vec![
+                (unconstrained("*"), "strong", false),
+                (constrained("*"), "strong", true),
+                (unconstrained("_"), "em", false),
+                (constrained("_"), "em", true),
+                (unconstrained("#"), "mark", false),
+                (constrained("#"), "mark", true),
+                (
+                    Regex::new(r"(?:\[\.([\w.-]+)\])?\^(\S+?)\^").unwrap(),
+                    "sup",
+                    false,
+                ),
+                (Regex::new(r"(?:\[\.([\w.-]+)\])?~(\S+?)~").unwrap(), "sub", false),
+            ],
+            replacements: REPLACEMENTS
+                .iter()
+                .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), *replacement))
+                .collect(),
+            stashed: Regex::new(&format!("{}(\\d+){}", STASH_START, STASH_END)).unwrap(),
+        }
+    }
+}
+
+/// Item of a list with the lines of its text and of the blocks attached to it
+struct ListItem {
+    /// Marker normalized to the kind of item (``*``, ``**``, ``-``, ``.``, ``1.``, ``a.``, ``<>``
+    /// or the separator of description list terms)
+    marker: String,
+    /// Number of the first item of numbered lists
+    start: Option<usize>,
+    /// Term of description list items
+    term: Option<String>,
+    /// Whether the checkbox of checklist items is checked
+    checked: Option<bool>,
+    text: Vec<Line>,
+    attached: Vec<Line>,
+}
+
+/// Level and text of a section title (``== Title``, or ``## Title`` like in markdown)
+fn section_title(text: &str) -> Option<(usize, String)> {
+    let marker = text.chars().next().filter(|c| *c == '=' || *c == '#')?;
+    let level = text.chars().take_while(|c| *c == marker).count();
+    let rest = &text[level..];
+    if level > 6 || !rest.starts_with(' ') {
+        return None;
+    }
+
+    let title = rest.trim();
+    let closing = marker.to_string().repeat(level);
+    let title = title
+        .strip_suffix(&closing)
+        .map(str::trim_end)
+        .filter(|title| title.ends_with(|c: char| c != marker) && rest.trim() != closing)
+        .unwrap_or(title);
+
+    (!title.is_empty()).then(|| (level, title.to_string()))
+}
+
+/// Delimiter of a block starting at the line (fenced code blocks start with a language)
+fn delimiter(text: &str) -> Option<&str> {
+    if text == "--" {
+        return Some(text);
+    }
+    if text.starts_with("```") {
+        return Some("```");
+    }
+
+    let first = text.chars().next()?;
+    match first {
+        '|' | ',' if text.len() >= 4 && text[1..].chars().all(|c| c == '=') => Some(text),
+        '-' | '.' | '=' | '*' | '_' | '+' | '/'
+            if text.len() >= 4 && text.chars().all(|c| c == first) =>
+        {
+            Some(text)
+        }
+        _ => None,
+    }
+}
+
+/// Line after the paragraph starting at the line
+fn paragraph_end(lines: &[Line], start: usize) -> usize {
+    start
+        + 1
+        + lines[start + 1..]
+            .iter()
+            .take_while(|(_, line)| !is_blank(line) && delimiter(line).is_none())
+            .count()
+}
+
+/// Anchor generated from a section title like Asciidoctor does (``_section_title``)
+fn generated_id(
+    title: &str,
+    attributes: &HashMap<String, String>,
+    ids: &HashSet<String>,
+) -> String {
+    let prefix = attributes.get("idprefix").map_or("_", String::as_str);
+    let separator = attributes.get("idseparator").map_or("_", String::as_str);
+    let words: Vec<String> = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    let base = format!("{}{}", prefix, words.join(separator));
+    let mut id = base.clone();
+    let mut count = 2;
+    while ids.contains(&id) {
+        id = format!("{}{}{}", base, separator, count);
+        count += 1;
+    }
+
+    id
+}
+
+fn admonition_alert(label: &str, contents: &str) -> String {
+    let title = label[..1].to_string() + &label[1..].to_lowercase();
+    alert(&label.to_lowercase(), &title, contents)
+}
+
+/// Adds the anchor to the first element of the HTML
+fn with_id(html: &str, id: &str) -> String {
+    if !html.starts_with('<') {
+        return html.to_string();
+    }
+    let name_end = html[1..]
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .map_or(html.len(), |end| end + 1);
+
+    format!(
+        "{} id=\"{}\"{}",
+        &html[..name_end],
+        escape(id),
+        &html[name_end..]
+    )
+}
+
+/// Splits the attributes of a list at the commas, which aren't quoted
+fn split_attributes(list: &str) -> Vec<String> {
+    if list.trim().is_empty() {
+        return Vec::new();
+    }
+
+    let mut attributes = vec![String::new()];
+    let mut quote = None;
+    for c in list.chars() {
+        match (c, quote) {
+            ('"' | '\'', None) if attributes.last().unwrap().trim().is_empty() => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (',', None) => attributes.push(String::new()),
+            _ => {}
+        }
+        attributes.last_mut().unwrap().push(c);
+    }
+
+    attributes
+        .into_iter()
+        .map(|attribute| attribute.trim().trim_start_matches(',').trim().to_string())
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return value[1..value.len() - 1].to_string();
+        }
+    }
+
+    value.to_string()
+}
+
+/// Splits a line of a table at the unescaped ``|``, the first part is the text before the
+/// first cell of the line
+fn split_cells(text: &str) -> Vec<String> {
+    let mut cells = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => {
+                cells.last_mut().unwrap().push('|');
+                chars.next();
+            }
+            '|' => cells.push(String::new()),
+            c => cells.last_mut().unwrap().push(c),
+        }
+    }
+
+    cells
+}
+
+/// Splits the specifier of the next cell (span, alignment and style, e.g. ``2+^a``) off the
+/// end of the text before its ``|``
+fn cell_spec(text: &str) -> Option<(&str, Option<char>)> {
+    let token = text.rsplit(char::is_whitespace).next()?;
+    if token.is_empty() {
+        return None;
+    }
+    let captures = CELL_SPEC.captures(token)?;
+    let style = captures
+        .get(1)
+        .and_then(|style| style.as_str().chars().next());
+
+    Some((&text[..text.len() - token.len()], style))
+}
+
+/// Escapes the special characters, keeping quotes for the inline markup
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Stores the HTML and returns the placeholder replaced with it at the end
+fn stash_html(stash: &mut Vec<String>, html: String) -> String {
+    stash.push(html);
+    format!("{}{}{}", STASH_START, stash.len() - 1, STASH_END)
+}
+
+/// Replaces constrained markup (with the characters around it as the first and last group of
+/// the pattern) with the result of ``render`` for the text in it
+fn replace_constrained(
+    pattern: &Regex,
+    text: &str,
+    mut render: impl FnMut(&str) -> String,
+) -> String {
+    let mut text = text.to_string();
+    // The characters around the markup are part of the match, so markup directly after other
+    // markup needs another pass
+    for _ in 0..2 {
+        text = pattern
+            .replace_all(&text, |captures: &Captures| {
+                format!("{}{}{}", &captures[1], render(&captures[2]), &captures[3])
+            })
+            .into_owned();
+    }
+
+    text
+}
+
+fn dedent(lines: &[&str]) -> String {
+    let indentation = lines
+        .iter()
+        .filter(|line| !is_blank(line))
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or_default();
+    let lines: Vec<&str> = lines
+        .iter()
+        .map(|line| line.get(indentation..).unwrap_or_default())
+        .collect();
+
+    lines.join("\n")
+}
+
+fn is_blank(text: &str) -> bool {
+    text.trim().is_empty()
+}
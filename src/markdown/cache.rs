@@ -16,15 +16,16 @@
  */
 use ahash::RandomState;
 
-use super::MarkdownParser;
+use super::{MarkdownParser, Rendered};
 
+#[allow(dead_code)]
 pub struct CacheMarkdown {
     markdown_parser: Box<dyn MarkdownParser>,
-    cache_map: std::collections::HashMap<String, String, RandomState>,
+    cache_map: std::collections::HashMap<String, Rendered, RandomState>,
 }
 
 impl MarkdownParser for CacheMarkdown {
-    fn parse_to_html(&mut self, markdown: &str) -> String {
+    fn parse_to_html(&mut self, markdown: &str) -> Rendered {
         let result = self.markdown_parser.parse_to_html(markdown);
         self.cache_map.insert(markdown.to_string(), result.clone());
 
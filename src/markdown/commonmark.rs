@@ -14,28 +14,293 @@
  *
  *  You should have received a copy of the GNU General Public License
  */
-use super::MarkdownParser;
+use super::{math, MarkdownParser, Rendered};
 
-use pulldown_cmark::{html, Options, Parser};
+use pulldown_cmark::{escape::escape_html, html, CowStr, Event, Options, Parser, Tag};
+use regex::Regex;
+use serde::Deserialize;
 
-#[derive(Default)]
-pub struct CommonMarkParser {}
+/// Optional syntax of the CommonMark parser
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommonMarkExtension {
+    Tables,
+    Footnotes,
+    Strikethrough,
+    Tasklists,
+    SmartPunctuation,
+    /// ``# Heading {#id .class}``
+    HeadingAttributes,
+    /// Links from URLs in the text (without angle brackets)
+    Autolinks,
+    /// GitHub alerts (block quotes starting with ``[!NOTE]``, ``[!WARNING]``, ...)
+    Alerts,
+    /// TeX math in ``$...$`` and ``$$...$$`` rendered as MathML
+    Math,
+}
+
+/// Sets of extensions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommonMarkPreset {
+    /// Tables, footnotes and strikethrough
+    #[default]
+    Default,
+    /// Plain CommonMark without extensions
+    Strict,
+    /// GitHub-Flavored Markdown
+    Gfm,
+}
+
+impl CommonMarkPreset {
+    pub fn extensions(&self) -> Vec<CommonMarkExtension> {
+        use CommonMarkExtension::*;
+
+        match self {
+            CommonMarkPreset::Default => vec![Tables, Footnotes, Strikethrough],
+            CommonMarkPreset::Strict => vec![],
+            CommonMarkPreset::Gfm => vec![
+                Tables,
+                Footnotes,
+                Strikethrough,
+                Tasklists,
+                Autolinks,
+                Alerts,
+                Math,
+            ],
+        }
+    }
+}
+
+/// Types of GitHub alerts
+static ALERTS: &[&str] = &["NOTE", "TIP", "IMPORTANT", "WARNING", "CAUTION"];
+
+pub struct CommonMarkParser {
+    extensions: Vec<CommonMarkExtension>,
+}
+
+impl Default for CommonMarkParser {
+    fn default() -> Self {
+        Self::new(CommonMarkPreset::default().extensions())
+    }
+}
 
 impl CommonMarkParser {
-    fn create_options() -> Options {
-        // Enable some default quality of live improvements
-        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_FOOTNOTES | Options::ENABLE_TABLES
+    pub fn new(extensions: Vec<CommonMarkExtension>) -> Self {
+        Self { extensions }
+    }
+
+    fn has(&self, extension: CommonMarkExtension) -> bool {
+        self.extensions.contains(&extension)
     }
+
+    fn create_options(&self) -> Options {
+        let mut options = Options::empty();
+        for extension in self.extensions.iter() {
+            options |= match extension {
+                CommonMarkExtension::Tables => Options::ENABLE_TABLES,
+                CommonMarkExtension::Footnotes => Options::ENABLE_FOOTNOTES,
+                CommonMarkExtension::Strikethrough => Options::ENABLE_STRIKETHROUGH,
+                CommonMarkExtension::Tasklists => Options::ENABLE_TASKLISTS,
+                CommonMarkExtension::SmartPunctuation => Options::ENABLE_SMART_PUNCTUATION,
+                CommonMarkExtension::HeadingAttributes => Options::ENABLE_HEADING_ATTRIBUTES,
+                CommonMarkExtension::Autolinks
+                | CommonMarkExtension::Alerts
+                | CommonMarkExtension::Math => Options::empty(),
+            };
+        }
+
+        options
+    }
+}
+
+/// Merges consecutive text events, the parser splits text at characters, which might have
+/// started other syntax
+fn merge_text<'a>(events: impl Iterator<Item = Event<'a>>) -> Vec<Event<'a>> {
+    let mut merged: Vec<Event<'a>> = Vec::new();
+    for event in events {
+        match (merged.last_mut(), event) {
+            (Some(Event::Text(previous)), Event::Text(text)) => {
+                *previous = CowStr::from(format!("{}{}", previous, text));
+            }
+            (_, event) => merged.push(event),
+        }
+    }
+
+    merged
+}
+
+/// Replaces URLs in text (outside of links and code) with links
+fn autolinks(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let url = Regex::new(r#"\b(?:https?://|www\.)[^\s<]*[^\s<.,:;"')\]!?*_~]"#).unwrap();
+
+    let mut result = Vec::with_capacity(events.len());
+    let mut in_link = 0usize;
+    let mut in_code = 0usize;
+    for event in events {
+        match event {
+            Event::Start(Tag::Link(..)) | Event::Start(Tag::Image(..)) => in_link += 1,
+            Event::End(Tag::Link(..)) | Event::End(Tag::Image(..)) => in_link -= 1,
+            Event::Start(Tag::CodeBlock(_)) => in_code += 1,
+            Event::End(Tag::CodeBlock(_)) => in_code -= 1,
+            Event::Text(ref text) if in_link == 0 && in_code == 0 && url.is_match(text) => {
+                let mut html = String::new();
+                let mut last = 0;
+                for found in url.find_iter(text) {
+                    let href = if found.as_str().starts_with("www.") {
+                        format!("http://{}", found.as_str())
+                    } else {
+                        found.as_str().to_string()
+                    };
+
+                    escape_html(&mut html, &text[last..found.start()]).unwrap();
+                    html.push_str("<a href=\"");
+                    escape_html(&mut html, &href).unwrap();
+                    html.push_str("\">");
+                    escape_html(&mut html, found.as_str()).unwrap();
+                    html.push_str("</a>");
+                    last = found.end();
+                }
+                escape_html(&mut html, &text[last..]).unwrap();
+
+                result.push(Event::Html(html.into()));
+                continue;
+            }
+            _ => {}
+        }
+
+        result.push(event);
+    }
+
+    result
+}
+
+/// Replaces block quotes starting with ``[!NOTE]`` (or another alert type) with alerts
+fn alerts(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut result = Vec::with_capacity(events.len());
+    // Whether the open block quotes are alerts
+    let mut quotes: Vec<bool> = Vec::new();
+
+    let mut i = 0;
+    while i < events.len() {
+        match &events[i] {
+            Event::Start(Tag::BlockQuote) => {
+                let alert = match (events.get(i + 1), events.get(i + 2)) {
+                    (Some(Event::Start(Tag::Paragraph)), Some(Event::Text(text))) => ALERTS
+                        .iter()
+                        .find(|alert| text.trim_end() == format!("[!{}]", alert)),
+                    _ => None,
+                };
+
+                quotes.push(alert.is_some());
+                if let Some(alert) = alert {
+                    let name = alert.to_lowercase();
+                    let mut title = name.clone();
+                    title[..1].make_ascii_uppercase();
+                    result.push(Event::Html(
+                        format!(
+                            "<div class=\"markdown-alert markdown-alert-{}\">\n<p class=\"markdown-alert-title\">{}</p>\n",
+                            name, title
+                        )
+                        .into(),
+                    ));
+
+                    // Drop the marker and the paragraph, if it contained only the marker
+                    match events.get(i + 3) {
+                        Some(Event::End(Tag::Paragraph)) => i += 4,
+                        Some(Event::SoftBreak) | Some(Event::HardBreak) => {
+                            result.push(Event::Start(Tag::Paragraph));
+                            i += 4;
+                        }
+                        _ => {
+                            result.push(Event::Start(Tag::Paragraph));
+                            i += 3;
+                        }
+                    }
+                    continue;
+                }
+            }
+            Event::End(Tag::BlockQuote) => {
+                let is_alert = quotes.pop() == Some(true);
+                result.push(if is_alert {
+                    Event::Html("</div>\n".into())
+                } else {
+                    events[i].clone()
+                });
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        result.push(events[i].clone());
+        i += 1;
+    }
+
+    result
 }
 
 impl MarkdownParser for CommonMarkParser {
-    fn parse_to_html(&mut self, markdown: &str) -> String {
-        let parser = Parser::new_ext(markdown, Self::create_options());
+    fn parse_to_html(&mut self, markdown: &str) -> Rendered {
+        // Math is replaced before parsing, as backslashes in it aren't escapes
+        let (markdown, math) = if self.has(CommonMarkExtension::Math) {
+            math::extract_math(markdown)
+        } else {
+            (markdown.to_string(), Vec::new())
+        };
+        let parser = Parser::new_ext(&markdown, self.create_options());
+
+        let mut events = merge_text(parser);
+        if self.has(CommonMarkExtension::Autolinks) {
+            events = autolinks(events);
+        }
+        if self.has(CommonMarkExtension::Alerts) {
+            events = alerts(events);
+        }
 
         // Write to String buffer.
         let mut html_output = String::new();
-        html::push_html(&mut html_output, parser);
+        html::push_html(&mut html_output, events.into_iter());
+
+        math::insert_math(&html_output, &math)
+    }
+
+    fn block_lines(&mut self, markdown: &str) -> Vec<usize> {
+        // The placeholders of math keep the lines of the markdown
+        let markdown = if self.has(CommonMarkExtension::Math) {
+            math::extract_math(markdown).0
+        } else {
+            markdown.to_string()
+        };
+        let markdown = markdown.as_str();
+        let parser = Parser::new_ext(markdown, self.create_options());
+        let line_of = |offset: usize| markdown[..offset].matches('\n').count() + 1;
+
+        let mut lines = Vec::new();
+        let mut depth = 0usize;
+        // End of the last top-level HTML event. HTML blocks are split into an event per line,
+        // which directly follow each other.
+        let mut html_end = None;
+        for (event, range) in parser.into_offset_iter() {
+            match event {
+                Event::Start(_) => {
+                    if depth == 0 {
+                        lines.push(line_of(range.start));
+                    }
+                    depth += 1;
+                }
+                Event::End(_) => depth = depth.saturating_sub(1),
+                Event::Rule if depth == 0 => lines.push(line_of(range.start)),
+                Event::Html(_) if depth == 0 => {
+                    if html_end != Some(range.start) {
+                        lines.push(line_of(range.start));
+                    }
+                    html_end = Some(range.end);
+                }
+                _ => {}
+            }
+        }
 
-        html_output
+        lines
     }
 }
@@ -0,0 +1,190 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+//! Renders code blocks of diagram languages (Mermaid, Graphviz, PlantUML) to inline SVG with
+//! locally installed tools
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use regex::{Captures, Regex};
+
+use super::{html::escape, Diagnostic, Rendered, Severity};
+
+/// Commands rendering diagrams, they read the diagram from stdin and write SVG to stdout
+pub static DEFAULT_DIAGRAM_COMMANDS: &[(&str, &[&str])] = &[
+    (
+        "mermaid",
+        &[
+            "mmdc",
+            "--input",
+            "-",
+            "--output",
+            "-",
+            "--outputFormat",
+            "svg",
+            "--quiet",
+        ],
+    ),
+    ("dot", &["dot", "-Tsvg"]),
+    ("graphviz", &["dot", "-Tsvg"]),
+    ("plantuml", &["plantuml", "-tsvg", "-pipe"]),
+];
+
+/// Rendering a diagram is aborted after this time
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// The cache is cleared, when it contains more diagrams
+const CACHE_SIZE: usize = 256;
+
+/// Rendered diagrams by the hash of their language, command and source. Failures aren't cached,
+/// so installing a missing tool doesn't require a restart.
+static CACHE: Mutex<BTreeMap<u64, String>> = Mutex::new(BTreeMap::new());
+
+/// Replaces code blocks of the languages in ``commands`` with the rendered diagram or an error
+/// message. Both code blocks of pulldown-cmark (``<pre><code class="language-dot">``) and
+/// pandoc (``<pre class="dot"><code>``) are found. Failures are returned as errors at the line
+/// of the code block in ``markdown``.
+pub fn render_diagrams(
+    html: &str,
+    markdown: &str,
+    commands: &BTreeMap<String, Vec<String>>,
+) -> Rendered {
+    if commands.is_empty() {
+        return html.to_string().into();
+    }
+
+    let code_block = Regex::new(
+        r#"(?s)<pre(?: class="([^"]*)")?><code(?: class="language-([^"]*)")?>(.*?)</code></pre>"#,
+    )
+    .unwrap();
+
+    let mut diagnostics = Vec::new();
+    let fences: BTreeMap<&str, Vec<usize>> = commands
+        .keys()
+        .map(|language| (language.as_str(), fence_lines(markdown, language)))
+        .collect();
+    // Number of code blocks found per language, to determine their lines
+    let mut found: BTreeMap<String, usize> = BTreeMap::new();
+    let html = code_block
+        .replace_all(html, |captures: &Captures| {
+            let language = captures
+                .get(2)
+                .or_else(|| captures.get(1))
+                .map(|language| language.as_str())
+                .unwrap_or_default();
+
+            let command = match commands.get(language) {
+                Some(command) => command,
+                None => return captures[0].to_string(),
+            };
+            let index = found.entry(language.to_string()).or_default();
+            let line = fences
+                .get(language)
+                .and_then(|lines| lines.get(*index))
+                .copied();
+            *index += 1;
+
+            let source = super::decode_entities(&captures[3]);
+            match render_cached(language, command, &source) {
+                Ok(diagram) => diagram,
+                Err(err) => {
+                    diagnostics.push(Diagnostic::new(
+                        Severity::Error,
+                        format!("Rendering the {} diagram failed: {}", language, err),
+                        line,
+                    ));
+                    format!(
+                        "<div class=\"diagram-error\"><p>Rendering the {} diagram failed: {}</p><pre><code>{}</code></pre></div>",
+                        escape(language),
+                        escape(&err),
+                        escape(&source)
+                    )
+                }
+            }
+        })
+        .into_owned();
+
+    Rendered { html, diagnostics }
+}
+
+/// Lines (starting at 1) of the fenced code blocks of the language
+fn fence_lines(markdown: &str, language: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut fence: Option<String> = None;
+    for (i, line) in markdown.lines().enumerate() {
+        let trimmed = line.trim_start();
+        match &fence {
+            Some(open) if trimmed.trim_end() == open => fence = None,
+            Some(_) => {}
+            None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => {
+                let marker = trimmed.chars().next().unwrap();
+                let open: String = trimmed.chars().take_while(|c| *c == marker).collect();
+                let info = trimmed[open.len()..].trim_start_matches(['{', '.', ' ']);
+                if info.split([' ', '}', ',']).next() == Some(language) {
+                    lines.push(i + 1);
+                }
+                fence = Some(open);
+            }
+            None => {}
+        }
+    }
+
+    lines
+}
+
+fn render_cached(language: &str, command: &[String], source: &str) -> Result<String, String> {
+    let crc = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+    let mut digest = crc.digest();
+    digest.update(language.as_bytes());
+    for arg in command {
+        digest.update(b"\0");
+        digest.update(arg.as_bytes());
+    }
+    digest.update(b"\0");
+    digest.update(source.as_bytes());
+    let hash = digest.finalize();
+
+    if let Some(diagram) = CACHE.lock().unwrap().get(&hash) {
+        return Ok(diagram.clone());
+    }
+
+    let svg = render(command, source).map_err(|err| {
+        log::debug!("Rendering {} diagram failed: {}", language, err);
+        err
+    })?;
+    let diagram = format!(
+        "<div class=\"diagram diagram-{}\">{}</div>",
+        escape(language),
+        svg
+    );
+    let mut cache = CACHE.lock().unwrap();
+    if cache.len() >= CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(hash, diagram.clone());
+
+    Ok(diagram)
+}
+
+/// Runs the command with the source as input and returns the SVG it wrote
+fn render(command: &[String], source: &str) -> Result<String, String> {
+    let (output, _) = super::external::run(command, source, TIMEOUT)?;
+
+    // Skip the XML declaration and doctype
+    match output.find("<svg") {
+        Some(start) => Ok(output[start..].trim_end().to_string()),
+        None => Err(format!("{} didn't output SVG", command[0])),
+    }
+}
@@ -0,0 +1,311 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+//! Converts files to HTML with arbitrary commands (e.g. ``asciidoctor`` or ``rst2html``)
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::{self, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::{Diagnostic, MarkdownParser, Rendered, Severity};
+
+/// Replaced with the path of a file containing the source. Without it, the source is written to
+/// stdin.
+pub const INPUT_PLACEHOLDER: &str = "{input}";
+/// Replaced with the path of a file the command writes the HTML to. Without it, the HTML is read
+/// from stdout.
+pub const OUTPUT_PLACEHOLDER: &str = "{output}";
+
+/// Converting a file is aborted after this time, if no timeout is configured
+pub const DEFAULT_CONVERTER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Numbers the temporary files of the running commands
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Directory of the temporary files, which only the current user can access. Created on first
+/// use and removed by [`remove_temp_dir`].
+static TEMP_DIR: Lazy<Result<PathBuf, String>> = Lazy::new(create_temp_dir);
+
+/// Command converting files to HTML
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExternalCommand {
+    /// Program and arguments, which may contain ``{input}`` and ``{output}``
+    pub command: Vec<String>,
+    pub timeout: Duration,
+}
+
+/// Converts files by running a command for every file
+pub struct ExternalParser {
+    command: ExternalCommand,
+    /// Extension of the converted files, given to the temporary input file
+    extension: String,
+}
+
+impl ExternalParser {
+    pub fn new(command: ExternalCommand, extension: &str) -> Self {
+        Self {
+            command,
+            extension: extension.to_string(),
+        }
+    }
+
+    /// Runs the command and returns the HTML and the warnings it wrote
+    fn convert(&self, source: &str) -> Result<(String, String), String> {
+        let uses = |placeholder: &str| {
+            self.command
+                .command
+                .iter()
+                .any(|arg| arg.contains(placeholder))
+        };
+        let input = if uses(INPUT_PLACEHOLDER) {
+            let input = TempFile::new(&self.extension)?;
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&input.0)
+                .and_then(|mut file| file.write_all(source.as_bytes()))
+                .map_err(|err| format!("Writing the input file failed: {}", err))?;
+            Some(input)
+        } else {
+            None
+        };
+        let output = if uses(OUTPUT_PLACEHOLDER) {
+            Some(TempFile::new("html")?)
+        } else {
+            None
+        };
+
+        let command: Vec<String> = self
+            .command
+            .command
+            .iter()
+            .map(|arg| {
+                let mut arg = arg.clone();
+                if let Some(input) = &input {
+                    arg = arg.replace(INPUT_PLACEHOLDER, &input.0.to_string_lossy());
+                }
+                if let Some(output) = &output {
+                    arg = arg.replace(OUTPUT_PLACEHOLDER, &output.0.to_string_lossy());
+                }
+                arg
+            })
+            .collect();
+
+        let stdin = if input.is_some() { "" } else { source };
+        let (stdout, stderr) = run(&command, stdin, self.command.timeout)?;
+        let html = match &output {
+            Some(output) => fs::read_to_string(&output.0)
+                .map_err(|err| format!("{} didn't write the output file: {}", command[0], err))?,
+            None => stdout,
+        };
+
+        Ok((html, stderr))
+    }
+}
+
+impl MarkdownParser for ExternalParser {
+    fn parse_to_html(&mut self, markdown: &str) -> Rendered {
+        match self.convert(markdown) {
+            Ok((html, warnings)) => Rendered {
+                html,
+                diagnostics: command_diagnostics(&warnings, Severity::Warning),
+            },
+            Err(err) => {
+                log::error!(
+                    "Converting a file with {} failed: {}",
+                    self.command
+                        .command
+                        .first()
+                        .map(String::as_str)
+                        .unwrap_or_default(),
+                    err
+                );
+                Rendered {
+                    html: String::new(),
+                    diagnostics: command_diagnostics(&err, Severity::Error),
+                }
+            }
+        }
+    }
+}
+
+/// Converts the messages a command wrote to stderr to diagnostics. Indented lines continue the
+/// previous message. Lines are found in the usual formats (``line 5`` and ``file:5:``).
+fn command_diagnostics(stderr: &str, severity: Severity) -> Vec<Diagnostic> {
+    let line = Regex::new(r"\bline (\d+)|:(\d+):").unwrap();
+
+    let mut messages: Vec<String> = Vec::new();
+    for output_line in stderr.lines().filter(|line| !line.trim().is_empty()) {
+        match messages.last_mut() {
+            Some(message) if output_line.starts_with([' ', '\t']) => {
+                message.push('\n');
+                message.push_str(output_line.trim());
+            }
+            _ => messages.push(output_line.trim().to_string()),
+        }
+    }
+
+    messages
+        .into_iter()
+        .map(|message| {
+            let source_line = line.captures(&message).and_then(|captures| {
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(2))
+                    .and_then(|found| found.as_str().parse().ok())
+            });
+
+            Diagnostic::new(severity, message, source_line)
+        })
+        .collect()
+}
+
+/// Creates a new directory for the temporary files of this process. An existing directory (e.g.
+/// created by another user guessing the name) is never reused.
+fn create_temp_dir() -> Result<PathBuf, String> {
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default();
+
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+    let mut last_error = None;
+    for attempt in 0..100u32 {
+        let dir = std::env::temp_dir().join(format!(
+            "md-dir-builder-{}-{:08x}",
+            process::id(),
+            seed.wrapping_add(attempt.wrapping_mul(0x9e37_79b9))
+        ));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => last_error = Some(err),
+            Err(err) => return Err(format!("Creating {} failed: {}", dir.display(), err)),
+        }
+    }
+
+    Err(format!(
+        "Creating a temporary directory failed: {}",
+        last_error.map(|err| err.to_string()).unwrap_or_default()
+    ))
+}
+
+/// Removes the directory of the temporary files, if it was created
+pub fn remove_temp_dir() {
+    if let Some(Ok(dir)) = Lazy::get(&TEMP_DIR) {
+        if let Err(err) = fs::remove_dir_all(dir) {
+            log::debug!("Removing {} failed: {}", dir.display(), err);
+        }
+    }
+}
+
+/// Temporary file in the private directory, which is removed when dropped. The file itself isn't
+/// created.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(extension: &str) -> Result<Self, String> {
+        let dir = TEMP_DIR.as_ref().map_err(Clone::clone)?;
+        let number = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+        Ok(Self(dir.join(format!("{}.{}", number, extension))))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Runs the command with ``input`` written to stdin and returns what it wrote to stdout and
+/// stderr. Fails, if the command can't be started, exits unsuccessfully or runs longer than
+/// ``timeout``.
+pub(super) fn run(
+    command: &[String],
+    input: &str,
+    timeout: Duration,
+) -> Result<(String, String), String> {
+    let program = command.first().ok_or("The command is empty")?;
+    let mut child = process::Command::new(program)
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => format!("{} isn't installed", program),
+            _ => format!("{} couldn't be started: {}", program, err),
+        })?;
+
+    // Write and read in threads, so full pipes don't block the process
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let mut stderr = child.stderr.take().unwrap();
+    let error_reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stderr.read_to_end(&mut output);
+        output
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() > timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} timed out", program));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+            Err(err) => return Err(err.to_string()),
+        }
+    };
+
+    let _ = writer.join();
+    let output = reader
+        .join()
+        .map_err(|_| "Reading the output failed".to_string())?
+        .map_err(|err| err.to_string())?;
+    let errors = error_reader.join().unwrap_or_default();
+    let errors = String::from_utf8_lossy(&errors).trim().to_string();
+
+    if !status.success() {
+        return Err(if errors.is_empty() {
+            format!("{} exited with {}", program, status)
+        } else {
+            errors
+        });
+    }
+
+    Ok((String::from_utf8_lossy(&output).into_owned(), errors))
+}
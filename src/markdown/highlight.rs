@@ -0,0 +1,909 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+//! Highlights code blocks while building. The tokens get the classes of Prism, so the same theme
+//! applies. Prism still highlights the code blocks of the languages not known here.
+
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use super::html::escape;
+
+/// Where code blocks are highlighted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Highlighting {
+    /// In the browser with Prism (supports more languages)
+    #[default]
+    Client,
+    /// While building, Prism only highlights the languages not known while building
+    Server,
+}
+
+/// Syntax of a language, as far as required for highlighting
+struct Language {
+    names: &'static [&'static str],
+    keywords: &'static [&'static str],
+    /// Keywords are matched ignoring the case (e.g. SQL)
+    ignore_case: bool,
+    constants: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// Strings in three quotes may span lines (e.g. Python)
+    triple_quotes: bool,
+    /// Characters allowed in identifiers in addition to letters, digits and underscores
+    identifier_chars: &'static [char],
+}
+
+const C_LIKE: Language = Language {
+    names: &[],
+    keywords: &[],
+    ignore_case: false,
+    constants: &["true", "false", "null"],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    identifier_chars: &[],
+};
+
+const SCRIPT: Language = Language {
+    names: &[],
+    keywords: &[],
+    ignore_case: false,
+    constants: &[],
+    line_comments: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    identifier_chars: &[],
+};
+
+static LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+            "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+            "type", "unsafe", "use", "where", "while",
+        ],
+        constants: &["true", "false"],
+        quotes: &['"'],
+        ..C_LIKE
+    },
+    Language {
+        names: &["c", "h", "cpp", "c++", "cc", "hpp"],
+        keywords: &[
+            "auto",
+            "bool",
+            "break",
+            "case",
+            "catch",
+            "char",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "double",
+            "else",
+            "enum",
+            "extern",
+            "float",
+            "for",
+            "goto",
+            "if",
+            "inline",
+            "int",
+            "long",
+            "namespace",
+            "new",
+            "operator",
+            "private",
+            "protected",
+            "public",
+            "register",
+            "return",
+            "short",
+            "signed",
+            "sizeof",
+            "static",
+            "struct",
+            "switch",
+            "template",
+            "this",
+            "throw",
+            "try",
+            "typedef",
+            "typename",
+            "union",
+            "unsigned",
+            "using",
+            "virtual",
+            "void",
+            "volatile",
+            "while",
+        ],
+        constants: &["true", "false", "NULL", "nullptr"],
+        ..C_LIKE
+    },
+    Language {
+        names: &["java"],
+        keywords: &[
+            "abstract",
+            "assert",
+            "boolean",
+            "break",
+            "byte",
+            "case",
+            "catch",
+            "char",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "do",
+            "double",
+            "else",
+            "enum",
+            "extends",
+            "final",
+            "finally",
+            "float",
+            "for",
+            "goto",
+            "if",
+            "implements",
+            "import",
+            "instanceof",
+            "int",
+            "interface",
+            "long",
+            "native",
+            "new",
+            "package",
+            "private",
+            "protected",
+            "public",
+            "record",
+            "return",
+            "short",
+            "static",
+            "strictfp",
+            "super",
+            "switch",
+            "synchronized",
+            "this",
+            "throw",
+            "throws",
+            "transient",
+            "try",
+            "var",
+            "void",
+            "volatile",
+            "while",
+            "yield",
+        ],
+        constants: &["true", "false", "null"],
+        ..C_LIKE
+    },
+    Language {
+        names: &["kotlin", "kt", "kts"],
+        keywords: &[
+            "abstract",
+            "as",
+            "break",
+            "by",
+            "catch",
+            "class",
+            "companion",
+            "const",
+            "constructor",
+            "continue",
+            "data",
+            "do",
+            "else",
+            "enum",
+            "external",
+            "final",
+            "finally",
+            "for",
+            "fun",
+            "if",
+            "import",
+            "in",
+            "init",
+            "inline",
+            "interface",
+            "internal",
+            "is",
+            "lateinit",
+            "object",
+            "open",
+            "operator",
+            "out",
+            "override",
+            "package",
+            "private",
+            "protected",
+            "public",
+            "reified",
+            "return",
+            "sealed",
+            "super",
+            "suspend",
+            "this",
+            "throw",
+            "try",
+            "typealias",
+            "val",
+            "var",
+            "vararg",
+            "when",
+            "where",
+            "while",
+        ],
+        constants: &["true", "false", "null"],
+        ..C_LIKE
+    },
+    Language {
+        names: &["csharp", "cs", "c#"],
+        keywords: &[
+            "abstract",
+            "as",
+            "async",
+            "await",
+            "base",
+            "bool",
+            "break",
+            "byte",
+            "case",
+            "catch",
+            "char",
+            "checked",
+            "class",
+            "const",
+            "continue",
+            "decimal",
+            "default",
+            "delegate",
+            "do",
+            "double",
+            "else",
+            "enum",
+            "event",
+            "explicit",
+            "extern",
+            "finally",
+            "fixed",
+            "float",
+            "for",
+            "foreach",
+            "get",
+            "goto",
+            "if",
+            "implicit",
+            "in",
+            "int",
+            "interface",
+            "internal",
+            "is",
+            "lock",
+            "long",
+            "namespace",
+            "new",
+            "object",
+            "operator",
+            "out",
+            "override",
+            "params",
+            "private",
+            "protected",
+            "public",
+            "readonly",
+            "record",
+            "ref",
+            "return",
+            "sbyte",
+            "sealed",
+            "set",
+            "short",
+            "sizeof",
+            "stackalloc",
+            "static",
+            "string",
+            "struct",
+            "switch",
+            "this",
+            "throw",
+            "try",
+            "typeof",
+            "uint",
+            "ulong",
+            "unchecked",
+            "unsafe",
+            "ushort",
+            "using",
+            "var",
+            "virtual",
+            "void",
+            "volatile",
+            "when",
+            "where",
+            "while",
+            "yield",
+        ],
+        constants: &["true", "false", "null"],
+        ..C_LIKE
+    },
+    Language {
+        names: &["scala"],
+        keywords: &[
+            "abstract",
+            "case",
+            "catch",
+            "class",
+            "def",
+            "do",
+            "else",
+            "extends",
+            "final",
+            "finally",
+            "for",
+            "forSome",
+            "given",
+            "if",
+            "implicit",
+            "import",
+            "lazy",
+            "match",
+            "new",
+            "object",
+            "override",
+            "package",
+            "private",
+            "protected",
+            "return",
+            "sealed",
+            "super",
+            "then",
+            "this",
+            "throw",
+            "trait",
+            "try",
+            "type",
+            "using",
+            "val",
+            "var",
+            "while",
+            "with",
+            "yield",
+        ],
+        constants: &["true", "false", "null"],
+        ..C_LIKE
+    },
+    Language {
+        names: &["swift"],
+        keywords: &[
+            "as",
+            "associatedtype",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "continue",
+            "default",
+            "defer",
+            "deinit",
+            "do",
+            "else",
+            "enum",
+            "extension",
+            "fallthrough",
+            "fileprivate",
+            "for",
+            "func",
+            "guard",
+            "if",
+            "import",
+            "in",
+            "init",
+            "inout",
+            "internal",
+            "is",
+            "let",
+            "mutating",
+            "open",
+            "operator",
+            "private",
+            "protocol",
+            "public",
+            "repeat",
+            "rethrows",
+            "return",
+            "self",
+            "Self",
+            "static",
+            "struct",
+            "subscript",
+            "super",
+            "switch",
+            "throw",
+            "throws",
+            "try",
+            "typealias",
+            "var",
+            "where",
+            "while",
+        ],
+        constants: &["true", "false", "nil"],
+        ..C_LIKE
+    },
+    Language {
+        names: &["go", "golang"],
+        keywords: &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "fallthrough",
+            "for",
+            "func",
+            "go",
+            "goto",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "type",
+            "var",
+        ],
+        constants: &["true", "false", "nil", "iota"],
+        quotes: &['"', '\'', '`'],
+        ..C_LIKE
+    },
+    Language {
+        names: &["javascript", "js", "jsx", "mjs", "typescript", "ts", "tsx"],
+        keywords: &[
+            "as",
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "enum",
+            "export",
+            "extends",
+            "finally",
+            "for",
+            "from",
+            "function",
+            "if",
+            "implements",
+            "import",
+            "in",
+            "instanceof",
+            "interface",
+            "let",
+            "new",
+            "of",
+            "return",
+            "static",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "try",
+            "type",
+            "typeof",
+            "var",
+            "void",
+            "while",
+            "yield",
+        ],
+        constants: &["true", "false", "null", "undefined", "NaN"],
+        quotes: &['"', '\'', '`'],
+        identifier_chars: &['$'],
+        ..C_LIKE
+    },
+    Language {
+        names: &["php"],
+        keywords: &[
+            "abstract",
+            "array",
+            "as",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "echo",
+            "else",
+            "elseif",
+            "extends",
+            "final",
+            "finally",
+            "for",
+            "foreach",
+            "function",
+            "if",
+            "implements",
+            "include",
+            "interface",
+            "namespace",
+            "new",
+            "private",
+            "protected",
+            "public",
+            "require",
+            "return",
+            "static",
+            "switch",
+            "throw",
+            "trait",
+            "try",
+            "use",
+            "while",
+        ],
+        line_comments: &["//", "#"],
+        identifier_chars: &['$'],
+        ..C_LIKE
+    },
+    Language {
+        names: &["python", "py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+            "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+            "with", "yield",
+        ],
+        constants: &["True", "False", "None"],
+        triple_quotes: true,
+        ..SCRIPT
+    },
+    Language {
+        names: &["ruby", "rb"],
+        keywords: &[
+            "alias", "and", "begin", "break", "case", "class", "def", "do", "else", "elsif", "end",
+            "ensure", "for", "if", "in", "module", "next", "not", "or", "redo", "rescue", "retry",
+            "return", "self", "super", "then", "unless", "until", "when", "while", "yield",
+        ],
+        constants: &["true", "false", "nil"],
+        ..SCRIPT
+    },
+    Language {
+        names: &["bash", "sh", "shell", "zsh", "console"],
+        keywords: &[
+            "case", "do", "done", "echo", "elif", "else", "esac", "exit", "export", "fi", "for",
+            "function", "if", "in", "local", "read", "return", "set", "shift", "then", "unset",
+            "until", "while",
+        ],
+        constants: &["true", "false"],
+        identifier_chars: &['$', '-'],
+        ..SCRIPT
+    },
+    Language {
+        names: &["json", "jsonc"],
+        keywords: &[],
+        constants: &["true", "false", "null"],
+        quotes: &['"'],
+        ..C_LIKE
+    },
+    Language {
+        names: &["sql", "mysql", "postgresql", "sqlite"],
+        keywords: &[
+            "add",
+            "alter",
+            "and",
+            "as",
+            "asc",
+            "between",
+            "by",
+            "case",
+            "create",
+            "delete",
+            "desc",
+            "distinct",
+            "drop",
+            "else",
+            "end",
+            "exists",
+            "from",
+            "group",
+            "having",
+            "in",
+            "index",
+            "inner",
+            "insert",
+            "into",
+            "is",
+            "join",
+            "key",
+            "left",
+            "like",
+            "limit",
+            "not",
+            "on",
+            "or",
+            "order",
+            "outer",
+            "primary",
+            "references",
+            "right",
+            "select",
+            "set",
+            "table",
+            "then",
+            "union",
+            "update",
+            "values",
+            "when",
+            "where",
+        ],
+        ignore_case: true,
+        constants: &["true", "false", "null"],
+        line_comments: &["--"],
+        quotes: &['\''],
+        ..C_LIKE
+    },
+    Language {
+        names: &["lua"],
+        keywords: &[
+            "and", "break", "do", "else", "elseif", "end", "for", "function", "goto", "if", "in",
+            "local", "not", "or", "repeat", "return", "then", "until", "while",
+        ],
+        constants: &["true", "false", "nil"],
+        line_comments: &["--"],
+        block_comment: Some(("--[[", "]]")),
+        ..C_LIKE
+    },
+    Language {
+        names: &["css", "scss", "less"],
+        keywords: &["important", "media", "import", "keyframes", "supports"],
+        constants: &[],
+        line_comments: &[],
+        identifier_chars: &['-'],
+        ..C_LIKE
+    },
+];
+
+static MARKUP: &[&str] = &["html", "xml", "svg", "markup", "vue"];
+
+/// Highlights the code blocks in HTML of the languages known here. Other code blocks are kept,
+/// so Prism highlights them in the browser. Highlighted ones are marked with
+/// ``data-highlighted``, which Prism skips.
+pub fn highlight_code_blocks(html: &str) -> String {
+    let code_block =
+        Regex::new(r#"(?s)<pre><code class="language-([^"]*)">(.*?)</code></pre>"#).unwrap();
+
+    code_block
+        .replace_all(html, |captures: &Captures| {
+            let name = &captures[1];
+            let code = super::decode_entities(&captures[2]);
+            let highlighted = if MARKUP.contains(&name) {
+                highlight_markup(&code)
+            } else {
+                match language(name) {
+                    Some(language) => highlight(language, &code),
+                    None => return captures[0].to_string(),
+                }
+            };
+
+            format!(
+                "<pre class=\"language-{0}\" data-highlighted><code class=\"language-{0}\">{1}</code></pre>",
+                name, highlighted
+            )
+        })
+        .into_owned()
+}
+
+fn language(name: &str) -> Option<&'static Language> {
+    let name = name.to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|language| language.names.contains(&name.as_str()))
+}
+
+fn token(class: &str, text: &str) -> String {
+    format!("<span class=\"token {}\">{}</span>", class, escape(text))
+}
+
+/// Highlights code of a language
+fn highlight(language: &Language, code: &str) -> String {
+    let mut html = String::with_capacity(code.len() * 2);
+    let mut rest = code;
+
+    while let Some(c) = rest.chars().next() {
+        // Comments
+        if let Some(comment) = language
+            .block_comment
+            .filter(|(start, _)| rest.starts_with(start))
+            .map(|(start, end)| match rest[start.len()..].find(end) {
+                Some(i) => &rest[..start.len() + i + end.len()],
+                None => rest,
+            })
+            .or_else(|| {
+                language
+                    .line_comments
+                    .iter()
+                    .find(|start| rest.starts_with(*start))
+                    .map(|_| rest.split('\n').next().unwrap())
+            })
+        {
+            html.push_str(&token("comment", comment));
+            rest = &rest[comment.len()..];
+            continue;
+        }
+
+        let length = if language.quotes.contains(&c) {
+            let string = string_length(language, rest, c);
+            // Keys of objects
+            let class = if rest[string..]
+                .trim_start_matches([' ', '\t'])
+                .starts_with(':')
+                && language.names.contains(&"json")
+            {
+                "property"
+            } else {
+                "string"
+            };
+            html.push_str(&token(class, &rest[..string]));
+            string
+        } else if c.is_ascii_digit() {
+            let number = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            html.push_str(&token("number", &rest[..number]));
+            number
+        } else if c.is_alphabetic() || c == '_' || language.identifier_chars.contains(&c) {
+            let word = rest
+                .find(|c: char| {
+                    !(c.is_alphanumeric() || c == '_' || language.identifier_chars.contains(&c))
+                })
+                .unwrap_or(rest.len());
+            let text = &rest[..word];
+            let is_keyword = if language.ignore_case {
+                language
+                    .keywords
+                    .iter()
+                    .any(|keyword| keyword.eq_ignore_ascii_case(text))
+            } else {
+                language.keywords.contains(&text)
+            };
+
+            if is_keyword {
+                html.push_str(&token("keyword", text));
+            } else if language.constants.contains(&text) {
+                html.push_str(&token("boolean", text));
+            } else if rest[word..].starts_with('(') {
+                html.push_str(&token("function", text));
+            } else {
+                html.push_str(&escape(text));
+            }
+            word
+        } else if "+-*/%=<>!&|^~?:".contains(c) {
+            html.push_str(&token("operator", &c.to_string()));
+            c.len_utf8()
+        } else if "{}[]();,.".contains(c) {
+            html.push_str(&token("punctuation", &c.to_string()));
+            c.len_utf8()
+        } else {
+            html.push_str(&escape(&c.to_string()));
+            c.len_utf8()
+        };
+
+        rest = &rest[length..];
+    }
+
+    html
+}
+
+/// Length of the string starting at the beginning of ``code``
+fn string_length(language: &Language, code: &str, quote: char) -> usize {
+    let triple = quote.to_string().repeat(3);
+    if language.triple_quotes && code.starts_with(&triple) {
+        return code[3..]
+            .find(&triple)
+            .map(|end| end + 6)
+            .unwrap_or(code.len());
+    }
+
+    let mut escaped = false;
+    for (i, c) in code.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            // Unterminated strings end at the line
+            '\n' if quote != '`' => return i,
+            c if c == quote && !escaped => return i + 1,
+            _ => escaped = false,
+        }
+    }
+
+    code.len()
+}
+
+/// Highlights HTML and XML
+fn highlight_markup(code: &str) -> String {
+    let markup = Regex::new(
+        r#"(?s)(<!--.*?-->)|(</?[A-Za-z][^\s/>]*)((?:\s+[^\s=/>]+(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s>]+))?)*)(\s*/?>)"#,
+    )
+    .unwrap();
+    let attribute = Regex::new(r#"([^\s=/>]+)(?:(\s*=\s*)("[^"]*"|'[^']*'|[^\s>]+))?"#).unwrap();
+
+    let mut html = String::with_capacity(code.len() * 2);
+    let mut last = 0;
+    for captures in markup.captures_iter(code) {
+        let whole = captures.get(0).unwrap();
+        html.push_str(&escape(&code[last..whole.start()]));
+        last = whole.end();
+
+        if let Some(comment) = captures.get(1) {
+            html.push_str(&token("comment", comment.as_str()));
+            continue;
+        }
+
+        html.push_str("<span class=\"token tag\">");
+        html.push_str(&token(
+            "punctuation",
+            if captures[2].starts_with("</") {
+                "</"
+            } else {
+                "<"
+            },
+        ));
+        html.push_str(&escape(captures[2].trim_start_matches(['<', '/'])));
+        let attributes = &captures[3];
+        let mut attribute_last = 0;
+        for attr in attribute.captures_iter(attributes) {
+            let whole = attr.get(0).unwrap();
+            html.push_str(&escape(&attributes[attribute_last..whole.start()]));
+            attribute_last = whole.end();
+            html.push_str(&token("attr-name", &attr[1]));
+            if let (Some(equals), Some(value)) = (attr.get(2), attr.get(3)) {
+                html.push_str(&token("punctuation", equals.as_str()));
+                html.push_str(&token("attr-value", value.as_str()));
+            }
+        }
+        html.push_str(&escape(&attributes[attribute_last..]));
+        html.push_str(&token("punctuation", &captures[4]));
+        html.push_str("</span>");
+    }
+    html.push_str(&escape(&code[last..]));
+
+    html
+}
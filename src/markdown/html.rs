@@ -0,0 +1,114 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+//! HTML of the elements the native parsers of other formats have in common, so they look like
+//! the ones of markdown
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Code block like the ones of pulldown-cmark, so they are highlighted the same
+pub fn code_block(code: &str, language: Option<&str>) -> String {
+    match language {
+        Some(language) => format!(
+            "<pre><code class=\"language-{}\">{}\n</code></pre>",
+            escape(language),
+            escape(code)
+        ),
+        None => format!("<pre><code>{}\n</code></pre>", escape(code)),
+    }
+}
+
+/// Admonition shown like a GitHub alert (``kind`` is one of ``note``, ``tip``, ``important``,
+/// ``warning`` and ``caution``)
+pub fn alert(kind: &str, title: &str, contents: &str) -> String {
+    format!(
+        "<div class=\"markdown-alert markdown-alert-{}\">\n<p class=\"markdown-alert-title\">{}</p>\n{}\n</div>",
+        kind, title, contents
+    )
+}
+
+/// Table of the rendered cells
+pub fn table(caption: Option<String>, header: &[Vec<String>], body: &[Vec<String>]) -> String {
+    let spanning = |rows: &[Vec<String>]| -> Vec<Vec<(String, usize)>> {
+        rows.iter()
+            .map(|cells| cells.iter().map(|cell| (cell.clone(), 1)).collect())
+            .collect()
+    };
+
+    spanning_table(caption, &spanning(header), &spanning(body))
+}
+
+/// Table of the rendered cells with the number of columns they span
+pub fn spanning_table(
+    caption: Option<String>,
+    header: &[Vec<(String, usize)>],
+    body: &[Vec<(String, usize)>],
+) -> String {
+    let row = |cells: &Vec<(String, usize)>, tag: &str| {
+        let cells: String = cells
+            .iter()
+            .map(|(cell, columns)| match columns {
+                1 => format!("<{0}>{1}</{0}>", tag, cell),
+                _ => format!("<{0} colspan=\"{1}\">{2}</{0}>", tag, columns, cell),
+            })
+            .collect();
+        format!("<tr>{}</tr>", cells)
+    };
+
+    let mut html = String::from("<table>");
+    if let Some(caption) = caption {
+        html.push_str(&format!("\n<caption>{}</caption>", caption));
+    }
+    if !header.is_empty() {
+        html.push_str("\n<thead>");
+        for cells in header {
+            html.push_str(&row(cells, "th"));
+        }
+        html.push_str("</thead>");
+    }
+    html.push_str("\n<tbody>");
+    for cells in body {
+        html.push('\n');
+        html.push_str(&row(cells, "td"));
+    }
+    html.push_str("\n</tbody>\n</table>");
+
+    html
+}
+
+/// Footnote like the ones of pulldown-cmark
+pub fn footnote(id: &str, label: &str, contents: &str) -> String {
+    format!(
+        "<div class=\"footnote-definition\" id=\"footnote-{}\"><sup class=\"footnote-definition-label\">{}</sup>\n{}\n</div>",
+        escape(id),
+        escape(label),
+        contents
+    )
+}
+
+/// Reference to a footnote
+pub fn footnote_reference(id: &str, label: &str) -> String {
+    format!(
+        "<sup class=\"footnote-reference\"><a href=\"#footnote-{}\">{}</a></sup>",
+        escape(id),
+        escape(label)
+    )
+}
@@ -0,0 +1,1049 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+//! Converts TeX math (``$...$`` and ``$$...$$``) to MathML, so no script is required to show it
+
+use regex::Regex;
+
+use super::{code_ranges, html::escape, Diagnostic, Rendered, Severity};
+
+/// Marks the start of a placeholder of math in the markdown (a private use character, so it
+/// doesn't clash with the text and isn't interpreted as markdown)
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+/// Fills lines of multi-line math, so the lines of the markdown stay the same
+const PLACEHOLDER_LINE: char = '\u{E002}';
+
+/// Math found in markdown
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Math {
+    pub tex: String,
+    /// ``$$...$$`` instead of ``$...$``
+    pub display: bool,
+    /// Line of the markdown the math starts at (starting at 1)
+    pub line: usize,
+}
+
+/// Replaces math in the markdown with placeholders, so the markdown parser doesn't interpret
+/// it. Math in code and HTML isn't replaced. The placeholders contain as many line breaks as the math.
+pub fn extract_math(markdown: &str) -> (String, Vec<Math>) {
+    let mut math = Vec::new();
+    let mut result = String::with_capacity(markdown.len());
+    let chars: Vec<(usize, char)> = markdown.char_indices().collect();
+    let code_ranges = code_ranges(markdown);
+    let in_code = |offset: usize| code_ranges.iter().any(|range| range.contains(&offset));
+    // Math doesn't span paragraphs
+    let blank_line = Regex::new(r"\n[ \t]*\n").unwrap();
+
+    let mut i = 0;
+    let mut copied = 0;
+    while i < chars.len() {
+        let (offset, c) = chars[i];
+        if c == '\\' {
+            i += 2;
+            continue;
+        }
+        if c != '$' || in_code(offset) {
+            i += 1;
+            continue;
+        }
+
+        let display = markdown[offset..].starts_with("$$");
+        let found = if display {
+            display_math_end(markdown, offset + 2)
+                .map(|end| (offset + 2, end, end + 2))
+                .filter(|(start, end, _)| {
+                    start < end && !in_code(*end) && !blank_line.is_match(&markdown[*start..*end])
+                })
+        } else {
+            inline_math_end(markdown, offset, in_code)
+        };
+
+        match found {
+            Some((start, end, after)) => {
+                let tex = &markdown[start..end];
+                result.push_str(&markdown[copied..offset]);
+                result.push(PLACEHOLDER_START);
+                result.push_str(&math.len().to_string());
+                for _ in tex.matches('\n') {
+                    result.push('\n');
+                    result.push(PLACEHOLDER_LINE);
+                }
+                result.push(PLACEHOLDER_END);
+                math.push(Math {
+                    tex: tex.trim().to_string(),
+                    display,
+                    line: markdown[..offset].matches('\n').count() + 1,
+                });
+
+                copied = after;
+                while i < chars.len() && chars[i].0 < after {
+                    i += 1;
+                }
+            }
+            None => i += if display { 2 } else { 1 },
+        }
+    }
+    result.push_str(&markdown[copied..]);
+
+    (result, math)
+}
+
+/// Finds the end of inline math starting at the dollar at ``offset``. Like pandoc, the math
+/// mustn't start or end with whitespace and the closing dollar mustn't be followed by a digit.
+/// Dollars in code don't end it.
+fn inline_math_end(
+    text: &str,
+    offset: usize,
+    in_code: impl Fn(usize) -> bool,
+) -> Option<(usize, usize, usize)> {
+    let start = offset + 1;
+    let rest = &text[start..];
+    if rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let mut escaped = false;
+    for (i, c) in rest.char_indices() {
+        match c {
+            '\n' if rest[i + 1..]
+                .trim_start_matches([' ', '\t'])
+                .starts_with('\n') =>
+            {
+                return None;
+            }
+            '\\' if !escaped => {
+                escaped = true;
+                continue;
+            }
+            '$' if !escaped && i > 0 && !in_code(start + i) => {
+                let before = rest[..i].chars().last().unwrap();
+                let after = rest[i + 1..].chars().next();
+                if !before.is_whitespace() && !after.is_some_and(|c| c.is_ascii_digit()) {
+                    return Some((start, start + i, start + i + 1));
+                }
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    None
+}
+
+/// Finds the closing ``$$`` of display math, which content starts at ``start``. Escaped dollars
+/// don't close it.
+fn display_math_end(text: &str, start: usize) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text[start..].char_indices() {
+        match c {
+            '\\' if !escaped => {
+                escaped = true;
+                continue;
+            }
+            '$' if !escaped && text[start + i..].starts_with("$$") => return Some(start + i),
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    None
+}
+
+/// Replaces the placeholders in the HTML with the math converted to MathML. Unknown commands
+/// are returned as warnings.
+pub fn insert_math(html: &str, math: &[Math]) -> Rendered {
+    if math.is_empty() {
+        return html.to_string().into();
+    }
+
+    let placeholder = Regex::new(&format!(
+        "{}([0-9]+)[\\s{}]*{}",
+        PLACEHOLDER_START, PLACEHOLDER_LINE, PLACEHOLDER_END
+    ))
+    .unwrap();
+
+    let mut diagnostics = Vec::new();
+    let html = placeholder
+        .replace_all(html, |captures: &regex::Captures| {
+            let math = match captures[1].parse::<usize>().ok().and_then(|i| math.get(i)) {
+                Some(math) => math,
+                None => return String::new(),
+            };
+
+            let (mathml, errors) = to_mathml(&math.tex, math.display);
+            diagnostics.extend(
+                errors
+                    .into_iter()
+                    .map(|error| Diagnostic::new(Severity::Warning, error, Some(math.line))),
+            );
+
+            mathml
+        })
+        .into_owned();
+
+    Rendered { html, diagnostics }
+}
+
+/// Converts TeX math to MathML. The TeX is kept as annotation. Also returns the unknown
+/// commands and environments found.
+pub fn to_mathml(tex: &str, display: bool) -> (String, Vec<String>) {
+    let mut parser = TexParser {
+        chars: tex.chars().collect(),
+        pos: 0,
+        display,
+        depth: 0,
+        errors: Vec::new(),
+    };
+    let mut items = Vec::new();
+    loop {
+        items.append(&mut parser.parse_row());
+        // Stray closing braces, alignments and line breaks outside of environments
+        if !parser.skip_stray() {
+            break;
+        }
+    }
+
+    let mathml = format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"{}><semantics>{}<annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { " display=\"block\"" } else { "" },
+        mrow(items),
+        escape(tex)
+    );
+
+    (mathml, parser.errors)
+}
+
+/// Combines elements into one
+fn mrow(items: Vec<String>) -> String {
+    if items.len() == 1 {
+        items.into_iter().next().unwrap()
+    } else {
+        format!("<mrow>{}</mrow>", items.concat())
+    }
+}
+
+fn mo(op: &str) -> String {
+    format!("<mo>{}</mo>", escape(op))
+}
+
+fn mi(identifier: &str) -> String {
+    format!("<mi>{}</mi>", escape(identifier))
+}
+
+/// Element of math with information required for attaching scripts
+struct Atom {
+    mathml: String,
+    /// Scripts are placed below and above in display mode (e.g. ``\sum``)
+    limits: bool,
+}
+
+impl Atom {
+    fn new(mathml: String) -> Self {
+        Atom {
+            mathml,
+            limits: false,
+        }
+    }
+}
+
+static GREEK: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ϵ"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("vartheta", "ϑ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("varpi", "ϖ"),
+    ("rho", "ρ"),
+    ("varrho", "ϱ"),
+    ("sigma", "σ"),
+    ("varsigma", "ς"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "ϕ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Upsilon", "Υ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("emptyset", "∅"),
+    ("varnothing", "∅"),
+    ("hbar", "ℏ"),
+    ("ell", "ℓ"),
+    ("Re", "ℜ"),
+    ("Im", "ℑ"),
+    ("aleph", "ℵ"),
+    ("imath", "ı"),
+    ("jmath", "ȷ"),
+];
+
+static OPERATORS: &[(&str, &str)] = &[
+    ("cdot", "⋅"),
+    ("times", "×"),
+    ("div", "÷"),
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("leq", "≤"),
+    ("le", "≤"),
+    ("geq", "≥"),
+    ("ge", "≥"),
+    ("neq", "≠"),
+    ("ne", "≠"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("simeq", "≃"),
+    ("cong", "≅"),
+    ("propto", "∝"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("ni", "∋"),
+    ("subset", "⊂"),
+    ("subseteq", "⊆"),
+    ("supset", "⊃"),
+    ("supseteq", "⊇"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("setminus", "∖"),
+    ("wedge", "∧"),
+    ("land", "∧"),
+    ("vee", "∨"),
+    ("lor", "∨"),
+    ("neg", "¬"),
+    ("lnot", "¬"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("gets", "←"),
+    ("leftrightarrow", "↔"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"),
+    ("implies", "⟹"),
+    ("iff", "⟺"),
+    ("mapsto", "↦"),
+    ("uparrow", "↑"),
+    ("downarrow", "↓"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("cdots", "⋯"),
+    ("vdots", "⋮"),
+    ("ddots", "⋱"),
+    ("circ", "∘"),
+    ("bullet", "∙"),
+    ("star", "⋆"),
+    ("ast", "∗"),
+    ("oplus", "⊕"),
+    ("otimes", "⊗"),
+    ("perp", "⊥"),
+    ("parallel", "∥"),
+    ("mid", "∣"),
+    ("angle", "∠"),
+    ("triangle", "△"),
+    ("prime", "′"),
+    ("colon", ":"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+    ("lbrace", "{"),
+    ("rbrace", "}"),
+    ("vert", "|"),
+    ("Vert", "‖"),
+    ("int", "∫"),
+    ("iint", "∬"),
+    ("iiint", "∭"),
+    ("oint", "∮"),
+];
+
+/// Large operators with scripts below and above in display mode
+static LARGE_OPERATORS: &[(&str, &str)] = &[
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("coprod", "∐"),
+    ("bigcup", "⋃"),
+    ("bigcap", "⋂"),
+    ("bigoplus", "⨁"),
+    ("bigotimes", "⨂"),
+    ("bigvee", "⋁"),
+    ("bigwedge", "⋀"),
+];
+
+static FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "log", "ln", "lg", "exp", "dim", "ker", "deg", "arg", "hom",
+];
+
+/// Functions with scripts below in display mode
+static LIMIT_FUNCTIONS: &[&str] = &[
+    "lim", "limsup", "liminf", "max", "min", "sup", "inf", "det", "gcd", "Pr",
+];
+
+static SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"),
+    (":", "0.2222em"),
+    (">", "0.2222em"),
+    (";", "0.2778em"),
+    (" ", "0.3333em"),
+    ("quad", "1em"),
+    ("qquad", "2em"),
+    ("!", "-0.1667em"),
+];
+
+static ACCENTS: &[(&str, &str)] = &[
+    ("hat", "^"),
+    ("widehat", "^"),
+    ("bar", "¯"),
+    ("overline", "¯"),
+    ("vec", "→"),
+    ("overrightarrow", "→"),
+    ("dot", "˙"),
+    ("ddot", "¨"),
+    ("tilde", "~"),
+    ("widetilde", "~"),
+    ("overbrace", "⏞"),
+];
+
+static UNDER_ACCENTS: &[(&str, &str)] = &[("underline", "_"), ("underbrace", "⏟")];
+
+static FONTS: &[(&str, &str)] = &[
+    ("mathbf", "bold"),
+    ("boldsymbol", "bold-italic"),
+    ("mathit", "italic"),
+    ("mathrm", "normal"),
+    ("mathbb", "double-struck"),
+    ("mathcal", "script"),
+    ("mathfrak", "fraktur"),
+    ("mathsf", "sans-serif"),
+    ("mathtt", "monospace"),
+];
+
+/// Environments with their delimiters
+static MATRICES: &[(&str, &str, &str)] = &[
+    ("matrix", "", ""),
+    ("smallmatrix", "", ""),
+    ("array", "", ""),
+    ("aligned", "", ""),
+    ("align", "", ""),
+    ("align*", "", ""),
+    ("gathered", "", ""),
+    ("gather", "", ""),
+    ("gather*", "", ""),
+    ("split", "", ""),
+    ("pmatrix", "(", ")"),
+    ("bmatrix", "[", "]"),
+    ("Bmatrix", "{", "}"),
+    ("vmatrix", "|", "|"),
+    ("Vmatrix", "‖", "‖"),
+    ("cases", "{", ""),
+];
+
+fn lookup<'a>(table: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    table
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| *value)
+}
+
+struct TexParser {
+    chars: Vec<char>,
+    pos: usize,
+    display: bool,
+    /// Number of groups and arguments the parser is in
+    depth: usize,
+    /// Unknown commands and environments
+    errors: Vec<String>,
+}
+
+/// Maximum number of nested groups and arguments. The parser recurses for each of them, so deeper
+/// math would overflow the stack.
+const MAX_NESTING: usize = 100;
+
+impl TexParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Name of the command at the current position (without consuming it)
+    fn peek_command(&self) -> Option<String> {
+        if self.peek() != Some('\\') {
+            return None;
+        }
+
+        let rest = &self.chars[self.pos + 1..];
+        let name: String = rest
+            .iter()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect();
+        if name.is_empty() {
+            rest.first().map(|c| c.to_string())
+        } else {
+            Some(name)
+        }
+    }
+
+    fn read_command(&mut self) -> Option<String> {
+        let name = self.peek_command()?;
+        self.pos += 1 + name.chars().count();
+        Some(name)
+    }
+
+    /// Skips what ends a row outside of an environment. Returns ``false`` at the end.
+    fn skip_stray(&mut self) -> bool {
+        match self.peek() {
+            None => false,
+            Some('}' | '&') => {
+                self.pos += 1;
+                true
+            }
+            Some('\\') => {
+                if self.read_command().is_none() {
+                    self.pos += 1;
+                }
+                true
+            }
+            _ => {
+                self.pos += 1;
+                true
+            }
+        }
+    }
+
+    /// Parses elements until the end of a group, row, cell or delimited part
+    fn parse_row(&mut self) -> Vec<String> {
+        if self.depth >= MAX_NESTING {
+            // Skips the rest of the group
+            let mut depth = 0usize;
+            while let Some(c) = self.peek() {
+                match c {
+                    '{' => depth += 1,
+                    '}' if depth == 0 => break,
+                    '}' => depth -= 1,
+                    '\\' => self.pos += 1,
+                    _ => {}
+                }
+                self.pos += 1;
+            }
+            return vec![self.too_deep()];
+        }
+
+        self.depth += 1;
+        let items = self.parse_row_items();
+        self.depth -= 1;
+
+        items
+    }
+
+    /// Returns an error for math nested deeper than ``MAX_NESTING``, which is reported once
+    fn too_deep(&mut self) -> String {
+        let error = "Math is nested too deeply";
+        if !self.errors.iter().any(|other| other == error) {
+            self.errors.push(error.to_string());
+        }
+
+        "<merror><mtext>…</mtext></merror>".to_string()
+    }
+
+    fn parse_row_items(&mut self) -> Vec<String> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some('}' | '&') => break,
+                Some('\\') => {
+                    if matches!(self.peek_command().as_deref(), Some("\\" | "right" | "end")) {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(item) = self.parse_scripted() {
+                items.push(item);
+            }
+        }
+
+        items
+    }
+
+    /// Parses an element with its sub- and superscripts
+    fn parse_scripted(&mut self) -> Option<String> {
+        let base = self.parse_atom()?;
+
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.parse_argument());
+                }
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.parse_argument());
+                }
+                Some('\'') => {
+                    // Primes are superscripts
+                    let mut primes = String::new();
+                    while self.peek() == Some('\'') {
+                        primes.push('′');
+                        self.pos += 1;
+                    }
+                    sup = Some(match sup {
+                        Some(sup) => mrow(vec![mo(&primes), sup]),
+                        None => mo(&primes),
+                    });
+                }
+                _ => break,
+            }
+        }
+
+        let under_over = base.limits && self.display;
+        Some(match (sub, sup) {
+            (None, None) => base.mathml,
+            (Some(sub), None) if under_over => format!("<munder>{}{}</munder>", base.mathml, sub),
+            (Some(sub), None) => format!("<msub>{}{}</msub>", base.mathml, sub),
+            (None, Some(sup)) if under_over => format!("<mover>{}{}</mover>", base.mathml, sup),
+            (None, Some(sup)) => format!("<msup>{}{}</msup>", base.mathml, sup),
+            (Some(sub), Some(sup)) if under_over => {
+                format!("<munderover>{}{}{}</munderover>", base.mathml, sub, sup)
+            }
+            (Some(sub), Some(sup)) => {
+                format!("<msubsup>{}{}{}</msubsup>", base.mathml, sub, sup)
+            }
+        })
+    }
+
+    /// Parses the argument of a command or script: a group or a single character or command
+    fn parse_argument(&mut self) -> String {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let items = self.parse_row();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                }
+                mrow(items)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                self.pos += 1;
+                format!("<mn>{}</mn>", c)
+            }
+            Some(_) if self.depth >= MAX_NESTING => {
+                if self.read_command().is_none() {
+                    self.pos += 1;
+                }
+                self.too_deep()
+            }
+            Some(_) => {
+                self.depth += 1;
+                let atom = self.parse_atom();
+                self.depth -= 1;
+
+                atom.map(|atom| atom.mathml)
+                    .unwrap_or_else(|| mrow(Vec::new()))
+            }
+            None => mrow(Vec::new()),
+        }
+    }
+
+    /// Reads a group without interpreting it (e.g. the text of ``\text``)
+    fn read_raw_group(&mut self) -> String {
+        self.skip_whitespace();
+        if self.peek() != Some('{') {
+            return self
+                .peek()
+                .map(|c| {
+                    self.pos += 1;
+                    c.to_string()
+                })
+                .unwrap_or_default();
+        }
+
+        self.pos += 1;
+        let mut depth = 1;
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            text.push(c);
+        }
+
+        text
+    }
+
+    /// Reads an optional argument in brackets (e.g. the index of ``\sqrt[3]{x}``)
+    fn read_optional(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        if self.peek() != Some('[') {
+            return None;
+        }
+
+        self.pos += 1;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c != ']') {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        self.pos += 1;
+
+        Some(text)
+    }
+
+    /// Reads the delimiter following ``\left``, ``\right`` or ``\big``
+    fn read_delimiter(&mut self) -> String {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('\\') => {
+                let name = self.read_command().unwrap_or_default();
+                match name.as_str() {
+                    "{" | "}" | "|" => name,
+                    name => lookup(OPERATORS, name).unwrap_or_default().to_string(),
+                }
+            }
+            Some('.') => {
+                self.pos += 1;
+                String::new()
+            }
+            Some(c) => {
+                self.pos += 1;
+                c.to_string()
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Parses a single element without scripts
+    fn parse_atom(&mut self) -> Option<Atom> {
+        self.skip_whitespace();
+        let c = self.peek()?;
+        match c {
+            '{' => Some(Atom::new(self.parse_argument())),
+            '\\' => self.parse_command(),
+            '^' | '_' => {
+                // Script without base
+                self.pos += 1;
+                let script = self.parse_argument();
+                let element = if c == '^' { "msup" } else { "msub" };
+                Some(Atom::new(format!(
+                    "<{0}><mrow></mrow>{1}</{0}>",
+                    element, script
+                )))
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                Some(Atom::new(format!("<mn>{}</mn>", number)))
+            }
+            c if c.is_alphabetic() => {
+                self.pos += 1;
+                Some(Atom::new(mi(&c.to_string())))
+            }
+            '-' => {
+                self.pos += 1;
+                Some(Atom::new(mo("−")))
+            }
+            '~' => {
+                self.pos += 1;
+                Some(Atom::new("<mspace width=\"0.3333em\"/>".to_string()))
+            }
+            c => {
+                self.pos += 1;
+                Some(Atom::new(mo(&c.to_string())))
+            }
+        }
+    }
+
+    fn parse_command(&mut self) -> Option<Atom> {
+        let Some(name) = self.read_command() else {
+            // Backslash at the end of the math
+            self.pos += 1;
+            self.errors.push("Math ends with a backslash".to_string());
+            return Some(Atom::new("<merror><mtext>\\</mtext></merror>".to_string()));
+        };
+        let name = name.as_str();
+
+        if let Some(symbol) = lookup(GREEK, name) {
+            return Some(Atom::new(mi(symbol)));
+        }
+        if let Some(op) = lookup(OPERATORS, name) {
+            return Some(Atom::new(mo(op)));
+        }
+        if let Some(op) = lookup(LARGE_OPERATORS, name) {
+            return Some(Atom {
+                mathml: mo(op),
+                limits: true,
+            });
+        }
+        if FUNCTIONS.contains(&name) {
+            return Some(Atom::new(format!("{}<mo>&#x2061;</mo>", mi(name))));
+        }
+        if LIMIT_FUNCTIONS.contains(&name) {
+            let name = match name {
+                "limsup" => "lim sup",
+                "liminf" => "lim inf",
+                name => name,
+            };
+            return Some(Atom {
+                mathml: format!("<mi mathvariant=\"normal\">{}</mi>", name),
+                limits: true,
+            });
+        }
+        if let Some(width) = lookup(SPACES, name) {
+            return Some(Atom::new(format!("<mspace width=\"{}\"/>", width)));
+        }
+        if let Some(accent) = lookup(ACCENTS, name) {
+            let base = self.parse_argument();
+            return Some(Atom::new(format!(
+                "<mover accent=\"true\">{}{}</mover>",
+                base,
+                mo(accent)
+            )));
+        }
+        if let Some(accent) = lookup(UNDER_ACCENTS, name) {
+            let base = self.parse_argument();
+            return Some(Atom::new(format!(
+                "<munder accentunder=\"true\">{}{}</munder>",
+                base,
+                mo(accent)
+            )));
+        }
+        if let Some(variant) = lookup(FONTS, name) {
+            let text = self.read_raw_group();
+            return Some(Atom::new(format!(
+                "<mi mathvariant=\"{}\">{}</mi>",
+                variant,
+                escape(text.trim())
+            )));
+        }
+
+        let atom = match name {
+            "{" | "}" | "|" => mo(name),
+            "%" | "$" | "#" | "&" | "_" => mo(name),
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.parse_argument();
+                let denominator = self.parse_argument();
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let top = self.parse_argument();
+                let bottom = self.parse_argument();
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    top, bottom
+                )
+            }
+            "sqrt" => match self.read_optional() {
+                Some(index) => {
+                    let base = self.parse_argument();
+                    let index = self.parse_nested(&index);
+                    format!("<mroot>{}{}</mroot>", base, index)
+                }
+                None => format!("<msqrt>{}</msqrt>", self.parse_argument()),
+            },
+            "text" | "textrm" | "textit" | "textbf" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape(&self.read_raw_group()))
+            }
+            "operatorname" => {
+                let name = self.read_raw_group();
+                format!("{}<mo>&#x2061;</mo>", mi(name.trim()))
+            }
+            "left" => {
+                let open = self.read_delimiter();
+                let items = self.parse_row();
+                let close = if self.peek_command().as_deref() == Some("right") {
+                    self.read_command();
+                    self.read_delimiter()
+                } else {
+                    String::new()
+                };
+                fenced(&open, items, &close)
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl"
+            | "biggr" | "Biggl" | "Biggr" => mo(&self.read_delimiter()),
+            "begin" => {
+                let environment = self.read_raw_group();
+                if environment == "array" {
+                    // Column specification
+                    self.read_raw_group();
+                }
+                self.parse_environment(&environment)
+            }
+            "displaystyle" | "textstyle" | "limits" | "nolimits" | "nonumber" | "notag" => {
+                return self
+                    .parse_atom()
+                    .or_else(|| Some(Atom::new(mrow(Vec::new()))));
+            }
+            name => {
+                self.errors.push(format!("Unknown math command \\{}", name));
+                format!("<merror><mtext>\\{}</mtext></merror>", escape(name))
+            }
+        };
+
+        Some(Atom::new(atom))
+    }
+
+    /// Converts TeX given as argument (e.g. in brackets) to a row of MathML elements
+    fn parse_nested(&mut self, tex: &str) -> String {
+        let mut parser = TexParser {
+            chars: tex.chars().collect(),
+            pos: 0,
+            display: false,
+            depth: self.depth,
+            errors: Vec::new(),
+        };
+        let row = mrow(parser.parse_row());
+        self.errors.append(&mut parser.errors);
+
+        row
+    }
+
+    /// Parses the rows of an environment until its end
+    fn parse_environment(&mut self, environment: &str) -> String {
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            cells.push(mrow(self.parse_row()));
+            match self.peek() {
+                Some('&') => {
+                    self.pos += 1;
+                }
+                Some('\\') => match self.read_command().as_deref() {
+                    Some("\\") => rows.push(std::mem::take(&mut cells)),
+                    Some("end") => {
+                        self.read_raw_group();
+                        break;
+                    }
+                    // ``\right`` without ``\left``
+                    _ => {}
+                },
+                Some('}') => {
+                    self.pos += 1;
+                }
+                None => break,
+                _ => {}
+            }
+        }
+        // A line break after the last row doesn't start another one
+        if cells.len() > 1 || cells.first().is_some_and(|cell| cell != "<mrow></mrow>") {
+            rows.push(cells);
+        }
+
+        let columnalign = match environment {
+            "cases" => " columnalign=\"left\"",
+            "aligned" | "align" | "align*" | "split" => " columnalign=\"right left\"",
+            _ => "",
+        };
+        let table = format!(
+            "<mtable{}>{}</mtable>",
+            columnalign,
+            rows.into_iter()
+                .map(|row| format!(
+                    "<mtr>{}</mtr>",
+                    row.into_iter()
+                        .map(|cell| format!("<mtd>{}</mtd>", cell))
+                        .collect::<String>()
+                ))
+                .collect::<String>()
+        );
+
+        match MATRICES.iter().find(|(name, _, _)| *name == environment) {
+            Some((_, open, close)) if !open.is_empty() || !close.is_empty() => {
+                fenced(open, vec![table], close)
+            }
+            Some(_) => table,
+            None => {
+                self.errors
+                    .push(format!("Unknown math environment {}", environment));
+                format!(
+                    "<merror><mtext>Unknown environment {}</mtext></merror>",
+                    escape(environment)
+                )
+            }
+        }
+    }
+}
+
+/// Surrounds the elements with stretching delimiters
+fn fenced(open: &str, items: Vec<String>, close: &str) -> String {
+    let mut row = Vec::new();
+    if !open.is_empty() {
+        row.push(format!(
+            "<mo fence=\"true\" stretchy=\"true\">{}</mo>",
+            escape(open)
+        ));
+    }
+    row.extend(items);
+    if !close.is_empty() {
+        row.push(format!(
+            "<mo fence=\"true\" stretchy=\"true\">{}</mo>",
+            escape(close)
+        ));
+    }
+
+    format!("<mrow>{}</mrow>", row.concat())
+}
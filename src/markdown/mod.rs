@@ -14,18 +14,160 @@
  *
  *  You should have received a copy of the GNU General Public License
  */
+mod asciidoc;
 mod cache;
 mod commonmark;
+mod diagrams;
+mod external;
+mod highlight;
+mod html;
+mod math;
+mod notebook;
 mod pandoc;
-pub use commonmark::CommonMarkParser;
-pub use pandoc::PandocParser;
+mod rst;
+pub use asciidoc::AsciiDocParser;
+pub use commonmark::{CommonMarkExtension, CommonMarkParser, CommonMarkPreset};
+pub use diagrams::{render_diagrams, DEFAULT_DIAGRAM_COMMANDS};
+pub use external::{remove_temp_dir, ExternalCommand, ExternalParser, DEFAULT_CONVERTER_TIMEOUT};
+pub use highlight::{highlight_code_blocks, Highlighting};
+pub use notebook::{is_notebook, NotebookParser, NOTEBOOK_EXTENSION};
+pub use pandoc::{
+    default_concurrency, is_markdown_format, pandoc_format, pandoc_permits, pandoc_version,
+    PandocOptions, PandocParser,
+};
+pub use rst::RestructuredTextParser;
+
+use std::{borrow::Cow, ops::Range};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Heading of a built file
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Heading {
+    /// 1 for ``<h1>`` up to 6 for ``<h6>``
+    pub level: u8,
+    pub text: String,
+    /// Anchor of the heading (if the parser generated one)
+    pub id: Option<String>,
+}
+
+/// Returns all headings of the HTML in order
+pub fn outline(html: &str) -> Vec<Heading> {
+    let heading = Regex::new(r"(?s)<h([1-6])([^>]*)>(.*?)</h[1-6]>").unwrap();
+    let id = Regex::new(r#"id="([^"]*)""#).unwrap();
+    let tag = Regex::new(r"<[^>]*>").unwrap();
+
+    heading
+        .captures_iter(html)
+        .map(|captures| Heading {
+            level: captures[1].parse().unwrap(),
+            text: decode_entities(tag.replace_all(&captures[3], "").trim()),
+            id: id
+                .captures(&captures[2])
+                .map(|captures| decode_entities(&captures[1])),
+        })
+        .collect()
+}
+
+/// Returns the title of the HTML (the first top-level heading or else the first heading)
+pub fn title(html: &str) -> Option<String> {
+    let outline = outline(html);
+    outline
+        .iter()
+        .find(|heading| heading.level == 1)
+        .or_else(|| outline.first())
+        .map(|heading| heading.text.clone())
+}
+
+/// Byte ranges of the markdown, which aren't parsed as markdown: code blocks (fenced and
+/// indented), code spans and HTML
+pub fn code_ranges(markdown: &str) -> Vec<Range<usize>> {
+    use pulldown_cmark::{Event, Parser, Tag};
+
+    Parser::new(markdown)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) | Event::Html(_) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// How severe a problem found while building is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    /// Parts of the file may be shown wrong
+    Warning,
+    /// Parts of the file (or the whole file) couldn't be built
+    Error,
+}
+
+/// Problem found while building a file
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Line of the source (starting at 1), if known
+    pub line: Option<usize>,
+    /// Top-level block of the HTML containing the line, if known
+    #[serde(default)]
+    pub block: Option<usize>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>, line: Option<usize>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            line,
+            block: None,
+        }
+    }
+}
+
+/// HTML parsed from markdown with the problems found while parsing
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rendered {
+    pub html: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl From<String> for Rendered {
+    fn from(html: String) -> Self {
+        Self {
+            html,
+            diagnostics: Vec::new(),
+        }
+    }
+}
 
 /// Generic for parsing markdown to html
 pub trait MarkdownParser {
-    /// Returns HTML parsed from the input `markdown`
+    /// Returns HTML parsed from the input `markdown` and the problems found in it
     ///
     /// # Arguments
     ///
     /// * `markdown`: Input markdown (CommonMark)
-    fn parse_to_html(&mut self, markdown: &str) -> String;
+    fn parse_to_html(&mut self, markdown: &str) -> Rendered;
+
+    /// Returns the line (starting at 1) of the input ``markdown`` each top-level block of the
+    /// HTML starts at. Empty, if the parser can't map blocks to lines.
+    fn block_lines(&mut self, _markdown: &str) -> Vec<usize> {
+        Vec::new()
+    }
+
+    /// Returns the text of the input ``markdown`` the words are counted in
+    fn text<'a>(&mut self, markdown: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(markdown)
+    }
 }
@@ -0,0 +1,454 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+//! Renders Jupyter notebooks (nbformat 4): markdown cells with a markdown parser, code cells as
+//! code blocks and the stored outputs of the code cells
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{
+    html::{code_block, escape},
+    math, Diagnostic, MarkdownParser, Rendered, Severity,
+};
+
+/// Extension of notebook files
+pub const NOTEBOOK_EXTENSION: &str = "ipynb";
+
+/// Returns ``true``, if the file is a notebook. Notebooks are only shown and can't be edited, as
+/// their source is JSON
+pub fn is_notebook(path: &str) -> bool {
+    path.rsplit_once('.')
+        .is_some_and(|(_, ext)| ext == NOTEBOOK_EXTENSION)
+}
+
+/// Types of outputs in the order they are preferred, if an output has several
+static OUTPUT_TYPES: &[&str] = &[
+    "text/html",
+    "image/svg+xml",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "text/markdown",
+    "text/latex",
+    "text/plain",
+];
+
+#[derive(Deserialize)]
+struct Notebook {
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: NotebookMetadata,
+}
+
+#[derive(Default, Deserialize)]
+struct NotebookMetadata {
+    kernelspec: Option<KernelSpec>,
+    language_info: Option<LanguageInfo>,
+}
+
+#[derive(Deserialize)]
+struct KernelSpec {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LanguageInfo {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Cell {
+    cell_type: String,
+    #[serde(default)]
+    source: Value,
+    execution_count: Option<u64>,
+    #[serde(default)]
+    outputs: Vec<Output>,
+    /// Files the markdown of the cell references as ``attachment:name`` by name and type
+    #[serde(default)]
+    attachments: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "output_type", rename_all = "snake_case")]
+enum Output {
+    Stream {
+        name: String,
+        text: Value,
+    },
+    DisplayData {
+        data: BTreeMap<String, Value>,
+    },
+    ExecuteResult {
+        execution_count: Option<u64>,
+        data: BTreeMap<String, Value>,
+    },
+    Error {
+        ename: String,
+        evalue: String,
+        traceback: Vec<String>,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+/// Renders notebooks, parsing the markdown cells with another parser
+pub struct NotebookParser {
+    markdown: Box<dyn MarkdownParser + Send>,
+}
+
+impl NotebookParser {
+    pub fn new(markdown: Box<dyn MarkdownParser + Send>) -> Self {
+        Self { markdown }
+    }
+
+    /// Renders a cell as a single element
+    fn render_cell(
+        &mut self,
+        cell: &Cell,
+        language: &str,
+        position: &CellPosition,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> String {
+        let source = multiline(&cell.source).unwrap_or_default();
+        match cell.cell_type.as_str() {
+            "markdown" => {
+                let rendered = self.markdown.parse_to_html(&source);
+                diagnostics.extend(rendered.diagnostics.into_iter().map(|mut diagnostic| {
+                    diagnostic.line = Some(position.line_of(diagnostic.line));
+                    diagnostic
+                }));
+                format!(
+                    "<div class=\"cell cell-markdown\">\n{}\n</div>",
+                    with_attachments(rendered.html.trim_end(), &cell.attachments)
+                )
+            }
+            "code" => {
+                let prompt = match cell.execution_count {
+                    Some(count) => format!("In [{}]:", count),
+                    None => "In [ ]:".to_string(),
+                };
+                let mut html = format!(
+                    "<div class=\"cell cell-code\">\n<div class=\"cell-input\"><div class=\"prompt\">{}</div>\n{}</div>",
+                    prompt,
+                    code_block(&source, Some(language))
+                );
+                for output in cell.outputs.iter() {
+                    if let Some(output) = self.render_output(output, position, diagnostics) {
+                        html.push('\n');
+                        html.push_str(&output);
+                    }
+                }
+                html.push_str("\n</div>");
+
+                html
+            }
+            _ => format!(
+                "<div class=\"cell cell-raw\">\n{}</div>",
+                code_block(&source, None)
+            ),
+        }
+    }
+
+    fn render_output(
+        &mut self,
+        output: &Output,
+        position: &CellPosition,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<String> {
+        let (prompt, contents) = match output {
+            Output::Stream { name, text } => (
+                None,
+                format!(
+                    "<pre class=\"output-{}\">{}</pre>",
+                    escape(name),
+                    escape(&strip_ansi(&multiline(text).unwrap_or_default()))
+                ),
+            ),
+            Output::DisplayData { data } => (None, self.render_data(data, position, diagnostics)?),
+            Output::ExecuteResult {
+                execution_count,
+                data,
+            } => (
+                execution_count.map(|count| format!("Out[{}]:", count)),
+                self.render_data(data, position, diagnostics)?,
+            ),
+            Output::Error {
+                ename,
+                evalue,
+                traceback,
+            } => {
+                let text = match traceback.is_empty() {
+                    true => format!("{}: {}", ename, evalue),
+                    false => traceback.join("\n"),
+                };
+                (
+                    None,
+                    format!(
+                        "<pre class=\"output-error\">{}</pre>",
+                        escape(&strip_ansi(&text))
+                    ),
+                )
+            }
+            Output::Unknown => return None,
+        };
+
+        Some(match prompt {
+            Some(prompt) => format!(
+                "<div class=\"cell-output\"><div class=\"prompt\">{}</div>\n{}</div>",
+                prompt, contents
+            ),
+            None => format!("<div class=\"cell-output\">{}</div>", contents),
+        })
+    }
+
+    /// Renders the preferred type of the data of an output
+    fn render_data(
+        &mut self,
+        data: &BTreeMap<String, Value>,
+        position: &CellPosition,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<String> {
+        for output_type in OUTPUT_TYPES {
+            let Some(value) = data.get(*output_type).and_then(multiline) else {
+                continue;
+            };
+
+            match *output_type {
+                "text/html" => return Some(value),
+                "image/svg+xml" => {
+                    return Some(format!(
+                        "<img src=\"data:image/svg+xml,{}\" alt=\"Output\" />",
+                        urlencoding::encode(&value)
+                    ))
+                }
+                "text/markdown" => {
+                    let rendered = self.markdown.parse_to_html(&value);
+                    diagnostics.extend(rendered.diagnostics.into_iter().map(|mut diagnostic| {
+                        diagnostic.line = Some(position.line);
+                        diagnostic
+                    }));
+                    return Some(rendered.html);
+                }
+                "text/latex" => {
+                    let tex = value.trim().trim_matches('$').trim();
+                    let (mathml, errors) = math::to_mathml(tex, true);
+                    // Shown as text, if it isn't just math
+                    if errors.is_empty() {
+                        return Some(mathml);
+                    }
+                }
+                "text/plain" => return Some(format!("<pre>{}</pre>", escape(&value))),
+                image => {
+                    return Some(format!(
+                        "<img src=\"{}\" alt=\"Output\" />",
+                        escape(&format!(
+                            "data:{};base64,{}",
+                            image,
+                            value.replace(['\n', '\r'], "")
+                        ))
+                    ))
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl MarkdownParser for NotebookParser {
+    fn parse_to_html(&mut self, source: &str) -> Rendered {
+        let notebook = match parse(source) {
+            Ok(notebook) => notebook,
+            Err(diagnostic) => {
+                return Rendered {
+                    html: String::new(),
+                    diagnostics: vec![diagnostic],
+                }
+            }
+        };
+
+        let language = notebook
+            .metadata
+            .language_info
+            .and_then(|info| info.name)
+            .or_else(|| {
+                notebook
+                    .metadata
+                    .kernelspec
+                    .and_then(|kernelspec| kernelspec.language)
+            })
+            .unwrap_or_else(|| "python".to_string());
+        let positions = cell_positions(source, notebook.cells.len());
+
+        let mut diagnostics = Vec::new();
+        let html = notebook
+            .cells
+            .iter()
+            .zip(positions.iter())
+            .map(|(cell, position)| {
+                self.render_cell(cell, &language, position, &mut diagnostics) + "\n"
+            })
+            .collect();
+
+        Rendered { html, diagnostics }
+    }
+
+    fn block_lines(&mut self, source: &str) -> Vec<usize> {
+        match parse(source) {
+            Ok(notebook) => cell_positions(source, notebook.cells.len())
+                .into_iter()
+                .map(|position| position.line)
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn text<'a>(&mut self, source: &'a str) -> Cow<'a, str> {
+        match parse(source) {
+            Ok(notebook) => {
+                let sources: Vec<String> = notebook
+                    .cells
+                    .iter()
+                    .filter_map(|cell| multiline(&cell.source))
+                    .collect();
+                Cow::Owned(sources.join("\n"))
+            }
+            Err(_) => Cow::Borrowed(""),
+        }
+    }
+}
+
+fn parse(source: &str) -> Result<Notebook, Diagnostic> {
+    serde_json::from_str(source).map_err(|err| {
+        Diagnostic::new(
+            Severity::Error,
+            format!("Invalid notebook: {}", err),
+            Some(err.line()).filter(|line| *line > 0),
+        )
+    })
+}
+
+/// Lines of a cell in the notebook file
+struct CellPosition {
+    /// Line the cell starts at
+    line: usize,
+    /// Line of the first line of the source, if every line of the source is on its own line
+    source_line: Option<usize>,
+}
+
+impl CellPosition {
+    /// Line in the notebook file of a line of the source of the cell
+    fn line_of(&self, source_line: Option<usize>) -> usize {
+        match (self.source_line, source_line) {
+            (Some(first), Some(line)) => first + line - 1,
+            _ => self.line,
+        }
+    }
+}
+
+/// Finds the lines of the cells in the notebook file. Notebooks written by Jupyter have every
+/// line of the source of a cell on its own line.
+fn cell_positions(source: &str, cells: usize) -> Vec<CellPosition> {
+    let cell_type = Regex::new(r#""cell_type"\s*:"#).unwrap();
+    let cell_source = Regex::new(r#""source"\s*:\s*(\[\s*\n)?"#).unwrap();
+    let line_at = |offset: usize| source[..offset].matches('\n').count() + 1;
+
+    let starts: Vec<usize> = cell_type
+        .find_iter(source)
+        .map(|found| found.start())
+        .collect();
+    if starts.len() != cells {
+        return (0..cells)
+            .map(|_| CellPosition {
+                line: 1,
+                source_line: None,
+            })
+            .collect();
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, start)| {
+            // The source is somewhere in the cell, not necessarily after the type
+            let cell_start = source[..*start].rfind('{').unwrap_or(*start);
+            let cell_end = starts.get(i + 1).copied().unwrap_or(source.len());
+            let source_line = cell_source
+                .captures(&source[cell_start..cell_end])
+                .and_then(|captures| captures.get(1))
+                .map(|list| line_at(cell_start + list.end()));
+
+            CellPosition {
+                line: line_at(cell_start),
+                source_line,
+            }
+        })
+        .collect()
+}
+
+/// Text of a string, or of the lines of a list of strings, of the notebook file
+fn multiline(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(lines) => Some(
+            lines
+                .iter()
+                .filter_map(|line| line.as_str())
+                .collect::<Vec<&str>>()
+                .concat(),
+        ),
+        _ => None,
+    }
+}
+
+/// Replaces references to attachments (``attachment:name``) with data URIs
+fn with_attachments(html: &str, attachments: &BTreeMap<String, BTreeMap<String, Value>>) -> String {
+    let mut html = html.to_string();
+    for (name, data) in attachments {
+        let Some((mime_type, value)) = data
+            .iter()
+            .find_map(|(mime_type, value)| multiline(value).map(|value| (mime_type, value)))
+        else {
+            continue;
+        };
+
+        let uri = match mime_type.as_str() {
+            "image/svg+xml" => format!("data:image/svg+xml,{}", urlencoding::encode(&value)),
+            _ => format!(
+                "data:{};base64,{}",
+                mime_type,
+                value.replace(['\n', '\r'], "")
+            ),
+        };
+        html = html.replace(
+            &format!("\"attachment:{}\"", escape(name)),
+            &format!("\"{}\"", escape(&uri)),
+        );
+    }
+
+    html
+}
+
+/// Removes the colors of terminal output
+fn strip_ansi(text: &str) -> String {
+    let ansi = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap();
+    ansi.replace_all(text, "").into_owned()
+}
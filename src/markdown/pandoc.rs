@@ -14,48 +14,434 @@
  *
  *  You should have received a copy of the GNU General Public License
  */
-use super::MarkdownParser;
+use super::{Diagnostic, MarkdownParser, Rendered, Severity};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use std::{
-    io::{BufReader, BufWriter, Read, Write},
-    process::{self, Stdio},
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{self, ChildStdin, ChildStdout, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
 };
+use tokio::sync::Semaphore;
 
+/// Options of pandoc for rendering math
+static MATH_OPTIONS: &[&str] = &["--mathml", "--mathjax", "--katex", "--webtex", "--gladtex"];
+
+/// Input formats of pandoc by the extension of files in other formats than markdown
+static FORMATS: &[(&str, &str)] = &[
+    ("rst", "rst"),
+    ("adoc", "asciidoc"),
+    ("asciidoc", "asciidoc"),
+    ("tex", "latex"),
+    ("latex", "latex"),
+    ("org", "org"),
+    ("textile", "textile"),
+    ("wiki", "mediawiki"),
+    ("mediawiki", "mediawiki"),
+    ("dbk", "docbook"),
+    ("docbook", "docbook"),
+    ("typ", "typst"),
+    ("dj", "djot"),
+    ("muse", "muse"),
+    ("t2t", "t2t"),
+    ("html", "html"),
+    ("htm", "html"),
+];
+
+/// Limits the files converted with pandoc at once, together with the limit it was created for
+static PERMITS: Lazy<Mutex<(usize, Arc<Semaphore>)>> =
+    Lazy::new(|| Mutex::new((0, Arc::new(Semaphore::new(0)))));
+
+/// Starting a pandoc worker is given up after this time
+const WORKER_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Idle pandoc workers
+static WORKERS: Mutex<Workers> = Mutex::new(Workers {
+    idle: Vec::new(),
+    unavailable: false,
+});
+
+/// Lua script run by ``pandoc lua``, converting documents until stdin is closed. A request is
+/// the input format and the length of the document on a line each, followed by the document.
+/// The response is the HTML (or the error) and the messages of pandoc, each preceded by a line
+/// with its kind and length.
+const WORKER_SCRIPT: &str = r#"
+local function convert(text, from)
+  return pandoc.write(pandoc.read(text, from), 'html5', {html_math_method = 'mathml'})
+end
+local function run(text, from)
+  if pandoc.log then
+    local log, html = pandoc.log.silence(convert, text, from)
+    return html, log
+  end
+  return convert(text, from), {}
+end
+local function send(kind, text)
+  io.stdout:write(kind, ' ', #text, '\n', text)
+end
+io.stdout:write('ready\n')
+io.stdout:flush()
+while true do
+  local from = io.stdin:read('l')
+  if not from then break end
+  local text = io.stdin:read(tonumber(io.stdin:read('l'))) or ''
+  local ok, result, log = pcall(run, text, from)
+  local messages = {}
+  for _, message in ipairs(ok and log or {}) do
+    messages[#messages + 1] = '[WARNING] ' .. tostring(message)
+  end
+  send(ok and 'html' or 'error', tostring(result))
+  send('log', table.concat(messages, '\n'))
+  io.stdout:flush()
+end
+"#;
+
+/// Settings of pandoc
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PandocOptions {
+    /// Input format with extensions (e.g. ``markdown+emoji``)
+    pub from: String,
+    /// JSON filters (``--filter``)
+    pub filters: Vec<String>,
+    /// Lua filters (``--lua-filter``)
+    pub lua_filters: Vec<String>,
+    /// Additional arguments
+    pub args: Vec<String>,
+    /// Maximum number of files converted at once
+    pub concurrency: usize,
+}
+
+impl Default for PandocOptions {
+    fn default() -> Self {
+        Self {
+            from: "markdown".to_string(),
+            filters: Vec::new(),
+            lua_filters: Vec::new(),
+            args: Vec::new(),
+            concurrency: default_concurrency(),
+        }
+    }
+}
+
+/// One file converted by pandoc per CPU
+pub fn default_concurrency() -> usize {
+    thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(4)
+}
+
+/// Returns the input format of pandoc for files with the extension, if it's a known format other
+/// than markdown
+pub fn pandoc_format(extension: &str) -> Option<&'static str> {
+    FORMATS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, format)| *format)
+}
+
+/// Returns ``true``, if the input format (with extensions, e.g. ``gfm+emoji``) is a flavour of
+/// markdown
+pub fn is_markdown_format(from: &str) -> bool {
+    let format = from.split(['+', '-']).next().unwrap_or_default();
+
+    format.starts_with("markdown") || matches!(format, "commonmark" | "commonmark_x" | "gfm")
+}
+
+/// Returns the version of the installed pandoc (e.g. ``pandoc 3.1.2``)
+pub fn pandoc_version() -> anyhow::Result<String> {
+    let output = process::Command::new("pandoc")
+        .arg("--version")
+        .output()
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => {
+                anyhow::anyhow!("pandoc isn't installed (or not in the PATH)")
+            }
+            _ => anyhow::anyhow!("Running pandoc failed: {}", err),
+        })?;
+    if !output.status.success() {
+        anyhow::bail!("Running pandoc --version failed with {}", output.status);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// Returns the semaphore limiting the files converted with pandoc at once to ``limit``. Files are
+/// converted in blocking threads, which should only be spawned after a permit was acquired.
+pub fn pandoc_permits(limit: usize) -> Arc<Semaphore> {
+    let limit = limit.max(1);
+    let mut permits = PERMITS.lock().unwrap();
+    if permits.0 != limit {
+        *permits = (limit, Arc::new(Semaphore::new(limit)));
+    }
+
+    permits.1.clone()
+}
+
+/// Pandoc workers, which aren't converting a file at the moment
+struct Workers {
+    idle: Vec<Worker>,
+    /// ``pandoc lua`` couldn't be started (e.g. pandoc is older than 3.0)
+    unavailable: bool,
+}
+
+/// Running ``pandoc lua`` process converting one document after another, so pandoc doesn't have
+/// to be started for every file. Killed when dropped.
+struct Worker {
+    child: process::Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Worker {
+    /// Starts the worker and waits until it's ready
+    fn start() -> io::Result<Self> {
+        let mut child = process::Command::new("pandoc")
+            .args(["lua", "-e", WORKER_SCRIPT])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        // Read in a thread, so a pandoc not knowing ``lua`` (and reading stdin) can be killed
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut ready = String::new();
+            let _ = tx.send(stdout.read_line(&mut ready).map(|_| (ready, stdout)));
+        });
+
+        match rx.recv_timeout(WORKER_STARTUP_TIMEOUT) {
+            Ok(Ok((ready, stdout))) if ready == "ready\n" => Ok(Self {
+                stdin: child.stdin.take().unwrap(),
+                stdout,
+                child,
+            }),
+            _ => {
+                let _ = child.kill();
+                let status = child.wait()?;
+                Err(io::Error::other(format!(
+                    "pandoc lua didn't start (exited with {})",
+                    status
+                )))
+            }
+        }
+    }
+
+    /// Converts the document. Fails, if the worker can't be used anymore, otherwise the result
+    /// is the HTML and the warnings or the error of pandoc.
+    fn convert(&mut self, from: &str, text: &str) -> io::Result<Result<(String, String), String>> {
+        write!(self.stdin, "{}\n{}\n", from, text.len())?;
+        self.stdin.write_all(text.as_bytes())?;
+        self.stdin.flush()?;
+
+        let (kind, output) = self.read_part()?;
+        let (_, messages) = self.read_part()?;
+
+        Ok(match kind.as_str() {
+            "html" => Ok((output, messages)),
+            _ => Err(output),
+        })
+    }
+
+    /// Reads a part of the response: the line with its kind and length and the text
+    fn read_part(&mut self) -> io::Result<(String, String)> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid response");
+
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        let (kind, length) = header.trim_end().split_once(' ').ok_or_else(invalid)?;
+        let mut text = vec![0; length.parse().map_err(|_| invalid())?];
+        self.stdout.read_exact(&mut text)?;
+
+        Ok((
+            kind.to_string(),
+            String::from_utf8_lossy(&text).into_owned(),
+        ))
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Converts the document with an idle worker or a new one. Returns ``None``, if the document has
+/// to be converted by starting pandoc, because no worker can be used.
+fn convert_with_worker(from: &str, text: &str) -> Option<Result<(String, String), String>> {
+    let idle = {
+        let mut workers = WORKERS.lock().unwrap();
+        if workers.unavailable {
+            return None;
+        }
+        workers.idle.pop()
+    };
+
+    let mut worker = match idle {
+        Some(worker) => worker,
+        None => match Worker::start() {
+            Ok(worker) => worker,
+            Err(err) => {
+                log::info!(
+                    "Starting pandoc for every file, since pandoc lua isn't available: {}",
+                    err
+                );
+                WORKERS.lock().unwrap().unavailable = true;
+                return None;
+            }
+        },
+    };
+
+    match worker.convert(from, text) {
+        Ok(result) => {
+            WORKERS.lock().unwrap().idle.push(worker);
+            Some(result)
+        }
+        Err(err) => {
+            log::warn!("A pandoc worker failed: {}", err);
+            None
+        }
+    }
+}
+
+/// Converts markdown with pandoc. Files are converted by a pool of ``pandoc lua`` workers, if
+/// neither filters nor arguments are configured, otherwise a process is started for every file.
+/// The caller limits the conversions running at once with [`pandoc_permits`].
 #[derive(Default)]
-pub struct PandocParser {}
+pub struct PandocParser {
+    options: PandocOptions,
+}
 
-impl MarkdownParser for PandocParser {
-    fn parse_to_html(&mut self, markdown: &str) -> String {
-        match process::Command::new("pandoc")
-            .args(["-f", "markdown"])
+impl PandocParser {
+    pub fn new(options: PandocOptions) -> Self {
+        Self { options }
+    }
+
+    /// Math is rendered as MathML, unless another option was given. Other options require
+    /// scripts or sites, which aren't included.
+    fn math_args(&self) -> &'static [&'static str] {
+        let configured = self.options.args.iter().any(|arg| {
+            MATH_OPTIONS
+                .iter()
+                .any(|option| arg == option || arg.starts_with(&format!("{}=", option)))
+        });
+
+        if configured {
+            &[]
+        } else {
+            &["--mathml"]
+        }
+    }
+
+    /// Converts the markdown with a worker or else a pandoc process and returns the HTML and the
+    /// warnings of pandoc
+    fn run(&self, markdown: &str) -> Result<(String, String), String> {
+        let options = &self.options;
+        if options.filters.is_empty() && options.lua_filters.is_empty() && options.args.is_empty() {
+            if let Some(result) = convert_with_worker(&options.from, markdown) {
+                return result;
+            }
+        }
+
+        self.run_process(markdown)
+    }
+
+    /// Runs pandoc and returns the HTML and the warnings it wrote
+    fn run_process(&self, markdown: &str) -> Result<(String, String), String> {
+        let mut command = process::Command::new("pandoc");
+        command
+            .args(["-f", &self.options.from])
             .args(["-t", "html5"])
-            .arg("-")
-            .args(["-o", "-"])
+            .args(self.math_args());
+        for filter in self.options.filters.iter() {
+            command.args(["--filter", filter]);
+        }
+        for filter in self.options.lua_filters.iter() {
+            command.args(["--lua-filter", filter]);
+        }
+        let mut child = command
+            .args(&self.options.args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
-        {
-            Ok(mut handle) => {
-                {
-                    let mut stdin = handle.stdin.as_ref().unwrap();
-                    let mut writer = BufWriter::new(&mut stdin);
-                    let _ = writer.write_all(markdown.as_bytes());
-                    drop(writer);
-                }
+            .map_err(|err| format!("Starting pandoc failed: {}", err))?;
 
-                if handle.wait().is_err() {
-                    return "Parsing markdown with pandoc failed".to_string();
-                }
+        // Written in a thread, so a full stdout doesn't block pandoc
+        let mut stdin = child.stdin.take().unwrap();
+        let markdown = markdown.to_string();
+        let writer = thread::spawn(move || stdin.write_all(markdown.as_bytes()));
+        let output = child
+            .wait_with_output()
+            .map_err(|err| format!("Running pandoc failed: {}", err))?;
+        let _ = writer.join();
 
-                let mut stdout = handle.stdout.unwrap();
-                let mut reader = BufReader::new(&mut stdout);
-                let mut stdout = String::with_capacity(1024 * 10);
-                if reader.read_to_string(&mut stdout).is_err() {
-                    return "Parsing markdown with pandoc failed".to_string();
-                }
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if !output.status.success() {
+            return Err(if stderr.is_empty() {
+                format!("pandoc exited with {}", output.status)
+            } else {
+                stderr
+            });
+        }
 
-                stdout
+        Ok((String::from_utf8_lossy(&output.stdout).into_owned(), stderr))
+    }
+}
+
+/// Converts the messages pandoc wrote to stderr to diagnostics. Messages start with their level
+/// (e.g. ``[WARNING]``) and may continue on indented lines.
+pub fn pandoc_diagnostics(stderr: &str, severity: Severity) -> Vec<Diagnostic> {
+    let line = Regex::new(r"line (\d+)").unwrap();
+
+    let mut messages: Vec<String> = Vec::new();
+    for output_line in stderr.lines().filter(|line| !line.trim().is_empty()) {
+        match messages.last_mut() {
+            Some(message) if !output_line.starts_with('[') => {
+                message.push('\n');
+                message.push_str(output_line.trim());
+            }
+            _ => messages.push(output_line.trim().to_string()),
+        }
+    }
+
+    messages
+        .into_iter()
+        .map(|message| {
+            let source_line = line
+                .captures(&message)
+                .and_then(|captures| captures[1].parse().ok());
+            let message = match message.strip_prefix('[').and_then(|m| m.split_once("] ")) {
+                Some((_, message)) => message.to_string(),
+                None => message,
+            };
+
+            Diagnostic::new(severity, message, source_line)
+        })
+        .collect()
+}
+
+impl MarkdownParser for PandocParser {
+    fn parse_to_html(&mut self, markdown: &str) -> Rendered {
+        match self.run(markdown) {
+            Ok((html, warnings)) => Rendered {
+                html,
+                diagnostics: pandoc_diagnostics(&warnings, Severity::Warning),
+            },
+            Err(err) => {
+                log::error!("Parsing markdown with pandoc failed: {}", err);
+                Rendered {
+                    html: String::new(),
+                    diagnostics: pandoc_diagnostics(&err, Severity::Error),
+                }
             }
-            _ => "Parsing markdown with pandoc failed".to_string(),
         }
     }
 }
@@ -0,0 +1,1694 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+//! Parses reStructuredText to HTML. Supports the commonly used parts of docutils (and the
+//! directives and roles of Sphinx, which don't need a project): sections, lists, tables,
+//! literal blocks, admonitions, images, footnotes, hyperlinks, substitutions and math.
+
+use std::collections::{HashMap, HashSet};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::{
+    html::{alert, code_block, escape, footnote, footnote_reference, spanning_table, table},
+    math, Diagnostic, MarkdownParser, Rendered, Severity,
+};
+
+/// Characters allowed in section adornments and transitions
+const ADORNMENTS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+/// Characters allowed before inline markup
+const MARKUP_START: &str = "-:/'\"<([{";
+/// Characters allowed after inline markup
+const MARKUP_END: &str = "-.,:;!?\\/'\")]}>";
+/// Maximum number of nested blocks (e.g. block quotes). Blocks are parsed recursively, so
+/// deeper ones would overflow the stack.
+const MAX_NESTING: usize = 100;
+
+static TARGET: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\.\. _(`[^`]+`|[^:`][^:]*):(?:\s+(.*))?$").unwrap());
+static ANONYMOUS_TARGET: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?:\.\. __:|__)(?:\s+(.*))?$").unwrap());
+static AUTO_NUMBERED: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\.\. \[#([\w-]*)\]").unwrap());
+static SUBSTITUTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\.\. \|([^|]+)\|\s+([\w-]+)::\s*(.*)$").unwrap());
+static ENUMERATOR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^(?:(\d+|#|[A-Za-z]|[ivxlcdm]+|[IVXLCDM]+)([.)])|\((\d+|#|[A-Za-z]|[ivxlcdm]+|[IVXLCDM]+)\))(?:\s+|$)",
+    )
+    .unwrap()
+});
+static FIELD: Lazy<Regex> = Lazy::new(|| Regex::new(r"^:((?:[^:\\]|\\.)+):(?:\s+(.*))?$").unwrap());
+static FOOTNOTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\[(#[\w-]*|\*|\d+|[A-Za-z][\w.-]*)\](?:\s+(.*))?$").unwrap());
+static DIRECTIVE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([A-Za-z0-9][\w:.+-]*?)::(?:\s+(.*))?$").unwrap());
+static GRID_TABLE_BORDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\+(?:[-=]+\+)+$").unwrap());
+static SIMPLE_TABLE_BORDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^=+(?: +=+)+$").unwrap());
+/// Reference with an embedded target (``text <target>``)
+static EMBEDDED: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)^(.*?)\s*<([^<>]+)>$").unwrap());
+
+/// Directives of admonitions with their title and the type of alert they are shown as
+static ADMONITIONS: &[(&str, &str, &str)] = &[
+    ("note", "Note", "note"),
+    ("seealso", "See also", "note"),
+    ("tip", "Tip", "tip"),
+    ("hint", "Hint", "tip"),
+    ("important", "Important", "important"),
+    ("attention", "Attention", "important"),
+    ("warning", "Warning", "warning"),
+    ("caution", "Caution", "caution"),
+    ("danger", "Danger", "caution"),
+    ("error", "Error", "caution"),
+];
+
+/// Directives, which don't change the shown contents (the outline of the page and the list of
+/// files are shown anyway)
+static IGNORED_DIRECTIVES: &[&str] = &[
+    "contents",
+    "toctree",
+    "index",
+    "highlight",
+    "meta",
+    "sectnum",
+    "default-role",
+    "role",
+    "title",
+    "header",
+    "footer",
+    "currentmodule",
+    "module",
+];
+
+/// Roles referencing other parts of the documentation, they are shown as their title
+static REFERENCE_ROLES: &[&str] = &["ref", "doc", "term", "numref", "any", "download"];
+
+/// Line of the source with its number (starting at 1)
+type Line = (usize, String);
+
+#[derive(Default)]
+pub struct RestructuredTextParser;
+
+impl MarkdownParser for RestructuredTextParser {
+    fn parse_to_html(&mut self, source: &str) -> Rendered {
+        let (blocks, diagnostics) = parse(source);
+
+        Rendered {
+            html: blocks.into_iter().map(|block| block.html + "\n").collect(),
+            diagnostics,
+        }
+    }
+
+    fn block_lines(&mut self, source: &str) -> Vec<usize> {
+        parse(source)
+            .0
+            .into_iter()
+            .map(|block| block.line)
+            .collect()
+    }
+}
+
+/// Top-level element of the HTML
+struct Block {
+    /// Line the element starts at
+    line: usize,
+    html: String,
+}
+
+fn parse(source: &str) -> (Vec<Block>, Vec<Diagnostic>) {
+    let lines: Vec<Line> = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, expand_tabs(line).trim_end().to_string()))
+        .collect();
+
+    let mut document = Document::new(&lines);
+    let blocks = document.parse_blocks(&lines, true);
+
+    (blocks, document.diagnostics)
+}
+
+/// Substitution definition (``.. |name| replace:: text``)
+enum Substitution {
+    Text(String),
+    Image(String),
+}
+
+struct Document {
+    /// Adornments (character and whether there is an overline) of the section levels in the
+    /// order they appeared
+    title_styles: Vec<(char, bool)>,
+    /// Anchors of the section titles by the line of the title
+    title_ids: HashMap<usize, String>,
+    /// Hyperlink targets by their normalized name
+    targets: HashMap<String, String>,
+    /// Targets of anonymous hyperlinks in order
+    anonymous_targets: Vec<String>,
+    /// Number of anonymous hyperlinks found
+    anonymous_references: usize,
+    substitutions: HashMap<String, Substitution>,
+    /// Numbers of the auto-numbered footnotes (in the order of their definitions) by label and
+    /// of the ones without a label in order
+    footnote_numbers: HashMap<String, usize>,
+    anonymous_footnotes: Vec<usize>,
+    /// Numbers of auto-numbered footnote references and definitions without a label found
+    footnote_references: usize,
+    footnote_definitions: usize,
+    /// Depth of substitutions being replaced, to stop at recursive ones
+    substituting: usize,
+    /// Number of nested blocks being parsed
+    depth: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    /// Collects targets and substitutions, which can be used before their definition
+    fn new(lines: &[Line]) -> Self {
+        let mut document = Document {
+            title_styles: Vec::new(),
+            title_ids: HashMap::new(),
+            targets: HashMap::new(),
+            anonymous_targets: Vec::new(),
+            anonymous_references: 0,
+            substitutions: HashMap::new(),
+            footnote_numbers: HashMap::new(),
+            anonymous_footnotes: Vec::new(),
+            footnote_references: 0,
+            footnote_definitions: 0,
+            substituting: 0,
+            depth: 0,
+            diagnostics: Vec::new(),
+        };
+
+        // Anchors of the titles, so references to titles and to targets before them work
+        let mut ids = HashSet::new();
+        let mut title_lines = Vec::new();
+        for i in 0..lines.len() {
+            if let Some(title) = title_at(lines, i) {
+                let id = unique_id(&mut ids, &title);
+                document
+                    .targets
+                    .entry(normalize_name(&title))
+                    .or_insert_with(|| format!("#{}", id));
+                document.title_ids.insert(lines[i].0, id);
+                title_lines.push(i);
+            }
+        }
+
+        for (i, (_, text)) in lines.iter().enumerate() {
+            let indent = indentation(text);
+            let text = text.trim_start();
+            if let Some(captures) = TARGET.captures(text) {
+                let name = normalize_name(captures[1].trim_matches('`'));
+                let mut uri: String = captures
+                    .get(2)
+                    .map(|uri| uri.as_str().to_string())
+                    .unwrap_or_default();
+                for (_, continued) in lines[i + 1..]
+                    .iter()
+                    .take_while(|(_, line)| indentation(line) > indent && !is_blank(line))
+                {
+                    uri.push_str(continued.trim());
+                }
+
+                let href = if uri.is_empty() {
+                    // Internal targets point to the following title
+                    match title_lines.iter().find(|line| **line > i) {
+                        Some(line) => format!("#{}", document.title_ids[&lines[*line].0]),
+                        None => format!("#{}", slug(&name)),
+                    }
+                } else {
+                    uri
+                };
+                document.targets.insert(name, href);
+            } else if let Some(captures) = ANONYMOUS_TARGET.captures(text) {
+                document.anonymous_targets.push(
+                    captures
+                        .get(1)
+                        .map(|uri| uri.as_str().trim().to_string())
+                        .unwrap_or_default(),
+                );
+            } else if let Some(captures) = AUTO_NUMBERED.captures(text) {
+                let number =
+                    document.footnote_numbers.len() + document.anonymous_footnotes.len() + 1;
+                match &captures[1] {
+                    "" => document.anonymous_footnotes.push(number),
+                    label => {
+                        document.footnote_numbers.insert(label.to_string(), number);
+                    }
+                }
+            } else if let Some(captures) = SUBSTITUTION.captures(text) {
+                let value = captures[3].trim().to_string();
+                let substitution = match &captures[2] {
+                    "image" => Substitution::Image(value),
+                    _ => Substitution::Text(value),
+                };
+                document
+                    .substitutions
+                    .insert(captures[1].to_string(), substitution);
+            }
+        }
+
+        document
+    }
+
+    fn warn(&mut self, line: usize, message: String) {
+        self.diagnostics
+            .push(Diagnostic::new(Severity::Warning, message, Some(line)));
+    }
+
+    /// Parses the lines (indented relative to their context) to blocks. Sections are only
+    /// allowed at the top-level. Blocks nested too deeply are shown as literal text.
+    fn parse_blocks(&mut self, lines: &[Line], top_level: bool) -> Vec<Block> {
+        let Some(&(number, _)) = lines.first() else {
+            return Vec::new();
+        };
+        if self.depth >= MAX_NESTING {
+            self.warn(number, "Blocks are nested too deeply".to_string());
+            let text: Vec<&str> = lines.iter().map(|(_, line)| line.as_str()).collect();
+            return vec![Block {
+                line: number,
+                html: code_block(&text.join("\n"), None),
+            }];
+        }
+
+        self.depth += 1;
+        let blocks = self.parse_blocks_in(lines, top_level);
+        self.depth -= 1;
+
+        blocks
+    }
+
+    fn parse_blocks_in(&mut self, lines: &[Line], top_level: bool) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let (number, text) = (lines[i].0, lines[i].1.as_str());
+            if is_blank(text) {
+                i += 1;
+                continue;
+            }
+
+            let html = if indentation(text) > 0 {
+                let (quoted, end) = indented(lines, i);
+                i = end;
+                Some(format!(
+                    "<blockquote>\n{}\n</blockquote>",
+                    self.render_blocks(&quoted)
+                ))
+            } else if let Some((html, end)) = top_level.then(|| self.section(lines, i)).flatten() {
+                i = end;
+                Some(html)
+            } else if adornment(text).is_some_and(|_| text.len() >= 4)
+                && lines.get(i + 1).is_none_or(|(_, next)| is_blank(next))
+            {
+                i += 1;
+                Some("<hr />".to_string())
+            } else if text == ".." || text.starts_with(".. ") {
+                let (html, end) = self.explicit_markup(lines, i);
+                i = end;
+                html
+            } else if text == ">>>" || text.starts_with(">>> ") {
+                let end = block_end(lines, i);
+                let code: Vec<&str> = lines[i..end]
+                    .iter()
+                    .map(|(_, line)| line.as_str())
+                    .collect();
+                i = end;
+                Some(code_block(&code.join("\n"), Some("python")))
+            } else if let Some((html, end)) = self.grid_table(lines, i) {
+                i = end;
+                Some(html)
+            } else if let Some((html, end)) = self.simple_table(lines, i) {
+                i = end;
+                Some(html)
+            } else if bullet(text).is_some() {
+                // Items with another bullet start another list
+                let (items, end) = list_items(lines, i, |line| {
+                    bullet(line).filter(|_| line.chars().next() == text.chars().next())
+                });
+                i = end;
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|item| format!("<li>{}</li>", self.render_item(item)))
+                    .collect();
+                Some(format!("<ul>\n{}\n</ul>", items.join("\n")))
+            } else if let Some((html, end)) = self.enumerated_list(lines, i) {
+                i = end;
+                Some(html)
+            } else if FIELD.is_match(text) {
+                let (html, end) = self.field_list(lines, i);
+                i = end;
+                Some(html)
+            } else if text == "|" || text.starts_with("| ") {
+                let (html, end) = self.line_block(lines, i);
+                i = end;
+                Some(html)
+            } else if lines
+                .get(i + 1)
+                .is_some_and(|(_, next)| !is_blank(next) && indentation(next) > 0)
+                && !text.ends_with("::")
+            {
+                let (html, end) = self.definition_list(lines, i);
+                i = end;
+                Some(html)
+            } else {
+                let end = block_end(lines, i);
+                let text: Vec<&str> = lines[i..end].iter().map(|(_, line)| line.trim()).collect();
+                let mut text = text.join("\n");
+                i = end;
+
+                // A paragraph ending with ``::`` introduces a literal block
+                if text.ends_with("::") {
+                    text.truncate(text.len() - 1);
+                    if text == ":" || text.ends_with([' ', '\n']) {
+                        text.truncate(text.len() - 1);
+                    }
+                    let text = text.trim_end();
+                    if !text.is_empty() {
+                        let html = format!("<p>{}</p>", self.inline(text, number));
+                        blocks.push(Block { line: number, html });
+                    }
+
+                    let start = i + lines[i..]
+                        .iter()
+                        .take_while(|(_, line)| is_blank(line))
+                        .count();
+                    if lines
+                        .get(start)
+                        .is_some_and(|(_, line)| indentation(line) > 0)
+                    {
+                        let (literal, end) = indented(lines, start);
+                        let code: Vec<&str> =
+                            literal.iter().map(|(_, line)| line.as_str()).collect();
+                        blocks.push(Block {
+                            line: lines[start].0,
+                            html: code_block(&code.join("\n"), None),
+                        });
+                        i = end;
+                    }
+                    continue;
+                }
+
+                Some(format!("<p>{}</p>", self.inline(&text, number)))
+            };
+
+            if let Some(html) = html {
+                blocks.push(Block { line: number, html });
+            }
+        }
+
+        blocks
+    }
+
+    fn render_blocks(&mut self, lines: &[Line]) -> String {
+        self.parse_blocks(lines, false)
+            .into_iter()
+            .map(|block| block.html)
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Renders the contents of a list item or a table cell, a single paragraph without ``<p>``
+    fn render_item(&mut self, lines: &[Line]) -> String {
+        let blocks = self.parse_blocks(lines, false);
+        match &blocks[..] {
+            [block] if block.html.starts_with("<p>") && block.html.ends_with("</p>") => {
+                block.html[3..block.html.len() - 4].to_string()
+            }
+            _ => blocks
+                .into_iter()
+                .map(|block| block.html)
+                .collect::<Vec<String>>()
+                .join("\n"),
+        }
+    }
+
+    /// Renders the section title at the line
+    fn section(&mut self, lines: &[Line], i: usize) -> Option<(String, usize)> {
+        let title_line = if adornment(&lines[i].1).is_some() {
+            i + 1
+        } else {
+            i
+        };
+        let title = title_at(lines, title_line)?;
+        let overline = title_line != i;
+        let style = (
+            adornment(&lines[title_line + 1].1).unwrap_or_default(),
+            overline,
+        );
+
+        let level = match self.title_styles.iter().position(|known| *known == style) {
+            Some(level) => level,
+            None => {
+                self.title_styles.push(style);
+                self.title_styles.len() - 1
+            }
+        };
+        let level = (level + 1).min(6);
+        let number = lines[title_line].0;
+        let id = self.title_ids.get(&number).cloned().unwrap_or_default();
+
+        Some((
+            format!(
+                "<h{0} id=\"{1}\">{2}</h{0}>",
+                level,
+                escape(&id),
+                self.inline(&title, number)
+            ),
+            title_line + 2,
+        ))
+    }
+
+    /// Renders a directive, comment, footnote, hyperlink target or substitution definition
+    fn explicit_markup(&mut self, lines: &[Line], i: usize) -> (Option<String>, usize) {
+        let number = lines[i].0;
+        let rest = lines[i].1[2..].trim();
+        let (body, end) = indented(lines, i + 1);
+
+        if let Some(captures) = FOOTNOTE.captures(rest) {
+            let label = captures[1].to_string();
+            let text = captures.get(2).map(|text| text.as_str().to_string());
+            let (id, label) = self.footnote_label(&label, false);
+            let mut lines = Vec::new();
+            if let Some(text) = text {
+                lines.push((number, text));
+            }
+            lines.extend(body);
+            let contents = self.render_blocks(&lines);
+
+            return (Some(footnote(&id, &label, &contents)), end);
+        }
+
+        // Targets and substitutions were collected before
+        if rest.starts_with('_') || rest.starts_with('|') {
+            return (None, end);
+        }
+
+        match DIRECTIVE.captures(rest) {
+            Some(captures) => {
+                let name = captures[1].to_lowercase();
+                let argument = captures
+                    .get(2)
+                    .map(|argument| argument.as_str().trim().to_string())
+                    .unwrap_or_default();
+
+                (self.directive(&name, &argument, &body, number), end)
+            }
+            // Comment
+            None => (None, end),
+        }
+    }
+
+    fn directive(
+        &mut self,
+        name: &str,
+        argument: &str,
+        body: &[Line],
+        number: usize,
+    ) -> Option<String> {
+        // Options (``:name: value``) are at the start of the body
+        let mut options: HashMap<String, String> = HashMap::new();
+        let mut content_start = 0;
+        for (_, line) in body.iter() {
+            match FIELD.captures(line) {
+                Some(captures) => {
+                    options.insert(
+                        captures[1].to_lowercase(),
+                        captures
+                            .get(2)
+                            .map(|value| value.as_str().trim().to_string())
+                            .unwrap_or_default(),
+                    );
+                    content_start += 1;
+                }
+                None => break,
+            }
+        }
+        let content = dedent(&body[content_start..]);
+        let text: Vec<&str> = content.iter().map(|(_, line)| line.as_str()).collect();
+        let text = text.join("\n");
+        let text = text.trim_matches('\n');
+
+        if let Some((_, title, kind)) = ADMONITIONS
+            .iter()
+            .find(|(admonition, ..)| *admonition == name)
+        {
+            let mut lines = Vec::new();
+            if !argument.is_empty() {
+                lines.push((number, argument.to_string()));
+            }
+            lines.extend(content);
+
+            return Some(alert(kind, title, &self.render_blocks(&lines)));
+        }
+
+        match name {
+            "code" | "code-block" | "sourcecode" => {
+                Some(code_block(text, argument.split_whitespace().next()))
+            }
+            "math" => {
+                let tex = if text.is_empty() { argument } else { text };
+                let mut html = String::new();
+                for equation in tex.split("\n\n").filter(|tex| !tex.trim().is_empty()) {
+                    let (mathml, errors) = math::to_mathml(equation.trim(), true);
+                    for error in errors {
+                        self.warn(number, error);
+                    }
+                    html.push_str(&mathml);
+                }
+
+                Some(format!("<p>{}</p>", html))
+            }
+            "admonition" => {
+                let title = self.inline(argument, number);
+                let contents = self.render_blocks(&content);
+                Some(alert("note", &title, &contents))
+            }
+            "image" => Some(format!("<p>{}</p>", image(argument, &options))),
+            "figure" => {
+                let contents = self.render_blocks(&content);
+                Some(format!(
+                    "<figure>{}{}</figure>",
+                    image(argument, &options),
+                    if contents.is_empty() {
+                        String::new()
+                    } else {
+                        format!("\n<figcaption>\n{}\n</figcaption>\n", contents)
+                    }
+                ))
+            }
+            "topic" | "sidebar" => {
+                let title = self.inline(argument, number);
+                let contents = self.render_blocks(&content);
+                Some(format!(
+                    "<aside class=\"{}\">\n<p class=\"{0}-title\">{}</p>\n{}\n</aside>",
+                    name, title, contents
+                ))
+            }
+            "rubric" => Some(format!(
+                "<p class=\"rubric\">{}</p>",
+                self.inline(argument, number)
+            )),
+            "container" => {
+                let contents = self.render_blocks(&content);
+                Some(format!(
+                    "<div class=\"{}\">\n{}\n</div>",
+                    escape(argument),
+                    contents
+                ))
+            }
+            "epigraph" | "highlights" | "pull-quote" => Some(format!(
+                "<blockquote>\n{}\n</blockquote>",
+                self.render_blocks(&content)
+            )),
+            "raw" if argument.split_whitespace().any(|format| format == "html") => {
+                Some(text.to_string())
+            }
+            "raw" => None,
+            "csv-table" => Some(self.csv_table(argument, &options, &content, number)),
+            "list-table" => Some(self.list_table(argument, &options, &content, number)),
+            "table" => {
+                let contents = self.render_blocks(&content);
+                Some(match contents.strip_prefix("<table>") {
+                    Some(table) if !argument.is_empty() => format!(
+                        "<table>\n<caption>{}</caption>{}",
+                        self.inline(argument, number),
+                        table
+                    ),
+                    _ => contents,
+                })
+            }
+            "include" | "literalinclude" => {
+                self.warn(number, format!("The {} directive isn't supported", name));
+                None
+            }
+            _ if IGNORED_DIRECTIVES.contains(&name) => None,
+            _ => {
+                self.warn(number, format!("Unknown directive \"{}\"", name));
+                None
+            }
+        }
+    }
+
+    fn enumerated_list(&mut self, lines: &[Line], i: usize) -> Option<(String, usize)> {
+        let first = ENUMERATOR.captures(&lines[i].1)?;
+        let style = list_style(&first);
+        let enumerator = first
+            .get(1)
+            .or_else(|| first.get(3))
+            .map(|found| found.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        // A single line starting like an item (e.g. ``A. Smith``) is a paragraph
+        let next = lines.get(i + 1).map(|(_, line)| line.as_str());
+        if !next
+            .is_none_or(|next| is_blank(next) || indentation(next) > 0 || ENUMERATOR.is_match(next))
+        {
+            return None;
+        }
+
+        let (items, end) = list_items(lines, i, |text| {
+            let captures = ENUMERATOR.captures(text)?;
+            (list_style(&captures) == style).then(|| captures[0].len())
+        });
+        let items: Vec<String> = items
+            .iter()
+            .map(|item| format!("<li>{}</li>", self.render_item(item)))
+            .collect();
+
+        let (kind, start) = enumerator_value(&enumerator);
+        let mut attributes = String::new();
+        if let Some(kind) = kind {
+            attributes.push_str(&format!(" type=\"{}\"", kind));
+        }
+        if start != 1 {
+            attributes.push_str(&format!(" start=\"{}\"", start));
+        }
+
+        Some((
+            format!("<ol{}>\n{}\n</ol>", attributes, items.join("\n")),
+            end,
+        ))
+    }
+
+    fn field_list(&mut self, lines: &[Line], mut i: usize) -> (String, usize) {
+        let mut html = String::from("<dl class=\"field-list\">");
+        while let Some(captures) = lines.get(i).and_then(|(_, line)| FIELD.captures(line)) {
+            let number = lines[i].0;
+            let name = captures[1].to_string();
+            let (mut body, end) = indented(lines, i + 1);
+            if let Some(text) = captures.get(2) {
+                body.insert(0, (number, text.as_str().to_string()));
+            }
+
+            let name = self.inline(&name, number);
+            let contents = self.render_item(&body);
+            html.push_str(&format!("\n<dt>{}</dt>\n<dd>{}</dd>", name, contents));
+            i = end;
+            while lines.get(i).is_some_and(|(_, line)| is_blank(line)) {
+                i += 1;
+            }
+        }
+        html.push_str("\n</dl>");
+
+        (html, i)
+    }
+
+    fn line_block(&mut self, lines: &[Line], i: usize) -> (String, usize) {
+        let end = block_end(lines, i);
+        let mut block_lines: Vec<(usize, String)> = Vec::new();
+        for (number, line) in lines[i..end].iter() {
+            match line.strip_prefix('|') {
+                Some(text) => block_lines.push((*number, text.trim().to_string())),
+                // Continuation of the previous line
+                None => {
+                    if let Some((_, previous)) = block_lines.last_mut() {
+                        previous.push(' ');
+                        previous.push_str(line.trim());
+                    }
+                }
+            }
+        }
+
+        let mut html = String::from("<div class=\"line-block\">");
+        for (number, text) in block_lines {
+            html.push_str(&format!(
+                "\n<div class=\"line\">{}</div>",
+                self.inline(&text, number)
+            ));
+        }
+        html.push_str("\n</div>");
+
+        (html, end)
+    }
+
+    fn definition_list(&mut self, lines: &[Line], mut i: usize) -> (String, usize) {
+        let mut html = String::from("<dl>");
+        loop {
+            let number = lines[i].0;
+            let term = lines[i].1.clone();
+            let (definition, end) = indented(lines, i + 1);
+
+            let term = match term.split_once(" : ") {
+                Some((term, classifier)) => format!(
+                    "{} <span class=\"classifier\">{}</span>",
+                    self.inline(term, number),
+                    self.inline(classifier, number)
+                ),
+                None => self.inline(&term, number),
+            };
+            let definition = self.render_blocks(&definition);
+            html.push_str(&format!("\n<dt>{}</dt>\n<dd>\n{}\n</dd>", term, definition));
+
+            i = end;
+            let next = i + lines[i..]
+                .iter()
+                .take_while(|(_, line)| is_blank(line))
+                .count();
+            let continues = lines.get(next).is_some_and(|(_, line)| {
+                indentation(line) == 0
+                    && !line.starts_with("..")
+                    && bullet(line).is_none()
+                    && !ENUMERATOR.is_match(line)
+                    && !FIELD.is_match(line)
+            }) && lines
+                .get(next + 1)
+                .is_some_and(|(_, line)| !is_blank(line) && indentation(line) > 0);
+            if !continues {
+                break;
+            }
+            i = next;
+        }
+        html.push_str("\n</dl>");
+
+        (html, i)
+    }
+
+    /// Renders a table drawn with ``+``, ``-``, ``=`` and ``|``
+    fn grid_table(&mut self, lines: &[Line], i: usize) -> Option<(String, usize)> {
+        if !GRID_TABLE_BORDER.is_match(&lines[i].1) {
+            return None;
+        }
+
+        let bounds: Vec<usize> = lines[i]
+            .1
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| *c == '+')
+            .map(|(i, _)| i)
+            .collect();
+        let end = i + lines[i..]
+            .iter()
+            .take_while(|(_, line)| line.starts_with(['+', '|']))
+            .count();
+
+        // Lines of every row, a row ends at a border
+        let mut rows: Vec<Vec<&Line>> = Vec::new();
+        let mut header_rows = 0;
+        let mut row = Vec::new();
+        for line in lines[i + 1..end].iter() {
+            if line.1.starts_with('+') {
+                rows.push(std::mem::take(&mut row));
+                if line.1.contains('=') {
+                    header_rows = rows.len();
+                }
+            } else {
+                row.push(line);
+            }
+        }
+
+        // Cells span the columns, which aren't separated by ``|`` on all lines of the row
+        let mut cells: Vec<Vec<(String, usize)>> = Vec::new();
+        for row in rows {
+            let lines: Vec<Vec<char>> =
+                row.iter().map(|(_, line)| line.chars().collect()).collect();
+            let separators: Vec<usize> = bounds
+                .iter()
+                .enumerate()
+                .filter(|(k, bound)| {
+                    *k == 0
+                        || *k == bounds.len() - 1
+                        || lines.iter().all(|chars| chars.get(**bound) == Some(&'|'))
+                })
+                .map(|(k, _)| k)
+                .collect();
+
+            let row_cells = separators
+                .windows(2)
+                .map(|columns| {
+                    let cell: Vec<Line> = row
+                        .iter()
+                        .zip(lines.iter())
+                        .map(|((number, _), chars)| {
+                            let from = (bounds[columns[0]] + 1).min(chars.len());
+                            let to = bounds[columns[1]].min(chars.len());
+                            let text: String = chars[from..to].iter().collect();
+                            (*number, text.trim_end().to_string())
+                        })
+                        .collect();
+                    (self.render_item(&dedent(&cell)), columns[1] - columns[0])
+                })
+                .collect();
+            cells.push(row_cells);
+        }
+        let body = cells.split_off(header_rows);
+
+        Some((spanning_table(None, &cells, &body), end))
+    }
+
+    /// Renders a table with columns marked by ``=``
+    fn simple_table(&mut self, lines: &[Line], i: usize) -> Option<(String, usize)> {
+        if !SIMPLE_TABLE_BORDER.is_match(&lines[i].1) {
+            return None;
+        }
+
+        let chars: Vec<char> = lines[i].1.chars().collect();
+        let starts: Vec<usize> = (0..chars.len())
+            .filter(|k| chars[*k] == '=' && (*k == 0 || chars[k - 1] == ' '))
+            .collect();
+
+        let mut rows: Vec<Vec<Vec<Line>>> = Vec::new();
+        let mut header_rows = 0;
+        let mut j = i + 1;
+        let mut closed = false;
+        while j < lines.len() {
+            let (number, line) = (lines[j].0, lines[j].1.as_str());
+            j += 1;
+            if SIMPLE_TABLE_BORDER.is_match(line) {
+                if lines.get(j).is_none_or(|(_, next)| is_blank(next)) {
+                    closed = true;
+                    break;
+                }
+                header_rows = rows.len();
+                continue;
+            }
+            if is_blank(line) || line.trim().chars().all(|c| c == '-' || c == ' ') {
+                continue;
+            }
+
+            let chars: Vec<char> = line.chars().collect();
+            let cells: Vec<Line> = starts
+                .iter()
+                .enumerate()
+                .map(|(column, start)| {
+                    let from = (*start).min(chars.len());
+                    let to = match starts.get(column + 1) {
+                        Some(next) => (*next).min(chars.len()),
+                        None => chars.len(),
+                    };
+                    let text: String = chars[from..to].iter().collect();
+                    (number, text.trim().to_string())
+                })
+                .collect();
+
+            // Rows with an empty first column continue the previous row
+            match rows.last_mut() {
+                Some(row) if cells[0].1.is_empty() => {
+                    for (cell, line) in row.iter_mut().zip(cells) {
+                        cell.push(line);
+                    }
+                }
+                _ => rows.push(cells.into_iter().map(|cell| vec![cell]).collect()),
+            }
+        }
+        if !closed {
+            return None;
+        }
+
+        let mut rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| self.render_item(cell)).collect())
+            .collect();
+        let body = rows.split_off(header_rows);
+
+        Some((table(None, &rows, &body), j))
+    }
+
+    fn csv_table(
+        &mut self,
+        title: &str,
+        options: &HashMap<String, String>,
+        content: &[Line],
+        number: usize,
+    ) -> String {
+        if options.contains_key("file") || options.contains_key("url") {
+            self.warn(number, "Tables from files aren't supported".to_string());
+        }
+        let delimiter = options
+            .get("delim")
+            .and_then(|delimiter| delimiter.chars().next())
+            .unwrap_or(',');
+
+        let mut header: Vec<Vec<String>> = Vec::new();
+        if let Some(cells) = options.get("header") {
+            header.push(
+                split_csv(cells, ',')
+                    .iter()
+                    .map(|cell| self.inline(cell, number))
+                    .collect(),
+            );
+        }
+        let mut rows: Vec<Vec<String>> = content
+            .iter()
+            .filter(|(_, line)| !is_blank(line))
+            .map(|(number, line)| {
+                split_csv(line, delimiter)
+                    .iter()
+                    .map(|cell| self.inline(cell, *number))
+                    .collect()
+            })
+            .collect();
+        let header_rows = header_rows(options).min(rows.len());
+        header.extend(rows.drain(..header_rows));
+
+        let caption = (!title.is_empty()).then(|| self.inline(title, number));
+        table(caption, &header, &rows)
+    }
+
+    fn list_table(
+        &mut self,
+        title: &str,
+        options: &HashMap<String, String>,
+        content: &[Line],
+        number: usize,
+    ) -> String {
+        let start = content
+            .iter()
+            .position(|(_, line)| !is_blank(line))
+            .unwrap_or(content.len());
+        let (items, _) = list_items(content, start, bullet);
+
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        for item in items.iter() {
+            let start = item
+                .iter()
+                .position(|(_, line)| !is_blank(line))
+                .unwrap_or(item.len());
+            let (cells, _) = list_items(item, start, bullet);
+            rows.push(cells.iter().map(|cell| self.render_item(cell)).collect());
+        }
+        let body = rows.split_off(header_rows(options).min(rows.len()));
+
+        let caption = (!title.is_empty()).then(|| self.inline(title, number));
+        table(caption, &rows, &body)
+    }
+
+    /// Returns the anchor and the shown label of a footnote. Auto-numbered footnotes (``[#]``)
+    /// are numbered in the order of the definitions, the ones without a label are matched to
+    /// the definitions in the order of the references.
+    fn footnote_label(&mut self, label: &str, reference: bool) -> (String, String) {
+        match label {
+            "#" | "*" => {
+                let count = if reference {
+                    &mut self.footnote_references
+                } else {
+                    &mut self.footnote_definitions
+                };
+                *count += 1;
+                let number = match label {
+                    "#" => self.anonymous_footnotes.get(*count - 1).copied(),
+                    _ => None,
+                }
+                .unwrap_or(*count);
+                (number.to_string(), number.to_string())
+            }
+            _ if label.starts_with('#') && self.footnote_numbers.contains_key(&label[1..]) => {
+                let number = self.footnote_numbers[&label[1..]];
+                (number.to_string(), number.to_string())
+            }
+            _ => {
+                let label = label.trim_start_matches('#');
+                (slug(label), label.to_string())
+            }
+        }
+    }
+
+    /// Renders the inline markup of the text
+    fn inline(&mut self, text: &str, line: usize) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut html = String::with_capacity(text.len());
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '\\' {
+                // Escaped whitespace is removed
+                if let Some(next) = chars.get(i + 1).filter(|next| !next.is_whitespace()) {
+                    html.push_str(&escape(&next.to_string()));
+                }
+                i += 2;
+                continue;
+            }
+
+            let at_start =
+                i == 0 || chars[i - 1].is_whitespace() || MARKUP_START.contains(chars[i - 1]);
+            if at_start {
+                if let Some((markup, end)) = self.markup(&chars, i, line) {
+                    html.push_str(&markup);
+                    i = end;
+                    continue;
+                }
+            }
+
+            html.push_str(&escape(&c.to_string()));
+            i += 1;
+        }
+
+        html
+    }
+
+    /// Renders the inline markup starting at ``i``
+    fn markup(&mut self, chars: &[char], i: usize, line: usize) -> Option<(String, usize)> {
+        let starts = |at: usize, prefix: &str| {
+            prefix
+                .chars()
+                .enumerate()
+                .all(|(offset, c)| chars.get(at + offset) == Some(&c))
+        };
+
+        if starts(i, "``") {
+            let (inner, end) = find_end(chars, i + 2, "``")?;
+            return Some((format!("<code>{}</code>", escape(&inner)), end));
+        }
+        if starts(i, "**") {
+            let (inner, end) = find_end(chars, i + 2, "**")?;
+            return Some((format!("<strong>{}</strong>", escape(&inner)), end));
+        }
+        if starts(i, "*") {
+            let (inner, end) = find_end(chars, i + 1, "*")?;
+            return Some((format!("<em>{}</em>", escape(&inner)), end));
+        }
+        if starts(i, "_`") {
+            let (inner, end) = find_end(chars, i + 2, "`")?;
+            return Some((
+                format!("<span id=\"{}\">{}</span>", slug(&inner), escape(&inner)),
+                end,
+            ));
+        }
+        if starts(i, ":") {
+            let role = role_at(chars, i)?;
+            let start = i + role.chars().count() + 2;
+            if chars.get(start) != Some(&'`') {
+                return None;
+            }
+            let (inner, end) = find_end(chars, start + 1, "`")?;
+            return Some((self.role(&role, &inner, line), end));
+        }
+        if starts(i, "`") {
+            return self.interpreted_text(chars, i, line);
+        }
+        if starts(i, "|") {
+            let (name, mut end) = find_end_with_suffix(chars, i + 1, '|')?;
+            let reference = if starts(end, "__") {
+                end += 2;
+                true
+            } else if starts(end, "_") {
+                end += 1;
+                true
+            } else {
+                false
+            };
+            if !ends_markup(chars, end) {
+                return None;
+            }
+
+            let html = self.substitute(&name, line);
+            return Some(if reference {
+                match self.target(&name) {
+                    Some(href) => (format!("<a href=\"{}\">{}</a>", escape(&href), html), end),
+                    None => (html, end),
+                }
+            } else {
+                (html, end)
+            });
+        }
+        if starts(i, "[") {
+            let close = (i + 1..chars.len()).find(|k| chars[*k] == ']')?;
+            let label: String = chars[i + 1..close].iter().collect();
+            let end = close + 2;
+            if chars.get(close + 1) != Some(&'_')
+                || !ends_markup(chars, end)
+                || !FOOTNOTE.is_match(&format!("[{}]", label))
+            {
+                return None;
+            }
+
+            let (id, label) = self.footnote_label(&label, true);
+            return Some((footnote_reference(&id, &label), end));
+        }
+        if ["http://", "https://", "ftp://", "mailto:"]
+            .iter()
+            .any(|scheme| starts(i, scheme))
+        {
+            let mut end = i;
+            while end < chars.len() && !chars[end].is_whitespace() && !"<>\"".contains(chars[end]) {
+                end += 1;
+            }
+            while end > i && ".,;:!?)'".contains(chars[end - 1]) {
+                end -= 1;
+            }
+            let uri: String = chars[i..end].iter().collect();
+            return Some((format!("<a href=\"{0}\">{0}</a>", escape(&uri)), end));
+        }
+        if chars[i].is_alphanumeric() {
+            // Simple reference names (``name_``)
+            let mut end = i;
+            while end < chars.len()
+                && (chars[end].is_alphanumeric()
+                    || ("-_.+:".contains(chars[end])
+                        && chars
+                            .get(end + 1)
+                            .is_some_and(|next| next.is_alphanumeric())))
+            {
+                end += 1;
+            }
+            if chars.get(end) != Some(&'_') {
+                return None;
+            }
+            let name: String = chars[i..end].iter().collect();
+            let anonymous = chars.get(end + 1) == Some(&'_');
+            let after = if anonymous { end + 2 } else { end + 1 };
+            if !ends_markup(chars, after) {
+                return None;
+            }
+
+            return Some((self.reference(&name, None, anonymous, line), after));
+        }
+
+        None
+    }
+
+    /// Renders text in backquotes: a hyperlink reference, text with a role or a title
+    fn interpreted_text(
+        &mut self,
+        chars: &[char],
+        i: usize,
+        line: usize,
+    ) -> Option<(String, usize)> {
+        if chars.get(i + 1).is_none_or(|c| c.is_whitespace()) {
+            return None;
+        }
+
+        for close in i + 2..chars.len() {
+            if chars[close] != '`' || chars[close - 1].is_whitespace() || chars[close - 1] == '\\' {
+                continue;
+            }
+
+            let inner: String = chars[i + 1..close].iter().collect();
+            let after = close + 1;
+            let suffix: String = chars[after..].iter().take(2).collect();
+            if suffix == "__" && ends_markup(chars, after + 2) {
+                return Some((self.embedded_reference(&inner, true, line), after + 2));
+            }
+            if suffix.starts_with('_') && ends_markup(chars, after + 1) {
+                return Some((self.embedded_reference(&inner, false, line), after + 1));
+            }
+            if chars.get(after) == Some(&':') {
+                if let Some(role) = role_at(chars, after) {
+                    let end = after + role.chars().count() + 2;
+                    if ends_markup(chars, end) {
+                        return Some((self.role(&role, &inner, line), end));
+                    }
+                }
+            }
+            if ends_markup(chars, after) {
+                return Some((format!("<cite>{}</cite>", escape(&inner)), after));
+            }
+        }
+
+        None
+    }
+
+    /// Renders a reference, which may embed its target (``text <https://example.com>``)
+    fn embedded_reference(&mut self, text: &str, anonymous: bool, line: usize) -> String {
+        match EMBEDDED.captures(text) {
+            Some(captures) => {
+                let label = if captures[1].is_empty() {
+                    &captures[2]
+                } else {
+                    &captures[1]
+                };
+                let target = captures[2].replace('\n', "");
+                let href = match target.strip_suffix('_') {
+                    Some(name) => self.target(name),
+                    None => Some(target.clone()),
+                };
+                if !anonymous && !captures[1].is_empty() {
+                    if let Some(href) = &href {
+                        self.targets.insert(normalize_name(label), href.clone());
+                    }
+                }
+
+                match href {
+                    Some(href) => format!("<a href=\"{}\">{}</a>", escape(&href), escape(label)),
+                    None => self.reference(target.trim_end_matches('_'), Some(label), false, line),
+                }
+            }
+            None => self.reference(text, None, anonymous, line),
+        }
+    }
+
+    /// Renders a reference to a named (or the next anonymous) target
+    fn reference(
+        &mut self,
+        name: &str,
+        label: Option<&str>,
+        anonymous: bool,
+        line: usize,
+    ) -> String {
+        let label = label.unwrap_or(name);
+        let href = if anonymous {
+            self.anonymous_references += 1;
+            self.anonymous_targets
+                .get(self.anonymous_references - 1)
+                .cloned()
+        } else {
+            self.target(name)
+        };
+
+        match href {
+            Some(href) => format!("<a href=\"{}\">{}</a>", escape(&href), escape(label)),
+            None => {
+                self.warn(line, format!("Unknown target name \"{}\"", name));
+                escape(label)
+            }
+        }
+    }
+
+    /// Returns the URI of a named target, following indirect targets
+    fn target(&self, name: &str) -> Option<String> {
+        let mut href = self.targets.get(&normalize_name(name))?;
+        for _ in 0..8 {
+            match href.strip_suffix('_') {
+                Some(name) if !href.contains("://") => {
+                    href = self.targets.get(&normalize_name(name.trim_matches('`')))?;
+                }
+                _ => break,
+            }
+        }
+
+        Some(href.clone())
+    }
+
+    fn substitute(&mut self, name: &str, line: usize) -> String {
+        let substitution = match self.substitutions.get(name) {
+            Some(Substitution::Text(text)) => Some(text.clone()),
+            Some(Substitution::Image(uri)) => {
+                return format!("<img src=\"{}\" alt=\"{}\" />", escape(uri), escape(name))
+            }
+            None => None,
+        };
+
+        match substitution {
+            Some(text) if self.substituting < 8 => {
+                self.substituting += 1;
+                let html = self.inline(&text, line);
+                self.substituting -= 1;
+                html
+            }
+            _ => {
+                self.warn(line, format!("Unknown substitution \"{}\"", name));
+                escape(name)
+            }
+        }
+    }
+
+    fn role(&mut self, role: &str, text: &str, line: usize) -> String {
+        // Roles referencing something may have an explicit title (``Title <target>``)
+        let title = || match text.rsplit_once('<') {
+            Some((title, _)) if text.ends_with('>') && !title.trim().is_empty() => {
+                title.trim().to_string()
+            }
+            _ => text.trim_start_matches(['~', '!']).to_string(),
+        };
+
+        match role {
+            "math" => {
+                let (mathml, errors) = math::to_mathml(text, false);
+                for error in errors {
+                    self.warn(line, error);
+                }
+                mathml
+            }
+            "emphasis" => format!("<em>{}</em>", escape(text)),
+            "strong" => format!("<strong>{}</strong>", escape(text)),
+            "literal" | "code" | "samp" | "file" | "command" | "program" => {
+                format!("<code>{}</code>", escape(text))
+            }
+            "kbd" => format!("<kbd>{}</kbd>", escape(text)),
+            "sub" | "subscript" => format!("<sub>{}</sub>", escape(text)),
+            "sup" | "superscript" => format!("<sup>{}</sup>", escape(text)),
+            "title-reference" | "title" | "t" => format!("<cite>{}</cite>", escape(text)),
+            "abbr" => match text.split_once(" (") {
+                Some((abbreviation, explanation)) => format!(
+                    "<abbr title=\"{}\">{}</abbr>",
+                    escape(explanation.trim_end_matches(')')),
+                    escape(abbreviation)
+                ),
+                None => format!("<abbr>{}</abbr>", escape(text)),
+            },
+            _ if REFERENCE_ROLES.contains(&role) => escape(&title()),
+            // Roles of Sphinx domains (e.g. ``:py:func:``) reference code
+            _ => format!("<code>{}</code>", escape(&title())),
+        }
+    }
+}
+
+/// Returns the text of the section title at the line, which is followed by an adornment (and
+/// may be preceded by the same adornment)
+fn title_at(lines: &[Line], i: usize) -> Option<String> {
+    let text = &lines.get(i)?.1;
+    let underline = adornment(&lines.get(i + 1)?.1)?;
+    let overline = i
+        .checked_sub(1)
+        .and_then(|previous| adornment(&lines[previous].1));
+    if is_blank(text) || adornment(text).is_some() {
+        return None;
+    }
+    if indentation(text) > 0 && overline != Some(underline) {
+        return None;
+    }
+
+    let width = text.trim().chars().count();
+    let length = lines[i + 1].1.len();
+    if overline.is_some() && overline != Some(underline) {
+        return None;
+    }
+    if overline.is_none() && length < width && length < 4 {
+        return None;
+    }
+    // Titles are separated from the preceding paragraph
+    let start = if overline.is_some() { i - 1 } else { i };
+    if start > 0 && !is_blank(&lines[start - 1].1) {
+        return None;
+    }
+
+    Some(text.trim().to_string())
+}
+
+/// Character of a line consisting only of one repeated punctuation character
+fn adornment(text: &str) -> Option<char> {
+    let c = text.chars().next()?;
+    (text.len() >= 2
+        && text != "::"
+        && ADORNMENTS.contains(c)
+        && text.chars().all(|other| other == c))
+    .then_some(c)
+}
+
+/// Bullet and width of the marker of a bullet list item
+fn bullet(text: &str) -> Option<usize> {
+    let c = text.chars().next()?;
+    if !"-*+•‣⁃".contains(c) {
+        return None;
+    }
+
+    let rest = &text[c.len_utf8()..];
+    if rest.is_empty() {
+        Some(c.len_utf8())
+    } else if rest.starts_with(' ') {
+        Some(text.len() - rest.trim_start().len())
+    } else {
+        None
+    }
+}
+
+/// Splits a list into the lines of its items. ``marker`` returns the width of the marker of a
+/// line starting an item.
+fn list_items(
+    lines: &[Line],
+    mut i: usize,
+    marker: impl Fn(&str) -> Option<usize>,
+) -> (Vec<Vec<Line>>, usize) {
+    let mut items = Vec::new();
+    while let Some(width) = lines.get(i).and_then(|(_, line)| marker(line)) {
+        let (number, text) = &lines[i];
+        let first = text[width.min(text.len())..].trim();
+        let (mut body, end) = indented(lines, i + 1);
+        if !first.is_empty() {
+            body.insert(0, (*number, first.to_string()));
+        }
+        items.push(body);
+
+        i = end;
+        let next = i + lines[i..]
+            .iter()
+            .take_while(|(_, line)| is_blank(line))
+            .count();
+        if lines.get(next).and_then(|(_, line)| marker(line)).is_none() {
+            break;
+        }
+        i = next;
+    }
+
+    (items, i)
+}
+
+/// Formatting of the enumerator of a list item (``1.``, ``1)`` or ``(1)``) and its kind
+fn list_style(captures: &regex::Captures) -> (String, u8) {
+    let (enumerator, format) = match captures.get(1) {
+        Some(enumerator) => (enumerator.as_str(), captures[2].to_string()),
+        None => (&captures[3], "()".to_string()),
+    };
+    let kind = if enumerator.chars().all(|c| c.is_ascii_digit()) || enumerator == "#" {
+        0
+    } else if enumerator.len() == 1 && !"iI".contains(enumerator) {
+        1
+    } else {
+        2
+    };
+
+    (format, kind)
+}
+
+/// Type of the list (``a``, ``A``, ``i`` or ``I`` for letters and roman numerals) and the number
+/// of the first item
+fn enumerator_value(enumerator: &str) -> (Option<&'static str>, usize) {
+    if enumerator == "#" {
+        return (None, 1);
+    }
+    if let Ok(number) = enumerator.parse() {
+        return (None, number);
+    }
+
+    let c = enumerator.chars().next().unwrap_or('a');
+    if enumerator.len() == 1 && !"iI".contains(c) {
+        let kind = if c.is_ascii_uppercase() { "A" } else { "a" };
+        return (
+            Some(kind),
+            (c.to_ascii_lowercase() as usize) - ('a' as usize) + 1,
+        );
+    }
+
+    let kind = if c.is_ascii_uppercase() { "I" } else { "i" };
+    (Some(kind), roman_value(enumerator))
+}
+
+fn roman_value(numeral: &str) -> usize {
+    let digit = |c: char| match c.to_ascii_lowercase() {
+        'i' => 1,
+        'v' => 5,
+        'x' => 10,
+        'l' => 50,
+        'c' => 100,
+        'd' => 500,
+        'm' => 1000,
+        _ => 0,
+    };
+    let digits: Vec<usize> = numeral.chars().map(digit).collect();
+
+    digits
+        .iter()
+        .enumerate()
+        .fold(0, |value, (i, digit)| match digits.get(i + 1) {
+            Some(next) if next > digit => value - digit,
+            _ => value + digit,
+        })
+        .max(1)
+}
+
+/// Number of header rows given as option of a table
+fn header_rows(options: &HashMap<String, String>) -> usize {
+    options
+        .get("header-rows")
+        .and_then(|rows| rows.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Splits a line of comma separated values, values may be quoted with ``"``
+pub(super) fn split_csv(line: &str, delimiter: char) -> Vec<String> {
+    let mut cells = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cells.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => cells.push(String::new()),
+            c => cells.last_mut().unwrap().push(c),
+        }
+    }
+
+    cells.iter().map(|cell| cell.trim().to_string()).collect()
+}
+
+fn image(uri: &str, options: &HashMap<String, String>) -> String {
+    let mut attributes = format!(
+        "src=\"{}\" alt=\"{}\"",
+        escape(uri),
+        escape(options.get("alt").map(String::as_str).unwrap_or_default())
+    );
+    for attribute in ["width", "height"] {
+        if let Some(value) = options.get(attribute) {
+            attributes.push_str(&format!(" {}=\"{}\"", attribute, escape(value)));
+        }
+    }
+
+    match options.get("target") {
+        Some(target) => format!("<a href=\"{}\"><img {} /></a>", escape(target), attributes),
+        None => format!("<img {} />", attributes),
+    }
+}
+
+/// Name of a role starting at ``i`` (``:name:``)
+fn role_at(chars: &[char], i: usize) -> Option<String> {
+    let close =
+        (i + 1..chars.len()).find(|k| chars[*k] == ':' && chars.get(k + 1) != Some(&':'))?;
+    let name: String = chars[i + 1..close].iter().collect();
+    let valid = !name.is_empty()
+        && name.chars().next().is_some_and(|c| c.is_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.+:".contains(c));
+
+    valid.then_some(name)
+}
+
+/// Finds the end of inline markup started before ``start``. Returns the text in between and the
+/// position after the end.
+fn find_end(chars: &[char], start: usize, end: &str) -> Option<(String, usize)> {
+    let end: Vec<char> = end.chars().collect();
+    if chars.get(start).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+
+    (start + 1..=chars.len().saturating_sub(end.len()))
+        .find(|k| {
+            chars[*k..].starts_with(&end)
+                && !chars[k - 1].is_whitespace()
+                && chars[k - 1] != '\\'
+                && ends_markup(chars, k + end.len())
+        })
+        .map(|k| (chars[start..k].iter().collect(), k + end.len()))
+}
+
+/// Like ``find_end``, but the end may be followed by a suffix (e.g. ``|name|_``)
+fn find_end_with_suffix(chars: &[char], start: usize, end: char) -> Option<(String, usize)> {
+    if chars.get(start).is_none_or(|c| c.is_whitespace()) {
+        return None;
+    }
+
+    (start + 1..chars.len())
+        .find(|k| chars[*k] == end && !chars[k - 1].is_whitespace())
+        .map(|k| (chars[start..k].iter().collect(), k + 1))
+}
+
+/// Whether inline markup may end before ``i``
+fn ends_markup(chars: &[char], i: usize) -> bool {
+    chars
+        .get(i)
+        .is_none_or(|c| c.is_whitespace() || MARKUP_END.contains(*c))
+}
+
+/// End of the lines before the next blank line
+fn block_end(lines: &[Line], i: usize) -> usize {
+    i + lines[i..]
+        .iter()
+        .take_while(|(_, line)| !is_blank(line))
+        .count()
+}
+
+/// Lines from ``start``, which are blank or indented, without their common indentation.
+/// Returns them and the index after them.
+fn indented(lines: &[Line], start: usize) -> (Vec<Line>, usize) {
+    let mut end = start;
+    while end < lines.len() && (is_blank(&lines[end].1) || indentation(&lines[end].1) > 0) {
+        end += 1;
+    }
+    while end > start && is_blank(&lines[end - 1].1) {
+        end -= 1;
+    }
+
+    (dedent(&lines[start..end]), end)
+}
+
+/// Removes the common indentation of the lines
+fn dedent(lines: &[Line]) -> Vec<Line> {
+    let indent = lines
+        .iter()
+        .filter(|(_, line)| !is_blank(line))
+        .map(|(_, line)| indentation(line))
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|(number, line)| (*number, line.get(indent..).unwrap_or_default().to_string()))
+        .collect()
+}
+
+fn indentation(text: &str) -> usize {
+    text.len() - text.trim_start_matches(' ').len()
+}
+
+fn is_blank(text: &str) -> bool {
+    text.trim().is_empty()
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    for c in line.chars() {
+        if c == '\t' {
+            let width = 8 - expanded.chars().count() % 8;
+            expanded.push_str(&" ".repeat(width));
+        } else {
+            expanded.push(c);
+        }
+    }
+
+    expanded
+}
+
+/// Reference names are case insensitive and whitespace is normalized
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Anchor for a title (like docutils, lowercase words separated by ``-``)
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_end_matches('-').to_string()
+}
+
+fn unique_id(ids: &mut HashSet<String>, title: &str) -> String {
+    let mut base = slug(title);
+    if base.is_empty() {
+        base = "section".to_string();
+    }
+    let mut id = base.clone();
+    let mut count = 1;
+    while !ids.insert(id.clone()) {
+        id = format!("{}-{}", base, count);
+        count += 1;
+    }
+
+    id
+}
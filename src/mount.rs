@@ -0,0 +1,80 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+use std::path::Path;
+
+use crate::router::{normalize_base_path, strip_base_path};
+
+/// A directory served under an URL prefix
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mount {
+    /// URL prefix of the mount (empty for the root or starting with a slash)
+    pub prefix: String,
+    /// Directory the markdown files are read from
+    pub directory: String,
+}
+
+impl Mount {
+    pub fn new(prefix: &str, directory: &str) -> Self {
+        Self {
+            prefix: normalize_base_path(prefix),
+            directory: expand_home(directory),
+        }
+    }
+}
+
+/// Parses a mount from the command line in the form ``PREFIX=DIRECTORY``
+pub fn parse_mount(s: &str) -> Result<Mount, String> {
+    match s.split_once('=') {
+        Some((prefix, directory)) if !directory.is_empty() => Ok(Mount::new(prefix, directory)),
+        _ => Err(format!("Expected PREFIX=DIRECTORY, got {}", s)),
+    }
+}
+
+/// Replaces a leading ``~`` with the home directory. The shell doesn't do that in arguments
+/// like ``--mount /handbook=~/handbook``.
+fn expand_home(directory: &str) -> String {
+    match (directory.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", home, rest)
+        }
+        _ => directory.to_string(),
+    }
+}
+
+/// Returns the prefix of the mount the web path belongs to. If multiple mounts match, the one
+/// with the longest prefix is returned.
+pub fn find_mount<'a>(path: &str, prefixes: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    prefixes
+        .filter(|prefix| strip_base_path(path, prefix).is_some())
+        .max_by_key(|prefix| prefix.len())
+}
+
+/// Returns the web path of a file in one of the mounted directories. If the file is in multiple
+/// mounted directories, the most specific directory is used.
+pub fn web_path_of_file(file: &Path, mounts: &[Mount]) -> Option<String> {
+    let file = file.canonicalize().ok()?;
+
+    mounts
+        .iter()
+        .filter_map(|mount| {
+            let directory = Path::new(&mount.directory).canonicalize().ok()?;
+            let relative = file.strip_prefix(&directory).ok()?;
+            Some((directory.components().count(), mount, relative.to_owned()))
+        })
+        .max_by_key(|(depth, _, _)| *depth)
+        .map(|(_, mount, relative)| format!("{}/{}", mount.prefix, relative.to_string_lossy()))
+}
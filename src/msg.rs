@@ -14,16 +14,38 @@
  *
  *  You should have received a copy of the GNU General Public License
  */
+use std::path::PathBuf;
+
 use tokio::sync;
 
-use crate::builder::BuiltFile;
+use crate::builder::{BuildStatus, BuiltFile, FileMetadata, PageAction, WriteError};
+use crate::config::BuilderConfig;
+use crate::patch::ContentPatch;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum MsgSrv {
-    /// Announces a file change
-    File(/* path: */ String, /* content: */ BuiltFile),
-    /// Announces a new file (without contents because they're definitly not required)
-    NewFile(/* path: */ String, /* all_files: */ Vec<String>),
+    /// Announces a file change. The patch (if any) changes the previous contents to the new ones.
+    File(
+        /* mount: */ String,
+        /* path: */ String,
+        /* content: */ Box<BuiltFile>,
+        /* patch: */ Option<ContentPatch>,
+    ),
+    /// Announces a new or removed file (without contents because they're definitly not required)
+    NewFile(
+        /* mount: */ String,
+        /* path: */ String,
+        /* all_files: */ Vec<String>,
+    ),
+    /// Announces that clients showing the file have to show a position in it (e.g. the cursor of
+    /// an editor)
+    Goto(
+        /* path: */ String,
+        /* line: */ usize,
+        /* block: */ Option<usize>,
+    ),
+    /// Announces that clients have to reload the page (e.g. because the configuration changed)
+    Reload(),
     Exit(),
 }
 
@@ -38,10 +60,45 @@ pub enum MsgBuilder {
     AllFiles(
         /* result: */ sync::oneshot::Sender</* all_files: */ Vec<String>>,
     ),
+    /// Requests the markdown source of a file
+    Source(
+        /* path: */ String,
+        /* result: */ sync::oneshot::Sender<Option<String>>,
+    ),
+    /// Requests the location of a file in the file system
+    FsPath(
+        /* path: */ String,
+        /* result: */ sync::oneshot::Sender<Option<PathBuf>>,
+    ),
+    /// Builds markdown (e.g. an older version of a file) like the file would be built
+    Render(
+        /* path: */ String,
+        /* markdown: */ String,
+        /* result: */ sync::oneshot::Sender<Option<BuiltFile>>,
+    ),
+    /// Replaces the markdown source of a file, if its current source has the expected hash
+    Save(
+        /* path: */ String,
+        /* contents: */ String,
+        /* expected_hash: */ Option<String>,
+        /* result: */ sync::oneshot::Sender<Result</* hash: */ String, WriteError>>,
+    ),
+    /// Creates, renames or deletes a page
+    Page(
+        /* action: */ PageAction,
+        /* result: */
+        sync::oneshot::Sender<Result</* changed_files: */ Vec<String>, WriteError>>,
+    ),
+    /// Requests information about all built files
+    Metadata(/* result: */ sync::oneshot::Sender<Vec<FileMetadata>>),
+    /// Requests the progress of building the files
+    Status(/* result: */ sync::oneshot::Sender<BuildStatus>),
+    /// Changes the configuration of the builder and rebuilds all files
+    Reconfigure(/* config: */ BuilderConfig),
     Exit(),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum MsgInternalBuilder {
     /// Announces that a file was created
     FileCreated(/* path: */ String),
@@ -49,6 +106,19 @@ pub enum MsgInternalBuilder {
     FileModified(/* path: */ String),
     /// Announces that a file was deleted
     FileDeleted(/* path: */ String),
-    Ignore(),
+    /// Creates, renames or deletes a page
+    Page(
+        /* action: */ PageAction,
+        /* result: */
+        sync::oneshot::Sender<Result</* changed_files: */ Vec<String>, WriteError>>,
+    ),
+    /// Builds markdown with the current configuration without storing it
+    Render(
+        /* path: */ String,
+        /* markdown: */ String,
+        /* result: */ sync::oneshot::Sender<BuiltFile>,
+    ),
+    /// Changes the configuration of the builder and rebuilds all files
+    Reconfigure(/* config: */ Box<BuilderConfig>),
     Exit(),
 }
@@ -0,0 +1,284 @@
+/*
+ *  md-dir-builder serve markdown files in a given directory
+ *  Copyright (C) 2022 Fionn Langhans
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ */
+use serde::Serialize;
+
+use crate::builder::BuiltFile;
+
+/// Elements without closing tag
+static VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements, which contents aren't parsed as HTML
+static RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea"];
+
+/// Maximum size of the table comparing the changed blocks of two contents (about 8 MB)
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+/// Changes of the top-level blocks of a built file. Applying the patch to the blocks of the
+/// previous contents results in the blocks of the new contents.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct ContentPatch {
+    /// Version of the contents the patch applies to
+    pub from_version: String,
+    /// Number of blocks of the contents the patch applies to
+    pub count: usize,
+    /// Index of the first changed block
+    pub start: usize,
+    /// Number of blocks removed at ``start``
+    pub remove: usize,
+    /// Blocks inserted at ``start``
+    pub insert: Vec<String>,
+    /// Source line of the first changed block (if known)
+    pub line: Option<usize>,
+}
+
+impl ContentPatch {
+    /// Computes the patch between the previous and the new contents. Returns ``None``, if the
+    /// contents can't be split into blocks, so they have to be replaced completely.
+    pub fn diff(previous: &BuiltFile, current: &BuiltFile) -> Option<Self> {
+        let old_blocks = split_blocks(&previous.contents)?;
+        let new_blocks = split_blocks(&current.contents)?;
+
+        let prefix = old_blocks
+            .iter()
+            .zip(new_blocks.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = old_blocks[prefix..]
+            .iter()
+            .rev()
+            .zip(new_blocks[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        // Lines are only usable, if the parser produced the same blocks
+        let line = if current.block_lines.len() == new_blocks.len() {
+            current.block_lines.get(prefix).copied()
+        } else {
+            None
+        };
+
+        Some(ContentPatch {
+            from_version: previous.version(),
+            count: old_blocks.len(),
+            start: prefix,
+            remove: old_blocks.len() - prefix - suffix,
+            insert: new_blocks[prefix..new_blocks.len() - suffix]
+                .iter()
+                .map(|block| block.to_string())
+                .collect(),
+            line,
+        })
+    }
+}
+
+/// Returns the index of the top-level block, which contains the given source line. Returns
+/// ``None``, if the source lines of the blocks are unknown.
+pub fn block_of_line(file: &BuiltFile, line: usize) -> Option<usize> {
+    let blocks = split_blocks(&file.contents)?;
+    if blocks.is_empty() || blocks.len() != file.block_lines.len() {
+        return None;
+    }
+
+    Some(
+        file.block_lines
+            .iter()
+            .rposition(|&block_line| block_line <= line)
+            .unwrap_or(0),
+    )
+}
+
+/// Renders the changes between two contents. Removed top-level blocks are wrapped in
+/// ``<div class="diff-removed">``, added ones in ``<div class="diff-added">``. If the contents
+/// can't be split into blocks, they are shown as completely replaced.
+pub fn diff_html(old: &str, new: &str) -> String {
+    let (old_blocks, new_blocks) = match (split_blocks(old), split_blocks(new)) {
+        (Some(old_blocks), Some(new_blocks)) => (old_blocks, new_blocks),
+        _ if old == new => return new.to_string(),
+        _ => (vec![old], vec![new]),
+    };
+
+    let prefix = old_blocks
+        .iter()
+        .zip(new_blocks.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_blocks[prefix..]
+        .iter()
+        .rev()
+        .zip(new_blocks[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_changed = &old_blocks[prefix..old_blocks.len() - suffix];
+    let new_changed = &new_blocks[prefix..new_blocks.len() - suffix];
+
+    let mut html = String::with_capacity(old.len() + new.len());
+    for block in &old_blocks[..prefix] {
+        html.push_str(block);
+        html.push('\n');
+    }
+    diff_blocks(&mut html, old_changed, new_changed);
+    for block in &old_blocks[old_blocks.len() - suffix..] {
+        html.push_str(block);
+        html.push('\n');
+    }
+
+    html
+}
+
+/// Renders the changes between the blocks like [`diff_html`]. If there are too many blocks to
+/// compare all of them, all old blocks are shown as removed and all new ones as added.
+fn diff_blocks(html: &mut String, old_blocks: &[&str], new_blocks: &[&str]) {
+    let removed = |block: &str| format!("<div class=\"diff-removed\">{}</div>\n", block);
+    let added = |block: &str| format!("<div class=\"diff-added\">{}</div>\n", block);
+
+    if (old_blocks.len() + 1).saturating_mul(new_blocks.len() + 1) > MAX_DIFF_CELLS {
+        for block in old_blocks {
+            html.push_str(&removed(block));
+        }
+        for block in new_blocks {
+            html.push_str(&added(block));
+        }
+        return;
+    }
+
+    // Longest common subsequence of the blocks, ``common[i][j]`` is the length for the blocks
+    // starting at ``i`` and ``j``
+    let mut common = vec![vec![0usize; new_blocks.len() + 1]; old_blocks.len() + 1];
+    for i in (0..old_blocks.len()).rev() {
+        for j in (0..new_blocks.len()).rev() {
+            common[i][j] = if old_blocks[i] == new_blocks[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old_blocks.len() || j < new_blocks.len() {
+        if i < old_blocks.len() && j < new_blocks.len() && old_blocks[i] == new_blocks[j] {
+            html.push_str(old_blocks[i]);
+            html.push('\n');
+            i += 1;
+            j += 1;
+        } else if j == new_blocks.len()
+            || (i < old_blocks.len() && common[i + 1][j] >= common[i][j + 1])
+        {
+            html.push_str(&removed(old_blocks[i]));
+            i += 1;
+        } else {
+            html.push_str(&added(new_blocks[j]));
+            j += 1;
+        }
+    }
+}
+
+/// Splits HTML into its top-level elements. Returns ``None``, if the HTML contains anything
+/// else at the top-level than elements and whitespace or the elements aren't balanced.
+pub fn split_blocks(html: &str) -> Option<Vec<&str>> {
+    let mut blocks = Vec::new();
+    let mut depth = 0usize;
+    let mut block_start = 0;
+    let mut i = 0;
+
+    while i < html.len() {
+        let rest = &html[i..];
+        let is_tag = rest.starts_with('<')
+            && rest[1..]
+                .starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?'));
+        if !is_tag {
+            let c = rest.chars().next()?;
+            if depth == 0 && !c.is_whitespace() {
+                return None;
+            }
+
+            i += c.len_utf8();
+            continue;
+        }
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            if depth == 0 {
+                return None;
+            }
+
+            i += 4 + comment.find("-->")? + 3;
+            continue;
+        }
+
+        let tag_len = tag_length(rest)?;
+        let tag = &rest[..tag_len];
+        let name = tag_name(tag);
+        if depth == 0 {
+            block_start = i;
+        }
+        i += tag_len;
+
+        if tag.starts_with("</") {
+            depth = depth.checked_sub(1)?;
+        } else if tag.starts_with("<!") || tag.starts_with("<?") {
+            if depth == 0 {
+                return None;
+            }
+        } else if tag.ends_with("/>") || VOID_ELEMENTS.contains(&name.as_str()) {
+            // Element without contents
+        } else if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+            let end = format!("</{}", name);
+            i += html[i..].to_ascii_lowercase().find(&end)?;
+            depth += 1;
+        } else {
+            depth += 1;
+        }
+
+        if depth == 0 {
+            blocks.push(&html[block_start..i]);
+        }
+    }
+
+    if depth == 0 {
+        Some(blocks)
+    } else {
+        None
+    }
+}
+
+/// Length of the tag at the start of the HTML (including the angle brackets)
+fn tag_length(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Lowercase name of the tag (without ``/`` of closing tags)
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
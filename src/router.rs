@@ -14,12 +14,18 @@
  *
  *  You should have received a copy of the GNU General Public License
  */
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc,
+    },
+};
 
 use crc::Crc;
 
 use ahash::RandomState;
-use futures::{stream::SplitSink, SinkExt, StreamExt};
+use futures::{stream::SplitSink, SinkExt, Stream, StreamExt};
 use tokio::{
     sync::{self, Mutex},
     task,
@@ -29,11 +35,14 @@ use axum::headers::{ETag, IfNoneMatch};
 use axum::{
     body::{Bytes, Full},
     extract::{
-        ws::{Message, WebSocket},
-        TypedHeader, WebSocketUpgrade,
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        Query, TypedHeader, WebSocketUpgrade,
+    },
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
     },
-    http::{StatusCode, Uri},
-    response::{Html, IntoResponse, Response},
     routing::{get, post},
     Extension, Json, Router,
 };
@@ -41,22 +50,18 @@ use axum::{
 use serde::{Deserialize, Serialize};
 
 use super::{MsgBuilder, MsgSrv};
+use crate::api;
+use crate::builder::{BuiltFile, PageAction};
+use crate::editor::GotoRequest;
+use crate::markdown::Diagnostic;
+use crate::patch::ContentPatch;
+use crate::ui::UiConfig;
 
-#[derive(Serialize, Deserialize)]
-struct PingResponse {
-    pub success: bool,
-    pub msg: String,
-}
+/// Page listing the files with problems, clients showing it get updates of all files
+pub const PROBLEMS_PATH: &str = "/.problems";
 
-async fn ping() -> impl IntoResponse {
-    (
-        StatusCode::OK,
-        Json(PingResponse {
-            success: true,
-            msg: format!("Pong"),
-        }),
-    )
-}
+/// Files the root redirects to, if they exist
+static README_FILES: &[&str] = &["/README.md", "/Readme.md", "/README.rst", "/README.adoc"];
 
 macro_rules! get_resource_generator {
     ($name:ident, $type:literal, $path:literal) => {
@@ -88,10 +93,12 @@ macro_rules! get_resource_generator {
 }
 
 macro_rules! get_full_text_page {
-    ($path:literal, $tx_file:ident) => {{
+    ($path:literal, $tx_file:ident, $ui_config:ident) => {{
         let tx_file = $tx_file.clone();
+        let ui_config = $ui_config.clone();
 
-        get(|| async move {
+        get(|headers: HeaderMap| async move {
+            let ui_config = ui_config_for_request(&headers, &ui_config);
             let (tx_onefile, rx_onefile) = sync::oneshot::channel();
 
             tx_file
@@ -105,6 +112,7 @@ macro_rules! get_full_text_page {
                     "License",
                     crate::ui::Contents::Text(include_str!($path)),
                     &all_files[..],
+                    &ui_config,
                 );
                 (StatusCode::OK, Html(format!("{}", result.into_string())))
             } else {
@@ -114,12 +122,216 @@ macro_rules! get_full_text_page {
     }};
 }
 
+/// Route of an API request about a file. The path of the file follows the given path.
+macro_rules! get_api_file {
+    ($path:expr, $handler:path, $tx_file:ident) => {{
+        let tx_file = $tx_file.clone();
+        let prefix = $path;
+
+        get(|uri: Uri| async move {
+            let requested_file = determine_real_path(&uri.path()[prefix.len()..]);
+
+            $handler(requested_file, tx_file).await
+        })
+    }};
+    ($path:expr, $handler:path, $tx_file:ident, $query:ty) => {{
+        let tx_file = $tx_file.clone();
+        let prefix = $path;
+
+        get(|uri: Uri, Query(query): Query<$query>| async move {
+            let requested_file = determine_real_path(&uri.path()[prefix.len()..]);
+
+            $handler(requested_file, query, tx_file).await
+        })
+    }};
+}
+
 get_resource_generator!(ws_js_file, "application/javascript", "./ui/ws.js");
 get_resource_generator!(prism_js_file, "application/javascript", "./ui/prism.js");
 
+/// Open websocket connection
+#[derive(Clone)]
+struct WsClient {
+    pub tx_ws: sync::mpsc::Sender<MsgSrv>,
+    /// Path of the file the client shows, only updates of this file are sent to the client
+    pub path: Option<String>,
+}
+
+type WsChannels = Arc<Mutex<HashMap<u64, WsClient, RandomState>>>;
+
+/// Id of the next connected client
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Clone)]
 struct WsState {
-    pub ws_channels: Arc<Mutex<HashMap<i64, sync::mpsc::Sender<MsgSrv>, RandomState>>>,
+    pub ws_channels: WsChannels,
+    pub ui_config: sync::watch::Receiver<UiConfig>,
+    pub tx_file: sync::mpsc::Sender<MsgBuilder>,
+}
+
+/// Messages sent to the client over the websocket
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+enum ServerMsg {
+    /// Replaces the shown contents
+    UpdateContent {
+        mount: String,
+        path: String,
+        content: FileContent,
+    },
+    /// Replaces only the changed blocks of the shown contents
+    PatchContent {
+        mount: String,
+        path: String,
+        patch: ContentPatch,
+        version: String,
+        word_count: usize,
+        modified: Option<String>,
+        changed: Option<String>,
+        diagnostics: Vec<Diagnostic>,
+    },
+    UpdateSidebar {
+        content: String,
+        version: String,
+    },
+    /// Shows a position in a file
+    Goto {
+        path: String,
+        line: usize,
+        block: Option<usize>,
+    },
+    Reload,
+}
+
+impl ServerMsg {
+    /// Converts a server event to the message sent to clients. Returns ``None``, if the
+    /// connection has to be closed.
+    fn from_msg(msg: MsgSrv, ui_config: &UiConfig) -> Option<Self> {
+        let msg = match msg {
+            MsgSrv::File(mount, path, content, patch) => {
+                // Send the client update of the content, only the changed blocks if possible
+                match patch {
+                    Some(patch) => ServerMsg::PatchContent {
+                        mount,
+                        path,
+                        patch,
+                        version: content.version(),
+                        word_count: content.word_count,
+                        modified: content.modified.clone(),
+                        changed: crate::ui::changed_text(&content),
+                        diagnostics: content.diagnostics.clone(),
+                    },
+                    None => ServerMsg::UpdateContent {
+                        mount,
+                        path,
+                        content: (*content).into(),
+                    },
+                }
+            }
+            MsgSrv::NewFile(_, _, all_files) => {
+                // Send the client update of the sidebar
+                ServerMsg::UpdateSidebar {
+                    content: crate::ui::render_sidebar(&all_files[..], ui_config).into_string(),
+                    version: crate::ui::files_version(&all_files[..]),
+                }
+            }
+            MsgSrv::Goto(path, line, block) => ServerMsg::Goto { path, line, block },
+            MsgSrv::Reload() => ServerMsg::Reload,
+            MsgSrv::Exit() => return None,
+        };
+
+        Some(msg)
+    }
+}
+
+/// Rendered contents of a file sent to the client
+#[derive(Debug, Serialize)]
+struct FileContent {
+    contents: String,
+    word_count: usize,
+    version: String,
+    /// Modification time of the file (RFC 3339)
+    modified: Option<String>,
+    /// When and by whom the file was changed last
+    changed: Option<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl From<BuiltFile> for FileContent {
+    fn from(file: BuiltFile) -> Self {
+        FileContent {
+            version: file.version(),
+            changed: crate::ui::changed_text(&file),
+            modified: file.modified,
+            contents: file.contents,
+            word_count: file.word_count,
+            diagnostics: file.diagnostics,
+        }
+    }
+}
+
+/// Messages sent by the client over the websocket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+enum ClientMsg {
+    /// Sent after (re-)connecting with the versions of the contents and the sidebar the client
+    /// currently shows
+    Hello {
+        path: String,
+        version: Option<String>,
+        sidebar_version: Option<String>,
+    },
+    /// Sent after navigating to another file
+    Subscribe { path: String },
+}
+
+/// Normalizes a base path, so that it either is empty (served at the root) or starts with a
+/// slash and doesn't end with one.
+pub fn normalize_base_path(base_path: &str) -> String {
+    let base_path = base_path.trim().trim_matches('/');
+    if base_path.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", base_path)
+    }
+}
+
+/// Strips the base path from the requested path. Returns `None`, if the requested path isn't
+/// below the base path.
+pub fn strip_base_path<'a>(path: &'a str, base_path: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(base_path)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// Determines the settings to render a page with for a specific request.
+///
+/// Reverse proxies, which strip a prefix from the forwarded request, announce that prefix with
+/// the `X-Forwarded-Prefix` header. All links generated in pages must contain that prefix,
+/// otherwise the browser would request them outside of the proxied location.
+fn ui_config_for_request(
+    headers: &HeaderMap,
+    ui_config: &sync::watch::Receiver<UiConfig>,
+) -> UiConfig {
+    let ui_config = ui_config.borrow().clone();
+    let forwarded_prefix = headers
+        .get("X-Forwarded-Prefix")
+        .and_then(|prefix| prefix.to_str().ok())
+        .map(normalize_base_path)
+        .unwrap_or_default();
+
+    if forwarded_prefix.is_empty() {
+        ui_config
+    } else {
+        log::debug!("Using forwarded prefix: {}", forwarded_prefix);
+        UiConfig {
+            base_path: format!("{}{}", forwarded_prefix, ui_config.base_path),
+            ..ui_config
+        }
+    }
 }
 
 fn determine_real_path(path: &str) -> String {
@@ -152,30 +364,84 @@ async fn request_just_file_contents(
     if let Ok(result) = rx_onefile.await {
         match result {
             (Some(result), _files) => {
-                let result = format!(
-                    "{}",
-                    crate::ui::render_contents(crate::ui::Contents::Html(&result)).into_string()
-                );
+                let result =
+                    crate::ui::render_contents(crate::ui::Contents::Html(&result)).into_string();
 
                 (StatusCode::OK, Html(result))
             }
             (None, _files) => {
-                let result = format!(
-                    "{}",
-                    crate::ui::render_contents(crate::ui::Contents::NotFound()).into_string()
-                );
+                let result =
+                    crate::ui::render_contents(crate::ui::Contents::NotFound()).into_string();
 
                 (StatusCode::NOT_FOUND, Html(result))
             }
         }
     } else {
-        (StatusCode::GONE, Html(format!("Internal server error")))
+        (StatusCode::GONE, Html("Internal server error".to_string()))
+    }
+}
+
+/// Returns the markdown source of a file as it is
+async fn request_raw_file(
+    requested_file: String,
+    tx_file: sync::mpsc::Sender<MsgBuilder>,
+) -> impl IntoResponse {
+    log::debug!("Requested raw file: {}", requested_file);
+    let (tx_source, rx_source) = sync::oneshot::channel();
+    tx_file
+        .send(MsgBuilder::Source(requested_file, tx_source))
+        .await
+        .unwrap_or_else(|_| panic!("Failed awaiting result"));
+
+    match rx_source.await {
+        Ok(Some(source)) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            source,
+        ),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            "404 - Not found".to_string(),
+        ),
+        Err(_) => (
+            StatusCode::GONE,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            "Internal server error".to_string(),
+        ),
+    }
+}
+
+/// Lists the files with problems found while building them
+async fn request_problems(
+    tx_file: sync::mpsc::Sender<MsgBuilder>,
+    ui_config: UiConfig,
+) -> impl IntoResponse {
+    let (tx_files, rx_files) = sync::oneshot::channel();
+    if tx_file.send(MsgBuilder::AllFiles(tx_files)).await.is_err() {
+        return (StatusCode::GONE, Html("Internal server error".to_string()));
+    }
+
+    match (rx_files.await, api::problem_files(&tx_file).await) {
+        (Ok(files), Some(problems)) => {
+            let result = crate::ui::render_page(
+                "Problems",
+                crate::ui::Contents::Problems(&problems),
+                &files[..],
+                &ui_config,
+            )
+            .into_string();
+
+            (StatusCode::OK, Html(result))
+        }
+        _ => (StatusCode::GONE, Html("Internal server error".to_string())),
     }
 }
 
 async fn request_file(
     requested_file: String,
     tx_file: sync::mpsc::Sender<MsgBuilder>,
+    ui_config: UiConfig,
 ) -> impl IntoResponse {
     log::debug!("Requested file: {}", requested_file);
     let (tx_onefile, rx_onefile) = sync::oneshot::channel();
@@ -188,48 +454,47 @@ async fn request_file(
     if let Ok(result) = rx_onefile.await {
         match result {
             (Some(result), files) => {
-                let result = format!(
-                    "{}",
-                    crate::ui::render_page(
-                        requested_file.as_str(),
-                        crate::ui::Contents::Html(&result),
-                        &files[..]
-                    )
-                    .into_string()
-                );
+                let result = crate::ui::render_page(
+                    requested_file.as_str(),
+                    crate::ui::Contents::Html(&result),
+                    &files[..],
+                    &ui_config,
+                )
+                .into_string();
 
                 (StatusCode::OK, Html(result))
             }
             (None, files) => {
-                let result = format!(
-                    "{}",
-                    crate::ui::render_page(
-                        requested_file.as_str(),
-                        crate::ui::Contents::NotFound(),
-                        &files[..]
-                    )
-                    .into_string()
-                );
+                let result = crate::ui::render_page(
+                    requested_file.as_str(),
+                    crate::ui::Contents::NotFound(),
+                    &files[..],
+                    &ui_config,
+                )
+                .into_string();
 
                 (StatusCode::NOT_FOUND, Html(result))
             }
         }
     } else {
-        (StatusCode::GONE, Html(format!("Internal server error")))
+        (StatusCode::GONE, Html("Internal server error".to_string()))
     }
 }
 
 pub async fn create_router(
     tx_file: sync::mpsc::Sender<MsgBuilder>,
+    tx_srv: sync::mpsc::Sender<MsgSrv>,
+    ui_config: sync::watch::Receiver<UiConfig>,
 ) -> (
     Router,
     tokio::sync::mpsc::Sender<MsgSrv>,
     tokio::task::JoinHandle<()>,
 ) {
     let (tx, mut rx) = sync::mpsc::channel(crate::CHANNEL_COUNT);
-    let ws_channels: Arc<Mutex<HashMap<i64, sync::mpsc::Sender<MsgSrv>, RandomState>>> = Arc::new(
-        Mutex::new(HashMap::with_capacity_and_hasher(4, RandomState::new())),
-    );
+    let ws_channels: WsChannels = Arc::new(Mutex::new(HashMap::with_capacity_and_hasher(
+        4,
+        RandomState::new(),
+    )));
 
     let ws_channels_for_listener = ws_channels.clone();
     let server_router_handle = task::spawn(async move {
@@ -237,56 +502,224 @@ pub async fn create_router(
             log::debug!("Server event: {:?}", msg);
 
             match msg {
-                MsgSrv::File(path, content) => {
+                MsgSrv::File(mount, path, content, patch) => {
                     let ws_channels = ws_channels_for_listener.lock().await;
-                    log::debug!("Open websockets: {}", ws_channels.len());
-                    for tx_ws in ws_channels.values() {
-                        tx_ws
-                            .send(MsgSrv::File(path.clone(), content.clone()))
+                    // The list of problems changes with any file
+                    let subscribers: Vec<&WsClient> = ws_channels
+                        .values()
+                        .filter(|client| {
+                            client.path.as_ref() == Some(&path)
+                                || client.path.as_deref() == Some(PROBLEMS_PATH)
+                        })
+                        .collect();
+                    log::debug!(
+                        "Open websockets: {}, subscribed to {}: {}",
+                        ws_channels.len(),
+                        path,
+                        subscribers.len()
+                    );
+                    for client in subscribers {
+                        client
+                            .tx_ws
+                            .send(MsgSrv::File(
+                                mount.clone(),
+                                path.clone(),
+                                content.clone(),
+                                patch.clone(),
+                            ))
                             .await
-                            .unwrap();
+                            .ok();
                     }
                 }
-                MsgSrv::NewFile(path, all_files) => {
+                MsgSrv::NewFile(mount, path, all_files) => {
                     let ws_channels = ws_channels_for_listener.lock().await;
                     log::debug!("Open websockets: {}", ws_channels.len());
-                    for tx_ws in ws_channels.values() {
-                        tx_ws
-                            .send(MsgSrv::NewFile(path.clone(), all_files.clone()))
+                    for client in ws_channels.values() {
+                        client
+                            .tx_ws
+                            .send(MsgSrv::NewFile(
+                                mount.clone(),
+                                path.clone(),
+                                all_files.clone(),
+                            ))
+                            .await
+                            .ok();
+                    }
+                }
+                MsgSrv::Goto(path, line, block) => {
+                    // Other clients aren't navigated away from the pages they show
+                    let ws_channels = ws_channels_for_listener.lock().await;
+                    for client in ws_channels
+                        .values()
+                        .filter(|client| client.path.as_ref() == Some(&path))
+                    {
+                        client
+                            .tx_ws
+                            .send(MsgSrv::Goto(path.clone(), line, block))
                             .await
-                            .unwrap();
+                            .ok();
+                    }
+                }
+                MsgSrv::Reload() => {
+                    let ws_channels = ws_channels_for_listener.lock().await;
+                    for client in ws_channels.values() {
+                        client.tx_ws.send(MsgSrv::Reload()).await.ok();
                     }
                 }
                 MsgSrv::Exit() => {
+                    // Server is shutting down, close all websockets and wait until the close
+                    // frames were sent
+                    let ws_channels: Vec<_> = ws_channels_for_listener
+                        .lock()
+                        .await
+                        .values()
+                        .map(|client| client.tx_ws.clone())
+                        .collect();
+                    for tx_ws in ws_channels.iter() {
+                        tx_ws.send(MsgSrv::Exit()).await.ok();
+                    }
+                    futures::future::join_all(ws_channels.iter().map(|tx_ws| tx_ws.closed())).await;
                     break;
                 }
             }
         }
     });
 
-    let router = Router::new()
+    let base_path = ui_config.borrow().base_path.clone();
+    let route = |path: &str| format!("{}{}", base_path, path);
+
+    let mut router = Router::new()
         //.route("/.rsc/Roboto/Roboto-Regular.ttf", get(rsc_roboto_regular))
-        .route("/.rsc/ws.js", get(ws_js_file))
-        .route("/.rsc/prism.js", get(prism_js_file))
-        .route("/.ws", get(handle_ws))
-        .layer(Extension(WsState { ws_channels }))
-        .route("/.ping", get(ping))
-        .route("/.api", post(|| async {}))
-        .route("/.license", get_full_text_page!("../LICENSE", tx_file))
-        .route("/.contents/*rest", {
+        .route(&route("/.rsc/ws.js"), get(ws_js_file))
+        .route(&route("/.rsc/prism.js"), get(prism_js_file))
+        .route(&route("/.ws"), get(handle_ws))
+        .route(&route("/.events"), get(handle_events))
+        .layer(Extension(WsState {
+            ws_channels,
+            ui_config: ui_config.clone(),
+            tx_file: tx_file.clone(),
+        }))
+        .route(&route("/.ping"), get(api::ping))
+        .route(&route("/.api"), {
             let tx_file = tx_file.clone();
+            post(|Json(request): Json<GotoRequest>| async move {
+                api::goto(request, tx_file, tx_srv).await
+            })
+        })
+        .route(&route("/.api/files"), {
+            let tx_file = tx_file.clone();
+            get(|| async move { api::files(tx_file).await })
+        })
+        .route(&route("/.api/problems"), {
+            let tx_file = tx_file.clone();
+            get(|| async move { api::problems(tx_file).await })
+        })
+        .route(&route(PROBLEMS_PATH), {
+            let tx_file = tx_file.clone();
+            let ui_config = ui_config.clone();
+            get(|headers: HeaderMap| async move {
+                let ui_config = ui_config_for_request(&headers, &ui_config);
+                request_problems(tx_file, ui_config).await
+            })
+        })
+        .route(
+            &route("/.api/files/*rest"),
+            get_api_file!(route("/.api/files"), api::file, tx_file),
+        )
+        .route(
+            &route("/.api/source/*rest"),
+            get_api_file!(route("/.api/source"), api::source, tx_file).put({
+                let tx_file = tx_file.clone();
+                let ui_config = ui_config.clone();
+                let prefix = route("/.api/source");
+                move |uri: Uri, headers: HeaderMap, Json(request): Json<api::SaveRequest>| async move {
+                    if !ui_config.borrow().edit {
+                        return api::forbidden("Editing is disabled");
+                    }
+
+                    let requested_file = determine_real_path(&uri.path()[prefix.len()..]);
+                    // Required, so changes made in the meantime are only overwritten on purpose
+                    // (with ``*``)
+                    let Some(if_match) = headers
+                        .get(header::IF_MATCH)
+                        .and_then(|value| value.to_str().ok())
+                    else {
+                        return api::precondition_required();
+                    };
+                    let if_match = Some(if_match)
+                        .filter(|value| *value != "*")
+                        .map(|value| value.trim_start_matches("W/").trim_matches('"').to_string());
+
+                    api::save(requested_file, request, if_match, tx_file).await
+                }
+            }),
+        )
+        .route(
+            &route("/.api/outline/*rest"),
+            get_api_file!(route("/.api/outline"), api::outline, tx_file),
+        )
+        .route(
+            &route("/.api/history/*rest"),
+            get_api_file!(route("/.api/history"), api::history, tx_file),
+        )
+        .route(
+            &route("/.api/blame/*rest"),
+            get_api_file!(route("/.api/blame"), api::blame, tx_file),
+        )
+        .route(
+            &route("/.api/revision/*rest"),
+            get_api_file!(
+                route("/.api/revision"),
+                api::revision,
+                tx_file,
+                api::RevisionQuery
+            ),
+        )
+        .route(
+            &route("/.api/diff/*rest"),
+            get_api_file!(route("/.api/diff"), api::diff, tx_file, api::DiffQuery),
+        )
+        .route(&route("/.api/pages"), {
+            let tx_file = tx_file.clone();
+            let ui_config = ui_config.clone();
+            post(|Json(action): Json<PageAction>| async move {
+                if !ui_config.borrow().edit {
+                    return api::forbidden("Editing is disabled");
+                }
+
+                api::page(action, tx_file).await
+            })
+        })
+        .route(&route("/.api/status"), {
+            let tx_file = tx_file.clone();
+            get(|| async move { api::status(tx_file).await })
+        })
+        .route(
+            &route("/.license"),
+            get_full_text_page!("../LICENSE", tx_file, ui_config),
+        )
+        .route(&route("/.contents/*rest"), {
+            let tx_file = tx_file.clone();
+            let contents_path = route("/.contents");
             get(|uri: Uri| async move {
-                let requested_file = uri.path()["/.contents".len()..].to_string();
+                let requested_file = uri.path()[contents_path.len()..].to_string();
                 let requested_file = determine_real_path(&requested_file);
 
                 request_just_file_contents(requested_file, tx_file).await
             })
         })
-        .route("/", {
+        .route(
+            &route("/.raw/*rest"),
+            get_api_file!(route("/.raw"), request_raw_file, tx_file),
+        )
+        .route(&route("/"), {
             let tx_file = tx_file.clone();
-            get(|| async move {
+            let ui_config = ui_config.clone();
+            get(|headers: HeaderMap| async move {
                 log::debug!("Route: /");
 
+                let ui_config = ui_config_for_request(&headers, &ui_config);
+
                 let (tx_files, rx_files) = sync::oneshot::channel();
                 tx_file
                     .clone()
@@ -295,127 +728,248 @@ pub async fn create_router(
                     .unwrap_or_else(|_| panic!("Failed awaiting result"));
 
                 if let Ok(files) = rx_files.await {
-                    if files.contains(&"/README.md".into()) {
-                        (
-                            StatusCode::TEMPORARY_REDIRECT,
-                            [("Location", "/README.md")],
-                            Html(format!("")),
-                        )
-                            .into_response()
-                    } else if files.contains(&"/Readme.md".into()) {
+                    if let Some(readme) = README_FILES
+                        .iter()
+                        .find(|readme| files.contains(&readme.to_string()))
+                    {
                         (
                             StatusCode::TEMPORARY_REDIRECT,
-                            [("Location", "/Readme.md")],
-                            Html(format!("")),
+                            [("Location", format!("{}{}", ui_config.base_path, readme))],
+                            Html(String::new()),
                         )
                             .into_response()
                     } else {
-                        let result = format!(
-                            "{}",
-                            crate::ui::render_page(
-                                "/",
-                                crate::ui::Contents::NotFound(),
-                                &files[..]
-                            )
-                            .into_string()
-                        );
+                        let result = crate::ui::render_page(
+                            "/",
+                            crate::ui::Contents::NotFound(),
+                            &files[..],
+                            &ui_config,
+                        )
+                        .into_string();
 
                         (StatusCode::NOT_FOUND, Html(result)).into_response()
                     }
                 } else {
                     (
                         StatusCode::GONE,
-                        Html(format!("<h1>Internal server error</h1>")),
+                        Html("<h1>Internal server error</h1>".to_string()),
                     )
                         .into_response()
                 }
             })
-        })
-        .fallback(get(|uri: Uri| async move {
-            let requested_file = uri.path().to_string();
-            let requested_file = determine_real_path(&requested_file);
+        });
 
-            log::debug!("Route: {}", requested_file);
+    if !base_path.is_empty() {
+        // Requesting the base path without a trailing slash would break relative links
+        let ui_config = ui_config.clone();
+        router = router.route(
+            &base_path,
+            get(|headers: HeaderMap| async move {
+                let ui_config = ui_config_for_request(&headers, &ui_config);
 
-            request_file(requested_file, tx_file).await
-        }));
+                (
+                    StatusCode::PERMANENT_REDIRECT,
+                    [("Location", format!("{}/", ui_config.base_path))],
+                    Html(String::new()),
+                )
+            }),
+        );
+    }
+
+    let router = router.fallback(get(|uri: Uri, headers: HeaderMap| async move {
+        let ui_config = ui_config_for_request(&headers, &ui_config);
+
+        let requested_file = match strip_base_path(uri.path(), &base_path) {
+            Some(requested_file) => determine_real_path(requested_file),
+            None => {
+                log::debug!("Route outside of base path: {}", uri.path());
+                let result = crate::ui::render_page(
+                    uri.path(),
+                    crate::ui::Contents::NotFound(),
+                    &[],
+                    &ui_config,
+                )
+                .into_string();
+
+                return (StatusCode::NOT_FOUND, Html(result)).into_response();
+            }
+        };
+
+        log::debug!("Route: {}", requested_file);
+
+        request_file(requested_file, tx_file, ui_config)
+            .await
+            .into_response()
+    }));
 
     (router, tx, server_router_handle)
 }
 
-async fn handle_ws(ws: WebSocketUpgrade, Extension(state): Extension<WsState>) -> Response {
+async fn handle_ws(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Extension(state): Extension<WsState>,
+) -> Response {
     log::debug!("Trying to establish websocket connection");
-    ws.on_upgrade(|socket| handle_ws_socket(socket, state))
+    let ui_config = ui_config_for_request(&headers, &state.ui_config);
+
+    ws.on_upgrade(|socket| handle_ws_socket(socket, state, ui_config))
 }
 
-async fn handle_ws_socket(socket: WebSocket, state: WsState) {
+/// Query of clients connecting to the event stream (same as the websocket's hello message)
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    path: String,
+    version: Option<String>,
+    sidebar_version: Option<String>,
+}
+
+/// Removes a client from the open connections, when its event stream is dropped
+struct EventsGuard {
+    ws_channels: WsChannels,
+    id: u64,
+}
+
+impl Drop for EventsGuard {
+    fn drop(&mut self) {
+        let ws_channels = self.ws_channels.clone();
+        let id = self.id;
+        task::spawn(async move {
+            ws_channels.lock().await.remove_entry(&id);
+            log::debug!("Closed event stream");
+        });
+    }
+}
+
+/// Sends the same messages as the websocket as server-sent events. Used by clients, which can't
+/// establish websocket connections (e.g. behind proxies). Clients reconnect to subscribe to
+/// another file.
+async fn handle_events(
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+    Extension(state): Extension<WsState>,
+) -> Sse<impl Stream<Item = Result<Event, serde_json::Error>>> {
+    log::debug!("Established event stream for {}", query.path);
+    let ui_config = ui_config_for_request(&headers, &state.ui_config);
+
+    let (tx_events, rx_events) = sync::mpsc::channel(crate::CHANNEL_COUNT);
+    let id = NEXT_CLIENT_ID.fetch_add(1, atomic::Ordering::Relaxed);
+    state.ws_channels.lock().await.insert(
+        id,
+        WsClient {
+            tx_ws: tx_events.clone(),
+            path: Some(query.path.clone()),
+        },
+    );
+
+    let mounts = ui_config.mounts.clone();
+    let guard = EventsGuard {
+        ws_channels: state.ws_channels.clone(),
+        id,
+    };
+    task::spawn(async move {
+        resync_client(
+            query.path,
+            query.version,
+            query.sidebar_version,
+            &mounts,
+            &state.tx_file,
+            &tx_events,
+        )
+        .await;
+    });
+
+    let stream = futures::stream::unfold(
+        (rx_events, guard, ui_config),
+        |(mut rx_events, guard, ui_config)| async move {
+            let msg = ServerMsg::from_msg(rx_events.recv().await?, &ui_config)?;
+            let event = serde_json::to_string(&msg).map(|data| Event::default().data(data));
+
+            Some((event, (rx_events, guard, ui_config)))
+        },
+    );
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn handle_ws_socket(socket: WebSocket, state: WsState, ui_config: UiConfig) {
     log::debug!("Established websocket connection");
 
     let (tx_ws, mut rx_ws) = sync::mpsc::channel(crate::CHANNEL_COUNT);
     let (mut sender, mut receiver) = socket.split();
 
+    let mounts = ui_config.mounts.clone();
     let websocket_sender_handle = task::spawn(async move {
         while let Some(msg) = rx_ws.recv().await {
             log::debug!("WebSocket Channel received: {:?}", msg);
 
-            match msg {
-                MsgSrv::File(path, content) => {
-                    // Send the client update of the content
-                    let content: json::JsonValue = content.into();
-                    if let Err(err) = send_msg(
-                        &mut sender,
-                        json::object! {
-                            action: "update-content",
-                            path: path,
-                            content: content
-                        },
-                    )
-                    .await
-                    {
-                        log::error!("Web socket connection broke: {}", err);
-                        break;
-                    }
-                }
-                MsgSrv::NewFile(_, all_files) => {
-                    let content = crate::ui::render_sidebar(&all_files[..]);
-                    // Send the client update of the sidebar
-                    if let Err(err) = send_msg(
-                        &mut sender,
-                        json::object! {
-                            action: "update-sidebar",
-                            content: content.into_string()
-                        },
-                    )
-                    .await
-                    {
-                        log::error!("Web socket connection broke: {}", err);
-                        break;
-                    }
-                }
-                MsgSrv::Exit() => {
-                    log::debug!("Web socket closed by client");
+            let msg = match ServerMsg::from_msg(msg, &ui_config) {
+                Some(msg) => msg,
+                None => {
+                    // Fails, if the client already closed the connection
+                    sender
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "server shutting down".into(),
+                        })))
+                        .await
+                        .ok();
                     break; // Exit websocket session
                 }
+            };
+
+            if let Err(err) = send_msg(&mut sender, &msg).await {
+                log::error!("Web socket connection broke: {}", err);
+                break;
             }
         }
     });
 
     let websocket_listener_handle = task::spawn(async move {
         // Announce presence of the web socket
-        let id = chrono::offset::Utc::now().timestamp_nanos();
+        let id = NEXT_CLIENT_ID.fetch_add(1, atomic::Ordering::Relaxed);
         {
             let mut ws_channels = state.ws_channels.lock().await;
-            ws_channels.insert(id, tx_ws.clone());
+            ws_channels.insert(
+                id,
+                WsClient {
+                    tx_ws: tx_ws.clone(),
+                    path: None,
+                },
+            );
         }
 
         while let Some(msg) = receiver.next().await {
-            if let Ok(_msg) = msg {
-                continue;
-            } else {
-                // client disconnected
-                tx_ws.send(MsgSrv::Exit()).await.unwrap();
-                break;
-            };
+            match msg {
+                Ok(Message::Text(text)) => match serde_json::from_str(&text) {
+                    Ok(ClientMsg::Hello {
+                        path,
+                        version,
+                        sidebar_version,
+                    }) => {
+                        subscribe(&state.ws_channels, id, &path).await;
+                        resync_client(
+                            path,
+                            version,
+                            sidebar_version,
+                            &mounts,
+                            &state.tx_file,
+                            &tx_ws,
+                        )
+                        .await;
+                    }
+                    Ok(ClientMsg::Subscribe { path }) => {
+                        subscribe(&state.ws_channels, id, &path).await;
+                    }
+                    Err(err) => log::error!("Invalid websocket message: {}", err),
+                },
+                Ok(_) => continue,
+                Err(_) => {
+                    // client disconnected
+                    tx_ws.send(MsgSrv::Exit()).await.ok();
+                    break;
+                }
+            }
         }
 
         state.ws_channels.lock().await.remove_entry(&id);
@@ -426,11 +980,73 @@ async fn handle_ws_socket(socket: WebSocket, state: WsState) {
     log::debug!("Closed websocket connection");
 }
 
+/// Sends the websocket connection only updates of the file at the given path from now on
+async fn subscribe(ws_channels: &WsChannels, id: u64, path: &str) {
+    log::debug!("Websocket {} subscribed to {}", id, path);
+    if let Some(client) = ws_channels.lock().await.get_mut(&id) {
+        client.path = Some(path.to_string());
+    }
+}
+
+/// Sends the client the contents of the file it shows and the sidebar, if they differ from the
+/// versions the client knows
+async fn resync_client(
+    path: String,
+    version: Option<String>,
+    sidebar_version: Option<String>,
+    mounts: &[String],
+    tx_file: &sync::mpsc::Sender<MsgBuilder>,
+    tx_ws: &sync::mpsc::Sender<MsgSrv>,
+) {
+    log::debug!("Resyncing client showing {}", path);
+
+    let (tx_onefile, rx_onefile) = sync::oneshot::channel();
+    if tx_file
+        .send(MsgBuilder::File(path.clone(), tx_onefile))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let (file, all_files) = match rx_onefile.await {
+        Ok(result) => result,
+        Err(_) => return,
+    };
+
+    let mount = crate::mount::find_mount(&path, mounts.iter().map(|m| m.as_str()))
+        .unwrap_or_default()
+        .to_string();
+
+    if let Some(file) = file {
+        if version.as_deref() != Some(file.version().as_str()) {
+            tx_ws
+                .send(MsgSrv::File(
+                    mount.clone(),
+                    path.clone(),
+                    Box::new(file),
+                    None,
+                ))
+                .await
+                .ok();
+        }
+    }
+
+    if sidebar_version.as_deref() != Some(crate::ui::files_version(&all_files[..]).as_str()) {
+        tx_ws
+            .send(MsgSrv::NewFile(mount, path, all_files))
+            .await
+            .ok();
+    }
+}
+
 async fn send_msg(
     sender: &mut SplitSink<WebSocket, Message>,
-    val: json::JsonValue,
+    msg: &ServerMsg,
 ) -> anyhow::Result<()> {
-    sender.send(Message::Text(val.dump())).await?;
+    sender
+        .send(Message::Text(serde_json::to_string(msg)?))
+        .await?;
 
     Ok(())
 }
@@ -2,8 +2,8 @@ use std::time::Duration;
 
 use crate::{
     builder::*,
-    msg::{MsgBuilder, MsgInternalBuilder, MsgSrv},
-    ParserType,
+    config::BuilderConfig,
+    msg::{MsgBuilder, MsgInternalBuilder},
 };
 use simplelog::{CombinedLogger, TermLogger, TerminalMode};
 use tokio::{sync, task};
@@ -14,12 +14,13 @@ fn setup_log() {
         simplelog::Config::default(),
         TerminalMode::Mixed,
         simplelog::ColorChoice::Auto,
-    )]);
+    )])
+    .ok();
 }
 
 macro_rules! broad_file_search_generate {
     ($files:expr) => {
-        |_| $files.iter().map(|s| s.to_string()).collect()
+        |_, _: &BuilderConfig| $files.iter().map(|s| s.to_string()).collect()
     };
 }
 
@@ -28,8 +29,7 @@ fn fs_read_file(s: String) -> anyhow::Result<String> {
         "./README.md" | ".\\README.md" => Ok("# README".to_string()),
         "./test.md" | ".\\test.md" => Ok("# test header".to_string()),
         _ => {
-            assert!(false, "Should not be reached");
-            Err(anyhow::anyhow!("Should not be reached"))
+            unreachable!("Should not be reached")
         }
     }
 }
@@ -59,21 +59,22 @@ async fn test_initial_build() {
     setup_log();
 
     let (tx_file, rx_file) = sync::mpsc::channel(1);
-    let (tx_srv, mut rx_srv) = sync::mpsc::channel(1);
+    let (tx_srv, _rx_srv) = sync::mpsc::channel(1);
 
     let builder_handle = {
         let tx_file = tx_file.clone();
 
         task::spawn(async move {
             builder_with_fs_change(
-                ParserType::CommonMark,
+                BuilderConfig::default(),
                 tx_srv,
+                String::new(),
                 ".".to_string(),
                 tx_file,
                 rx_file,
                 fs_no_change,
                 fs_read_file,
-                broad_file_search_generate!(vec!["README.md", "test.md"]),
+                broad_file_search_generate!(["README.md", "test.md"]),
             )
             .await;
         })
@@ -97,11 +98,11 @@ async fn test_initial_build() {
 
     assert_eq!(2, files.len(), "Expected 2 files");
     assert_eq!(
-        Some(format!("<h1>README</h1>\n")),
+        Some("<h1>README</h1>\n".to_string()),
         file.map(|file| file.contents)
     );
     assert_eq!(
-        Some(format!("<h1>test header</h1>\n")),
+        Some("<h1>test header</h1>\n".to_string()),
         file_test.map(|file_test| file_test.contents)
     );
 }
@@ -118,14 +119,15 @@ async fn test_add_file() {
 
         task::spawn(async move {
             builder_with_fs_change(
-                ParserType::CommonMark,
+                BuilderConfig::default(),
                 tx_srv,
+                String::new(),
                 ".".to_string(),
                 tx_file.clone(),
                 rx_file,
                 fs_change_add_test,
                 fs_read_file,
-                broad_file_search_generate!(vec!["README.md"]),
+                broad_file_search_generate!(["README.md"]),
             )
             .await;
         })
@@ -152,11 +154,288 @@ async fn test_add_file() {
 
     assert_eq!(2, files.len(), "Expected 2 files");
     assert_eq!(
-        Some(format!("<h1>README</h1>\n")),
+        Some("<h1>README</h1>\n".to_string()),
         file.map(|file| file.contents)
     );
     assert_eq!(
-        Some(format!("<h1>test header</h1>\n")),
+        Some("<h1>test header</h1>\n".to_string()),
         file_test.map(|file_test| file_test.contents)
     );
 }
+
+/// Empty directory for the test in the temporary directory
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("md-dir-builder-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+#[cfg(unix)]
+#[test]
+fn test_write_atomically() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("write");
+    let file = dir.join("a.md");
+    std::fs::write(&file, "# A").unwrap();
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o640)).unwrap();
+    let link = dir.join("link.md");
+    std::os::unix::fs::symlink(&file, &link).unwrap();
+
+    write_atomically(&link, "# B").unwrap();
+
+    // The file the symlink points to is replaced, keeping its permissions
+    assert!(std::fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!("# B", std::fs::read_to_string(&file).unwrap());
+    assert_eq!(
+        0o640,
+        std::fs::metadata(&file).unwrap().permissions().mode() & 0o777
+    );
+
+    write_atomically(&dir.join("new.md"), "# New").unwrap();
+    assert_eq!(
+        "# New",
+        std::fs::read_to_string(dir.join("new.md")).unwrap()
+    );
+
+    // Symlinks next to the file are never written to
+    let outside = dir.join("outside");
+    std::fs::write(&outside, "outside").unwrap();
+    std::os::unix::fs::symlink(&outside, dir.join(".a.md.md-dir-builder~")).unwrap();
+    write_atomically(&file, "# C").unwrap();
+    assert_eq!("# C", std::fs::read_to_string(&file).unwrap());
+    assert_eq!("outside", std::fs::read_to_string(&outside).unwrap());
+    let mut names = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        vec![
+            ".a.md.md-dir-builder~",
+            "a.md",
+            "link.md",
+            "new.md",
+            "outside"
+        ],
+        names
+    );
+    std::fs::remove_dir_all(dir).ok();
+}
+
+/// Changes a page of the files in ``dir`` like the builder of the mount ``/docs``
+async fn change_page_in(
+    dir: &std::path::Path,
+    pages: &[&str],
+    action: PageAction,
+) -> (Result<Vec<String>, WriteError>, Vec<String>) {
+    let map = std::sync::Arc::new(sync::Mutex::new(Default::default()));
+    let files = std::sync::Arc::new(sync::Mutex::new(
+        pages
+            .iter()
+            .map(|page| format!("/docs/{}", page))
+            .collect::<Vec<String>>(),
+    ));
+    let (tx_srv, _rx_srv) = sync::mpsc::channel(16);
+
+    let changed = change_page(
+        action,
+        &BuilderConfig::default(),
+        "/docs",
+        dir,
+        map,
+        files.clone(),
+        Default::default(),
+        |path: String| Ok(std::fs::read_to_string(path)?),
+        &tx_srv,
+    )
+    .await;
+    let files = files.lock().await.clone();
+
+    (changed, files)
+}
+
+#[tokio::test]
+async fn test_create_page() {
+    let dir = test_dir("create");
+
+    let (changed, files) = change_page_in(
+        &dir,
+        &[],
+        PageAction::Create {
+            path: "/docs/notes/new.md".to_string(),
+        },
+    )
+    .await;
+
+    assert_eq!(Ok(vec![]), changed);
+    assert_eq!(vec!["/docs/notes/new.md".to_string()], files);
+    assert_eq!(
+        "# new\n",
+        std::fs::read_to_string(dir.join("notes/new.md")).unwrap()
+    );
+
+    let (changed, _) = change_page_in(
+        &dir,
+        &["notes/new.md"],
+        PageAction::Create {
+            path: "/docs/notes/new.md".to_string(),
+        },
+    )
+    .await;
+    assert_eq!(Err(WriteError::AlreadyExists), changed);
+
+    // Hidden directories
+    for path in [
+        "/docs/.git/a.md",
+        "/docs/.trash/a.md",
+        "/docs/x/.ipynb_checkpoints/a.md",
+    ] {
+        let (changed, _) = change_page_in(
+            &dir,
+            &[],
+            PageAction::Create {
+                path: path.to_string(),
+            },
+        )
+        .await;
+        assert_eq!(Err(WriteError::InvalidPath), changed);
+    }
+    assert!(!dir.join(".git").exists());
+
+    // Never replaces a page created in the meantime
+    assert_eq!(
+        std::io::ErrorKind::AlreadyExists,
+        write_new(&dir.join("notes/new.md"), "# Other")
+            .unwrap_err()
+            .kind()
+    );
+    assert_eq!(
+        "# new\n",
+        std::fs::read_to_string(dir.join("notes/new.md")).unwrap()
+    );
+    assert_eq!(1, std::fs::read_dir(dir.join("notes")).unwrap().count());
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_rename_page() {
+    let dir = test_dir("rename");
+    std::fs::write(
+        dir.join("a.md"),
+        "# A\n\n[B](b.md) [Self](a.md#top)\n\n```\n[B](b.md)\n```\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("b.md"),
+        "[A](a.md) `[A](a.md)`\n\n    [A](a.md)\n\n[a]: ./a.md\n",
+    )
+    .unwrap();
+    // Not parsed as markdown, so the link isn't found
+    std::fs::write(dir.join("c.rst"), "`A <a.md>`_ [A](a.md)\n").unwrap();
+
+    let (changed, files) = change_page_in(
+        &dir,
+        &["a.md", "b.md", "c.rst"],
+        PageAction::Rename {
+            path: "/docs/a.md".to_string(),
+            new_path: "/docs/dir/c.md".to_string(),
+            rewrite_links: true,
+        },
+    )
+    .await;
+
+    assert_eq!(Ok(vec!["/docs/b.md".to_string()]), changed);
+    assert!(files.contains(&"/docs/dir/c.md".to_string()));
+    assert!(!files.contains(&"/docs/a.md".to_string()));
+    assert!(!dir.join("a.md").exists());
+    // The links of the moved page point to the same files, except in code
+    assert_eq!(
+        "# A\n\n[B](../b.md) [Self](c.md#top)\n\n```\n[B](b.md)\n```\n",
+        std::fs::read_to_string(dir.join("dir/c.md")).unwrap()
+    );
+    assert_eq!(
+        "[A](dir/c.md) `[A](a.md)`\n\n    [A](a.md)\n\n[a]: dir/c.md\n",
+        std::fs::read_to_string(dir.join("b.md")).unwrap()
+    );
+    assert_eq!(
+        "`A <a.md>`_ [A](a.md)\n",
+        std::fs::read_to_string(dir.join("c.rst")).unwrap()
+    );
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_delete_page() {
+    let dir = test_dir("delete");
+    std::fs::write(dir.join("a.md"), "# A\n").unwrap();
+
+    let (changed, files) = change_page_in(
+        &dir,
+        &["a.md"],
+        PageAction::Delete {
+            path: "/docs/a.md".to_string(),
+        },
+    )
+    .await;
+
+    assert_eq!(Ok(vec![]), changed);
+    assert!(files.is_empty());
+    assert!(!dir.join("a.md").exists());
+    assert_eq!(
+        "# A\n",
+        std::fs::read_to_string(dir.join(TRASH_DIR).join("a.md")).unwrap()
+    );
+
+    let (changed, _) = change_page_in(
+        &dir,
+        &[],
+        PageAction::Delete {
+            path: "/docs/a.md".to_string(),
+        },
+    )
+    .await;
+    assert_eq!(Err(WriteError::NotFound), changed);
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_change_notebook() {
+    let dir = test_dir("change_notebook");
+    std::fs::write(dir.join("a.ipynb"), "{\"cells\": []}").unwrap();
+
+    for action in [
+        PageAction::Create {
+            path: "/docs/b.ipynb".to_string(),
+        },
+        PageAction::Rename {
+            path: "/docs/a.ipynb".to_string(),
+            new_path: "/docs/c.ipynb".to_string(),
+            rewrite_links: false,
+        },
+        PageAction::Rename {
+            path: "/docs/a.ipynb".to_string(),
+            new_path: "/docs/c.md".to_string(),
+            rewrite_links: false,
+        },
+        PageAction::Delete {
+            path: "/docs/a.ipynb".to_string(),
+        },
+    ] {
+        let (changed, files) = change_page_in(&dir, &["a.ipynb"], action).await;
+        assert_eq!(Err(WriteError::ReadOnly), changed);
+        assert_eq!(vec!["/docs/a.ipynb".to_string()], files);
+    }
+    assert!(dir.join("a.ipynb").exists());
+    assert!(!dir.join("b.ipynb").exists());
+
+    let config = BuilderConfig::default();
+    assert!(build_file(&config, "a.ipynb", "{\"cells\": []}").read_only);
+    assert!(!build_file(&config, "a.md", "# A").read_only);
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,246 @@
+use std::time::Duration;
+
+use clap::Parser;
+
+use crate::{
+    config::{find_config_file, matches_pattern, Config, CONFIG_FILE_NAME},
+    markdown::DEFAULT_CONVERTER_TIMEOUT,
+    ui::Theme,
+    ParserType,
+};
+
+#[test]
+fn test_parse_config() {
+    let config: Config = toml::from_str(
+        r#"
+        port = 8081
+        bind = ["0.0.0.0"]
+        parser = "pandoc"
+        ignore = ["node_modules", "*.draft.md"]
+        extensions = ["md", "markdown"]
+        theme = "dark"
+
+        [pandoc]
+        from = "markdown+emoji"
+        lua-filters = ["filters/links.lua"]
+        args = ["--toc"]
+        "#,
+    )
+    .expect("Valid configuration");
+
+    assert_eq!(Some(8081), config.port);
+    assert_eq!(Some(ParserType::Pandoc), config.parser);
+    assert_eq!(Some(Theme::Dark), config.theme);
+    assert_eq!(
+        vec!["--toc".to_string()],
+        config.builder_config().pandoc.args
+    );
+    assert_eq!("markdown+emoji", config.builder_config().pandoc.from);
+    assert_eq!(
+        vec!["filters/links.lua".to_string()],
+        config.builder_config().pandoc.lua_filters
+    );
+    assert!(toml::from_str::<Config>("unknown = 1").is_err());
+}
+
+#[test]
+fn test_merge_config() {
+    let overrides = Config {
+        port: Some(9000),
+        ..Config::default()
+    };
+    let file = Config {
+        port: Some(8081),
+        parser: Some(ParserType::Pandoc),
+        ..Config::default()
+    };
+
+    let config = overrides.merge(file);
+    assert_eq!(9000, config.port());
+    assert_eq!(ParserType::Pandoc, config.builder_config().parser);
+    assert_eq!(Theme::Auto, config.ui_config(vec![]).theme);
+}
+
+#[test]
+fn test_is_markdown() {
+    let config = Config {
+        ignore: Some(vec!["node_modules".to_string(), "*.draft.md".to_string()]),
+        extensions: Some(vec!["md".to_string(), "markdown".to_string()]),
+        ..Config::default()
+    }
+    .builder_config();
+
+    assert!(config.is_markdown("README.md"));
+    assert!(config.is_markdown("docs/index.markdown"));
+    assert!(!config.is_markdown("notes.draft.md"));
+    assert!(!config.is_markdown("node_modules/pkg/README.md"));
+    assert!(!config.is_markdown(".git/README.md"));
+    assert!(!config.is_markdown("main.rs"));
+}
+
+#[test]
+fn test_converters() {
+    let config: Config = toml::from_str(
+        r#"
+        [converters.adoc]
+        command = ["asciidoctor", "-s", "-o", "-", "-"]
+        timeout = 5
+
+        [converters.rst]
+        command = ["rst2html", "{input}"]
+        "#,
+    )
+    .expect("Valid configuration");
+    let config = config.builder_config();
+
+    assert!(config.is_markdown("docs/guide.adoc"));
+    assert!(config.is_markdown("index.rst"));
+    assert!(!config.is_markdown("index.txt"));
+    assert_eq!(Duration::from_secs(5), config.converters["adoc"].timeout);
+    assert_eq!(DEFAULT_CONVERTER_TIMEOUT, config.converters["rst"].timeout);
+}
+
+#[test]
+fn test_parsers() {
+    let config: Config = toml::from_str(
+        r#"
+        [parsers]
+        txt = "common-mark"
+        rst = "pandoc"
+        "#,
+    )
+    .expect("Valid configuration");
+
+    assert!(config.uses_parser(ParserType::Pandoc));
+    let config = config.builder_config();
+    assert!(config.is_markdown("notes.txt"));
+    assert!(config.is_markdown("guide.adoc"));
+    assert!(config.is_markdown("analysis.ipynb"));
+    assert!(!config.is_markdown(".ipynb_checkpoints/analysis-checkpoint.ipynb"));
+    assert_eq!(ParserType::Pandoc, config.parsers["rst"]);
+    assert_eq!(ParserType::AsciiDoc, config.parsers["asciidoc"]);
+    assert!(!Config::default().uses_parser(ParserType::Pandoc));
+}
+
+#[test]
+fn test_is_markdown_source() {
+    let config: Config = toml::from_str(
+        r#"
+        extensions = ["md"]
+
+        [parsers]
+        txt = "common-mark"
+        tex = "pandoc"
+        text = "pandoc"
+
+        [converters.org]
+        command = ["pandoc", "-f", "org"]
+        "#,
+    )
+    .expect("Valid configuration");
+    let config = config.builder_config();
+
+    assert!(config.is_markdown_source("README.md"));
+    assert!(config.is_markdown_source("notes.txt"));
+    assert!(config.is_markdown_source("notes.text"));
+    assert!(!config.is_markdown_source("paper.tex"));
+    assert!(!config.is_markdown_source("index.rst"));
+    assert!(!config.is_markdown_source("guide.adoc"));
+    assert!(!config.is_markdown_source("todo.org"));
+    assert!(!config.is_markdown_source("analysis.ipynb"));
+    assert!(!config.is_markdown_source("main.rs"));
+
+    let config: Config = toml::from_str(
+        r#"
+        parser = "pandoc"
+
+        [pandoc]
+        from = "rst"
+        "#,
+    )
+    .expect("Valid configuration");
+    assert!(!config.builder_config().is_markdown_source("README.md"));
+}
+
+#[test]
+fn test_matches_pattern() {
+    assert!(matches_pattern("*.md", "README.md"));
+    assert!(matches_pattern("draft?", "draft1"));
+    assert!(matches_pattern("target", "target"));
+    assert!(!matches_pattern("target", "targets"));
+    assert!(!matches_pattern("*.md", "README.txt"));
+}
+
+#[test]
+fn test_untrusted_config() {
+    let config: Config = toml::from_str(
+        r#"
+        bind = ["0.0.0.0"]
+        edit = true
+        theme = "dark"
+
+        [pandoc]
+        from = "markdown+emoji"
+        filters = ["pandoc-crossref"]
+        lua-filters = ["filters/links.lua"]
+        args = ["--toc"]
+
+        [diagrams]
+        commands = { dot = ["sh", "-c", "touch pwned"] }
+
+        [converters.adoc]
+        command = ["asciidoctor", "-s", "-o", "-", "-"]
+        "#,
+    )
+    .expect("Valid configuration");
+
+    let config = config.without_untrusted(std::path::Path::new(CONFIG_FILE_NAME));
+    assert_eq!(None, config.bind);
+    assert_eq!(None, config.edit);
+    assert_eq!(None, config.pandoc.filters);
+    assert_eq!(None, config.pandoc.lua_filters);
+    assert_eq!(None, config.pandoc.args);
+    assert_eq!(None, config.diagrams.commands);
+    assert_eq!(None, config.converters);
+    assert_eq!(Some(Theme::Dark), config.theme);
+    assert_eq!("markdown+emoji", config.builder_config().pandoc.from);
+
+    // Only files given explicitly are trusted
+    let directory = std::env::temp_dir().join("md-dir-builder-test-untrusted-config");
+    let file = directory.join(CONFIG_FILE_NAME);
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(&file, "edit = true\n").unwrap();
+    let directory = directory.to_string_lossy();
+    assert_eq!(
+        Some((file.clone(), false)),
+        find_config_file(None, &directory)
+    );
+    assert_eq!(
+        Some((file.clone(), true)),
+        find_config_file(Some(&file.to_string_lossy()), &directory)
+    );
+}
+
+#[test]
+fn test_flags() {
+    let config = |args: &[&str]| {
+        let args = crate::Args::try_parse_from([&["md-dir-builder"], args].concat())
+            .expect("Valid arguments");
+        Config::from(&args)
+    };
+
+    assert_eq!(None, config(&[]).edit);
+    assert_eq!(Some(true), config(&["--edit"]).edit);
+    assert_eq!(Some(false), config(&["--no-edit"]).edit);
+    assert_eq!(Some(false), config(&["--edit", "--no-edit"]).edit);
+    assert_eq!(Some(true), config(&["--no-edit", "--edit"]).edit);
+    assert_eq!(None, config(&[]).scroll_to_changes);
+    assert_eq!(
+        Some(true),
+        config(&["--no-scroll-to-changes", "--scroll-to-changes"]).scroll_to_changes
+    );
+    assert_eq!(
+        Some(false),
+        config(&["--no-scroll-to-changes"]).scroll_to_changes
+    );
+}
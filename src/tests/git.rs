@@ -0,0 +1,83 @@
+use crate::git::{history, last_commits, parse_blame, parse_log, show, Commit};
+
+#[test]
+fn test_parse_blame() {
+    let output = "27b9a94d18c1652671a5c1df504342c98f2de7b6 1 1 2
+author Ann Author
+author-mail <a@x>
+author-time 1700000000
+author-tz +0000
+summary Add doc
+filename doc.md
+\t# Doc
+27b9a94d18c1652671a5c1df504342c98f2de7b6 2 2
+\t
+";
+    let lines = parse_blame(output);
+
+    assert_eq!(2, lines.len());
+    assert_eq!(1, lines[0].line);
+    assert_eq!("# Doc", lines[0].text);
+    assert_eq!("Ann Author", lines[1].author);
+    assert_eq!("2023-11-14T22:13:20+00:00", lines[1].date);
+    assert_eq!("", lines[1].text);
+}
+
+#[test]
+fn test_parse_log() {
+    let output = "\x1eb1\x1fAnn Author\x1f2023-11-15T10:00:00+00:00\x1fMove doc
+
+docs/new.md
+\x1ea1\x1fAnn Author\x1f2023-11-14T10:00:00+00:00\x1fAdd doc
+
+old.md
+";
+    let commits: Vec<(Commit, Vec<&str>)> = parse_log(output)
+        .map(|(commit, paths)| (commit, paths.collect()))
+        .collect();
+
+    assert_eq!(2, commits.len());
+    assert_eq!("b1", commits[0].0.id);
+    assert_eq!("Move doc", commits[0].0.message);
+    assert_eq!(vec!["docs/new.md"], commits[0].1);
+    assert_eq!(vec!["old.md"], commits[1].1);
+}
+
+#[tokio::test]
+async fn test_show_renamed() {
+    let dir = std::env::temp_dir().join(format!("md-dir-builder-git-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(dir.join("docs")).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=Ann", "-c", "user.email=a@x"])
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    };
+
+    git(&["init", "-q"]);
+    std::fs::write(dir.join("old.md"), "# Old\n").unwrap();
+    git(&["add", "old.md"]);
+    git(&["commit", "-q", "-m", "Add doc"]);
+    git(&["mv", "old.md", "docs/new.md"]);
+    git(&["commit", "-q", "-m", "Move doc"]);
+
+    let file = dir.join("docs/new.md");
+    let commits = history(&file).await.unwrap();
+    assert_eq!(2, commits.len());
+    // Revisions before the rename are shown at the old path
+    assert_eq!("# Old\n", show(&file, &commits[1].id).await.unwrap());
+    assert_eq!("# Old\n", show(&file, "HEAD").await.unwrap());
+
+    std::fs::write(dir.join("other.md"), "# Other\n").unwrap();
+    git(&["add", "other.md"]);
+    git(&["commit", "-q", "-m", "Add other"]);
+    let commits = last_commits(&dir.join("docs")).await.unwrap();
+    assert_eq!(vec!["new.md"], commits.keys().collect::<Vec<&String>>());
+    assert_eq!("Move doc", commits["new.md"].message);
+    std::fs::remove_dir_all(dir).ok();
+}
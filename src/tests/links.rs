@@ -0,0 +1,59 @@
+use crate::links::{rebase_links, relative_link, resolve_link, rewrite_links};
+
+#[test]
+fn test_resolve_link() {
+    assert_eq!(Some("/b.md".to_string()), resolve_link("/a.md", "b.md"));
+    assert_eq!(
+        Some("/dir/b.md".to_string()),
+        resolve_link("/dir/a.md", "./b.md")
+    );
+    assert_eq!(
+        Some("/b.md".to_string()),
+        resolve_link("/dir/a.md", "../b.md")
+    );
+    assert_eq!(None, resolve_link("/a.md", "../b.md"));
+    assert_eq!(None, resolve_link("/a.md", "/b.md"));
+    assert_eq!(None, resolve_link("/a.md", "#heading"));
+    assert_eq!(None, resolve_link("/a.md", "https://example.com/b.md"));
+}
+
+#[test]
+fn test_relative_link() {
+    assert_eq!("b.md", relative_link("/a.md", "/b.md"));
+    assert_eq!("dir/b.md", relative_link("/a.md", "/dir/b.md"));
+    assert_eq!("../b.md", relative_link("/dir/a.md", "/b.md"));
+    assert_eq!("../other/b.md", relative_link("/dir/a.md", "/other/b.md"));
+    assert_eq!("../dir", relative_link("/dir/a.md", "/dir"));
+}
+
+#[test]
+fn test_rewrite_links() {
+    let markdown = "[B](b.md#intro) ![img](b.md) [C](c.md) [abs](/b.md)
+
+[ref]: ./b.md
+";
+    assert_eq!(
+        Some(
+            "[B](new/b.md#intro) ![img](new/b.md) [C](c.md) [abs](/b.md)
+
+[ref]: new/b.md
+"
+            .to_string()
+        ),
+        rewrite_links(markdown, "/a.md", "/b.md", "/new/b.md")
+    );
+    assert_eq!(None, rewrite_links(markdown, "/a.md", "/d.md", "/new/d.md"));
+}
+
+#[test]
+fn test_rebase_links() {
+    assert_eq!(
+        Some("[B](../b.md) [A](c.md#top) [Web](https://example.com) `[B](b.md)`".to_string()),
+        rebase_links(
+            "[B](b.md) [A](a.md#top) [Web](https://example.com) `[B](b.md)`",
+            "/a.md",
+            "/dir/c.md"
+        )
+    );
+    assert_eq!(None, rebase_links("[B](./b.md)", "/a.md", "/c.md"));
+}
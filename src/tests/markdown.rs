@@ -0,0 +1,904 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::markdown::{
+    highlight_code_blocks, outline, render_diagrams, title, AsciiDocParser, CommonMarkExtension,
+    CommonMarkParser, CommonMarkPreset, Diagnostic, ExternalCommand, ExternalParser, Heading,
+    MarkdownParser, NotebookParser, RestructuredTextParser, Severity,
+};
+
+#[test]
+fn test_outline() {
+    let html = "<h2>Intro</h2>\n<p>a</p>\n<h1 id=\"title\">A &amp; <em>B</em></h1>\n<h3>\nC\n</h3>";
+
+    assert_eq!(
+        vec![
+            Heading {
+                level: 2,
+                text: "Intro".to_string(),
+                id: None
+            },
+            Heading {
+                level: 1,
+                text: "A & B".to_string(),
+                id: Some("title".to_string())
+            },
+            Heading {
+                level: 3,
+                text: "C".to_string(),
+                id: None
+            },
+        ],
+        outline(html)
+    );
+    assert_eq!(Some("A & B".to_string()), title(html));
+    assert_eq!(Some("C".to_string()), title("<h3>C</h3><h4>D</h4>"));
+    assert_eq!(None, title("<p>A</p>"));
+}
+
+#[test]
+fn test_gfm() {
+    let mut parser = CommonMarkParser::new(CommonMarkPreset::Gfm.extensions());
+
+    assert_eq!(
+        "<ul>\n<li><input disabled=\"\" type=\"checkbox\" checked=\"\"/>\nDone</li>\n</ul>\n",
+        parser.parse_to_html("* [x] Done\n").html
+    );
+    assert_eq!(
+        "<p>See <a href=\"https://example.com/a_b\">https://example.com/a_b</a>, \
+<a href=\"http://www.example.com\">www.example.com</a> and <a href=\"https://c.com\">c</a></p>\n",
+        parser
+            .parse_to_html("See https://example.com/a_b, www.example.com and [c](https://c.com)\n")
+            .html
+    );
+    assert_eq!(
+        "<div class=\"markdown-alert markdown-alert-warning\">\n\
+<p class=\"markdown-alert-title\">Warning</p>\n<p>Careful</p>\n</div>\n\
+<blockquote>\n<p>[!OTHER]</p>\n</blockquote>\n",
+        parser
+            .parse_to_html("> [!WARNING]\n> Careful\n\n> [!OTHER]\n")
+            .html
+    );
+
+    // Without the extensions
+    assert_eq!(
+        "<p>https://example.com</p>\n",
+        CommonMarkParser::default()
+            .parse_to_html("https://example.com\n")
+            .html
+    );
+}
+
+#[test]
+fn test_math() {
+    let mut parser = CommonMarkParser::new(vec![CommonMarkExtension::Math]);
+
+    assert_eq!(
+        "<p>Costs $5 and $10, $ and <code>$c$</code></p>\n",
+        parser
+            .parse_to_html("Costs $5 and $10, \\$ and `$c$`\n")
+            .html
+    );
+    assert_eq!(
+        "<p>Let <math xmlns=\"http://www.w3.org/1998/Math/MathML\"><semantics><mrow>\
+<msubsup><mi>x</mi><mn>1</mn><mn>2</mn></msubsup><mo>+</mo><mfrac><mi>a</mi><mi>b</mi></mfrac>\
+</mrow><annotation encoding=\"application/x-tex\">x_1^2 + \\frac{a}{b}</annotation></semantics></math></p>\n\
+<p><math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\"><semantics><mrow>\
+<munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>0</mn></mrow><mi>n</mi></munderover>\
+<msqrt><mi>α</mi></msqrt></mrow><annotation encoding=\"application/x-tex\">\\sum_{i=0}^n \\sqrt{\\alpha}\
+</annotation></semantics></math></p>\n",
+        parser.parse_to_html("Let $x_1^2 + \\frac{a}{b}$\n\n$$\n\\sum_{i=0}^n \\sqrt{\\alpha}\n$$\n").html
+    );
+    // Display math keeps the lines of the blocks after it
+    assert_eq!(
+        vec![1, 3, 8],
+        parser.block_lines("a\n\n$$\nx\n\\\\ y\n$$\n\nb\n")
+    );
+    // Unknown commands are reported at their line
+    assert_eq!(
+        vec![Diagnostic::new(
+            Severity::Warning,
+            "Unknown math command \\foo".to_string(),
+            Some(3)
+        )],
+        parser.parse_to_html("a\n\nb $\\foo$\n").diagnostics
+    );
+}
+
+#[test]
+fn test_render_diagrams() {
+    let commands = BTreeMap::from([
+        (
+            "dot".to_string(),
+            vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo '<?xml version=\"1.0\"?>'; printf '<svg>'; cat; echo '</svg>'".to_string(),
+            ],
+        ),
+        (
+            "mermaid".to_string(),
+            vec!["md-dir-builder-missing-tool".to_string()],
+        ),
+    ]);
+    let markdown = "```dot\na -> b\n```\n\n```mermaid\nx\n```\n\n```rust\nfn f() {}\n```\n";
+    let html = CommonMarkParser::default().parse_to_html(markdown).html;
+    let rendered = render_diagrams(&html, markdown, &commands);
+
+    assert_eq!(
+        "<div class=\"diagram diagram-dot\"><svg>a -> b\n</svg></div>\n\
+<div class=\"diagram-error\"><p>Rendering the mermaid diagram failed: md-dir-builder-missing-tool isn't installed</p>\
+<pre><code>x\n</code></pre></div>\n\
+<pre><code class=\"language-rust\">fn f() {}\n</code></pre>\n",
+        rendered.html
+    );
+    assert_eq!(
+        vec![Diagnostic::new(
+            Severity::Error,
+            "Rendering the mermaid diagram failed: md-dir-builder-missing-tool isn't installed"
+                .to_string(),
+            Some(5)
+        )],
+        rendered.diagnostics
+    );
+}
+
+#[test]
+fn test_highlight_code_blocks() {
+    let html = CommonMarkParser::default().parse_to_html(
+        "```rust\nlet s = \"a<b\"; // c\nf(1)\n```\n\n```html\n<a href=\"x\">y</a>\n```\n\n```unknown\nlet\n```\n",
+    ).html;
+
+    assert_eq!(
+        "<pre class=\"language-rust\" data-highlighted><code class=\"language-rust\">\
+<span class=\"token keyword\">let</span> s <span class=\"token operator\">=</span> \
+<span class=\"token string\">&quot;a&lt;b&quot;</span><span class=\"token punctuation\">;</span> \
+<span class=\"token comment\">// c</span>\n<span class=\"token function\">f</span>\
+<span class=\"token punctuation\">(</span><span class=\"token number\">1</span>\
+<span class=\"token punctuation\">)</span>\n</code></pre>\n\
+<pre class=\"language-html\" data-highlighted><code class=\"language-html\"><span class=\"token tag\">\
+<span class=\"token punctuation\">&lt;</span>a <span class=\"token attr-name\">href</span>\
+<span class=\"token punctuation\">=</span><span class=\"token attr-value\">&quot;x&quot;</span>\
+<span class=\"token punctuation\">&gt;</span></span>y<span class=\"token tag\">\
+<span class=\"token punctuation\">&lt;/</span>a<span class=\"token punctuation\">&gt;</span></span>\n\
+</code></pre>\n\
+<pre><code class=\"language-unknown\">let\n</code></pre>\n",
+        highlight_code_blocks(&html)
+    );
+
+    // Every language has its own keywords
+    let code = |language: &str| {
+        format!(
+            "<pre><code class=\"language-{}\">fun</code></pre>",
+            language
+        )
+    };
+    assert!(highlight_code_blocks(&code("kotlin")).contains("token keyword"));
+    assert!(!highlight_code_blocks(&code("java")).contains("token keyword"));
+}
+
+#[test]
+fn test_external_parser() {
+    let command = |script: &str| ExternalCommand {
+        command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+        timeout: Duration::from_secs(10),
+    };
+    let command_with_input = |script: &str| ExternalCommand {
+        command: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            script.to_string(),
+            "{input}".to_string(),
+        ],
+        timeout: Duration::from_secs(10),
+    };
+
+    // Source from stdin, HTML to stdout and warnings from stderr
+    let mut parser = ExternalParser::new(
+        command("sed 's/^= \\(.*\\)/<h1>\\1<\\/h1>/'; echo 'WARNING: <stdin>: line 2: odd' >&2"),
+        "adoc",
+    );
+    let rendered = parser.parse_to_html("= Title\n");
+    assert_eq!("<h1>Title</h1>\n", rendered.html);
+    assert_eq!(
+        vec![Diagnostic::new(
+            Severity::Warning,
+            "WARNING: <stdin>: line 2: odd",
+            Some(2)
+        )],
+        rendered.diagnostics
+    );
+
+    // Source and HTML in files
+    let mut parser = ExternalParser::new(
+        ExternalCommand {
+            command: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "tr a-z A-Z < \"$0\" > \"$1\"".to_string(),
+                "{input}".to_string(),
+                "{output}".to_string(),
+            ],
+            timeout: Duration::from_secs(10),
+        },
+        "rst",
+    );
+    assert_eq!("<P>A</P>\n", parser.parse_to_html("<p>a</p>\n").html);
+
+    // The files are in a directory only the user can access
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut parser = ExternalParser::new(command_with_input("dirname \"$0\""), "rst");
+        let dir = parser.parse_to_html("a\n").html;
+        let mode = std::fs::metadata(dir.trim()).unwrap().permissions().mode();
+        assert_eq!(0o700, mode & 0o777);
+    }
+
+    // Failures and timeouts are errors
+    let mut parser = ExternalParser::new(command("echo 'doc.rst:3: broken' >&2; exit 1"), "rst");
+    let rendered = parser.parse_to_html("a\n");
+    assert_eq!("", rendered.html);
+    assert_eq!(
+        vec![Diagnostic::new(
+            Severity::Error,
+            "doc.rst:3: broken",
+            Some(3)
+        )],
+        rendered.diagnostics
+    );
+    let mut parser = ExternalParser::new(
+        ExternalCommand {
+            timeout: Duration::from_millis(100),
+            ..command("exec sleep 5")
+        },
+        "rst",
+    );
+    assert_eq!(
+        "sh timed out",
+        parser.parse_to_html("a\n").diagnostics[0].message
+    );
+}
+
+#[test]
+fn test_restructured_text() {
+    let source = "\
+Title
+=====
+
+Some *emphasis*, ``code``, a link_ and a footnote [#f]_.
+
+.. _link: https://example.com
+
+- a
+- b
+
+Section
+-------
+
+.. note:: Careful *now*.
+
+.. code-block:: rust
+
+   fn main() {}
+
+.. [#f] The footnote.
+
+See missing_.
+";
+    let mut parser = RestructuredTextParser;
+    let rendered = parser.parse_to_html(source);
+
+    assert_eq!(
+        "<h1 id=\"title\">Title</h1>\n\
+<p>Some <em>emphasis</em>, <code>code</code>, a <a href=\"https://example.com\">link</a> and a \
+footnote <sup class=\"footnote-reference\"><a href=\"#footnote-1\">1</a></sup>.</p>\n\
+<ul>\n<li>a</li>\n<li>b</li>\n</ul>\n\
+<h2 id=\"section\">Section</h2>\n\
+<div class=\"markdown-alert markdown-alert-note\">\n\
+<p class=\"markdown-alert-title\">Note</p>\n<p>Careful <em>now</em>.</p>\n</div>\n\
+<pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n\
+<div class=\"footnote-definition\" id=\"footnote-1\"><sup class=\"footnote-definition-label\">1</sup>\n\
+<p>The footnote.</p>\n</div>\n\
+<p>See missing.</p>\n",
+        rendered.html
+    );
+    // Every top-level block starts at a line, so changes can be patched in
+    assert_eq!(
+        vec![1, 4, 8, 11, 14, 16, 20, 22],
+        parser.block_lines(source)
+    );
+    assert_eq!(
+        vec![Diagnostic::new(
+            Severity::Warning,
+            "Unknown target name \"missing\"",
+            Some(22)
+        )],
+        rendered.diagnostics
+    );
+}
+
+#[test]
+fn test_asciidoc() {
+    let source = "\
+= Title
+:version: 1.2
+
+Version {version} is *bold*, _new_ and `fast`, see <<usage>> and https://example.com[the site].
+A footnote:[The footnote.] +
+and more.
+
+[[usage]]
+== Usage
+
+NOTE: Careful.
+
+[source,rust]
+----
+fn main() {}
+----
+
+* a
+** nested
+* b
+
+|===
+|Name |Value
+
+|a |1
+|===
+
+Unknown {missing}.
+";
+    let mut parser = AsciiDocParser;
+    let rendered = parser.parse_to_html(source);
+
+    assert_eq!(
+        "<h1 id=\"_title\">Title</h1>\n\
+<p>Version 1.2 is <strong>bold</strong>, <em>new</em> and <code>fast</code>, see \
+<a href=\"#usage\">Usage</a> and <a href=\"https://example.com\">the site</a>.\n\
+A <sup class=\"footnote-reference\"><a href=\"#footnote-1\">1</a></sup><br />\nand more.</p>\n\
+<h2 id=\"usage\">Usage</h2>\n\
+<div class=\"markdown-alert markdown-alert-note\">\n\
+<p class=\"markdown-alert-title\">Note</p>\n<p>Careful.</p>\n</div>\n\
+<pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n\
+<ul>\n<li>a\n<ul>\n<li>nested</li>\n</ul></li>\n<li>b</li>\n</ul>\n\
+<table>\n<thead><tr><th>Name</th><th>Value</th></tr></thead>\n\
+<tbody>\n<tr><td>a</td><td>1</td></tr>\n</tbody>\n</table>\n\
+<p>Unknown {missing}.</p>\n\
+<div class=\"footnote-definition\" id=\"footnote-1\"><sup class=\"footnote-definition-label\">1</sup>\n\
+<p>The footnote.</p>\n</div>\n",
+        rendered.html
+    );
+    // Footnotes are listed at the end
+    assert_eq!(
+        vec![1, 4, 9, 11, 14, 18, 22, 28, 28],
+        parser.block_lines(source)
+    );
+    assert_eq!(
+        vec![Diagnostic::new(
+            Severity::Warning,
+            "Unknown attribute \"missing\"",
+            Some(28)
+        )],
+        rendered.diagnostics
+    );
+}
+
+#[test]
+fn test_restructured_text_tables() {
+    let mut parser = RestructuredTextParser;
+
+    let grid = "\
++------+-------+
+| Name | Value |
++======+=======+
+| a    | 1     |
++------+-------+
+| spans both   |
++--------------+
+";
+    assert_eq!(
+        "<table>\n<thead><tr><th>Name</th><th>Value</th></tr></thead>\n<tbody>\n\
+<tr><td>a</td><td>1</td></tr>\n<tr><td colspan=\"2\">spans both</td></tr>\n</tbody>\n</table>\n",
+        parser.parse_to_html(grid).html
+    );
+
+    let simple = "\
+=====  =====
+Name   Value
+=====  =====
+a      1
+b      2
+=====  =====
+";
+    assert_eq!(
+        "<table>\n<thead><tr><th>Name</th><th>Value</th></tr></thead>\n<tbody>\n\
+<tr><td>a</td><td>1</td></tr>\n<tr><td>b</td><td>2</td></tr>\n</tbody>\n</table>\n",
+        parser.parse_to_html(simple).html
+    );
+
+    let csv = "\
+.. csv-table:: Values
+   :header: \"Name\", \"Value\"
+
+   \"a, b\", 1
+   c, 2
+";
+    assert_eq!(
+        "<table>\n<caption>Values</caption>\n\
+<thead><tr><th>Name</th><th>Value</th></tr></thead>\n<tbody>\n\
+<tr><td>a, b</td><td>1</td></tr>\n<tr><td>c</td><td>2</td></tr>\n</tbody>\n</table>\n",
+        parser.parse_to_html(csv).html
+    );
+}
+
+#[test]
+fn test_restructured_text_substitutions() {
+    let source = "\
+The |name| is |logo|, |missing| isn't known.
+
+.. |name| replace:: *md-dir-builder*
+.. |logo| image:: logo.png
+";
+    let rendered = RestructuredTextParser.parse_to_html(source);
+
+    assert_eq!(
+        "<p>The <em>md-dir-builder</em> is <img src=\"logo.png\" alt=\"logo\" />, missing isn't \
+known.</p>\n",
+        rendered.html
+    );
+    assert_eq!(
+        vec![Diagnostic::new(
+            Severity::Warning,
+            "Unknown substitution \"missing\"",
+            Some(1)
+        )],
+        rendered.diagnostics
+    );
+}
+
+#[test]
+fn test_restructured_text_nested_lists() {
+    let source = "\
+1. One
+
+   - nested *a*
+   - nested b
+
+     #. deeper
+
+2. Two
+";
+
+    assert_eq!(
+        "<ol>\n<li><p>One</p>\n<ul>\n<li>nested <em>a</em></li>\n<li><p>nested b</p>\n\
+<ol>\n<li>deeper</li>\n</ol></li>\n</ul></li>\n<li>Two</li>\n</ol>\n",
+        RestructuredTextParser.parse_to_html(source).html
+    );
+}
+
+#[test]
+fn test_restructured_text_unknown_directives() {
+    let source = "\
+.. include:: other.rst
+
+.. unknown:: argument
+   :option: value
+
+   Body text.
+
+After.
+";
+    let rendered = RestructuredTextParser.parse_to_html(source);
+
+    // The directives are left out, the text after them is kept
+    assert_eq!("<p>After.</p>\n", rendered.html);
+    assert_eq!(
+        vec![
+            "The include directive isn't supported",
+            "Unknown directive \"unknown\""
+        ],
+        messages(&rendered.diagnostics)
+    );
+    assert_eq!(
+        vec![Some(1), Some(3)],
+        rendered
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.line)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_asciidoc_delimited_blocks() {
+    let source = "\
+====
+Example *body*
+====
+
+****
+Sidebar
+****
+
+[quote, Someone]
+____
+Quoted
+____
+
+....
+Literal <b>
+....
+
+++++
+<b>passed</b>
+++++
+
+--
+Open
+--
+
+////
+Comment
+////
+";
+    let mut parser = AsciiDocParser;
+
+    assert_eq!(
+        "<div class=\"example\">\n<p>Example <strong>body</strong></p>\n</div>\n\
+<aside class=\"sidebar\">\n<p>Sidebar</p>\n</aside>\n\
+<blockquote>\n<p>Quoted</p>\n<footer>&#8212; Someone</footer>\n</blockquote>\n\
+<pre><code>Literal &lt;b&gt;\n</code></pre>\n\
+<b>passed</b>\n\
+<div class=\"openblock\">\n<p>Open</p>\n</div>\n",
+        parser.parse_to_html(source).html
+    );
+    // The comment block has no HTML
+    assert_eq!(vec![1, 5, 10, 14, 18, 22], parser.block_lines(source));
+}
+
+#[test]
+fn test_asciidoc_nested_lists() {
+    let source = "\
+* a
+** nested
+*** deeper
+* [x] done
+* [ ] todo
+";
+
+    assert_eq!(
+        "<ul>\n<li>a\n<ul>\n<li>nested\n<ul>\n<li>deeper</li>\n</ul></li>\n</ul></li>\n\
+<li><input disabled=\"\" type=\"checkbox\" checked=\"\"/>\ndone</li>\n\
+<li><input disabled=\"\" type=\"checkbox\"/>\ntodo</li>\n</ul>\n",
+        AsciiDocParser.parse_to_html(source).html
+    );
+}
+
+#[test]
+fn test_asciidoc_csv_table() {
+    let source = "\
+[%header,format=csv]
+|===
+Name,Value
+\"a, b\",1
+|===
+";
+
+    assert_eq!(
+        "<table>\n<thead><tr><th>Name</th><th>Value</th></tr></thead>\n<tbody>\n\
+<tr><td>a, b</td><td>1</td></tr>\n</tbody>\n</table>\n",
+        AsciiDocParser.parse_to_html(source).html
+    );
+}
+
+#[test]
+fn test_malformed_input() {
+    let rst = "\
+Title
+=====
+
+Text with *emphasis, ``code, `link <http://x`_, |sub and [#f]_.
+
+.. |sub| replace:: |sub|
+
++-----+-----+
+| a   | b
++-----+
+
+=====  =====
+a
+=====
+
+.. csv-table::
+   :header: \"a
+
+   \"x, y
+.. list-table::
+
+   * - a
+     -
+   *
+
+.. math::
+
+   \\frac{a
+
+.. [#f]
+";
+    let asciidoc = "\
+= Title
+:a: {a}
+
+Text with *bold, `code, <<ref, footnote:[<<ref>> \\+ text], pass:[x and {a}.
+
+[[anchor
+
+|===
+|a |b
+|c
+2+|
+
+[format=csv]
+|===
+\"a,b
+|===
+
+* [x
+** 
+. 
+
+----
+unclosed
+";
+
+    // All prefixes (at line ends) of the documents
+    for (name, source) in [("reStructuredText", rst), ("AsciiDoc", asciidoc)] {
+        for (end, _) in source.match_indices('\n') {
+            let prefix = source[..end].to_string();
+            let is_rst = name == "reStructuredText";
+            without_hang(name, move || {
+                if is_rst {
+                    RestructuredTextParser.parse_to_html(&prefix);
+                    RestructuredTextParser.block_lines(&prefix)
+                } else {
+                    AsciiDocParser.parse_to_html(&prefix);
+                    AsciiDocParser.block_lines(&prefix)
+                }
+            });
+        }
+    }
+
+    // Placeholders of stashed HTML in the source and in nested texts
+    let rendered = without_hang("AsciiDoc placeholders", || {
+        AsciiDocParser.parse_to_html("\u{f000}0\u{f001} `\u{f000}0\u{f001}` footnote:[<<\\+>>]\n")
+    });
+    assert!(rendered.html.contains("<code>\u{fffd}0\u{fffd}</code>"));
+
+    // Nested too deeply to parse the blocks recursively
+    let rst: String = (0..500)
+        .map(|i| format!("{}x\n\n", " ".repeat(i)))
+        .collect();
+    let rendered = without_hang("nested block quotes", move || {
+        RestructuredTextParser.parse_to_html(&rst)
+    });
+    assert_eq!(
+        vec!["Blocks are nested too deeply"],
+        messages(&rendered.diagnostics)
+    );
+    let asciidoc: String = (4..504)
+        .map(|i| format!("{}\n", "=".repeat(i)))
+        .chain((4..504).rev().map(|i| format!("{}\n", "=".repeat(i))))
+        .collect();
+    let rendered = without_hang("nested delimited blocks", move || {
+        AsciiDocParser.parse_to_html(&asciidoc)
+    });
+    assert_eq!(
+        vec!["Blocks are nested too deeply"],
+        messages(&rendered.diagnostics)
+    );
+}
+
+#[test]
+fn test_notebook() {
+    let source = r##"{
+ "cells": [
+  {
+   "cell_type": "markdown",
+   "metadata": {},
+   "source": [
+    "# Analysis\n",
+    "\n",
+    "Uses $\\foo$.\n",
+    "![plot](attachment:plot.svg)"
+   ],
+   "attachments": {
+    "plot.svg": {"image/svg+xml": "<svg/>"}
+   }
+  },
+  {
+   "cell_type": "code",
+   "execution_count": 2,
+   "metadata": {},
+   "outputs": [
+    {
+     "name": "stdout",
+     "output_type": "stream",
+     "text": ["a < b\n"]
+    },
+    {
+     "data": {"image/png": "iVBO\nRw==\n", "text/plain": ["<Figure>"]},
+     "metadata": {},
+     "output_type": "display_data"
+    },
+    {
+     "data": {"text/plain": ["3"]},
+     "execution_count": 2,
+     "metadata": {},
+     "output_type": "execute_result"
+    },
+    {
+     "ename": "ValueError",
+     "evalue": "bad",
+     "output_type": "error",
+     "traceback": ["\u001b[0;31mValueError\u001b[0m: bad"]
+    }
+   ],
+   "source": ["print('a < b')\n", "1 + 2"]
+  }
+ ],
+ "metadata": {"language_info": {"name": "python"}},
+ "nbformat": 4,
+ "nbformat_minor": 5
+}
+"##;
+    let mut parser = NotebookParser::new(Box::new(CommonMarkParser::new(vec![
+        CommonMarkExtension::Math,
+    ])));
+    let rendered = parser.parse_to_html(source);
+
+    assert_eq!(
+        "<div class=\"cell cell-markdown\">\n\
+<h1>Analysis</h1>\n\
+<p>Uses <math xmlns=\"http://www.w3.org/1998/Math/MathML\"><semantics><merror><mtext>\\foo</mtext>\
+</merror><annotation encoding=\"application/x-tex\">\\foo</annotation></semantics></math>.\n\
+<img src=\"data:image/svg+xml,%3Csvg%2F%3E\" alt=\"plot\" /></p>\n</div>\n\
+<div class=\"cell cell-code\">\n\
+<div class=\"cell-input\"><div class=\"prompt\">In [2]:</div>\n\
+<pre><code class=\"language-python\">print('a &lt; b')\n1 + 2\n</code></pre></div>\n\
+<div class=\"cell-output\"><pre class=\"output-stdout\">a &lt; b\n</pre></div>\n\
+<div class=\"cell-output\"><img src=\"data:image/png;base64,iVBORw==\" alt=\"Output\" /></div>\n\
+<div class=\"cell-output\"><div class=\"prompt\">Out[2]:</div>\n<pre>3</pre></div>\n\
+<div class=\"cell-output\"><pre class=\"output-error\">ValueError: bad</pre></div>\n\
+</div>\n",
+        rendered.html
+    );
+    assert_eq!(vec![3, 16], parser.block_lines(source));
+    // Problems of markdown cells are reported at their line of the notebook
+    assert_eq!(
+        vec![Diagnostic::new(
+            Severity::Warning,
+            "Unknown math command \\foo".to_string(),
+            Some(9)
+        )],
+        rendered.diagnostics
+    );
+    assert_eq!(
+        "# Analysis\n\nUses $\\foo$.\n![plot](attachment:plot.svg)\nprint('a < b')\n1 + 2",
+        parser.text(source)
+    );
+
+    let invalid = parser.parse_to_html("{\n \"cells\": [\n");
+    assert_eq!("", invalid.html);
+    assert_eq!(Some(3), invalid.diagnostics[0].line);
+
+    // Outputs can't break out of the attribute
+    let quoted = parser.parse_to_html(
+        r#"{"cells": [{"cell_type": "code", "source": [], "outputs": [
+            {"output_type": "display_data", "data": {"image/png": "a\" onerror=\"b"}}
+        ]}]}"#,
+    );
+    assert!(quoted
+        .html
+        .contains("<img src=\"data:image/png;base64,a&quot; onerror=&quot;b\" alt=\"Output\" />"));
+}
+
+/// Runs the function in a thread and fails, if it doesn't finish in time
+fn without_hang<T: Send + 'static>(name: &str, f: impl FnOnce() -> T + Send + 'static) -> T {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || tx.send(f()).ok());
+
+    rx.recv_timeout(Duration::from_secs(10))
+        .unwrap_or_else(|_| panic!("Parsing {} didn't finish", name))
+}
+
+#[test]
+fn test_math_trailing_backslash() {
+    let error = Diagnostic::new(Severity::Warning, "Math ends with a backslash", Some(1));
+
+    let rendered = without_hang("inline math", || {
+        CommonMarkParser::new(vec![CommonMarkExtension::Math]).parse_to_html("a $b\\ $ $c\\\\$\n")
+    });
+    assert_eq!(Vec::<Diagnostic>::new(), rendered.diagnostics);
+    let rendered = without_hang("display math", || {
+        CommonMarkParser::new(vec![CommonMarkExtension::Math]).parse_to_html("$$\na \\\n$$\n")
+    });
+    assert!(rendered.html.contains("<merror><mtext>\\</mtext></merror>"));
+    assert_eq!(vec![error.clone()], rendered.diagnostics);
+
+    let rendered = without_hang("rst math", || {
+        RestructuredTextParser.parse_to_html(".. math::\n\n   a \\\n")
+    });
+    assert_eq!(
+        vec!["Math ends with a backslash"],
+        messages(&rendered.diagnostics)
+    );
+    let rendered = without_hang("AsciiDoc math", || {
+        AsciiDocParser.parse_to_html("Math stem:[a\\] here.\n")
+    });
+    assert_eq!(
+        vec!["Math ends with a backslash"],
+        messages(&rendered.diagnostics)
+    );
+    let rendered = without_hang("notebook math", || {
+        NotebookParser::new(Box::<CommonMarkParser>::default()).parse_to_html(
+            r#"{"cells": [{"cell_type": "code", "source": "x", "outputs": [
+                {"output_type": "display_data", "data": {"text/latex": "$a\\$"}}
+            ]}]}"#,
+        )
+    });
+    assert!(rendered.html.contains("cell-code"));
+}
+
+#[test]
+fn test_math_nesting() {
+    let deep = [
+        format!("${}x{}$\n", "{".repeat(3000), "}".repeat(3000)),
+        format!("${}x$\n", "\\hat ".repeat(3000)),
+        format!("${}x$\n", "^".repeat(3000)),
+        format!("${}x$\n", "\\frac{1}".repeat(3000)),
+    ];
+    for markdown in deep {
+        let rendered = without_hang("deeply nested math", move || {
+            CommonMarkParser::new(vec![CommonMarkExtension::Math]).parse_to_html(&markdown)
+        });
+        assert!(rendered.html.contains("<merror><mtext>…</mtext></merror>"));
+        assert_eq!(
+            vec!["Math is nested too deeply"],
+            messages(&rendered.diagnostics)
+        );
+    }
+
+    // Math nested less deeply is unchanged
+    let rendered = CommonMarkParser::new(vec![CommonMarkExtension::Math]).parse_to_html(&format!(
+        "${}x{}$\n",
+        "{".repeat(50),
+        "}".repeat(50)
+    ));
+    assert!(rendered.diagnostics.is_empty());
+    assert!(rendered.html.contains("<mi>x</mi>"));
+}
+
+#[test]
+fn test_math_in_code_and_html() {
+    let mut parser = CommonMarkParser::new(vec![CommonMarkExtension::Math]);
+
+    assert_eq!(
+        "<pre><code>$x$\n</code></pre>\n",
+        parser.parse_to_html("    $x$\n").html
+    );
+    assert_eq!(
+        "<div>\n$x$\n</div>\n",
+        parser.parse_to_html("<div>\n$x$\n</div>\n").html
+    );
+    // Escaped dollars don't end display math
+    assert_eq!("<p>$$a$$</p>\n", parser.parse_to_html("$$a\\$$\n").html);
+}
+
+fn messages(diagnostics: &[Diagnostic]) -> Vec<&str> {
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.message.as_str())
+        .collect()
+}
@@ -1 +1,8 @@
 mod builder;
+mod config;
+mod git;
+mod links;
+mod markdown;
+mod mount;
+mod patch;
+mod router;
@@ -0,0 +1,38 @@
+use crate::mount::{find_mount, parse_mount, Mount};
+
+#[test]
+fn test_parse_mount() {
+    assert_eq!(
+        Ok(Mount {
+            prefix: "/api".to_string(),
+            directory: "../api-docs".to_string()
+        }),
+        parse_mount("/api=../api-docs")
+    );
+    assert_eq!(
+        Ok(Mount {
+            prefix: "/api/v1".to_string(),
+            directory: "docs".to_string()
+        }),
+        parse_mount("api/v1/=docs")
+    );
+    assert!(parse_mount("/api").is_err());
+    assert!(parse_mount("/api=").is_err());
+}
+
+#[test]
+fn test_find_mount() {
+    let mounts = ["", "/api", "/api/v1"];
+
+    assert_eq!(Some(""), find_mount("/README.md", mounts.into_iter()));
+    assert_eq!(
+        Some("/api"),
+        find_mount("/api/README.md", mounts.into_iter())
+    );
+    assert_eq!(
+        Some("/api/v1"),
+        find_mount("/api/v1/a.md", mounts.into_iter())
+    );
+    assert_eq!(Some(""), find_mount("/apis/a.md", mounts.into_iter()));
+    assert_eq!(None, find_mount("/README.md", ["/api"].into_iter()));
+}
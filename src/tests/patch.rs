@@ -0,0 +1,144 @@
+use crate::builder::BuiltFile;
+use crate::markdown::{CommonMarkParser, MarkdownParser};
+use crate::patch::{block_of_line, diff_html, split_blocks, ContentPatch};
+
+fn built_file(contents: &str) -> BuiltFile {
+    BuiltFile {
+        contents: contents.to_string(),
+        word_count: 0,
+        block_lines: Vec::new(),
+        title: None,
+        modified: None,
+        last_commit: None,
+        diagnostics: Vec::new(),
+        read_only: false,
+    }
+}
+
+#[test]
+fn test_split_blocks() {
+    assert_eq!(
+        Some(vec![
+            "<h1>Title</h1>",
+            "<p>A <a href=\"a>b\">link</a><br/></p>",
+            "<hr />",
+            "<pre><code>a &lt; b</code></pre>",
+        ]),
+        split_blocks(
+            "<h1>Title</h1>
+<p>A <a href=\"a>b\">link</a><br/></p>
+<hr />
+<pre><code>a &lt; b</code></pre>
+"
+        )
+    );
+    assert_eq!(
+        Some(vec![
+            "<script>if (a<b) {}</script>",
+            "<div><!-- <p> --></div>"
+        ]),
+        split_blocks("<script>if (a<b) {}</script><div><!-- <p> --></div>")
+    );
+    assert_eq!(Some(vec!["<p>a < b</p>"]), split_blocks("<p>a < b</p>"));
+    assert_eq!(None, split_blocks("text <p>a</p>"));
+    assert_eq!(None, split_blocks("<!-- comment --><p>a</p>"));
+    assert_eq!(None, split_blocks("<div><p>a</p>"));
+}
+
+#[test]
+fn test_content_patch() {
+    let previous = built_file("<h1>A</h1>\n<p>B</p>\n<p>C</p>\n<p>D</p>\n");
+    let current = built_file("<h1>A</h1>\n<p>X</p>\n<p>Y</p>\n<p>D</p>\n");
+
+    assert_eq!(
+        Some(ContentPatch {
+            from_version: previous.version(),
+            count: 4,
+            start: 1,
+            remove: 2,
+            insert: vec!["<p>X</p>".to_string(), "<p>Y</p>".to_string()],
+            line: None,
+        }),
+        ContentPatch::diff(&previous, &current)
+    );
+
+    let current = built_file("<h1>A</h1>\n<p>B</p>\n<p>B</p>\n<p>C</p>\n<p>D</p>\n");
+    let patch = ContentPatch::diff(&previous, &current).unwrap();
+    assert_eq!((2, 0), (patch.start, patch.remove));
+    assert_eq!(vec!["<p>B</p>".to_string()], patch.insert);
+}
+
+#[test]
+fn test_content_patch_line() {
+    let built_file = |markdown: &str| BuiltFile {
+        contents: CommonMarkParser::default().parse_to_html(markdown).html,
+        word_count: 0,
+        block_lines: CommonMarkParser::default().block_lines(markdown),
+        title: None,
+        modified: None,
+        last_commit: None,
+        diagnostics: Vec::new(),
+        read_only: false,
+    };
+
+    let previous = built_file("# A\n\nB\n\n* C\n* D\n\n---\n\nE\n");
+    let current = built_file("# A\n\nB\n\n* C\n* D\n\n---\n\nF\n");
+    assert_eq!(vec![1, 3, 5, 8, 10], current.block_lines);
+    assert_eq!(
+        Some(10),
+        ContentPatch::diff(&previous, &current).and_then(|patch| patch.line)
+    );
+    assert_eq!(Some(0), block_of_line(&current, 1));
+    assert_eq!(Some(2), block_of_line(&current, 6));
+    assert_eq!(Some(4), block_of_line(&current, 100));
+
+    // Parsers without source positions
+    let without_lines = BuiltFile {
+        block_lines: Vec::new(),
+        ..current
+    };
+    assert_eq!(None, block_of_line(&without_lines, 1));
+
+    // HTML blocks are one block, although their lines are separate events
+    let html = built_file("# A\n\n<div>\nline\nline2\n</div>\n\npara\n\n<p>\nB\n</p>\n\nend\n");
+    assert_eq!(vec![1, 3, 8, 10, 14], html.block_lines);
+    assert_eq!(
+        Some(html.block_lines.len()),
+        split_blocks(&html.contents).map(|blocks| blocks.len())
+    );
+    assert_eq!(Some(2), block_of_line(&html, 8));
+}
+
+#[test]
+fn test_diff_html() {
+    assert_eq!(
+        "<h1>A</h1>
+<div class=\"diff-removed\"><p>old</p></div>
+<div class=\"diff-added\"><p>new</p></div>
+<p>same</p>
+<div class=\"diff-added\"><p>end</p></div>
+",
+        diff_html(
+            "<h1>A</h1>\n<p>old</p>\n<p>same</p>\n",
+            "<h1>A</h1>\n<p>new</p>\n<p>same</p>\n<p>end</p>\n"
+        )
+    );
+    assert_eq!("text", diff_html("text", "text"));
+
+    // Too many changed blocks to compare them all, unchanged ends are kept
+    let old = (0..2000)
+        .map(|i| format!("<p>{}</p>\n", i))
+        .collect::<String>();
+    let new = (0..2000)
+        .map(|i| format!("<p>{}</p>\n", i % 2))
+        .collect::<String>();
+    let html = diff_html(
+        &format!("<h1>A</h1>\n{}<p>end</p>\n", old),
+        &format!("<h1>A</h1>\n{}<p>end</p>\n", new),
+    );
+    assert!(html.starts_with(
+        "<h1>A</h1>\n<p>0</p>\n<p>1</p>\n<div class=\"diff-removed\"><p>2</p></div>\n"
+    ));
+    assert!(html.ends_with("<div class=\"diff-added\"><p>1</p></div>\n<p>end</p>\n"));
+    assert_eq!(2 * 1998, html.matches("diff-").count());
+}
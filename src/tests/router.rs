@@ -0,0 +1,22 @@
+use crate::router::{normalize_base_path, strip_base_path};
+
+#[test]
+fn test_normalize_base_path() {
+    assert_eq!("", normalize_base_path(""));
+    assert_eq!("", normalize_base_path("/"));
+    assert_eq!("/docs", normalize_base_path("docs"));
+    assert_eq!("/docs", normalize_base_path("/docs/"));
+    assert_eq!("/intranet/docs", normalize_base_path("/intranet/docs"));
+}
+
+#[test]
+fn test_strip_base_path() {
+    assert_eq!(Some("/README.md"), strip_base_path("/README.md", ""));
+    assert_eq!(
+        Some("/README.md"),
+        strip_base_path("/docs/README.md", "/docs")
+    );
+    assert_eq!(Some(""), strip_base_path("/docs", "/docs"));
+    assert_eq!(None, strip_base_path("/docsREADME.md", "/docs"));
+    assert_eq!(None, strip_base_path("/README.md", "/docs"));
+}
@@ -22,8 +22,47 @@ use std::{
 
 use maud::{html, Markup, PreEscaped, DOCTYPE};
 use regex::Regex;
+use serde::Deserialize;
 
-use crate::builder::BuiltFile;
+use crate::builder::{BuiltFile, FileMetadata};
+use crate::markdown::{Diagnostic, Severity};
+
+/// Color scheme of the pages
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+    /// Follow the preference of the browser
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Theme::Auto => "auto",
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+}
+
+/// Settings required for rendering pages
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UiConfig {
+    /// Path prefix all pages and resources are served at (empty or starting with a slash)
+    pub base_path: String,
+    /// URL prefixes of the mounted directories (except the one served at the root)
+    pub mounts: Vec<String>,
+    pub theme: Theme,
+    /// Scroll to and highlight changed contents after live updates
+    pub scroll_to_changes: bool,
+    /// Allow editing the markdown files in the browser
+    pub edit: bool,
+}
 
 fn hash(s: &'static str) -> u64 {
     let mut hasher = DefaultHasher::default();
@@ -32,7 +71,7 @@ fn hash(s: &'static str) -> u64 {
     hasher.finish()
 }
 
-fn render_head(title: &str) -> Markup {
+fn render_head(title: &str, config: &UiConfig) -> Markup {
     let css = format!(
         "{}{}",
         include_str!("./style.css"),
@@ -46,9 +85,9 @@ fn render_head(title: &str) -> Markup {
         meta charset="utf-8";
         title { (title) }
         meta name="description" content=(format!("{}", title));
-        script src=(format!("/.rsc/ws.js?{}", hash(include_str!("./ws.js")))) defer {
+        script src=(format!("{}/.rsc/ws.js?{}", config.base_path, hash(include_str!("./ws.js")))) defer {
         }
-        script src=(format!("/.rsc/prism.js?{}", hash(include_str!("./prism.js")))) defer {
+        script src=(format!("{}/.rsc/prism.js?{}", config.base_path, hash(include_str!("./prism.js")))) defer {
         }
         style {
             (PreEscaped(css))
@@ -57,16 +96,17 @@ fn render_head(title: &str) -> Markup {
 }
 
 #[inline]
-fn determine_visible_file<'a>(file: &'a Vec<&str>, depth: usize) -> &'a [&'a str] {
+fn determine_visible_file<'a>(file: &'a [&str], depth: usize) -> &'a [&'a str] {
     &file[depth..]
 }
 
 #[inline]
-fn render_sidebar_file(file: &Vec<&str>, depth: usize) -> Markup {
+fn render_sidebar_file(file: &Vec<&str>, depth: usize, config: &UiConfig) -> Markup {
     let visible_file = determine_visible_file(file, depth);
     let visible_file = visible_file.join("/");
     let href = format!(
-        "/{}",
+        "{}/{}",
+        config.base_path,
         file.iter()
             .map(|part| urlencoding::encode(part).to_string())
             .collect::<Vec<String>>()
@@ -127,7 +167,7 @@ fn split_dirs<'a>(
 }
 
 #[inline]
-fn render_sidebar_dir(files: &[&Vec<&str>], depth: usize) -> Markup {
+fn render_sidebar_dir(files: &[&Vec<&str>], depth: usize, config: &UiConfig) -> Markup {
     let dirs = split_dirs(files, depth);
 
     let files: Vec<&Vec<&str>> = files
@@ -138,7 +178,7 @@ fn render_sidebar_dir(files: &[&Vec<&str>], depth: usize) -> Markup {
 
     html! {
         @for file in files {
-            (render_sidebar_file(file, depth))
+            (render_sidebar_file(file, depth, config))
         }
         @for (dir, dirs) in dirs {
             div class="dir" {
@@ -146,13 +186,23 @@ fn render_sidebar_dir(files: &[&Vec<&str>], depth: usize) -> Markup {
                     (format!("/{}", dir))
                 }
 
-                (render_sidebar_dir(dirs, depth + 1))
+                (render_sidebar_dir(dirs, depth + 1, config))
             }
         }
     }
 }
 
-pub fn render_sidebar(files: &[String]) -> Markup {
+/// Identifies the list of files shown in the sidebar, so clients can tell, if they have missed
+/// changes
+pub fn files_version(files: &[String]) -> String {
+    let mut files: Vec<&str> = files.iter().map(|f| f.as_str()).collect();
+    files.sort_unstable();
+
+    let hasher = crc::Crc::<u64>::new(&crc::CRC_64_XZ);
+    format!("{:x}", hasher.checksum(files.join("\n").as_bytes()))
+}
+
+pub fn render_sidebar(files: &[String], config: &UiConfig) -> Markup {
     let mut files: Vec<&String> = files.iter().collect();
     files.sort_by(|&a, &b| -> Ordering {
         //let cnt_dir_a = a.matches("/").count();
@@ -161,15 +211,45 @@ pub fn render_sidebar(files: &[String]) -> Markup {
         a.cmp(b)
     });
 
-    let files: Vec<Vec<&str>> = files
+    let mut mounts: Vec<&str> = config.mounts.iter().map(|m| m.as_str()).collect();
+    mounts.sort();
+
+    // Every file belongs to exactly one mount (the root mount is empty)
+    let mount_of =
+        |file: &str| crate::mount::find_mount(file, mounts.iter().copied()).unwrap_or_default();
+
+    let files: Vec<(&str, Vec<&str>)> = files
         .iter()
         .map(|f| f.as_str())
-        .map(|f| f[1..].split('/').collect())
+        .map(|f| (mount_of(f), f[1..].split('/').collect()))
         .collect();
 
-    let files: Vec<&Vec<&str>> = files.iter().collect();
+    let files_of_mount = |mount: &str| -> Vec<&Vec<&str>> {
+        files
+            .iter()
+            .filter(|(m, _)| *m == mount)
+            .map(|(_, f)| f)
+            .collect()
+    };
 
-    render_sidebar_dir(&files[..], 0)
+    html! {
+        div id="sidebar-sort" {
+            button data-sort="name" { "Name" }
+            button data-sort="changed" { "Recently changed" }
+        }
+        div id="sidebar-tree" {
+            (render_sidebar_dir(&files_of_mount("")[..], 0, config))
+            @for mount in mounts {
+                div class="mount" {
+                    div class="mount-name" {
+                        (mount)
+                    }
+
+                    (render_sidebar_dir(&files_of_mount(mount)[..], mount.matches('/').count(), config))
+                }
+            }
+        }
+    }
 }
 
 /// Renders the page's main contents
@@ -178,7 +258,9 @@ pub fn render_contents(contents: Contents) -> Markup {
         main {
             @match contents {
                 Contents::Html(html_contents) => div {
-                    div id="built-content" {
+                    (render_diagnostics(&html_contents.diagnostics))
+
+                    div id="built-content" data-version=(html_contents.version()) data-read-only[html_contents.read_only] {
                         (PreEscaped(html_contents.contents.as_str()))
                     }
 
@@ -186,31 +268,177 @@ pub fn render_contents(contents: Contents) -> Markup {
                         "Words: " span id="word-count" {
                             (html_contents.word_count)
                         }
+                        " " span id="changed" {
+                            (changed_text(html_contents).unwrap_or_default())
+                        }
                     }
                 },
                 Contents::Text(text) =>  pre { (text) },
+                Contents::Problems(files) => div id="problems" {
+                    h1 { "Problems" }
+                    @if files.is_empty() {
+                        p { "No problems found." }
+                    }
+                    @for file in files.iter() {
+                        h2 {
+                            a href=(relative_file_url(&file.path)) {
+                                (file.title.as_deref().unwrap_or(&file.path))
+                            }
+                        }
+                        ul class="diagnostics" {
+                            @for diagnostic in file.diagnostics.iter() {
+                                (render_diagnostic(diagnostic))
+                            }
+                        }
+                    }
+                },
                 Contents::NotFound() => "404 - Not found"
             }
     }
     }
 }
 
+/// Link to the page of a file from a page at the base path (e.g. the problems)
+fn relative_file_url(path: &str) -> String {
+    path.trim_start_matches('/')
+        .split('/')
+        .map(|part| urlencoding::encode(part).to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn render_diagnostic(diagnostic: &Diagnostic) -> Markup {
+    let severity = match diagnostic.severity {
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    };
+
+    html! {
+        li class=(format!("diagnostic diagnostic-{}", severity)) data-block=[diagnostic.block] {
+            span class="diagnostic-severity" { (severity) }
+            @if let Some(line) = diagnostic.line {
+                " " span class="diagnostic-line" { "line " (line) }
+            }
+            " " span class="diagnostic-message" { (diagnostic.message) }
+        }
+    }
+}
+
+/// Renders the problems found while building the shown file (hidden, if there are none)
+fn render_diagnostics(diagnostics: &[Diagnostic]) -> Markup {
+    html! {
+        div id="diagnostics" hidden[diagnostics.is_empty()] {
+            ul class="diagnostics" {
+                @for diagnostic in diagnostics.iter() {
+                    (render_diagnostic(diagnostic))
+                }
+            }
+        }
+    }
+}
+
+/// Formats a date given in RFC 3339 for showing it
+fn format_date(date: &str) -> String {
+    chrono::DateTime::parse_from_rfc3339(date)
+        .map(|date| {
+            date.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|_| date.to_string())
+}
+
+/// Describes when and by whom the file was changed last
+pub fn changed_text(file: &BuiltFile) -> Option<String> {
+    let modified = file
+        .modified
+        .as_deref()
+        .map(|modified| format!("Modified: {}", format_date(modified)));
+    let commit = file.last_commit.as_ref().map(|commit| {
+        format!(
+            "Last commit: {} by {}",
+            format_date(&commit.date),
+            commit.author
+        )
+    });
+
+    match (modified, commit) {
+        (Some(modified), Some(commit)) => Some(format!("{} · {}", modified, commit)),
+        (modified, commit) => modified.or(commit),
+    }
+}
+
 pub enum Contents<'a> {
     Html(&'a BuiltFile),
     Text(&'a str),
+    /// Files with problems found while building them
+    Problems(&'a [FileMetadata]),
     NotFound(),
 }
 
-/// Renders just the body
-fn render_body(contents: Contents, files: &[String]) -> Markup {
+/// Renders the (initially hidden) editor of the markdown source
+fn render_editor() -> Markup {
     html! {
-        nav id="sidebar" {
-            (render_sidebar(files))
+        div id="editor" hidden {
+            div id="editor-actions" {
+                button id="editor-save" { "Save" }
+                button id="editor-cancel" { "Close" }
+                span id="editor-status" {}
+            }
+            textarea id="editor-source" spellcheck="false" {}
+        }
+    }
+}
+
+/// Renders just the body. The edit and page buttons are hidden for read only files.
+fn render_body(contents: Contents, files: &[String], config: &UiConfig) -> Markup {
+    let read_only = matches!(contents, Contents::Html(file) if file.read_only);
+    html! {
+        nav id="sidebar" data-version=(files_version(files)) {
+            (render_sidebar(files, config))
+        }
+        div id="disconnected" hidden {
+            "Connection to the server lost, reconnecting …"
+        }
+        @if config.edit {
+            (render_editor())
         }
         div id="contents" {
             (render_contents(contents))
         }
-        a href="/.license" title="License" {
+        div id="history" hidden {
+            div id="history-actions" {
+                button id="history-blame" { "Blame" }
+                button id="history-close" { "Close" }
+            }
+            ul id="history-commits" {}
+        }
+        div id="page-actions" {
+            button id="source-button" title="View source" {
+                (md_icons::filled::maud_icon_code())
+            }
+            button id="history-button" title="History" {
+                (md_icons::filled::maud_icon_history())
+            }
+            a id="problems-button" href=(format!("{}{}", config.base_path, crate::router::PROBLEMS_PATH)) title="Problems" {
+                (md_icons::filled::maud_icon_report_problem())
+            }
+            @if config.edit {
+                button id="edit-button" hidden[read_only] title="Edit" {
+                    (md_icons::filled::maud_icon_edit())
+                }
+                button id="new-page-button" title="New page" {
+                    (md_icons::filled::maud_icon_add())
+                }
+                button id="rename-page-button" hidden[read_only] title="Rename page" {
+                    (md_icons::filled::maud_icon_drive_file_rename_outline())
+                }
+                button id="delete-page-button" hidden[read_only] title="Delete page" {
+                    (md_icons::filled::maud_icon_delete())
+                }
+            }
+        }
+        a href=(format!("{}/.license", config.base_path)) title="License" {
             div id="info" {
                 (md_icons::filled::maud_icon_info())
             }
@@ -219,15 +447,15 @@ fn render_body(contents: Contents, files: &[String]) -> Markup {
 }
 
 /// Renders to whole HTML Page
-pub fn render_page(title: &str, contents: Contents, files: &[String]) -> Markup {
+pub fn render_page(title: &str, contents: Contents, files: &[String], config: &UiConfig) -> Markup {
     html! {
         (DOCTYPE)
-        html {
+        html data-theme=(config.theme.as_str()) {
             head {
-                (render_head(title))
+                (render_head(title, config))
             }
-            body {
-                (render_body(contents, files))
+            body data-base-path=(config.base_path) data-scroll-to-changes=(config.scroll_to_changes.to_string()) {
+                (render_body(contents, files, config))
             }
         }
     }
@@ -1,3 +1,23 @@
+/** Path prefix the server is hosted at (empty or starting with a slash) */
+const base_path = document.body.dataset.basePath || "";
+
+/** Scroll to and highlight changed contents after live updates */
+const scroll_to_changes = document.body.dataset.scrollToChanges !== "false";
+
+/**
+ * Returns the path of the markdown file for the given location path (without the base path)
+ *
+ * @param {string} pathname
+ * @returns {string}
+ */
+function file_path(pathname) {
+  if (base_path && pathname.startsWith(base_path)) {
+    pathname = pathname.substring(base_path.length);
+  }
+
+  return pathname;
+}
+
 /** @var HTMLElement */
 const comp_content = document.body.querySelector("#contents");
 
@@ -5,52 +25,382 @@ const comp_content = document.body.querySelector("#contents");
 let comp_built_content = comp_content.querySelector("#built-content");
 /** @var HTMLElement */
 let comp_words = comp_content.querySelector("#word-count");
+/** @var HTMLElement */
+let comp_changed = comp_content.querySelector("#changed");
+/** @var HTMLElement */
+let comp_diagnostics = comp_content.querySelector("#diagnostics");
 
 function update_comp() {
   comp_built_content = comp_content.querySelector("#built-content");
   comp_words = comp_content.querySelector("#word-count");
+  comp_changed = comp_content.querySelector("#changed");
+  comp_diagnostics = comp_content.querySelector("#diagnostics");
 }
 
 /** @var HTMLElement */
 const comp_sidebar = document.body.querySelector("#sidebar");
 
-const wslink = "ws://" + document.location.host + "/.ws";
-const socket = new WebSocket(wslink);
-socket.onmessage = function (event) {
+/** @var HTMLElement */
+const comp_disconnected = document.body.querySelector("#disconnected");
+
+/**
+ * Returns the path of the markdown file currently shown
+ *
+ * @returns {string}
+ */
+function current_path() {
+  return decoded_file_path(document.location.pathname);
+}
+
+/**
+ * Returns the decoded path of the markdown file for the given location path
+ *
+ * @param {string} pathname
+ * @returns {string}
+ */
+function decoded_file_path(pathname) {
+  return file_path(pathname).split("/")
+    .map(part => decodeURI(part))
+    .join("/");
+}
+
+const wsprotocol = document.location.protocol === "https:" ? "wss://" : "ws://";
+const wslink = wsprotocol + document.location.host + base_path + "/.ws";
+
+/** Delay before the next reconnection attempt in milliseconds */
+const reconnect_delay_min = 500;
+const reconnect_delay_max = 30000;
+let reconnect_delay = reconnect_delay_min;
+
+/** @var WebSocket */
+let socket = null;
+
+/** Failed websocket connections before falling back to server-sent events */
+const ws_max_failures = 2;
+let ws_failures = 0;
+
+/** @var EventSource (only used, if websockets are unavailable) */
+let events = null;
+
+/**
+ * Tells the server, which file is shown, so only updates of that file are sent
+ *
+ * @param {string} pathname
+ */
+function subscribe(pathname) {
+  if (events) {
+    // Event streams are one-way, so subscribing requires a new connection
+    events.close();
+    connect_events(decoded_file_path(pathname));
+  } else if (socket && socket.readyState === WebSocket.OPEN) {
+    socket.send(JSON.stringify({
+      action: "subscribe",
+      path: decoded_file_path(pathname),
+    }));
+  }
+}
+
+function on_message(event) {
   const data = JSON.parse(event.data);
   console.debug(data);
 
+  if (["update-content", "patch-content", "update-sidebar"].includes(data.action)) {
+    refresh_problems();
+  }
+
   switch (data.action) {
     case "update-content":
-      const current_path = document.location.pathname.split("/")
-        .map(part => decodeURI(part))
-        .join("/");
-      console.debug("Check: " + current_path + " === " + data.path);
-      if (current_path === data.path) {
+      console.debug("Check: " + current_path() + " === " + data.path);
+      if (current_path() === data.path) {
         comp_built_content.innerHTML = data.content.contents;
+        comp_built_content.dataset.version = data.content.version;
         comp_words.textContent = data.content.word_count;
+        comp_changed.textContent = data.content.changed || "";
+        show_diagnostics(data.content.diagnostics);
+        move_to_top(data.path, data.content.modified);
         if (typeof window.Prism === "object") {
           window.Prism.highlightAllUnder(comp_built_content);
         }
+        if (refresh_view) {
+          refresh_view();
+        }
+      }
+      break;
+    case "patch-content":
+      if (current_path() === data.path && !apply_patch(data.patch)) {
+        // Contents shown differ from the ones the patch applies to
+        fetch_contents(document.location.pathname);
+        break;
+      }
+
+      if (current_path() === data.path) {
+        comp_built_content.dataset.version = data.version;
+        comp_words.textContent = data.word_count;
+        comp_changed.textContent = data.changed || "";
+        show_diagnostics(data.diagnostics);
+        move_to_top(data.path, data.modified);
+        if (refresh_view) {
+          refresh_view();
+        }
+        if (scroll_to_changes) {
+          show_change(data.patch);
+        }
+      }
+      break;
+    case "goto":
+      // Only sent to clients showing the file
+      if (current_path() === data.path) {
+        scroll_to_block(data.block);
       }
       break;
     case "update-sidebar":
       comp_sidebar.innerHTML = data.content;
+      comp_sidebar.dataset.version = data.version;
+      sort_sidebar();
+      break;
+    case "reload":
+      document.location.reload();
       break;
   }
-};
+}
+
+/**
+ * Replaces the changed top-level blocks of the built content
+ *
+ * @param {object} patch
+ * @returns {boolean} false, if the patch doesn't apply to the shown contents
+ */
+function apply_patch(patch) {
+  const blocks = comp_built_content.children;
+  if (comp_built_content.dataset.version !== patch.from_version
+    || blocks.length !== patch.count) {
+    return false;
+  }
+
+  const next = blocks[patch.start + patch.remove] || null;
+  for (let i = 0; i < patch.remove; ++i) {
+    blocks[patch.start].remove();
+  }
+
+  const template = document.createElement("template");
+  for (const block of patch.insert) {
+    template.innerHTML = block;
+    const element = template.content.firstElementChild;
+    comp_built_content.insertBefore(element, next);
+    if (typeof window.Prism === "object") {
+      window.Prism.highlightAllUnder(element);
+    }
+  }
+
+  return true;
+}
+
+/**
+ * Scrolls to the first block changed by the patch and highlights it briefly
+ *
+ * @param {object} patch
+ */
+function show_change(patch) {
+  if (patch.remove === 0 && patch.insert.length === 0) {
+    return;
+  }
+
+  const blocks = comp_built_content.children;
+  // Blocks at the end were removed, if there's no block at the start index anymore
+  const element = blocks[patch.start] || blocks[patch.start - 1];
+  if (!element) {
+    return;
+  }
+
+  if (patch.line) {
+    console.debug("Changed source line: " + patch.line);
+  }
+
+  element.scrollIntoView({ behavior: "smooth", block: "center" });
+  element.classList.add("changed");
+  setTimeout(() => element.classList.remove("changed"), 2000);
+}
+
+/**
+ * Replaces the problems shown above the built contents
+ *
+ * @param {Array<object>} diagnostics
+ */
+function show_diagnostics(diagnostics) {
+  if (!comp_diagnostics) {
+    return;
+  }
+
+  const list = comp_diagnostics.querySelector("ul");
+  list.replaceChildren(...diagnostics.map(diagnostic => {
+    const item = element("li", "diagnostic diagnostic-" + diagnostic.severity);
+    if (diagnostic.block !== null && diagnostic.block !== undefined) {
+      item.dataset.block = diagnostic.block;
+    }
+    item.append(element("span", "diagnostic-severity", diagnostic.severity));
+    if (diagnostic.line) {
+      item.append(" ", element("span", "diagnostic-line", "line " + diagnostic.line));
+    }
+    item.append(" ", element("span", "diagnostic-message", diagnostic.message));
+
+    return item;
+  }));
+  comp_diagnostics.hidden = diagnostics.length === 0;
+}
+
+// Problems with a known position show it when clicked
+comp_content.addEventListener("click", event => {
+  const item = event.target.closest("#diagnostics .diagnostic[data-block]");
+  if (item) {
+    scroll_to_block(Number(item.dataset.block));
+  }
+});
+
+/** Pending refresh of the problems page */
+let problems_timeout = null;
+
+/** Fetches the list of problems again, if it's shown (at most once a second) */
+function refresh_problems() {
+  if (!document.getElementById("problems") || problems_timeout) {
+    return;
+  }
+
+  problems_timeout = setTimeout(() => {
+    problems_timeout = null;
+    fetch(base_path + "/.problems")
+      .then(response => response.text())
+      .then(text => {
+        const page = new DOMParser().parseFromString(text, "text/html");
+        const comp_problems = document.getElementById("problems");
+        const new_problems = page.getElementById("problems");
+        if (comp_problems && new_problems) {
+          comp_problems.replaceWith(new_problems);
+        }
+      });
+  }, 1000);
+}
+
+/**
+ * Scrolls to a top-level block of the built content (e.g. the cursor position of an editor)
+ *
+ * @param {number|null} block
+ */
+function scroll_to_block(block) {
+  if (block === null || block === undefined) {
+    return;
+  }
+
+  const element = comp_built_content.children[block];
+  if (element) {
+    element.scrollIntoView({ behavior: "smooth", block: "center" });
+  }
+}
+
+function connect() {
+  let opened = false;
+  socket = new WebSocket(wslink);
+  socket.onopen = function () {
+    opened = true;
+    ws_failures = 0;
+    reconnect_delay = reconnect_delay_min;
+    comp_disconnected.hidden = true;
+
+    // Tell the server what is shown, so it can send everything missed while disconnected
+    socket.send(JSON.stringify({
+      action: "hello",
+      path: current_path(),
+      version: comp_built_content ? comp_built_content.dataset.version : null,
+      sidebar_version: comp_sidebar.dataset.version,
+    }));
+  };
+  socket.onmessage = on_message;
+  socket.onclose = function () {
+    comp_disconnected.hidden = false;
+
+    // Proxies may refuse websocket upgrades, while plain HTTP requests work
+    if (!opened && ++ws_failures >= ws_max_failures && typeof EventSource === "function") {
+      console.debug("Websocket unavailable, falling back to server-sent events");
+      socket = null;
+      connect_events(current_path());
+      return;
+    }
+
+    console.debug("Reconnecting in " + reconnect_delay + "ms");
+    setTimeout(connect, reconnect_delay);
+    reconnect_delay = Math.min(reconnect_delay * 2, reconnect_delay_max);
+  };
+}
+
+/**
+ * Receives updates of the file at the path as server-sent events
+ *
+ * @param {string} path
+ */
+function connect_events(path) {
+  // Same as the websocket's hello message
+  const query = new URLSearchParams({ path: path });
+  if (comp_built_content && comp_built_content.dataset.version) {
+    query.set("version", comp_built_content.dataset.version);
+  }
+  if (comp_sidebar.dataset.version) {
+    query.set("sidebar_version", comp_sidebar.dataset.version);
+  }
+
+  events = new EventSource(base_path + "/.events?" + query);
+  events.onopen = function () {
+    reconnect_delay = reconnect_delay_min;
+    comp_disconnected.hidden = true;
+  };
+  events.onmessage = on_message;
+  events.onerror = function () {
+    const current = events;
+    current.close();
+    comp_disconnected.hidden = false;
+
+    console.debug("Reconnecting in " + reconnect_delay + "ms");
+    setTimeout(() => {
+      // Navigating already reconnected
+      if (events === current) {
+        connect_events(current_path());
+      }
+    }, reconnect_delay);
+    reconnect_delay = Math.min(reconnect_delay * 2, reconnect_delay_max);
+  };
+}
+
+connect();
 
 function fetch_contents(pathname, successfn) {
-  fetch("/.contents" + pathname)
+  fetch(base_path + "/.contents" + file_path(pathname))
     .then(response => response.text())
     .then(contents => {
-      document.title = pathname;
+      document.title = file_path(pathname);
       comp_content.innerHTML = contents;
       if (typeof window.Prism === "object") {
         window.Prism.highlightAllUnder(comp_content);
       }
 
       update_comp();
+      subscribe(pathname);
+      // Views of other files were replaced, keep showing the view (e.g. the source) of the new
+      // file if possible
+      const refresh = refresh_view;
+      comp_view = null;
+      refresh_view = null;
+      if (refresh && comp_built_content) {
+        refresh(pathname);
+      }
+      if (!comp_history.hidden) {
+        load_history(pathname);
+      }
+      if (comp_editor) {
+        update_page_actions();
+        if (is_read_only()) {
+          comp_editor.hidden = true;
+        } else if (!comp_editor.hidden) {
+          open_editor(pathname);
+        }
+      }
 
       if (successfn) {
         successfn();
@@ -58,25 +408,521 @@ function fetch_contents(pathname, successfn) {
     });
 }
 
+/**
+ * View replacing the built contents of the shown file (e.g. the source), removed when other
+ * contents are fetched
+ *
+ * @var HTMLElement
+ */
+let comp_view = null;
+/**
+ * Shows the view for the current contents again (null, if the view doesn't change with them)
+ *
+ * @var {function(string)}
+ */
+let refresh_view = null;
+
+/**
+ * Shows an element instead of the built contents
+ *
+ * @param {HTMLElement} comp
+ * @param {function(string)} refresh
+ */
+function show_view(comp, refresh) {
+  if (comp_view && comp_view.isConnected) {
+    comp_view.replaceWith(comp);
+  } else {
+    comp_built_content.after(comp);
+  }
+  comp_view = comp;
+  refresh_view = refresh;
+  comp_built_content.hidden = true;
+}
+
+/** Shows the built contents again */
+function hide_view() {
+  if (comp_view) {
+    comp_view.remove();
+    comp_view = null;
+    refresh_view = null;
+  }
+  if (comp_built_content) {
+    comp_built_content.hidden = false;
+  }
+}
+
+/**
+ * Shows the markdown source instead of the built contents
+ *
+ * @param {string} pathname Location path of the file
+ */
+function show_source(pathname = document.location.pathname) {
+  fetch(base_path + "/.raw" + file_path(pathname))
+    .then(response => response.ok ? response.text() : Promise.reject(response.statusText))
+    .then(source => {
+      const comp_pre = document.createElement("pre");
+      comp_pre.className = "line-numbers language-markdown";
+      comp_pre.id = "source";
+      const comp_code = document.createElement("code");
+      comp_code.className = "language-markdown";
+      comp_code.textContent = source;
+      comp_pre.appendChild(comp_code);
+
+      show_view(comp_pre, show_source);
+      if (typeof window.Prism === "object") {
+        window.Prism.highlightElement(comp_code);
+      }
+    })
+    .catch(err => alert("Failed loading the source: " + err));
+}
+
+document.body.querySelector("#source-button").addEventListener("click", () => {
+  if (!comp_built_content) {
+    return;
+  }
+
+  if (refresh_view === show_source) {
+    hide_view();
+  } else {
+    show_source();
+  }
+});
+
 window.onpopstate = (event) => {
   const href = document.location.pathname;
-  if (href.startsWith("/.")) {
+  if (file_path(href).startsWith("/.")) {
     document.location.pathname = href;
   } else {
     fetch_contents(href);
   }
 };
 
+/**
+ * Shows the linked file without reloading the page
+ *
+ * @param {HTMLAnchorElement} comp_file
+ */
+function follow_on_click(comp_file) {
+  comp_file.addEventListener("click", (event) => {
+    event.preventDefault();
+
+    const url = new URL(comp_file.href);
+
+    fetch_contents(
+      url.pathname,
+      () => {history.pushState({}, url.pathname, url.pathname);});
+  });
+}
+
 const comp_files = document.body.querySelectorAll("#sidebar .file a");
-comp_files.forEach(
-  comp_file => {
-    comp_file.addEventListener("click", (event) => {
-      event.preventDefault();
+comp_files.forEach(follow_on_click);
+
+/** Order of the files in the sidebar: "name" (as directory tree) or "changed" */
+let sidebar_sort = localStorage.getItem("sidebar-sort") || "name";
+
+/** Lists the files in the sidebar in the chosen order */
+function sort_sidebar() {
+  const comp_tree = comp_sidebar.querySelector("#sidebar-tree");
+  comp_sidebar.querySelectorAll("#sidebar-sort button").forEach(comp_button => {
+    comp_button.classList.toggle("active", comp_button.dataset.sort === sidebar_sort);
+  });
+
+  if (sidebar_sort !== "changed") {
+    comp_sidebar.querySelector("#sidebar-recent")?.remove();
+    comp_tree.hidden = false;
+    return;
+  }
+
+  fetch(base_path + "/.api/files")
+    .then(response => response.json())
+    .then(files => {
+      files.sort((a, b) => (b.modified || "").localeCompare(a.modified || ""));
+
+      const comp_recent = document.createElement("div");
+      comp_recent.id = "sidebar-recent";
+      files.forEach(file => {
+        const comp_file = document.createElement("div");
+        comp_file.className = "file";
+        const comp_link = document.createElement("a");
+        comp_link.href = base_path + file.path.split("/").map(encodeURIComponent).join("/");
+        comp_link.textContent = file.title || file.path;
+        comp_link.title = file.path;
+        follow_on_click(comp_link);
+        comp_file.appendChild(comp_link);
+        if (file.modified) {
+          const comp_date = document.createElement("div");
+          comp_date.className = "file-date";
+          comp_date.textContent = file.modified.substring(0, 16).replace("T", " ");
+          comp_file.appendChild(comp_date);
+        }
+        comp_recent.appendChild(comp_file);
+      });
 
-      const url = new URL(comp_file.href);
+      comp_sidebar.querySelector("#sidebar-recent")?.remove();
+      comp_tree.hidden = true;
+      comp_tree.after(comp_recent);
+    });
+}
 
-      fetch_contents(
-        url.pathname,
-        () => {history.pushState({}, url.pathname, url.pathname);});
+/**
+ * Moves a changed file to the top of the files listed by modification time, without fetching
+ * all files again
+ *
+ * @param {string} path
+ * @param {string|null} modified
+ */
+function move_to_top(path, modified) {
+  const comp_recent = comp_sidebar.querySelector("#sidebar-recent");
+  const comp_link = Array.from(comp_recent?.querySelectorAll("a") || [])
+    .find(comp_link => comp_link.title === path);
+  if (!comp_link || !modified) {
+    return;
+  }
+
+  const comp_file = comp_link.parentElement;
+  let comp_date = comp_file.querySelector(".file-date");
+  if (!comp_date) {
+    comp_date = document.createElement("div");
+    comp_date.className = "file-date";
+    comp_file.appendChild(comp_date);
+  }
+  comp_date.textContent = modified.substring(0, 16).replace("T", " ");
+  comp_recent.prepend(comp_file);
+}
+
+comp_sidebar.addEventListener("click", (event) => {
+  const sort = event.target.dataset.sort;
+  if (sort) {
+    sidebar_sort = sort;
+    localStorage.setItem("sidebar-sort", sort);
+    sort_sidebar();
+  }
+});
+sort_sidebar();
+
+/** @var HTMLElement */
+const comp_editor = document.body.querySelector("#editor");
+
+/** Hash of the markdown source loaded into the editor */
+let editor_version = null;
+/** Path (without the base path) of the file loaded into the editor */
+let editor_path = null;
+
+/**
+ * Shows the editor status message
+ *
+ * @param {string} msg
+ */
+function editor_status(msg) {
+  comp_editor.querySelector("#editor-status").textContent = msg;
+}
+
+/**
+ * Returns true, if the shown file can't be edited (e.g. notebooks are only shown)
+ *
+ * @returns {boolean}
+ */
+function is_read_only() {
+  return !!comp_built_content && "readOnly" in comp_built_content.dataset;
+}
+
+/** Hides the buttons changing the shown file, if it can't be edited */
+function update_page_actions() {
+  const read_only = is_read_only();
+  for (const id of ["#edit-button", "#rename-page-button", "#delete-page-button"]) {
+    document.body.querySelector(id).hidden = read_only;
+  }
+}
+
+/**
+ * Loads the markdown source of a file into the editor
+ *
+ * @param {string} pathname Location path of the file
+ */
+function open_editor(pathname = document.location.pathname) {
+  const path = file_path(pathname);
+  fetch(base_path + "/.api/source" + path)
+    .then(response => response.ok ? response.json() : Promise.reject(response.statusText))
+    .then(source => {
+      comp_editor.querySelector("#editor-source").value = source.markdown;
+      editor_version = source.version;
+      editor_path = path;
+      editor_status("");
+      comp_editor.hidden = false;
+    })
+    .catch(err => alert("Failed loading the source: " + err));
+}
+
+/** Writes the editor's contents to the file, the page is updated by the file watcher */
+function save_editor() {
+  fetch(base_path + "/.api/source" + editor_path, {
+    method: "PUT",
+    headers: {
+      "Content-Type": "application/json",
+      "If-Match": "\"" + editor_version + "\"",
+    },
+    body: JSON.stringify({ markdown: comp_editor.querySelector("#editor-source").value }),
+  })
+    .then(response => response.json().then(result => [response.status, result]))
+    .then(([status, result]) => {
+      if (status === 200) {
+        editor_version = result.version;
+        editor_status("Saved");
+      } else if (status === 412) {
+        editor_status("Not saved");
+        if (confirm("The file was changed in the meantime. Discard your changes and load the current version?")) {
+          open_editor();
+        }
+      } else {
+        editor_status(result.msg);
+      }
     })
+    .catch(err => editor_status("Failed saving: " + err));
+}
+
+/**
+ * Shows a page without reloading
+ *
+ * @param {string} path Path of the page (without the base path)
+ */
+function navigate(path) {
+  const pathname = base_path + path.split("/").map(part => encodeURIComponent(part)).join("/");
+  fetch_contents(pathname, () => {history.pushState({}, pathname, pathname);});
+}
+
+/**
+ * Creates, renames or deletes a page
+ *
+ * @param {object} action
+ * @returns {Promise<object>} resolves to the result, if the page was changed
+ */
+function change_page(action) {
+  return fetch(base_path + "/.api/pages", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(action),
+  })
+    .then(response => response.json().then(result => response.ok ? result : Promise.reject(result.msg)))
+    .catch(err => {
+      alert(err);
+      return Promise.reject(err);
+    });
+}
+
+if (comp_editor) {
+  document.body.querySelector("#new-page-button").addEventListener("click", () => {
+    const dir = current_path().replace(/[^/]*$/, "");
+    const path = prompt("Path of the new page", dir + "new.md");
+    if (path) {
+      change_page({ action: "create", path }).then(result => navigate(result.path));
+    }
+  });
+  document.body.querySelector("#rename-page-button").addEventListener("click", () => {
+    const path = current_path();
+    const new_path = prompt("New path of " + path, path);
+    if (new_path && new_path !== path) {
+      const rewrite_links = confirm("Change the links to " + path + " in other pages?");
+      change_page({ action: "rename", path, new_path, rewrite_links })
+        .then(result => navigate(result.path));
+    }
+  });
+  document.body.querySelector("#delete-page-button").addEventListener("click", () => {
+    const path = current_path();
+    if (confirm("Move " + path + " to the trash?")) {
+      change_page({ action: "delete", path }).then(() => navigate("/"));
+    }
   });
+
+  document.body.querySelector("#edit-button").addEventListener("click", () => {
+    if (comp_editor.hidden) {
+      open_editor();
+    } else {
+      comp_editor.hidden = true;
+    }
+  });
+  comp_editor.querySelector("#editor-save").addEventListener("click", save_editor);
+  comp_editor.querySelector("#editor-cancel").addEventListener("click", () => {
+    comp_editor.hidden = true;
+  });
+  comp_editor.querySelector("#editor-source").addEventListener("keydown", (event) => {
+    if ((event.ctrlKey || event.metaKey) && event.key === "s") {
+      event.preventDefault();
+      save_editor();
+    }
+  });
+}
+
+/** @var HTMLElement */
+const comp_history = document.body.querySelector("#history");
+
+/**
+ * Returns the API URL of the shown file
+ *
+ * @param {string} endpoint
+ * @param {string} pathname Location path of the file
+ * @returns {string}
+ */
+function api_url(endpoint, pathname = document.location.pathname) {
+  return base_path + "/.api/" + endpoint + file_path(pathname);
+}
+
+/**
+ * Fetches JSON from the API and rejects with the error message on failures
+ *
+ * @param {string} url
+ * @returns {Promise<object>}
+ */
+function fetch_api(url) {
+  return fetch(url)
+    .then(response => response.json().then(result => response.ok ? result : Promise.reject(result.msg)));
+}
+
+/**
+ * Creates an element with a class and text
+ *
+ * @param {string} tag
+ * @param {string} className
+ * @param {string} text
+ * @returns {HTMLElement}
+ */
+function element(tag, className, text) {
+  const comp = document.createElement(tag);
+  comp.className = className;
+  comp.textContent = text;
+  return comp;
+}
+
+/**
+ * Shows rendered HTML of an older version instead of the built contents
+ *
+ * @param {string} title
+ * @param {string} html
+ */
+function show_history_view(title, html) {
+  const comp = element("div", "history-view", "");
+  comp.appendChild(element("div", "history-view-title", title));
+  const comp_html = document.createElement("div");
+  comp_html.innerHTML = html;
+  comp.appendChild(comp_html);
+
+  show_view(comp, null);
+  if (typeof window.Prism === "object") {
+    window.Prism.highlightAllUnder(comp);
+  }
+}
+
+/**
+ * Shows the file at a revision
+ *
+ * @param {object} commit
+ */
+function show_revision(commit) {
+  fetch_api(api_url("revision") + "?rev=" + encodeURIComponent(commit.id))
+    .then(result => show_history_view(
+      "Revision " + commit.id.substring(0, 8) + ": " + commit.message, result.html))
+    .catch(err => alert(err));
+}
+
+/**
+ * Shows the changes between two revisions (or the working tree, if ``to`` is null)
+ *
+ * @param {object} from
+ * @param {object|null} to
+ */
+function show_diff(from, to) {
+  let url = api_url("diff") + "?from=" + encodeURIComponent(from.id);
+  if (to) {
+    url += "&to=" + encodeURIComponent(to.id);
+  }
+
+  fetch_api(url)
+    .then(result => show_history_view(
+      "Changes from " + from.id.substring(0, 8) + " to " + (to ? to.id.substring(0, 8) : "the current version"),
+      result.html))
+    .catch(err => alert(err));
+}
+
+/** Shows the commit, which last changed each line of the file */
+function show_blame() {
+  fetch_api(api_url("blame"))
+    .then(lines => {
+      const comp_table = element("table", "blame", "");
+      let previous = null;
+      lines.forEach(line => {
+        const comp_row = document.createElement("tr");
+        // Only show the commit for the first of consecutive lines
+        const commit = line.commit === previous ? "" :
+          line.commit.substring(0, 8) + " " + line.author + " " + line.date.substring(0, 10);
+        previous = line.commit;
+        comp_row.appendChild(element("td", "blame-commit", commit));
+        comp_row.appendChild(element("td", "blame-line", line.line));
+        comp_row.appendChild(element("td", "blame-text", line.text));
+        comp_table.appendChild(comp_row);
+      });
+      show_view(comp_table, null);
+    })
+    .catch(err => alert(err));
+}
+
+/**
+ * Lists the commits, which changed the file
+ *
+ * @param {string} pathname Location path of the file
+ */
+function load_history(pathname = document.location.pathname) {
+  const comp_commits = comp_history.querySelector("#history-commits");
+  comp_commits.textContent = "";
+
+  fetch_api(api_url("history", pathname))
+    .then(commits => {
+      commits.forEach((commit, i) => {
+        const comp_commit = element("li", "commit", "");
+        comp_commit.appendChild(element("div", "commit-message", commit.message));
+        comp_commit.appendChild(element("div", "commit-meta",
+          commit.id.substring(0, 8) + " " + commit.author + " " + commit.date.substring(0, 10)));
+
+        const comp_show = element("button", "", "Show");
+        comp_show.addEventListener("click", () => show_revision(commit));
+        comp_commit.appendChild(comp_show);
+
+        const parent = commits[i + 1];
+        if (parent) {
+          const comp_changes = element("button", "", "Changes");
+          comp_changes.addEventListener("click", () => show_diff(parent, commit));
+          comp_commit.appendChild(comp_changes);
+        }
+
+        const comp_compare = element("button", "", "Compare with current");
+        comp_compare.addEventListener("click", () => show_diff(commit, null));
+        comp_commit.appendChild(comp_compare);
+
+        comp_commits.appendChild(comp_commit);
+      });
+    })
+    .catch(err => comp_commits.appendChild(element("li", "", err)));
+}
+
+document.body.querySelector("#history-button").addEventListener("click", () => {
+  if (comp_history.hidden) {
+    load_history();
+  }
+  comp_history.hidden = !comp_history.hidden;
+});
+comp_history.querySelector("#history-blame").addEventListener("click", () => {
+  if (comp_built_content) {
+    show_blame();
+  }
+});
+comp_history.querySelector("#history-close").addEventListener("click", () => {
+  comp_history.hidden = true;
+  hide_view();
+});
+
+// Prism is loaded after this script. It skips code blocks, which were highlighted while building.
+document.addEventListener("DOMContentLoaded", () => {
+  if (typeof window.Prism === "object") {
+    window.Prism.hooks.add("before-all-elements-highlight", (env) => {
+      env.elements = env.elements.filter((element) => !element.closest("pre[data-highlighted]"));
+    });
+  }
+});